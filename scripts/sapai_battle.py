@@ -0,0 +1,50 @@
+#!/usr/bin/env python3
+"""
+Bridge between super-auto-sim's `crossval` mode and sapai
+(https://github.com/manny405/sapai).
+
+Reads one matchup per line on stdin, as JSON:
+
+    {"id": 0,
+     "team_a": [{"name": "pet-ant", "attack": 2, "health": 1, "level": 1,
+                 "experience": 0, "status": "status-honey-bee"}, ...],
+     "team_b": [...]}
+
+Teams are listed front friend first.  For each matchup, writes one line to
+stdout of the form {"id": 0, "winner": 0}, where the winner is 0 for team A,
+1 for team B, and 2 for a draw (sapai's own convention).
+"""
+
+import json
+import sys
+
+from sapai import Pet, Team
+from sapai.battle import Battle
+
+
+def build_team(pets):
+    team = []
+    for p in pets:
+        pet = Pet(p["name"])
+        pet.set_attack(p["attack"])
+        pet.set_health(p["health"])
+        pet.experience = p["experience"]
+        pet.level = p["level"]
+        if p["status"] is not None:
+            pet.status = p["status"]
+        team.append(pet)
+    return Team(team)
+
+
+def main():
+    for line in sys.stdin:
+        if not line.strip():
+            continue
+        m = json.loads(line)
+        battle = Battle(build_team(m["team_a"]), build_team(m["team_b"]))
+        winner = battle.battle()
+        print(json.dumps({"id": m["id"], "winner": winner}), flush=True)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,280 @@
+//! Scripted abilities, written in a small declarative language rather than
+//! as bespoke Rust.
+//!
+//! An ability is a trigger (a [`Cause`]), a target selector, and an effect;
+//! its magnitudes come from [`AbilityParams`], one per level.  Most built-in
+//! species are described this way (see [`Species::abilities`]), and
+//! [custom species](crate::custom) can list their own, e.g.
+//!
+//! ```json
+//! {
+//!     "trigger": "Hurt",
+//!     "target": "RandomEnemy",
+//!     "effect": "Damage",
+//!     "levels": [
+//!         { "targets": 1, "damage": 2 },
+//!         { "targets": 1, "damage": 4 },
+//!         { "targets": 1, "damage": 6 }
+//!     ]
+//! }
+//! ```
+//!
+//! Abilities are run by a generic interpreter at each trigger site, in
+//! [battle](crate::battle), the [shop](crate::shop), and on
+//! [summons](crate::team::Team::on_summon).  Not every combination makes
+//! sense (e.g. gold in battle), so [`Ability::check`] rejects the ones which
+//! the interpreter can't run.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::Cause,
+    modifier::Modifier,
+    species::{AbilityParams, Species},
+    target::Target,
+};
+
+/// Picks the friends (or enemies) which an ability affects
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub enum Selector {
+    /// The friend using the ability
+    #[default]
+    User,
+    NearestAhead,
+    NearestBehind,
+    LowestHealth,
+    HighestAttack,
+    /// [`AbilityParams::targets`] distinct random enemies
+    RandomEnemy,
+    /// [`AbilityParams::targets`] distinct random friends, other than the
+    /// user
+    RandomFriend,
+    AllFriends,
+    AllEnemies,
+    /// The friend which was just summoned (only on [`Cause::Summon`])
+    Summoned,
+    /// Every friend for sale in the shop (only in the shop)
+    Shop,
+}
+
+impl Selector {
+    /// Converts the selector into a [`Target`], picking `n` random targets
+    /// where applicable.  Returns `None` for selectors which don't pick from
+    /// a team by position.
+    pub fn target(self, n: usize) -> Option<Target> {
+        Some(match self {
+            Self::NearestAhead => Target::NearestAhead,
+            Self::NearestBehind => Target::NearestBehind,
+            Self::LowestHealth => Target::LowestHealth,
+            Self::HighestAttack => Target::HighestAttack,
+            Self::RandomEnemy => Target::RandomEnemy(n),
+            Self::RandomFriend => Target::RandomFriend(n),
+            Self::AllFriends => Target::AllFriends,
+            Self::AllEnemies => Target::AllEnemies,
+            Self::User | Self::Summoned | Self::Shop => return None,
+        })
+    }
+
+    /// Describes the selected friends, e.g. `2 random friends`
+    fn describe(self, n: usize) -> String {
+        let random = |one: &str, many: &str| match n {
+            1 => format!("a random {}", one),
+            n => format!("{} random {}", n, many),
+        };
+        match self {
+            Self::User => "itself".to_owned(),
+            Self::NearestAhead => "the nearest friend ahead".to_owned(),
+            Self::NearestBehind => "the nearest friend behind".to_owned(),
+            Self::LowestHealth => "the enemy with the lowest health".to_owned(),
+            Self::HighestAttack => {
+                "the enemy with the highest attack".to_owned()
+            }
+            Self::RandomEnemy => random("enemy", "enemies"),
+            Self::RandomFriend => random("friend", "friends"),
+            Self::AllFriends => "all friends".to_owned(),
+            Self::AllEnemies => "all enemies".to_owned(),
+            Self::Summoned => "it".to_owned(),
+            Self::Shop => "shop pets".to_owned(),
+        }
+    }
+}
+
+/// What an ability does to each selected friend
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Effect {
+    /// Gives [`AbilityParams::attack`] and [`AbilityParams::health`]
+    Buff,
+    /// Gives stats until the end of battle (which is the same as [`Buff`]
+    /// in battle, since the battle is thrown away)
+    ///
+    /// [`Buff`]: Effect::Buff
+    TempBuff,
+    /// Deals [`AbilityParams::damage`] (only in battle)
+    Damage,
+    /// Gives a modifier, replacing any it already had
+    Equip(Modifier),
+    /// Earns [`AbilityParams::gold`], ignoring the selector (only in the
+    /// shop)
+    Gold,
+}
+
+/// A triggered ability
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ability {
+    pub trigger: Cause,
+    #[serde(default)]
+    pub target: Selector,
+    pub effect: Effect,
+    /// Magnitudes at levels 1-3.  Built-in abilities leave these out, since
+    /// their magnitudes come from [`Species::ability_params`] (and so follow
+    /// the [active rule set](crate::rules)); custom abilities must have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub levels: Option<[AbilityParams; 3]>,
+}
+
+/// Checks whether the cause only happens in battle
+pub fn in_battle(cause: Cause) -> bool {
+    matches!(
+        cause,
+        Cause::BattleStart
+            | Cause::Hurt
+            | Cause::KnockOut
+            | Cause::Faint
+            | Cause::AheadAttacks
+            | Cause::AheadFaints
+    )
+}
+
+impl Ability {
+    /// Builds an ability whose magnitudes come from its species
+    pub const fn new(trigger: Cause, target: Selector, effect: Effect) -> Self {
+        Self {
+            trigger,
+            target,
+            effect,
+            levels: None,
+        }
+    }
+
+    /// Returns the ability's own magnitudes at the given level (clamped to
+    /// 1-3), if it has any
+    pub fn params(&self, level: usize) -> Option<AbilityParams> {
+        self.levels.map(|p| p[level.clamp(1, 3) - 1])
+    }
+
+    /// Checks that the interpreter can run the ability, returning a
+    /// description of the problem if not
+    pub fn check(&self) -> Result<(), String> {
+        let battle = in_battle(self.trigger);
+        let shop = !battle && self.trigger != Cause::Summon;
+        let target = self.target.target(0);
+        let enemy = target.is_some_and(|t| t.is_enemy());
+        let err = |s: &str| Err(format!("{} {}", self, s));
+        if self.trigger == Cause::Summon {
+            if !matches!(self.target, Selector::User | Selector::Summoned) {
+                return err("can only target the user or the summoned");
+            } else if matches!(self.effect, Effect::Damage | Effect::Gold) {
+                return err("can only buff or equip");
+            }
+        } else if self.target == Selector::Summoned {
+            return err("has no summoned friend");
+        }
+        if battle && self.effect == Effect::Gold {
+            err("can't earn gold in battle")
+        } else if battle && self.target == Selector::Shop {
+            err("has no shop in battle")
+        } else if shop && self.effect == Effect::Damage {
+            err("can't deal damage in the shop")
+        } else if shop && enemy {
+            err("has no enemies in the shop")
+        } else if self.effect == Effect::Damage && target.is_none() {
+            err("can only damage friends or enemies by position")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Describes the ability with the given magnitudes, e.g.
+    /// `Faint: give a random friend +2/+1`
+    pub fn describe(&self, p: AbilityParams) -> String {
+        let when = match self.trigger {
+            Cause::BattleStart => "Start of battle",
+            Cause::Hurt => "Hurt",
+            Cause::KnockOut => "Knock out",
+            Cause::Faint => "Faint",
+            Cause::AheadAttacks => "Friend ahead attacks",
+            Cause::AheadFaints => "Friend ahead faints",
+            Cause::Summon => "Friend summoned",
+            Cause::Buy => "Buy",
+            Cause::Sell => "Sell",
+            Cause::Sold => "Friend sold",
+            Cause::LevelUp => "Level-up",
+            Cause::StartTurn => "Start of turn",
+            Cause::EndTurn => "End turn",
+        };
+        let who = self.target.describe(p.targets);
+        let what = match self.effect {
+            Effect::Buff if self.target == Selector::User => {
+                format!("gain +{}/+{}", p.attack, p.health)
+            }
+            Effect::Buff => format!("give {} +{}/+{}", who, p.attack, p.health),
+            Effect::TempBuff => format!(
+                "give {} +{}/+{} until end of battle",
+                who, p.attack, p.health
+            ),
+            Effect::Damage => format!("deal {} damage to {}", p.damage, who),
+            Effect::Equip(m) => format!("give {} {:?}", who, m),
+            Effect::Gold => format!("gain {} gold", p.gold),
+        };
+        format!("{}: {}", when, what)
+    }
+}
+
+impl std::fmt::Display for Ability {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} targeting {:?} on {:?}",
+            self.effect, self.target, self.trigger
+        )
+    }
+}
+
+/// Built-in abilities which are simple enough to be scripted.  The Cricket
+/// and Whale are still bespoke: the Cricket's summon comes from the
+/// [token registry](crate::token), and the Whale remembers what it
+/// swallowed.
+pub(crate) fn builtin(s: Species) -> &'static [Ability] {
+    use {Cause::*, Effect::*, Selector::*};
+    match s {
+        Species::Ant => const { &[Ability::new(Faint, RandomFriend, Buff)] },
+        Species::Beaver => const { &[Ability::new(Sell, RandomFriend, Buff)] },
+        Species::Duck => const { &[Ability::new(Sell, Shop, Buff)] },
+        Species::Fish => const { &[Ability::new(LevelUp, AllFriends, Buff)] },
+        Species::Horse => const { &[Ability::new(Summon, Summoned, TempBuff)] },
+        Species::Mosquito => {
+            const { &[Ability::new(BattleStart, RandomEnemy, Damage)] }
+        }
+        Species::Otter => const { &[Ability::new(Buy, RandomFriend, Buff)] },
+        Species::Pig => const { &[Ability::new(Sell, User, Gold)] },
+        Species::Kangaroo => {
+            const { &[Ability::new(AheadAttacks, User, Buff)] }
+        }
+        Species::Ox => {
+            const {
+                &[
+                    Ability::new(AheadFaints, User, Equip(Modifier::Melon)),
+                    Ability::new(AheadFaints, User, Buff),
+                ]
+            }
+        }
+        Species::Cricket
+        | Species::Whale
+        | Species::GhostCricket
+        | Species::Bee
+        | Species::Custom(..) => &[],
+    }
+}
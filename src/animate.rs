@@ -0,0 +1,101 @@
+//! Frame-by-frame playback of a battle in the terminal
+use std::io::Write;
+
+use crate::{
+    battle::Battle,
+    event::{Event, Log},
+};
+
+/// The state of a battle at one point in time, along with the events which
+/// led up to it
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub events: Vec<Event>,
+    pub battle: Battle,
+}
+
+/// A log which records a [`Frame`] before the battle and at the start of every
+/// round, so that the battle can be played back with [`Frames::play`]
+#[derive(Clone, Debug, Default)]
+pub struct Frames {
+    frames: Vec<Frame>,
+    /// Events since the last snapshot
+    events: Vec<Event>,
+}
+
+impl Frames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Plays back the battle, clearing the terminal and redrawing the box art
+    /// for every frame, then waiting for `delay`.  Events after the last
+    /// frame (i.e. the end of the battle) are printed at the end.
+    pub fn play<W: Write>(
+        &self,
+        out: &mut W,
+        delay: std::time::Duration,
+    ) -> std::io::Result<()> {
+        for (i, frame) in self.frames.iter().enumerate() {
+            // Clear the screen and move the cursor to the top left
+            write!(out, "\x1b[2J\x1b[H")?;
+            writeln!(out, "Frame {}/{}\n", i + 1, self.frames.len())?;
+            writeln!(out, "{}\n", frame.battle)?;
+            for e in &frame.events {
+                writeln!(out, "{}", e)?;
+            }
+            out.flush()?;
+            std::thread::sleep(delay);
+        }
+        for e in &self.events {
+            writeln!(out, "{}", e)?;
+        }
+        out.flush()
+    }
+}
+
+impl Log for Frames {
+    fn log(&mut self, e: Event) {
+        self.events.push(e)
+    }
+    fn snapshot(&mut self, b: &Battle) {
+        self.frames.push(Frame {
+            events: std::mem::take(&mut self.events),
+            battle: *b,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::GameConfig, dice::DeterministicDice, team::Team};
+
+    #[test]
+    fn frames_per_round() {
+        // One frame before the battle and one at the start of every round,
+        // including the final check for a winner
+        let a: Team = "Fish:3/3".parse().unwrap();
+        let b: Team = "Fish:2/2,Ant:2/1".parse().unwrap();
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let mut frames = Frames::new();
+        let cfg = GameConfig::default();
+        let out = Battle(a, b).run_logged(&cfg, &mut dice, &mut frames);
+        assert!(!out.capped);
+        assert_eq!(frames.frames().len(), out.rounds + 2);
+        assert_eq!(frames.frames()[0].battle, Battle(a, b));
+        let last = frames.frames().last().unwrap().battle;
+        assert!(last.0.is_empty() || last.1.is_empty());
+
+        let mut text = vec![];
+        frames.play(&mut text, std::time::Duration::ZERO).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let n = frames.frames().len();
+        assert!(text.contains(&format!("Frame {}/{}\n", n, n)));
+    }
+}
@@ -1,11 +1,19 @@
-use log::trace;
+use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
+use tracing::{trace, trace_span};
 
 use crate::{
-    dice::Dice,
+    ability::{Effect, Selector},
+    config::{DamageModel, GameConfig, SummonPlacement},
+    dice::{DeterministicDice, Dice},
+    event::{Cause, Event, Log, Trigger},
+    friend::{AbilityState, Friend},
+    modifier::Modifier,
     params::TEAM_SIZE,
     species::Species,
+    target::Target,
     team::{Team, TeamPrinter},
+    token::Token,
 };
 
 #[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -18,79 +26,1146 @@ pub enum Winner {
 #[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct Battle(pub Team, pub Team);
 
+/// The result of a single battle
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Outcome {
+    pub winner: Winner,
+    /// Number of rounds of combat
+    pub rounds: usize,
+    /// Whether the battle was called as a tie after hitting the round cap
+    pub capped: bool,
+    /// Number of friends left on the winning team (0 for a tie)
+    pub survivors: usize,
+    /// Total level of the friends left on the winning team (0 for a tie)
+    pub survivor_levels: usize,
+}
+
+impl Outcome {
+    /// Returns the number of lives lost by the losing team, if this battle
+    /// happened on the given turn (starting from 1).  Ties cost nothing.
+    pub fn damage(&self, model: DamageModel, turn: usize) -> usize {
+        if self.winner == Winner::Tied {
+            return 0;
+        }
+        let max = match turn {
+            0..=2 => 1,
+            3..=4 => 2,
+            _ => 3,
+        };
+        match model {
+            DamageModel::Turn => max,
+            DamageModel::Survivors => self.survivor_levels.clamp(1, max),
+        }
+    }
+}
+
+/// A single instance of damage, applied as part of a simultaneous batch
+#[derive(Copy, Clone, Debug)]
+struct Hit {
+    team: bool,
+    pos: usize,
+    amount: usize,
+    /// Team and position of the friend dealing the damage, if any
+    source: Option<(bool, usize)>,
+}
+
+/// Friends affected by hits which have landed but not yet been resolved
+#[derive(Default)]
+struct Landed {
+    /// Team and position of every friend which took damage
+    hurt: Vec<(bool, usize)>,
+    /// Pairs of `(source, victim)` team and position, for every friend whose
+    /// health was reduced to zero by a hit with a source
+    knockouts: Vec<((bool, usize), (bool, usize))>,
+}
+
+/// Damage from an ability which triggers while hits are being resolved (e.g.
+/// on hurt or on faint).  It lands in a later batch, once the current batch
+/// is fully resolved; its targets are only picked then, because resolving
+/// moves friends around (removing the fainted, summoning tokens, ...).
+#[derive(Copy, Clone, Debug)]
+struct Strike {
+    /// Team of the friend whose ability dealt the damage
+    team: bool,
+    target: Target,
+    amount: usize,
+}
+
+/// Where damage dealt by a [scripted ability](crate::ability) goes
+enum Sink<'a> {
+    /// Hits land in the current batch, with targets picked right away
+    Land(&'a mut Landed),
+    /// Damage lands in a later batch, as a [`Strike`]
+    Strike(&'a mut Vec<Strike>),
+}
+
+/// A single dice path through a battle
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Replay {
+    /// Dice key, which can be passed to [`DeterministicDice::from_key`]
+    pub key: String,
+    pub winner: Winner,
+    pub events: Vec<Event>,
+}
+
+/// How often an outcome happens among the dice paths through a battle
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Tally {
+    pub outcome: Outcome,
+    /// Number of dice paths which end in the outcome
+    pub paths: usize,
+    /// Chance that real dice take one of those paths
+    pub probability: f64,
+}
+
+/// Adds paths to the tally for their outcome
+fn tally(out: &mut Vec<Tally>, outcome: Outcome, paths: usize, p: f64) {
+    match out.iter_mut().find(|t| t.outcome == outcome) {
+        Some(t) => {
+            t.paths += paths;
+            t.probability += p;
+        }
+        None => out.push(Tally {
+            outcome,
+            paths,
+            probability: p,
+        }),
+    }
+}
+
+/// A battle state whose subtree of dice paths is still being enumerated
+struct Open {
+    state: (Battle, usize),
+    /// Number of rolls made before reaching the state
+    depth: usize,
+    /// Chance of those rolls
+    probability: f64,
+    /// Outcomes so far, with probabilities conditional on reaching the state
+    tallies: Vec<Tally>,
+}
+
 impl Battle {
-    /// Simulates a battle, returning the winner
-    pub fn run<R: Dice>(mut self, rng: &mut R) -> Winner {
+    /// Simulates a battle, returning its outcome.  [Plain](Battle::is_plain)
+    /// battles never roll the dice, so they take a faster path.
+    pub fn run<R: Dice>(self, cfg: &GameConfig, rng: &mut R) -> Outcome {
+        if self.is_plain() {
+            return self.run_plain(cfg);
+        }
+        self.run_logged(cfg, rng, &mut ())
+    }
+
+    /// Checks whether no friend on either team has an ability or perk which
+    /// acts in battle, so that every round is just a clash between the
+    /// friends at the front
+    pub fn is_plain(&self) -> bool {
+        self.0
+            .iter()
+            .chain(self.1.iter())
+            .flatten()
+            .all(|f| f.modifier.is_none() && !f.species.acts_in_battle())
+    }
+
+    /// Simulates a [plain](Battle::is_plain) battle by arithmetic on the
+    /// friends' stats, with the same outcome as the full engine
+    fn run_plain(mut self, cfg: &GameConfig) -> Outcome {
+        self.0.apply_temp();
+        self.1.apply_temp();
+        let mut a: Vec<Friend> = self.0.iter().flatten().copied().collect();
+        let mut b: Vec<Friend> = self.1.iter().flatten().copied().collect();
+        for i in 0..=cfg.max_rounds {
+            let winner = match (a.is_empty(), b.is_empty()) {
+                (true, true) => Winner::Tied,
+                (false, true) => Winner::TeamA,
+                (true, false) => Winner::TeamB,
+                (false, false) if i < cfg.max_rounds => {
+                    let (x, y) = (a[0].attack, b[0].attack);
+                    // Nothing changes from round to round, so the battle
+                    // will hit the round cap
+                    if x == 0 && y == 0 {
+                        break;
+                    }
+                    a[0].health = a[0].health.saturating_sub(y);
+                    b[0].health = b[0].health.saturating_sub(x);
+                    a.retain(|f| f.health > 0);
+                    b.retain(|f| f.health > 0);
+                    continue;
+                }
+                (false, false) => break,
+            };
+            return Outcome {
+                winner,
+                rounds: i,
+                capped: false,
+                survivors: a.len() + b.len(),
+                survivor_levels: a.iter().chain(&b).map(Friend::level).sum(),
+            };
+        }
+        Outcome {
+            winner: Winner::Tied,
+            rounds: cfg.max_rounds,
+            capped: true,
+            survivors: 0,
+            survivor_levels: 0,
+        }
+    }
+
+    /// Simulates a battle, recording every event into the given log and
+    /// returning its outcome
+    pub fn run_logged<R: Dice, L: Log>(
+        self,
+        cfg: &GameConfig,
+        rng: &mut R,
+        log: &mut L,
+    ) -> Outcome {
+        self.run_until(cfg, rng, log, |_, _, _| false).unwrap()
+    }
+
+    /// Simulates a battle, calling `stop` with the state at the start of
+    /// each round (along with the round number and the dice).  Returns
+    /// `None` if `stop` ended the battle early.
+    fn run_until<R: Dice, L: Log, F: FnMut(&Battle, usize, &R) -> bool>(
+        mut self,
+        cfg: &GameConfig,
+        rng: &mut R,
+        log: &mut L,
+        mut stop: F,
+    ) -> Option<Outcome> {
+        let span = trace_span!(
+            "battle",
+            team_a = self.0.fingerprint(),
+            team_b = self.1.fingerprint(),
+        );
+        let _guard = span.enter();
         trace!("Initial state:\n{}", self);
-        self.before_battle(rng);
-        for i in 0.. {
-            trace!("Round {}:\n{}", i, self);
-            match (self.0.is_empty(), self.1.is_empty()) {
+        self.0.apply_temp();
+        self.1.apply_temp();
+        self.0.clear_battle_state();
+        self.1.clear_battle_state();
+        log.snapshot(&self);
+        self.before_battle(cfg, rng, log);
+        for i in 0..=cfg.max_rounds {
+            if stop(&self, i, rng) {
+                return None;
+            }
+            let span = trace_span!(
+                "round",
+                round = i,
+                team_a = self.0.fingerprint(),
+                team_b = self.1.fingerprint(),
+                dice = rng.index(),
+            );
+            let _guard = span.enter();
+            trace!("State:\n{}", self);
+            log.snapshot(&self);
+            debug_assert_eq!(self.check(), Ok(()));
+            let winner = match (self.0.is_empty(), self.1.is_empty()) {
                 (true, true) => {
-                    trace!("Battle ended with a tie");
-                    return Winner::Tied;
+                    trace!(winner = ?Winner::Tied, "Battle ended");
+                    Winner::Tied
                 }
                 (false, true) => {
-                    trace!("Battle ended with a win for Team A");
-                    return Winner::TeamA;
+                    trace!(winner = ?Winner::TeamA, "Battle ended");
+                    Winner::TeamA
                 }
                 (true, false) => {
-                    trace!("Battle ended with a win for Team B");
-                    return Winner::TeamB;
+                    trace!(winner = ?Winner::TeamB, "Battle ended");
+                    Winner::TeamB
+                }
+                (false, false) if i < cfg.max_rounds => {
+                    log.log(Event::Round(i));
+                    self.step(cfg, rng, log);
+                    continue;
+                }
+                (false, false) => break,
+            };
+            log.log(Event::End(winner));
+            return Some(Outcome {
+                winner,
+                rounds: i,
+                capped: false,
+                // The losing team is empty
+                survivors: self.0.count() + self.1.count(),
+                survivor_levels: self
+                    .0
+                    .iter()
+                    .chain(self.1.iter())
+                    .flatten()
+                    .map(Friend::level)
+                    .sum(),
+            });
+        }
+        trace!(
+            rounds = cfg.max_rounds,
+            "Battle hit the round cap; calling a tie"
+        );
+        log.log(Event::End(Winner::Tied));
+        Some(Outcome {
+            winner: Winner::Tied,
+            rounds: cfg.max_rounds,
+            capped: true,
+            survivors: 0,
+            survivor_levels: 0,
+        })
+    }
+
+    /// Checks invariants that hold between rounds: both teams are valid and
+    /// compact, so that the front friend is at index 0.
+    pub fn check(&self) -> Result<(), String> {
+        for t in [true, false] {
+            let name = if t { "Team A" } else { "Team B" };
+            self[t].check().map_err(|e| format!("{}: {}", name, e))?;
+            if !self[t].is_compact() {
+                return Err(format!("{} is not compact", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every possible dice path through the battle, recording each one
+    pub fn replays(self, cfg: &GameConfig) -> Vec<Replay> {
+        let mut out = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let mut events = vec![];
+            let outcome = self.run_logged(cfg, &mut dice, &mut events);
+            out.push(Replay {
+                key: dice.key(),
+                winner: outcome.winner,
+                events,
+            });
+        }
+        out
+    }
+
+    /// Runs every possible dice path through the battle, returning how often
+    /// each outcome happens (as [`Battle::run`] would along each path)
+    pub fn outcomes(self, cfg: &GameConfig) -> Vec<Tally> {
+        if cfg.memo_battles && !self.is_plain() {
+            return self.memoized_outcomes(cfg);
+        }
+        let mut out = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let outcome = self.run(cfg, &mut dice);
+            tally(&mut out, outcome, 1, dice.probability());
+        }
+        out
+    }
+
+    /// Same as [`Battle::outcomes`], but collapsing paths which meet.
+    ///
+    /// Different dice often lead to the same state at the start of a round
+    /// (e.g. when an ability picks between identical friends), and every
+    /// path from then on is the same.  States are memoized along with the
+    /// outcomes of the paths through them, so each one is only explored
+    /// once.
+    fn memoized_outcomes(self, cfg: &GameConfig) -> Vec<Tally> {
+        let mut out = vec![];
+        let mut memo: HashMap<(Battle, usize), Vec<Tally>> = HashMap::new();
+        let mut open: Vec<Open> = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            // Paths are enumerated depth-first, so once a state's prefix has
+            // changed, every path through it has been seen
+            while open.last().is_some_and(|o| o.depth > dice.unchanged()) {
+                let o = open.pop().unwrap();
+                memo.insert(o.state, o.tallies);
+            }
+
+            let mut hit = None;
+            let outcome =
+                self.run_until(cfg, &mut dice, &mut (), |b, i, dice| {
+                    // Before the first roll, there's only one way to reach
+                    // the state, so it's not worth remembering
+                    let depth = dice.index().unwrap();
+                    if depth == 0 {
+                        return false;
+                    }
+                    let state = (*b, i);
+                    if open.iter().any(|o| o.state == state) {
+                        return false;
+                    }
+                    let probability = dice.prefix_probability();
+                    if let Some(ts) = memo.get(&state) {
+                        hit = Some((ts.clone(), probability));
+                        return true;
+                    }
+                    open.push(Open {
+                        state,
+                        depth,
+                        probability,
+                        tallies: vec![],
+                    });
+                    false
+                });
+            // Outcomes of the path (or of every path through a memoized
+            // state), with their probability from the start of the battle
+            let found = match (outcome, hit) {
+                (Some(o), None) => vec![(o, 1, dice.probability())],
+                (None, Some((ts, p))) => {
+                    dice.truncate();
+                    ts.iter()
+                        .map(|t| (t.outcome, t.paths, t.probability * p))
+                        .collect()
+                }
+                _ => unreachable!(),
+            };
+            for (outcome, paths, p) in found {
+                tally(&mut out, outcome, paths, p);
+                for o in &mut open {
+                    tally(&mut o.tallies, outcome, paths, p / o.probability);
                 }
-                (false, false) => self.step(rng),
             }
         }
-        unreachable!();
+        out
     }
 
-    /// Performs pre-battle actions, returning all possible states
-    fn before_battle<R: Dice>(&mut self, rng: &mut R) {
+    /// Performs pre-battle actions in place, along the path chosen by the
+    /// dice, then settles any faints and compacts both teams
+    fn before_battle<R: Dice, L: Log>(
+        &mut self,
+        cfg: &GameConfig,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let mut landed = Landed::default();
         for t in [true, false] {
             for i in 0..TEAM_SIZE {
-                self.on_battle_start(i, t, rng);
+                self.on_battle_start(i, t, &mut landed, rng, log);
+            }
+        }
+        // Battle-start damage can knock out friends, whose faint triggers can
+        // knock out (or summon) more friends, all before the first round;
+        // this cascades until things settle down
+        self.cascade(cfg, landed, rng, log);
+        debug_assert!(!self.has_fainted());
+
+        // Swallowed friends leave gaps, which are only closed here so that
+        // positions stay valid while hits are pending.
+        self.0.compact();
+        self.1.compact();
+    }
+
+    /// Returns the damage dealt when the friend at the given position attacks,
+    /// applying (and consuming, if single-use) its attack perk.
+    fn attack_damage<L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        log: &mut L,
+    ) -> usize {
+        let f = self[team][pos].as_mut().unwrap();
+        let bonus = match f.modifier {
+            Some(Modifier::MeatBone) => 3,
+            Some(Modifier::Steak) => 20,
+            _ => return f.attack,
+        };
+        let m = f.modifier.unwrap();
+        if m.is_single_use() {
+            f.modifier = None;
+        }
+        trace!(species = ?f.species, pos, modifier = ?m, bonus, "Perk attack");
+        log.log(Event::Perk {
+            team,
+            pos,
+            species: f.species,
+            modifier: m,
+        });
+        f.attack + bonus
+    }
+
+    /// Applies damage to the friend at the given position, after its defensive
+    /// perk, returning the damage actually dealt.  Fainted friends aren't
+    /// removed here; callers apply every simultaneous hit first, then call
+    /// [`Battle::resolve`] so that hurt and faint triggers run in order.
+    fn damage<L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        amount: usize,
+        log: &mut L,
+    ) -> usize {
+        let f = self[team][pos].as_mut().unwrap();
+        if amount == 0 {
+            return 0;
+        }
+        let dealt = match f.modifier {
+            Some(Modifier::Melon) => amount.saturating_sub(20),
+            Some(Modifier::Garlic) => {
+                std::cmp::max(amount.saturating_sub(2), 1)
             }
+            Some(Modifier::Coconut) => 0,
+            _ => amount,
+        };
+        if dealt != amount {
+            let m = f.modifier.unwrap();
+            if m.is_single_use() {
+                f.modifier = None;
+            }
+            trace!(
+                species = ?f.species,
+                pos,
+                modifier = ?m,
+                "Perk blocks damage"
+            );
+            log.log(Event::Perk {
+                team,
+                pos,
+                species: f.species,
+                modifier: m,
+            });
+        }
+        if dealt == 0 {
+            return 0;
         }
-        // XXX This architecture wouldn't work for more complex situations,
-        // e.g. a mosquito sniping a hedgehog which then kills other stuff
-        self.0.remove_dead(rng);
-        self.1.remove_dead(rng);
+        f.health = f.health.saturating_sub(dealt);
+        log.log(Event::Hurt {
+            team,
+            pos,
+            species: f.species,
+            damage: dealt,
+        });
+        dealt
     }
 
-    fn on_battle_start<R: Dice>(&mut self, i: usize, team: bool, rng: &mut R) {
+    /// Returns splash damage from the friend at the given position attacking
+    /// the enemy front, which hits the enemy behind its target.
+    fn splash<L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        log: &mut L,
+    ) -> Option<Hit> {
+        let f = self[team][pos].unwrap();
+        if f.modifier != Some(Modifier::Chili) || self[!team][1].is_none() {
+            return None;
+        }
+        trace!(
+            species = ?f.species,
+            pos,
+            modifier = ?Modifier::Chili,
+            "Splash"
+        );
+        log.log(Event::Perk {
+            team,
+            pos,
+            species: f.species,
+            modifier: Modifier::Chili,
+        });
+        Some(Hit {
+            team: !team,
+            pos: 1,
+            amount: 5,
+            source: Some((team, pos)),
+        })
+    }
+
+    /// Applies a batch of hits, recording their effects without resolving
+    /// any triggers.  Each friend is credited with the knock-out if its hit is
+    /// the one which reduces an enemy's health to zero.
+    fn land<L: Log>(&mut self, hits: &[Hit], landed: &mut Landed, log: &mut L) {
+        for h in hits {
+            if !matches!(self[h.team][h.pos], Some(f) if f.health > 0) {
+                continue;
+            }
+            if self.damage(h.team, h.pos, h.amount, log) == 0 {
+                continue;
+            }
+            if !landed.hurt.contains(&(h.team, h.pos)) {
+                landed.hurt.push((h.team, h.pos));
+            }
+            if let Some(src) = h.source {
+                if self[h.team][h.pos].unwrap().health == 0 {
+                    landed.knockouts.push((src, (h.team, h.pos)));
+                }
+            }
+        }
+    }
+
+    /// Resolves landed hits, then lands and resolves any damage which their
+    /// triggers dealt, repeating until the battle settles: no damage is
+    /// pending and no fainted friend is left on either team.
+    fn cascade<R: Dice, L: Log>(
+        &mut self,
+        cfg: &GameConfig,
+        mut landed: Landed,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        for depth in 0.. {
+            let strikes = self.resolve(cfg, landed, rng, log);
+            if strikes.is_empty() && !self.has_fainted() {
+                break;
+            }
+            trace!(depth, strikes = strikes.len(), "Cascading damage");
+            landed = Landed::default();
+            for s in strikes {
+                let targets = s.target.select(
+                    &self[s.team],
+                    None,
+                    Some(&self[!s.team]),
+                    rng,
+                );
+                let side = s.team != s.target.is_enemy();
+                let hits: Vec<Hit> = targets
+                    .into_iter()
+                    .map(|pos| Hit {
+                        team: side,
+                        pos,
+                        amount: s.amount,
+                        source: None,
+                    })
+                    .collect();
+                self.land(&hits, &mut landed, log);
+            }
+        }
+    }
+
+    /// Checks whether any friend is left at zero health, e.g. because a
+    /// faint trigger on one team knocked out a friend on the other team after
+    /// its fainted friends were removed
+    fn has_fainted(&self) -> bool {
+        [true, false]
+            .into_iter()
+            .flat_map(|t| self[t].iter().flatten())
+            .any(|f| f.health == 0)
+    }
+
+    /// Resolves landed hits: hurt triggers run first, then knock-out triggers
+    /// for surviving sources, then fainted friends are removed from both teams
+    /// (running their faint triggers).  Returns damage dealt by triggers,
+    /// which hasn't landed yet.
+    fn resolve<R: Dice, L: Log>(
+        &mut self,
+        cfg: &GameConfig,
+        landed: Landed,
+        rng: &mut R,
+        log: &mut L,
+    ) -> Vec<Strike> {
+        let mut strikes = vec![];
+        for &(team, pos) in &landed.hurt {
+            self.on_hurt(team, pos, &mut strikes, rng, log);
+        }
+        for &((team, pos), (vt, vp)) in &landed.knockouts {
+            let (f, g) = match (self[team][pos], self[vt][vp]) {
+                (Some(f), Some(g)) if f.health > 0 => (f, g),
+                _ => continue,
+            };
+            trace!(species = ?f.species, pos, victim = ?g.species, "Knock out");
+            log.log(Event::KnockOut {
+                team,
+                pos,
+                species: f.species,
+                victim: g.species,
+            });
+            self.on_knock_out(team, pos, &mut strikes, rng, log);
+        }
+        self.remove_dead(cfg, true, &mut strikes, rng, log);
+        self.remove_dead(cfg, false, &mut strikes, rng, log);
+        strikes
+    }
+
+    /// Asks the friend at the given position to perform its knock-out action,
+    /// after it reduced an enemy's health to zero.
+    fn on_knock_out<R: Dice, L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        strikes: &mut Vec<Strike>,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        if self.try_trigger(team, pos, Cause::KnockOut) {
+            let f = self[team][pos].unwrap();
+            let trigger = Trigger {
+                team,
+                pos,
+                species: f.species,
+                cause: Cause::KnockOut,
+            };
+            let sink = Sink::Strike(strikes);
+            self.run_scripted(trigger, &f, true, sink, rng, log);
+        }
+    }
+
+    /// Checks whether the friend at the given position may trigger its
+    /// ability for the cause, counting the trigger against its limit (if any).
+    fn try_trigger(&mut self, team: bool, pos: usize, cause: Cause) -> bool {
+        match self[team][pos].as_mut() {
+            Some(f) => f.try_trigger(cause),
+            None => false,
+        }
+    }
+
+    /// Asks the friend at the given position to perform its on-hurt action.
+    /// It may have fainted from the damage, but hasn't yet been removed.
+    fn on_hurt<R: Dice, L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        strikes: &mut Vec<Strike>,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        if self.try_trigger(team, pos, Cause::Hurt) {
+            let f = self[team][pos].unwrap();
+            let trigger = Trigger {
+                team,
+                pos,
+                species: f.species,
+                cause: Cause::Hurt,
+            };
+            let sink = Sink::Strike(strikes);
+            self.run_scripted(trigger, &f, true, sink, rng, log);
+        }
+    }
+
+    fn on_battle_start<R: Dice, L: Log>(
+        &mut self,
+        i: usize,
+        team: bool,
+        landed: &mut Landed,
+        rng: &mut R,
+        log: &mut L,
+    ) {
         let f = match self[team][i] {
             Some(f) => f,
             None => return,
         };
-        match f.species {
-            Species::Mosquito => {
-                for j in self[!team].random_friends(f.level(), rng) {
-                    let g = self[!team][j].as_mut().unwrap();
-                    trace!(
-                        "{} at {} shot {} at {} for 1",
-                        f.species,
-                        i,
-                        g.species,
-                        j
+        if !self.try_trigger(team, i, Cause::BattleStart) {
+            return;
+        }
+        let trigger = Trigger {
+            team,
+            pos: i,
+            species: f.species,
+            cause: Cause::BattleStart,
+        };
+        for s in f.species.templates() {
+            // Dispatch is a `match` on species, so that other abilities
+            // with this trigger slot in as new arms
+            #[allow(clippy::single_match)]
+            match s {
+                Species::Whale => {
+                    log.begin_trigger(trigger);
+                    let targets = Target::NearestAhead.select(
+                        &self[team],
+                        Some(i),
+                        None,
+                        rng,
                     );
-                    g.health = g.health.saturating_sub(1);
+                    log.targets(team, &targets);
+                    if let Some(&j) = targets.first() {
+                        let g = self[team][j].take().unwrap();
+                        trace!(
+                            species = ?f.species,
+                            pos = i,
+                            swallowed = ?g.species,
+                            "Swallow"
+                        );
+                        log.log(Event::Swallow {
+                            team,
+                            pos: i,
+                            species: f.species,
+                            swallowed: g.species,
+                        });
+                        self[team][i].as_mut().unwrap().state =
+                            AbilityState::Swallowed(g.species);
+                    }
+                    log.end_trigger();
                 }
+                _ => (),
             }
-            _ => (),
         }
+        self.run_scripted(trigger, &f, true, Sink::Land(landed), rng, log);
     }
 
-    /// Executes a single step of the battle, returning true if the battle ended
-    fn step<R: Dice>(&mut self, rng: &mut R) {
-        let f = self.0[0].as_mut().unwrap();
-        let g = self.1[0].as_mut().unwrap();
-        trace!("{} clashes with {}!", f.species, g.species);
-        f.health = f.health.saturating_sub(g.attack);
-        g.health = g.health.saturating_sub(f.attack);
+    /// Executes a single step of the battle
+    fn step<R: Dice, L: Log>(
+        &mut self,
+        cfg: &GameConfig,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let f = self.0[0].unwrap();
+        let g = self.1[0].unwrap();
+        trace!(a = ?f.species, b = ?g.species, "Clash");
+        log.log(Event::Clash {
+            a: f.species,
+            b: g.species,
+        });
+
+        // Both friends attack simultaneously, so compute outgoing damage
+        // (including splash) before applying any hits.
+        let a = self.attack_damage(true, 0, log);
+        let b = self.attack_damage(false, 0, log);
+        let mut hits = vec![
+            Hit {
+                team: true,
+                pos: 0,
+                amount: b,
+                source: Some((false, 0)),
+            },
+            Hit {
+                team: false,
+                pos: 0,
+                amount: a,
+                source: Some((true, 0)),
+            },
+        ];
+        hits.extend(self.splash(true, 0, log));
+        hits.extend(self.splash(false, 0, log));
 
-        // TODO
-        self.0.remove_dead(rng);
-        self.1.remove_dead(rng);
+        // Every hit lands before any triggers are resolved, so that (for
+        // example) a friend hit by both an attack and splash damage only
+        // faints once.  Nobody has been removed yet, so the friends behind
+        // the attackers are still where they were when the attack happened.
+        let mut landed = Landed::default();
+        self.land(&hits, &mut landed, log);
+        for t in [true, false] {
+            if let Some(j) = self[t].behind(0) {
+                self.on_ahead_attacks(t, j, &mut landed, rng, log);
+            }
+        }
+        self.cascade(cfg, landed, rng, log);
+    }
+
+    /// Asks the friend at the given position to perform its action after the
+    /// nearest friend ahead of it attacked.  It may have been hurt by the
+    /// attack (e.g. by splash damage), in which case it does nothing.
+    fn on_ahead_attacks<R: Dice, L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        landed: &mut Landed,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        if self[team][pos].unwrap().health == 0
+            || !self.try_trigger(team, pos, Cause::AheadAttacks)
+        {
+            return;
+        }
+        let f = self[team][pos].unwrap();
+        let trigger = Trigger {
+            team,
+            pos,
+            species: f.species,
+            cause: Cause::AheadAttacks,
+        };
+        self.run_scripted(trigger, &f, true, Sink::Land(landed), rng, log);
+    }
+
+    /// Asks the friend at the given position to perform its action after the
+    /// nearest friend ahead of it fainted.
+    fn on_ahead_faints<R: Dice, L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        strikes: &mut Vec<Strike>,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        if !self.try_trigger(team, pos, Cause::AheadFaints) {
+            return;
+        }
+        let f = self[team][pos].unwrap();
+        let trigger = Trigger {
+            team,
+            pos,
+            species: f.species,
+            cause: Cause::AheadFaints,
+        };
+        self.run_scripted(trigger, &f, true, Sink::Strike(strikes), rng, log);
+    }
+
+    /// Runs a friend's [scripted abilities](crate::ability) for the trigger,
+    /// as a single trigger in the log.  `present` is whether the friend is
+    /// still on the team at the trigger's position; friends which have left
+    /// (e.g. by fainting) don't exclude themselves from selection, and don't
+    /// make the selections which are relative to their position.
+    fn run_scripted<R: Dice, L: Log>(
+        &mut self,
+        trigger: Trigger,
+        f: &Friend,
+        present: bool,
+        mut sink: Sink,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let mut scripted = f.species.scripted(trigger.cause, f.level());
+        let Some(first) = scripted.next() else {
+            return;
+        };
+        let (team, user) = (trigger.team, present.then_some(trigger.pos));
+        log.begin_trigger(trigger);
+        for (a, p) in std::iter::once(first).chain(scripted) {
+            let target = a.target.target(p.targets);
+            let side = team != target.is_some_and(|t| t.is_enemy());
+            if let (Effect::Damage, Some(t), Sink::Strike(strikes)) =
+                (a.effect, target, &mut sink)
+            {
+                // Targets are picked once the strike lands
+                trace!(species = ?f.species, damage = p.damage, "Strike");
+                strikes.push(Strike {
+                    team,
+                    target: t,
+                    amount: p.damage,
+                });
+                continue;
+            }
+            let targets = match (a.target, target) {
+                (Selector::User, _) => user.into_iter().collect(),
+                (_, Some(t)) => {
+                    let enemy = Some(&self[!team]);
+                    let targets = t.select(&self[team], user, enemy, rng);
+                    log.targets(side, &targets);
+                    targets
+                }
+                _ => vec![],
+            };
+            for j in targets {
+                match a.effect {
+                    Effect::Buff | Effect::TempBuff => {
+                        self.buff(side, j, p.attack, p.health, log)
+                    }
+                    Effect::Damage => {
+                        let g = self[side][j].unwrap();
+                        trace!(
+                            species = ?f.species,
+                            target_species = ?g.species,
+                            target_pos = j,
+                            damage = p.damage,
+                            "Shot"
+                        );
+                        let hit = Hit {
+                            team: side,
+                            pos: j,
+                            amount: p.damage,
+                            source: user.map(|i| (team, i)),
+                        };
+                        if let Sink::Land(landed) = &mut sink {
+                            self.land(&[hit], landed, log);
+                        }
+                    }
+                    Effect::Equip(m) => {
+                        let g = self[side][j].as_mut().unwrap();
+                        trace!(
+                            species = ?g.species,
+                            pos = j,
+                            modifier = ?m,
+                            "Equip"
+                        );
+                        g.modifier = Some(m);
+                        log.log(Event::Equip {
+                            team: side,
+                            pos: j,
+                            species: g.species,
+                            modifier: m,
+                        });
+                    }
+                    // Only in the shop
+                    Effect::Gold => (),
+                }
+            }
+        }
+        log.end_trigger();
+    }
+
+    /// Gives the friend at the given position permanent stats
+    fn buff<L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        attack: usize,
+        health: usize,
+        log: &mut L,
+    ) {
+        let f = self[team][pos].as_mut().unwrap();
+        trace!(species = ?f.species, pos, attack, health, "Buff");
+        f.attack += attack;
+        f.health += health;
+        log.log(Event::Buff {
+            team,
+            pos,
+            species: f.species,
+            attack,
+            health,
+        });
+    }
+
+    /// Removes dead friends from the given team, performing their on-death
+    /// actions then compacting the team afterwards.
+    fn remove_dead<R: Dice, L: Log>(
+        &mut self,
+        cfg: &GameConfig,
+        team: bool,
+        strikes: &mut Vec<Strike>,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        // Remove every dead friend before running any on-death actions, so
+        // that friends which fainted simultaneously can't be targeted.
+        let before = self[team];
+        let mut dead = vec![];
+        for i in 0..TEAM_SIZE {
+            if self[team][i].is_some() && self[team][i].unwrap().health == 0 {
+                let f = self[team][i].take().unwrap();
+                trace!(species = ?f.species, pos = i, "Faint");
+                log.log(Event::Faint {
+                    team,
+                    pos: i,
+                    species: f.species,
+                });
+                dead.push((i, f));
+            }
+        }
+        let changed = !dead.is_empty();
+
+        // Friends behind a fainted friend react before on-death summons can
+        // shove them around.  Adjacency is judged from the team as it was,
+        // so when neighbours faint together only the rearmost is "ahead" of
+        // the survivor behind them.
+        for (i, _) in &dead {
+            if let Some(j) = before.behind(*i) {
+                if self[team][j].is_some() {
+                    self.on_ahead_faints(team, j, strikes, rng, log);
+                }
+            }
+        }
+        for (i, f) in dead {
+            self.on_death(cfg, team, f, i, strikes, rng, log);
+        }
+        if changed {
+            trace!("Compacting team");
+            self[team].compact();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn on_death<R: Dice, L: Log>(
+        &mut self,
+        cfg: &GameConfig,
+        team: bool,
+        f: Friend,
+        i: usize,
+        strikes: &mut Vec<Strike>,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let trigger = Trigger {
+            team,
+            pos: i,
+            species: f.species,
+            cause: Cause::Faint,
+        };
+        for s in f.species.templates() {
+            #[allow(clippy::single_match)]
+            match s {
+                Species::Whale => {
+                    if let AbilityState::Swallowed(s) = f.state {
+                        log.begin_trigger(trigger);
+                        let g = Friend::with_level(s, f.level());
+                        self.summon_at(team, g, i, cfg, log);
+                        log.end_trigger();
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.run_scripted(trigger, &f, false, Sink::Strike(strikes), rng, log);
+        for t in Token::summoned_by(&f) {
+            log.begin_trigger(trigger);
+            self.summon_at(team, t.summon(f.level()), i, cfg, log);
+            log.end_trigger();
+        }
+    }
+
+    /// Pushes the friend at the given position forward (towards the front of
+    /// the team, if `forward`) or back by up to `n` spaces, shifting the
+    /// friends in between.  Returns the friend's new position; other friends
+    /// can be tracked with [`Team::reindex`].
+    pub fn push<L: Log>(
+        &mut self,
+        team: bool,
+        pos: usize,
+        forward: bool,
+        n: usize,
+        log: &mut L,
+    ) -> usize {
+        let species = self[team][pos].unwrap().species;
+        let to = if forward {
+            self[team].push_forward(pos, n)
+        } else {
+            self[team].push_back(pos, n)
+        };
+        if to != pos {
+            trace!(species = ?species, from = pos, to, "Move");
+            log.log(Event::Move {
+                team,
+                from: pos,
+                to,
+                species,
+            });
+        }
+        to
+    }
+
+    /// Makes space at the given position (according to the placement rule)
+    /// then summons a friend there.  If there's no room, the summon fails
+    /// (and is logged), so the team never grows past [`TEAM_SIZE`] (or the
+    /// challenge's team size, if smaller).  The
+    /// slot may already be occupied, e.g. if an earlier on-death summon shoved
+    /// a friend into it.
+    ///
+    /// Every summon in battle should go through this function.
+    fn summon_at<L: Log>(
+        &mut self,
+        team: bool,
+        friend: Friend,
+        pos: usize,
+        cfg: &GameConfig,
+        log: &mut L,
+    ) {
+        let room = self[team].count() < cfg.challenge.team_size();
+        let space = room
+            && match cfg.summon {
+                SummonPlacement::Shove => self[team].make_space_at(pos),
+                SummonPlacement::Push => self[team].push_at(pos),
+            };
+        if space {
+            trace!(species = ?friend.species, pos, "Summon");
+            self.summon(team, friend, pos, log);
+        } else {
+            trace!(species = ?friend.species, pos, "No room to summon");
+            log.log(Event::SummonBlocked {
+                team,
+                pos,
+                species: friend.species,
+            });
+        }
+    }
+
+    /// Places a friend into an empty slot on the given team, then performs
+    /// on-summon actions for the rest of the team.
+    fn summon<L: Log>(
+        &mut self,
+        team: bool,
+        friend: Friend,
+        pos: usize,
+        log: &mut L,
+    ) {
+        assert!(self[team][pos].is_none());
+        log.log(Event::Summon {
+            team,
+            pos,
+            species: friend.species,
+        });
+        self[team].summon(friend, pos, team, log);
+
+        // On-summon buffs are temporary, but the battle is thrown away at the
+        // end, so we can make them permanent immediately.
+        self[team].apply_temp();
     }
 }
 
@@ -115,8 +1190,18 @@ impl std::ops::IndexMut<bool> for Battle {
     }
 }
 
+/// Prints both teams facing each other, as box art or on a single line with
+/// the alternate flag (`{:#}`)
 impl std::fmt::Display for Battle {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if f.alternate() {
+            return write!(
+                f,
+                "{:#} | {:#}",
+                self.0,
+                TeamPrinter(&self.1, false)
+            );
+        }
         let team = format!("{}", self.0);
         let enemy = format!("{}", TeamPrinter(&self.1, false));
 
@@ -129,3 +1214,70 @@ impl std::fmt::Display for Battle {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ox_triggers_once_per_battle() {
+        // The Cricket and its Zombie Cricket both faint in front of the Ox,
+        // but only the first gives it Melon armor.  Counts from an earlier
+        // battle are reset.
+        let mut ox = Friend {
+            health: 50,
+            ..Friend::new(Species::Ox)
+        };
+        ox.triggers.battle = 1;
+        let mut a = Team::new();
+        a[0] = Some(Friend::new(Species::Cricket));
+        a[1] = Some(ox);
+        let mut b = Team::new();
+        b[0] = Some(Friend {
+            attack: 50,
+            health: 50,
+            ..Friend::new(Species::Fish)
+        });
+        let mut events: Vec<Event> = vec![];
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let cfg = GameConfig::default();
+        Battle(a, b).run_logged(&cfg, &mut dice, &mut events);
+        let equips = events
+            .iter()
+            .filter(|e| matches!(e, Event::Equip { team: true, .. }))
+            .count();
+        assert_eq!(equips, 1);
+    }
+
+    #[test]
+    fn summon_cap() {
+        // Every Cricket holding Honey tries to summon two friends, but only
+        // fainted friends make room on a full team, so some summons fail
+        let mut a = Team::new();
+        for i in 0..TEAM_SIZE {
+            a[i] = Some(Friend {
+                modifier: Some(Modifier::Honey),
+                ..Friend::new(Species::Cricket)
+            });
+        }
+        let b: Team = "Fish:50/50".parse().unwrap();
+        let mut events: Vec<Event> = vec![];
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let cfg = GameConfig::default();
+        Battle(a, b).run_logged(&cfg, &mut dice, &mut events);
+        let count =
+            |f: &dyn Fn(&Event) -> bool| events.iter().filter(|e| f(e)).count();
+        let faints = count(&|e| {
+            matches!(e, Event::Faint { team: true, species, .. }
+                if *species == Species::Cricket)
+        });
+        let summons = count(&|e| matches!(e, Event::Summon { team: true, .. }));
+        let blocked =
+            count(&|e| matches!(e, Event::SummonBlocked { team: true, .. }));
+        assert_eq!(faints, TEAM_SIZE);
+        assert_eq!(summons + blocked, faints * 2);
+        assert!(blocked > 0);
+    }
+}
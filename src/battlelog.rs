@@ -0,0 +1,179 @@
+//! Validation against battles recorded from the real game.
+//!
+//! A battle log file is a JSON array of recorded battles:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "turn 1 vs. ant/fish",
+//!     "team_a": [
+//!       {"species": "Mosquito", "attack": 2, "health": 2},
+//!       {"species": "Cricket", "attack": 1, "health": 2, "modifier": "Honey"}
+//!     ],
+//!     "team_b": [
+//!       {"species": "Ant", "attack": 2, "health": 1, "exp": 1}
+//!     ],
+//!     "winner": "TeamA",
+//!     "events": [
+//!       {"Faint": {"team": false, "pos": 0, "species": "Ant"}}
+//!     ]
+//!   }
+//! ]
+//! ```
+//!
+//! Teams are listed front friend first.  `exp` defaults to 0 and `modifier`
+//! to none; `name` and `events` are optional.  Events use the same encoding as
+//! [`Event`], where team `true` is team A.
+//!
+//! Since a screen capture rarely shows everything that happens, the observed
+//! events don't need to be a complete log: a recording matches if some dice
+//! path through the battle reaches the observed winner _and_ contains the
+//! observed events in the same order (possibly with other events between
+//! them).
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle::{Battle, Replay, Winner},
+    config::GameConfig,
+    error::Result,
+    event::Event,
+    friend::Friend,
+    modifier::Modifier,
+    species::Species,
+    team::Team,
+};
+
+/// A friend in a recorded team; also used by [fixtures](crate::fixture)
+#[derive(Deserialize, Serialize)]
+pub struct RecordedFriend {
+    species: Species,
+    attack: usize,
+    health: usize,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    exp: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modifier: Option<Modifier>,
+}
+
+fn is_zero(v: &usize) -> bool {
+    *v == 0
+}
+
+#[derive(Deserialize)]
+struct RecordedBattle {
+    #[serde(default)]
+    name: Option<String>,
+    team_a: Vec<RecordedFriend>,
+    team_b: Vec<RecordedFriend>,
+    winner: Winner,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+/// Builds a team from recorded friends, checking that the team and every
+/// friend on it are [legal](Friend::check_legal)
+pub fn build_team(fs: &[RecordedFriend]) -> Result<Team> {
+    let mut team = Team::builder();
+    for f in fs {
+        let mut b = Friend::builder()
+            .species(f.species)
+            .attack(f.attack)
+            .health(f.health)
+            .exp(f.exp);
+        if let Some(m) = f.modifier {
+            b = b.perk(m);
+        }
+        team = team.friend(b.build()?);
+    }
+    team.build()
+}
+
+/// Checks whether `observed` appears in order within `events`
+fn is_subsequence(observed: &[Event], events: &[Event]) -> bool {
+    let mut iter = events.iter();
+    observed.iter().all(|o| iter.any(|e| e == o))
+}
+
+/// Replays every recorded battle in the given file through the engine,
+/// printing each one that diverges.  Returns the number of divergences.
+pub fn run(cfg: &GameConfig, path: &str) -> Result<usize> {
+    let data = std::fs::read_to_string(path)?;
+    let battles: Vec<RecordedBattle> = serde_json::from_str(&data)?;
+
+    let mut divergences = 0;
+    for (i, b) in battles.iter().enumerate() {
+        let name = b.name.clone().unwrap_or_else(|| format!("#{}", i));
+        let (a, c) = match (build_team(&b.team_a), build_team(&b.team_b)) {
+            (Ok(a), Ok(c)) => (a, c),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Battle {} has an invalid team", name);
+                return Err(e);
+            }
+        };
+        let battle = Battle(a, c);
+        let replays = battle.replays(cfg);
+        let winners: Vec<&Replay> =
+            replays.iter().filter(|r| r.winner == b.winner).collect();
+        let reason = if winners.is_empty() {
+            "winner is unreachable"
+        } else if !winners.iter().any(|r| is_subsequence(&b.events, &r.events))
+        {
+            "event order is unreachable"
+        } else {
+            continue;
+        };
+
+        divergences += 1;
+        println!(
+            "Battle {} diverges ({}); recorded {:?}\n{}",
+            name, reason, b.winner, battle
+        );
+        if !b.events.is_empty() {
+            println!("  Recorded events:");
+            for e in &b.events {
+                println!("    {}", e);
+            }
+        }
+        for r in &replays {
+            println!("  Dice path '{}' ({:?}):", r.key, r.winner);
+            for e in &r.events {
+                println!("    {}", e);
+            }
+        }
+        println!();
+    }
+    info!(
+        "Checked {} battles from {}, found {} divergences",
+        battles.len(),
+        path,
+        divergences
+    );
+    Ok(divergences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn team(json: &str) -> Result<Team> {
+        let fs: Vec<RecordedFriend> = serde_json::from_str(json).unwrap();
+        build_team(&fs)
+    }
+
+    #[test]
+    fn build_team_checks_friends() {
+        let t = team(r#"[{"species": "Ant", "attack": 2, "health": 1}]"#);
+        assert_eq!(t.unwrap().code(), "Ant:2/1");
+
+        let exp = r#"[{"species": "Ant", "attack": 2, "health": 1, "exp": 9}]"#;
+        assert!(matches!(team(exp), Err(Error::Friend(..))));
+        let token = r#"[{"species": "Bee", "attack": 0, "health": 0}]"#;
+        assert!(matches!(team(token), Err(Error::Friend(..))));
+        let ant = r#"{"species": "Ant", "attack": 2, "health": 1}"#;
+        let six = format!("[{}]", [ant; 6].join(","));
+        assert!(matches!(team(&six), Err(Error::Team(..))));
+    }
+}
@@ -0,0 +1,330 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    params::{DEFAULT_GOLD, TEAM_SIZE},
+    shop::Shop,
+};
+
+/// Run-time configuration for the game rules
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct GameConfig {
+    /// Number of rounds after which a battle is called as a tie.  The real
+    /// game ends stalemates (e.g. between two pets with no attack) as a draw,
+    /// and without a cap, such battles would never end.
+    pub max_rounds: usize,
+
+    /// Rules for pruning teams which aren't worth scoring
+    pub dumb: DumbConfig,
+
+    /// How much damage the loser of a battle takes
+    pub damage: DamageModel,
+
+    /// Where summoned friends are placed during battle
+    pub summon: SummonPlacement,
+
+    /// Rule changes for custom modes, e.g. weekly challenges
+    #[serde(default)]
+    pub challenge: Challenge,
+
+    /// Enumerate the offers in each rolled shop as a multiset, rather than
+    /// an ordered tuple, during team generation.  The shop sorts its offers
+    /// anyway, so this only skips rolls which lead to the same shops.
+    #[serde(default)]
+    pub multiset_offers: bool,
+
+    /// Memoize battle states while enumerating dice paths, so that paths
+    /// which reach the same state share the rest of the battle (see
+    /// [`Battle::outcomes`](crate::battle::Battle::outcomes)).  This only
+    /// pays off when abilities roll often; in Tier 1 battles, paths rarely
+    /// meet before their last few rolls, and the bookkeeping costs more
+    /// than it saves.
+    #[serde(default)]
+    pub memo_battles: bool,
+
+    /// Rules for skipping shop actions during team generation
+    #[serde(default)]
+    pub prune: PruneConfig,
+
+    /// How far team generation explores each turn
+    #[serde(default)]
+    pub limits: ExploreLimits,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            max_rounds: 100,
+            dumb: DumbConfig::default(),
+            damage: DamageModel::default(),
+            summon: SummonPlacement::default(),
+            challenge: Challenge::default(),
+            multiset_offers: false,
+            memo_battles: false,
+            prune: PruneConfig::default(),
+            limits: ExploreLimits::default(),
+        }
+    }
+}
+
+/// Rules for how many lives (trophy damage) the loser of a battle loses; see
+/// [`Outcome::damage`](crate::battle::Outcome::damage)
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub enum DamageModel {
+    /// Damage depends only on the turn: 1 on turns 1–2, 2 on turns 3–4, and
+    /// 3 from turn 5 onwards (the current rules)
+    #[default]
+    Turn,
+    /// Damage is the total level of the winner's surviving friends, capped at
+    /// the turn-based damage (as in older versions of the game)
+    Survivors,
+}
+
+impl std::str::FromStr for DamageModel {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "turn" => Ok(Self::Turn),
+            "survivors" => Ok(Self::Survivors),
+            _ => Err(Error::DamageModel(s.to_owned())),
+        }
+    }
+}
+
+/// Rules for placing a friend which is summoned in battle (e.g. a token
+/// summoned when its summoner faints).  Placement changes who is in front,
+/// so it can visibly change the outcome of a close battle.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub enum SummonPlacement {
+    /// Summon at the fainted friend's slot, preferring to shove friends
+    /// behind it backwards, but shoving friends in front of it forwards if
+    /// there's no room behind; see
+    /// [`Team::make_space_at`](crate::team::Team::make_space_at)
+    #[default]
+    Shove,
+    /// Summon at exactly the fainted friend's slot, pushing friends behind it
+    /// backwards (as the real game does); if there's no room behind it, the
+    /// summon fails.  See [`Team::push_at`](crate::team::Team::push_at)
+    Push,
+}
+
+impl std::str::FromStr for SummonPlacement {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shove" => Ok(Self::Shove),
+            "push" => Ok(Self::Push),
+            _ => Err(Error::SummonPlacement(s.to_owned())),
+        }
+    }
+}
+
+/// Thresholds for deciding that a team is "dumb", i.e. that there's no reason
+/// to field it instead of a bigger one.  Dumb teams are discarded after
+/// generation; see [`Team::is_dumb`](crate::team::Team::is_dumb).
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DumbConfig {
+    /// Teams with fewer friends than this may be dumb; setting it to 0
+    /// disables pruning
+    pub min_friends: usize,
+    /// If set, small teams are only dumb if every friend has stock stats and
+    /// no modifier; otherwise, every small team is dumb
+    pub require_stock: bool,
+}
+
+impl Default for DumbConfig {
+    fn default() -> Self {
+        Self {
+            min_friends: 3,
+            require_stock: true,
+        }
+    }
+}
+
+/// Rule changes for custom game modes, such as the community's weekly
+/// challenges.  The default changes nothing.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize,
+)]
+pub struct Challenge {
+    /// The shop never sells food
+    pub no_food: bool,
+    /// Every turn starts with twice the usual gold
+    pub double_gold: bool,
+    /// Friends come with Honey when they're bought
+    pub honey: bool,
+    /// Most friends on a team (in the shop and in battle), if fewer than
+    /// [`TEAM_SIZE`]
+    pub team_size: Option<usize>,
+}
+
+impl Challenge {
+    /// Returns the gold at the start of each turn
+    pub fn gold(&self) -> usize {
+        if self.double_gold {
+            2 * DEFAULT_GOLD
+        } else {
+            DEFAULT_GOLD
+        }
+    }
+
+    /// Returns the most friends allowed on a team
+    pub fn team_size(&self) -> usize {
+        self.team_size.unwrap_or(TEAM_SIZE).min(TEAM_SIZE)
+    }
+
+    /// Checks whether any rule is changed
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::str::FromStr for Challenge {
+    type Err = Error;
+
+    /// Parses a comma-separated list of rule changes, e.g.
+    /// `no-food,team-size=4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut out = Self::default();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                None if part == "no-food" => out.no_food = true,
+                None if part == "double-gold" => out.double_gold = true,
+                None if part == "honey" => out.honey = true,
+                Some(("team-size", n)) => {
+                    out.team_size = match n.parse() {
+                        Ok(n) if (1..=TEAM_SIZE).contains(&n) => Some(n),
+                        _ => return Err(Error::Challenge(part.to_owned())),
+                    }
+                }
+                _ => return Err(Error::Challenge(part.to_owned())),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Rules for skipping shop actions which can't build anything that another
+/// action doesn't, to shrink the search during team generation; see
+/// [`Shop::step_pruned`](crate::shop::Shop::step_pruned).  Every rule is
+/// off by default, so that each can be checked by generating teams with and
+/// without it.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize,
+)]
+pub struct PruneConfig {
+    /// Never reroll while a friend on offer could be bought and merged onto
+    /// the team
+    pub reroll_duplicates: bool,
+    /// Never sell the friend which the previous action bought, unless buying
+    /// or selling it triggers an ability (so buying it was a waste of gold)
+    pub sell_bought: bool,
+    /// Take actions which trigger no abilities (buying friends, and feeding
+    /// them) in a canonical order when they don't touch the same friend,
+    /// rather than in every order, since every order builds the same shop
+    pub commute: bool,
+}
+
+impl PruneConfig {
+    /// Every rule
+    pub fn all() -> Self {
+        Self {
+            reroll_duplicates: true,
+            sell_bought: true,
+            commute: true,
+        }
+    }
+}
+
+impl std::str::FromStr for PruneConfig {
+    type Err = Error;
+
+    /// Parses a comma-separated list of rules, e.g.
+    /// `reroll-duplicates,commute`, or `all`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut out = Self::default();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            match part {
+                "all" => out = Self::all(),
+                "reroll-duplicates" => out.reroll_duplicates = true,
+                "sell-bought" => out.sell_bought = true,
+                "commute" => out.commute = true,
+                _ => return Err(Error::Prune(part.to_owned())),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Limits on how far team generation explores each turn, which trade
+/// completeness for run time.  Teams are still kept from every shop which is
+/// explored, since its turn can always be ended.  The default has no limits,
+/// so every reachable shop is explored.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize,
+)]
+pub struct ExploreLimits {
+    /// Most actions to take before ending the turn, not counting the end of
+    /// the turn itself
+    pub max_actions: Option<usize>,
+    /// Most rerolls to take before ending the turn, as counted by the
+    /// shop's [`Economy`](crate::shop::Economy) (which generation starts
+    /// afresh on the first turn)
+    pub max_rerolls: Option<usize>,
+    /// Shops with less gold than this end their turn rather than taking
+    /// another action
+    pub min_gold: usize,
+}
+
+impl ExploreLimits {
+    /// Checks whether a shop, reached after the given number of actions, is
+    /// within the limits (so that it may be explored)
+    pub fn allows(&self, shop: &Shop, actions: usize) -> bool {
+        self.max_actions.is_none_or(|m| actions <= m)
+            && self.max_rerolls.is_none_or(|m| shop.economy.rerolls <= m)
+    }
+
+    /// Checks whether a shop, reached after the given number of actions,
+    /// may only end its turn
+    pub fn ends(&self, shop: &Shop, actions: usize) -> bool {
+        self.max_actions.is_some_and(|m| actions >= m)
+            || shop.gold < self.min_gold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!("turn".parse::<DamageModel>().unwrap(), DamageModel::Turn);
+        assert!("lives".parse::<DamageModel>().is_err());
+        assert_eq!(
+            "push".parse::<SummonPlacement>().unwrap(),
+            SummonPlacement::Push
+        );
+        assert!("pull".parse::<SummonPlacement>().is_err());
+
+        assert!("".parse::<Challenge>().unwrap().is_default());
+        let c: Challenge = "no-food,honey,team-size=4".parse().unwrap();
+        assert!(c.no_food && c.honey && !c.double_gold);
+        assert_eq!(c.team_size(), 4);
+        assert_eq!(
+            "double-gold".parse::<Challenge>().unwrap().gold(),
+            2 * DEFAULT_GOLD
+        );
+        for bad in ["team-size=0", "team-size=6", "team-size", "no-gold"] {
+            assert!(bad.parse::<Challenge>().is_err(), "{:?}", bad);
+        }
+
+        assert_eq!("all".parse::<PruneConfig>().unwrap(), PruneConfig::all());
+        let p: PruneConfig = "commute,sell-bought".parse().unwrap();
+        assert!(p.commute && p.sell_bought && !p.reroll_duplicates);
+        assert!("reroll".parse::<PruneConfig>().is_err());
+    }
+}
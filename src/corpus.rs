@@ -0,0 +1,203 @@
+//! Training corpora of battles, written as one JSON object per line.
+//!
+//! Battles are recorded as they're run (e.g. while scoring teams), so that a
+//! corpus doesn't need a separate pass over every matchup.
+//!
+//! Each matchup is sampled with its own random numbers, seeded from the
+//! corpus seed and the matchup (see [`matchup_seed`]), so the same battles
+//! are sampled whatever order (or however many threads) the matchups are
+//! run in.  Only the `max` limit depends on the order, since it keeps the
+//! battles which are sampled first.
+
+use std::io::Write;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle::{Battle, Outcome, Winner},
+    dice::DeterministicDice,
+    error::Result,
+    team::Team,
+};
+
+/// A single line in a corpus
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BattleLine {
+    pub team_a: Team,
+    pub team_b: Team,
+    /// Dice key, which can be passed to [`DeterministicDice::from_key`]
+    pub dice: String,
+    pub winner: Winner,
+    pub rounds: usize,
+    /// Seed of the corpus which the battle was sampled into, so that the
+    /// corpus can be regenerated
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Controls which battles are written to a corpus
+#[derive(Copy, Clone, Debug)]
+pub struct Sampling {
+    /// Probability that each battle is written
+    pub rate: f64,
+    /// Largest number of battles to write
+    pub max: Option<usize>,
+    /// Seed for sampling, so that a corpus can be regenerated exactly
+    pub seed: u64,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            max: None,
+            seed: 0,
+        }
+    }
+}
+
+/// Mixes the bits of a value (the SplitMix64 finalizer), which is stable
+/// across platforms and releases, unlike the standard library's hashers
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Returns the seed for sampling battles from the matchup between teams `i`
+/// and `j`
+pub fn matchup_seed(seed: u64, i: usize, j: usize) -> u64 {
+    mix(mix(mix(seed) ^ i as u64) ^ j as u64)
+}
+
+/// A sink which writes sampled battles as JSON lines
+pub struct Corpus<W> {
+    out: W,
+    sampling: Sampling,
+    rng: StdRng,
+    written: usize,
+}
+
+impl Corpus<std::io::BufWriter<std::fs::File>> {
+    /// Creates a corpus file, replacing any existing file
+    pub fn create(path: &str, sampling: Sampling) -> Result<Self> {
+        let f = std::fs::File::create(path)?;
+        Ok(Self::new(std::io::BufWriter::new(f), sampling))
+    }
+}
+
+impl<W: Write> Corpus<W> {
+    pub fn new(out: W, sampling: Sampling) -> Self {
+        Self {
+            out,
+            sampling,
+            rng: StdRng::seed_from_u64(sampling.seed),
+            written: 0,
+        }
+    }
+
+    /// Returns the number of battles written so far
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Starts sampling battles from the matchup between teams `i` and `j`,
+    /// which makes sampling independent of the order of matchups
+    pub fn start_matchup(&mut self, i: usize, j: usize) {
+        self.rng =
+            StdRng::seed_from_u64(matchup_seed(self.sampling.seed, i, j));
+    }
+
+    /// Records a battle which was run with the given dice, if it's sampled
+    pub fn record(
+        &mut self,
+        battle: &Battle,
+        dice: &DeterministicDice,
+        outcome: &Outcome,
+    ) -> Result<()> {
+        if self.sampling.max.is_some_and(|m| self.written >= m)
+            || !self.rng.gen_bool(self.sampling.rate.clamp(0.0, 1.0))
+        {
+            return Ok(());
+        }
+        let line = BattleLine {
+            team_a: battle.0,
+            team_b: battle.1,
+            dice: dice.key(),
+            winner: outcome.winner,
+            rounds: outcome.rounds,
+            seed: self.sampling.seed,
+        };
+        serde_json::to_writer(&mut self.out, &line)?;
+        writeln!(self.out)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Flushes the corpus, returning the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+
+    #[test]
+    fn lines_replay() {
+        // Every dice path is written, and each line's dice key replays the
+        // battle which was recorded
+        let a: Team = "Mosquito:2/2,Cricket:1/2".parse().unwrap();
+        let b: Team = "Ant:2/1,Fish:2/3".parse().unwrap();
+        let battle = Battle(a, b);
+        let cfg = GameConfig::default();
+        let mut corpus = Corpus::new(vec![], Sampling::default());
+        let mut dice = DeterministicDice::new();
+        let mut paths = 0;
+        while dice.next() {
+            let out = battle.run(&cfg, &mut dice);
+            corpus.record(&battle, &dice, &out).unwrap();
+            paths += 1;
+        }
+        assert!(paths > 1);
+        assert_eq!(corpus.written(), paths);
+        let data = String::from_utf8(corpus.finish().unwrap()).unwrap();
+        for l in data.lines() {
+            let line: BattleLine = serde_json::from_str(l).unwrap();
+            assert_eq!(Battle(line.team_a, line.team_b), battle);
+            let mut dice = DeterministicDice::from_key(&line.dice).unwrap();
+            let out = battle.run(&cfg, &mut dice);
+            assert_eq!((out.winner, out.rounds), (line.winner, line.rounds));
+        }
+
+        // Nothing is written past the limit or without any chance
+        for (rate, max) in [(1.0, Some(0)), (0.0, None)] {
+            let sampling = Sampling {
+                rate,
+                max,
+                ..Sampling::default()
+            };
+            let mut corpus = Corpus::new(vec![], sampling);
+            let mut dice = DeterministicDice::new();
+            while dice.next() {
+                let out = battle.run(&cfg, &mut dice);
+                corpus.record(&battle, &dice, &out).unwrap();
+            }
+            assert!(corpus.finish().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn stable_seeds() {
+        // SplitMix64's first output from a zero state, so that seeds don't
+        // change between platforms or releases
+        assert_eq!(mix(0), 0xe220a8397b1dcdaf);
+        assert_ne!(matchup_seed(0, 1, 2), matchup_seed(0, 2, 1));
+        assert_ne!(matchup_seed(0, 1, 2), matchup_seed(1, 1, 2));
+    }
+}
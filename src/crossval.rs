@@ -0,0 +1,233 @@
+//! Differential testing against [sapai](https://github.com/manny405/sapai),
+//! a Python implementation of Super Auto Pets.
+//!
+//! Matchups are exported as one JSON object per line on the bridge command's
+//! standard input; the bridge replies with one JSON object per line of the form
+//! `{"id": 0, "winner": 0}`, using sapai's convention for winners (0 is team A,
+//! 1 is team B, 2 is a draw).  Since sapai rolls its own dice, its reported
+//! winner is a single sample, so a matchup only counts as a disagreement if
+//! the sapai winner isn't among the outcomes reachable in this engine.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use log::{info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle::{Battle, Winner},
+    config::GameConfig,
+    error::{Error, Result},
+    modifier::Modifier,
+    species::Species,
+    team::Team,
+};
+
+/// The bridge command used when none is provided on the command line
+pub const DEFAULT_BRIDGE: &[&str] = &["python3", "scripts/sapai_battle.py"];
+
+#[derive(Serialize)]
+struct SapaiPet {
+    name: &'static str,
+    attack: usize,
+    health: usize,
+    level: usize,
+    experience: usize,
+    status: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct SapaiMatchup {
+    id: usize,
+    team_a: Vec<SapaiPet>,
+    team_b: Vec<SapaiPet>,
+}
+
+#[derive(Deserialize)]
+struct SapaiResult {
+    id: usize,
+    winner: usize,
+}
+
+fn sapai_name(s: Species) -> &'static str {
+    match s {
+        Species::Ant => "pet-ant",
+        Species::Beaver => "pet-beaver",
+        Species::Cricket => "pet-cricket",
+        Species::Duck => "pet-duck",
+        Species::Fish => "pet-fish",
+        Species::Horse => "pet-horse",
+        Species::Mosquito => "pet-mosquito",
+        Species::Otter => "pet-otter",
+        Species::Pig => "pet-pig",
+        Species::Whale => "pet-whale",
+        Species::Kangaroo => "pet-kangaroo",
+        Species::Ox => "pet-ox",
+        Species::GhostCricket => "pet-zombie-cricket",
+        Species::Bee => "pet-bee",
+        Species::Custom(..) => unreachable!("sapai has no custom species"),
+    }
+}
+
+fn sapai_status(m: Modifier) -> &'static str {
+    match m {
+        Modifier::Honey => "status-honey-bee",
+        Modifier::MeatBone => "status-bone-attack",
+        Modifier::Steak => "status-steak-attack",
+        Modifier::Melon => "status-melon-armor",
+        Modifier::Garlic => "status-garlic-armor",
+        Modifier::Coconut => "status-coconut-shield",
+        Modifier::Chili => "status-splash-attack",
+    }
+}
+
+/// Converts a team into sapai's representation, front friend first
+fn sapai_team(team: &Team) -> Vec<SapaiPet> {
+    team.iter()
+        .flatten()
+        .map(|f| SapaiPet {
+            name: sapai_name(f.species),
+            attack: f.total_attack(),
+            health: f.total_health(),
+            level: f.level(),
+            experience: f.exp,
+            status: f.modifier.map(sapai_status),
+        })
+        .collect()
+}
+
+fn sapai_winner(w: usize) -> Option<Winner> {
+    match w {
+        0 => Some(Winner::TeamA),
+        1 => Some(Winner::TeamB),
+        2 => Some(Winner::Tied),
+        _ => None,
+    }
+}
+
+/// Runs `count` randomly chosen matchups through both simulators, printing a
+/// full replay for every matchup where they disagree.  Returns the number of
+/// disagreements, or an error if the bridge fails or misbehaves.
+pub fn run(
+    cfg: &GameConfig,
+    teams: &[Team],
+    count: usize,
+    bridge: &[String],
+) -> Result<usize> {
+    // Custom species can't be described to sapai
+    let teams: Vec<Team> = teams
+        .iter()
+        .filter(|t| t.iter().flatten().all(|f| !f.species.is_custom()))
+        .cloned()
+        .collect();
+    if teams.is_empty() {
+        warn!("No teams without custom species");
+        return Ok(0);
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let battles: Vec<Battle> = (0..count)
+        .map(|_| {
+            let a = teams[rng.gen_range(0..teams.len())];
+            let b = teams[rng.gen_range(0..teams.len())];
+            Battle(a, b)
+        })
+        .collect();
+
+    info!("Running {} matchups through {:?}", battles.len(), bridge);
+    let (cmd, args) = bridge
+        .split_first()
+        .ok_or_else(|| Error::Bridge("no bridge command".to_owned()))?;
+    let mut child = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+
+    // Matchups are written from a separate thread, so that the bridge never
+    // blocks on a full output pipe while we're still writing its input
+    let battles = &battles;
+    let result = std::thread::scope(|s| {
+        let writer = s.spawn(move || -> Result<()> {
+            for (id, b) in battles.iter().enumerate() {
+                let m = SapaiMatchup {
+                    id,
+                    team_a: sapai_team(&b.0),
+                    team_b: sapai_team(&b.1),
+                };
+                serde_json::to_writer(&mut stdin, &m)?;
+                writeln!(stdin)?;
+            }
+            Ok(())
+        });
+        let out = check(cfg, battles, stdout);
+        if out.is_err() {
+            // Stops the bridge, so that the writer can't block on it forever
+            let _ = child.kill();
+        }
+        let written = writer.join().expect("Bridge writer panicked");
+        out.and_then(|out| written.map(|()| out))
+    });
+    let (disagreements, checked) = match result {
+        Ok(out) => out,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    };
+
+    let status = child.wait()?;
+    if !status.success() {
+        warn!("Bridge exited with {}", status);
+    }
+    info!(
+        "Checked {} / {} matchups, found {} disagreements",
+        checked,
+        battles.len(),
+        disagreements
+    );
+    Ok(disagreements)
+}
+
+/// Reads the bridge's results, printing each matchup where they disagree.
+/// Returns the number of disagreements and of matchups checked.
+fn check<R: BufRead>(
+    cfg: &GameConfig,
+    battles: &[Battle],
+    results: R,
+) -> Result<(usize, usize)> {
+    let mut disagreements = 0;
+    let mut checked = 0;
+    for line in results.lines() {
+        let r: SapaiResult = serde_json::from_str(&line?)?;
+        let Some(battle) = battles.get(r.id) else {
+            return Err(Error::Bridge(format!("unknown matchup {}", r.id)));
+        };
+        let theirs = match sapai_winner(r.winner) {
+            Some(w) => w,
+            None => {
+                warn!("Invalid winner {} for matchup {}", r.winner, r.id);
+                continue;
+            }
+        };
+        checked += 1;
+
+        let ours = battle.replays(cfg);
+        if ours.iter().any(|r| r.winner == theirs) {
+            continue;
+        }
+        disagreements += 1;
+        println!("Matchup {}: sapai says {:?}\n{}", r.id, theirs, battle);
+        for r in &ours {
+            println!("  Dice path '{}' ({:?}):", r.key, r.winner);
+            for e in &r.events {
+                println!("    {}", e);
+            }
+        }
+        println!();
+    }
+    Ok((disagreements, checked))
+}
@@ -0,0 +1,197 @@
+//! Custom species, which are registered at run time so that homebrew pets
+//! can be tested through the full generation and scoring pipeline.
+//!
+//! A custom species has its own name, stats, and shop tier, and borrows its
+//! abilities from existing species (its *templates*): a species with the Ant
+//! and Mosquito templates buffs a friend when it faints and snipes an enemy at
+//! the start of battle.  Template magnitudes can be overridden per level, and
+//! new abilities can be written as [scripted abilities](crate::ability).
+//!
+//! Custom species are loaded from a JSON list, e.g.
+//!
+//! ```json
+//! [{
+//!     "name": "Hornet",
+//!     "emoji": "🐝",
+//!     "tier": 1,
+//!     "stats": [2, 2],
+//!     "templates": ["Mosquito", "Ant"],
+//!     "params": {
+//!         "Mosquito": [
+//!             { "targets": 1, "damage": 2 },
+//!             { "targets": 2, "damage": 2 },
+//!             { "targets": 3, "damage": 2 }
+//!         ]
+//!     },
+//!     "abilities": [{
+//!         "trigger": "Hurt",
+//!         "effect": "Buff",
+//!         "levels": [
+//!             { "attack": 1 },
+//!             { "attack": 2 },
+//!             { "attack": 3 }
+//!         ]
+//!     }]
+//! }]
+//! ```
+//!
+//! Like packs, the registry is global, and registered species are leaked.
+//! Built-in packs don't sell custom species, so they need to be
+//! [sold](crate::pack::Pack::sell) by the [active pack](crate::pack::active).
+//! Caches refer to custom species by registration order, so they record a
+//! [`checksum`] of the registry and must be loaded with the same species
+//! files.
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ability::Ability,
+    error::{Error, Result},
+    params::MAX_STAT,
+    species::{AbilityParams, Species, TriggerLimit},
+};
+
+/// A species defined at run time
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomSpecies {
+    pub name: String,
+    #[serde(default = "default_emoji")]
+    pub emoji: char,
+    /// Two-letter abbreviation for ASCII mode, defaulting to the start of
+    /// the name
+    #[serde(default)]
+    pub abbrev: Option<String>,
+    /// Shop tier at which the Turtle pack sells the species
+    pub tier: usize,
+    /// Base `[attack, health]`
+    pub stats: [usize; 2],
+    /// Species whose abilities this species has, in the order they trigger
+    #[serde(default)]
+    pub templates: Vec<Species>,
+    /// Ability magnitudes at levels 1-3, for templates whose magnitudes
+    /// differ from the template species'
+    #[serde(default)]
+    pub params: BTreeMap<Species, [AbilityParams; 3]>,
+    /// Scripted abilities, which trigger after the templates' abilities
+    #[serde(default)]
+    pub abilities: Vec<Ability>,
+    /// How often the species' abilities may trigger, e.g.
+    /// `{ "PerBattle": 1 }`; if unset, the first limit among its templates
+    #[serde(default)]
+    pub limit: Option<TriggerLimit>,
+}
+
+fn default_emoji() -> char {
+    '❔'
+}
+
+/// An entry in the registry, with its abbreviation filled in
+#[derive(Debug)]
+pub struct Entry {
+    pub species: CustomSpecies,
+    pub abbrev: String,
+}
+
+static REGISTRY: RwLock<Vec<&'static Entry>> = RwLock::new(vec![]);
+
+/// Looks up a registered custom species by index
+pub fn get(i: u8) -> &'static Entry {
+    REGISTRY.read().unwrap()[i as usize]
+}
+
+/// Returns every registered custom species, in registration order
+pub fn registered() -> Vec<Species> {
+    let n = REGISTRY.read().unwrap().len();
+    (0..n).map(|i| Species::Custom(i as u8)).collect()
+}
+
+/// Returns a checksum of every registered custom species, in registration
+/// order
+pub fn checksum() -> u64 {
+    let registry = REGISTRY.read().unwrap();
+    let species: Vec<&CustomSpecies> =
+        registry.iter().map(|e| &e.species).collect();
+    crate::util::checksum(&species).expect("Failed to checksum species")
+}
+
+/// Registers a custom species, returning it
+pub fn register(species: CustomSpecies) -> Result<Species> {
+    species.check()?;
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.len() > u8::MAX as usize {
+        return Err(Error::Custom("too many custom species".to_owned()));
+    }
+    let abbrev = species
+        .abbrev
+        .clone()
+        .unwrap_or_else(|| species.name.chars().take(2).collect());
+    registry.push(Box::leak(Box::new(Entry { species, abbrev })));
+    Ok(Species::Custom((registry.len() - 1) as u8))
+}
+
+/// Loads and registers a JSON list of custom species
+pub fn load(path: &str) -> Result<Vec<Species>> {
+    let data = std::fs::read_to_string(path)?;
+    let list: Vec<CustomSpecies> = serde_json::from_str(&data)?;
+    list.into_iter().map(register).collect()
+}
+
+impl CustomSpecies {
+    /// Checks that the name is new, the stats and tier are in range, that
+    /// every template is a built-in species which can be sold, and that
+    /// every scripted ability can be run and has magnitudes
+    pub fn check(&self) -> Result<()> {
+        let err =
+            |s: String| Err(Error::Custom(format!("{}: {}", self.name, s)));
+        if self.name.is_empty()
+            || !self.name.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return err("names must be alphanumeric".to_owned());
+        } else if self.name.parse::<Species>().is_ok() {
+            return err("name is already taken".to_owned());
+        }
+        let [attack, health] = self.stats;
+        if health == 0 || attack > MAX_STAT || health > MAX_STAT {
+            return err(format!("can't have {}/{} stats", attack, health));
+        } else if self.tier == 0 {
+            return err("tiers start at 1".to_owned());
+        }
+        for t in self.templates.iter().chain(self.params.keys()) {
+            if t.is_custom() || t.tier().is_none() {
+                return err(format!("{:?} can't be a template", t));
+            }
+        }
+        for t in self.params.keys() {
+            if !self.templates.contains(t) {
+                return err(format!("{:?} isn't one of its templates", t));
+            }
+        }
+        for a in &self.abilities {
+            a.check().or_else(err)?;
+            if a.levels.is_none() {
+                return err(format!("{} has no levels", a));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_changes_with_registry() {
+        let before = checksum();
+        assert_eq!(before, checksum());
+        let s: CustomSpecies = serde_json::from_str(
+            r#"{"name": "Checksummed", "tier": 1, "stats": [1, 1]}"#,
+        )
+        .unwrap();
+        register(s).unwrap();
+        assert_ne!(before, checksum());
+    }
+}
@@ -0,0 +1,324 @@
+//! Rated team databases, which answer how strong a team is without
+//! generating and scoring every team first (which takes hours).
+//!
+//! A database is a JSON file listing battle-ready teams (as
+//! [team codes](crate::team::Team::code)) and their ratings, best first, e.g.
+//!
+//! ```json
+//! {
+//!     "version": "0.1.0",
+//!     "command": "super-auto-sim database teams.json",
+//!     "rules": "current",
+//!     "pack": "turtle",
+//!     "config": { "max_rounds": 100, ... },
+//!     "teams": [
+//!         { "team": "Ant:3/4,Fish:3/3", "rating": 0.93 },
+//!         { "team": "Mosquito:2/2+Honey", "rating": 0.41 }
+//!     ]
+//! }
+//! ```
+//!
+//! A team's rating is its win rate against every team in the database
+//! (counting dice paths, as scores do), under the recorded rules, pack and
+//! [config](GameConfig).  The `database` command writes one from the cached
+//! teams and scores, recording the crate version and its own command line.
+//!
+//! With the `database` feature, a database of every team which can be built
+//! on the first turn (under the default config, rules and pack) is embedded
+//! in the crate; see [`rating_of`] and [`top_teams`].  To rewrite it, run
+//!
+//! ```text
+//! super-auto-sim database teams.json
+//! ```
+//!
+//! (a release build takes about ten minutes) in an empty directory, so that
+//! no stale cached teams or scores are reused, then copy `teams.json` over
+//! `assets/teams.json`.  It should be rewritten whenever the rules, pack or
+//! battle engine change which teams can be built on the first turn or how
+//! they fight; a unit test re-rates a sample of its teams to catch this.
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::GameConfig,
+    error::{Error, Result},
+    team::Team,
+};
+
+/// A team in a [`Database`], along with its rating
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rated {
+    pub team: String,
+    pub rating: f32,
+}
+
+/// Teams and their ratings, as stored on disk, along with how they were rated
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Database {
+    /// Version of the crate which rated the teams
+    pub version: String,
+    /// Command line which wrote the database
+    pub command: String,
+    /// Names of the rule set and pack under which the teams were rated
+    pub rules: String,
+    pub pack: String,
+    /// Settings under which the teams were generated and rated
+    pub config: GameConfig,
+    pub teams: Vec<Rated>,
+}
+
+/// Returns the key under which a team is stored, which ignores anything
+/// that doesn't matter in battle (including gaps between friends)
+pub fn key(team: &Team) -> Team {
+    let mut t = team.for_battle();
+    t.compact();
+    t
+}
+
+impl Database {
+    /// Builds a database from teams and their ratings, keeping the first of
+    /// any teams which are identical in battle, and listing the best first
+    pub fn new<I: IntoIterator<Item = (Team, f32)>>(
+        command: &str,
+        rules: &str,
+        pack: &str,
+        cfg: &GameConfig,
+        teams: I,
+    ) -> Self {
+        let mut seen = HashMap::new();
+        for (t, rating) in teams {
+            seen.entry(key(&t)).or_insert(rating);
+        }
+        let mut teams: Vec<(Team, f32)> = seen.into_iter().collect();
+        teams.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            command: command.to_owned(),
+            rules: rules.to_owned(),
+            pack: pack.to_owned(),
+            config: *cfg,
+            teams: teams
+                .into_iter()
+                .map(|(t, rating)| Rated {
+                    team: t.code(),
+                    rating,
+                })
+                .collect(),
+        }
+    }
+
+    /// Parses and checks a database from JSON
+    pub fn parse(data: &str) -> Result<Self> {
+        let db: Self = serde_json::from_str(data)?;
+        db.check()?;
+        Ok(db)
+    }
+
+    /// Loads and checks a database from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the database to a JSON file
+    pub fn save(&self, path: &str) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Parses every team, returning them in battle-ready form with their
+    /// ratings
+    pub fn teams(&self) -> Result<Vec<(Team, f32)>> {
+        self.teams
+            .iter()
+            .map(|r| Ok((key(&r.team.parse()?), r.rating)))
+            .collect()
+    }
+
+    /// Checks that every team parses and appears once, and that ratings are
+    /// between 0 and 1 and listed best first
+    pub fn check(&self) -> Result<()> {
+        let err = |s: String| Err(Error::Database(s));
+        let mut seen = HashMap::new();
+        for (t, rating) in self.teams()? {
+            if !(0.0..=1.0).contains(&rating) {
+                return err(format!("{} has rating {}", t.code(), rating));
+            }
+            if seen.insert(t, rating).is_some() {
+                return err(format!("{} appears more than once", t.code()));
+            }
+        }
+        if self.teams.windows(2).any(|w| w[0].rating < w[1].rating) {
+            return err("teams aren't sorted best first".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// A parsed [`Database`], for looking teams up
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    /// Battle-ready teams and their ratings, best first
+    teams: Vec<(Team, f32)>,
+    /// Index of each team in `teams`
+    index: HashMap<Team, usize>,
+}
+
+impl Lookup {
+    pub fn new(db: &Database) -> Result<Self> {
+        db.check()?;
+        let teams = db.teams()?;
+        let index = teams.iter().enumerate().map(|(i, t)| (t.0, i)).collect();
+        Ok(Self { teams, index })
+    }
+
+    /// Returns the number of teams in the database
+    pub fn len(&self) -> usize {
+        self.teams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.teams.is_empty()
+    }
+
+    /// Returns a team's rating, or `None` if it isn't in the database
+    pub fn rating_of(&self, team: &Team) -> Option<f32> {
+        self.index.get(&key(team)).map(|i| self.teams[*i].1)
+    }
+
+    /// Returns up to `n` of the best teams, best first
+    pub fn top_teams(&self, n: usize) -> &[(Team, f32)] {
+        &self.teams[..n.min(self.teams.len())]
+    }
+}
+
+#[cfg(feature = "database")]
+static EMBEDDED: std::sync::LazyLock<Lookup> = std::sync::LazyLock::new(|| {
+    let db = Database::parse(include_str!("../assets/teams.json"))
+        .expect("Embedded database is invalid");
+    Lookup::new(&db).unwrap()
+});
+
+/// Returns the embedded database of first-turn teams
+#[cfg(feature = "database")]
+pub fn embedded() -> &'static Lookup {
+    &EMBEDDED
+}
+
+/// Returns a team's rating in the [embedded](embedded) database, or `None`
+/// if it can't be built on the first turn
+#[cfg(feature = "database")]
+pub fn rating_of(team: &Team) -> Option<f32> {
+    EMBEDDED.rating_of(team)
+}
+
+/// Returns up to `n` of the best teams in the [embedded](embedded) database,
+/// best first
+#[cfg(feature = "database")]
+pub fn top_teams(n: usize) -> &'static [(Team, f32)] {
+    EMBEDDED.top_teams(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "database")]
+    use crate::{
+        battle::{Battle, Winner},
+        pack, rules,
+    };
+
+    #[test]
+    fn lookup_round_trip() {
+        // Databases survive a trip through their file format, and look up
+        // the first rating given to each team (in any battle-ready form)
+        let team = |s: &str| s.parse::<Team>().unwrap();
+        let mut gap = Team::new();
+        gap[2] = team("Ant:2/1")[0];
+        let rated = [
+            (team("Fish:2/3"), 0.25),
+            (team("Ant:2/1"), 0.75),
+            (gap, 0.5),
+            (team("Mosquito:2/2+Honey,Fish:2/3"), 1.0),
+        ];
+        let cfg = GameConfig::default();
+        let db = Database::new("test", "current", "turtle", &cfg, rated);
+        assert_eq!(db.teams.len(), 3);
+        let json = serde_json::to_string(&db).unwrap();
+        let loaded = Database::parse(&json).unwrap();
+        assert_eq!(loaded, db);
+
+        let lookup = Lookup::new(&loaded).unwrap();
+        assert_eq!(lookup.rating_of(&gap), Some(0.75));
+        assert_eq!(lookup.rating_of(&team("Fish:2/3")), Some(0.25));
+        assert_eq!(lookup.rating_of(&team("Fish:2/2")), None);
+        let top: Vec<f32> = lookup.top_teams(8).iter().map(|t| t.1).collect();
+        assert_eq!(top, [1.0, 0.75, 0.25]);
+        assert_eq!(
+            lookup.top_teams(1)[0].0,
+            team("Mosquito:2/2+Honey,Fish:2/3")
+        );
+
+        let mut bad = db.clone();
+        bad.teams[0].rating = 2.0;
+        assert!(bad.check().is_err());
+        let mut bad = db.clone();
+        bad.teams.swap(0, 1);
+        assert!(bad.check().is_err());
+        let mut bad = db;
+        bad.teams[1].team = bad.teams[0].team.clone();
+        assert!(bad.check().is_err());
+    }
+
+    /// Returns the fraction of dice paths on which the first team wins
+    #[cfg(feature = "database")]
+    fn wins(cfg: &GameConfig, a: &Team, b: &Team) -> f32 {
+        let outcomes = Battle(*a, *b).outcomes(cfg);
+        let paths: usize = outcomes.iter().map(|t| t.paths).sum();
+        let won: usize = outcomes
+            .iter()
+            .filter(|t| t.outcome.winner == Winner::TeamA)
+            .map(|t| t.paths)
+            .sum();
+        won as f32 / paths as f32
+    }
+
+    #[test]
+    #[cfg(feature = "database")]
+    fn embedded_ratings() {
+        // The embedded database was rated under the default settings, and
+        // rating a sample of its teams again gives the recorded ratings
+        let db = Database::parse(include_str!("../assets/teams.json")).unwrap();
+        assert_eq!(db.config, GameConfig::default());
+        assert_eq!(db.rules, rules::active().name);
+        assert_eq!(db.pack, pack::active().name);
+        let teams = db.teams().unwrap();
+        for (t, rating) in teams.iter().step_by(teams.len() / 8) {
+            let sum: f32 =
+                teams.iter().map(|(u, _)| wins(&db.config, t, u)).sum();
+            let r = sum / teams.len() as f32;
+            assert!(
+                (r - rating).abs() < 1e-4,
+                "{}: {} != {}",
+                t.code(),
+                r,
+                rating
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "database")]
+    fn embedded_lookup() {
+        // The embedded database parses, and its best teams look themselves up
+        for n in [0, 1, 10, 100] {
+            let top = top_teams(n);
+            assert_eq!(top.len(), n.min(embedded().len()));
+            for (t, r) in top {
+                assert_eq!(rating_of(t), Some(*r));
+            }
+        }
+    }
+}
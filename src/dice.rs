@@ -1,8 +1,16 @@
-#[derive(Debug)]
+use crate::error::{Error, Result};
+
+#[derive(Debug, Default)]
 pub struct DeterministicDice {
     initialized: bool,
     index: usize,
     data: Vec<(usize, std::ops::Range<usize>)>,
+    /// Relative weight of each choice in `data`, which is 1 for uniform rolls
+    weights: Vec<f64>,
+    /// Enumerate [multiset rolls](Dice::roll_multiset) once per multiset
+    multisets: bool,
+    /// Number of leading rolls which the last call to `next` left unchanged
+    unchanged: usize,
 }
 
 impl DeterministicDice {
@@ -11,9 +19,69 @@ impl DeterministicDice {
             initialized: false,
             index: 0,
             data: vec![],
+            weights: vec![],
+            multisets: false,
+            unchanged: 0,
         }
     }
 
+    /// Enumerates each [multiset roll](Dice::roll_multiset) once, rather
+    /// than once per ordering, weighting the path by its number of
+    /// orderings.  This changes the dice keys, so keys from these dice must
+    /// be replayed with this option set.
+    pub fn multisets(mut self) -> Self {
+        self.multisets = true;
+        self
+    }
+
+    /// Returns the weight of the current path, relative to a path of the
+    /// same length through uniform rolls.  This is 1 unless
+    /// [weighted rolls](Dice::roll_weighted) picked something more or less
+    /// likely than average, so averaging outcomes by weight (rather than
+    /// counting paths) keeps exhaustive enumeration exact.
+    pub fn weight(&self) -> f64 {
+        self.weights.iter().product()
+    }
+
+    /// Returns the chance that real dice would take the current path, i.e.
+    /// the product of `1 / n` for each roll among `n` options, scaled by the
+    /// [path weight](Self::weight).  Dice loaded from a key don't know their
+    /// ranges until they've been rolled.
+    pub fn probability(&self) -> f64 {
+        self.data
+            .iter()
+            .map(|(_, r)| 1.0 / r.len() as f64)
+            .product::<f64>()
+            * self.weight()
+    }
+
+    /// Returns the chance that real dice would make the rolls made so far in
+    /// this pass, i.e. the [probability](Self::probability) of the path's
+    /// prefix up to the current roll
+    pub fn prefix_probability(&self) -> f64 {
+        self.data[..self.index]
+            .iter()
+            .zip(&self.weights)
+            .map(|((_, r), w)| w / r.len() as f64)
+            .product()
+    }
+
+    /// Returns the number of leading rolls which are the same as in the
+    /// previous path, i.e. the index of the roll which [`next`](Self::next)
+    /// changed (0 for the first path).  Every path which shares a prefix of
+    /// that length is enumerated before the prefix changes.
+    pub fn unchanged(&self) -> usize {
+        self.unchanged
+    }
+
+    /// Ends the current path at the current roll, so that the next path
+    /// changes the last roll made so far rather than any which would come
+    /// after it
+    pub fn truncate(&mut self) {
+        self.data.truncate(self.index);
+        self.weights.truncate(self.index);
+    }
+
     /// Converts the given DeterministicDice state into a string key.
     /// Panics if any of the choices can't be represented as a single
     /// base-36 number.
@@ -24,28 +92,39 @@ impl DeterministicDice {
             .collect::<String>()
     }
 
-    pub fn from_key(s: &str) -> Self {
-        Self {
+    /// Builds a DeterministicDice from a [key](Self::key)
+    pub fn from_key(s: &str) -> Result<Self> {
+        let data: Vec<_> = s
+            .chars()
+            .map(|c| char::to_digit(c, 36).map(|v| (v as usize, 0..0)))
+            .collect::<Option<_>>()
+            .ok_or_else(|| Error::DiceKey(s.to_owned()))?;
+        let weights = vec![1.0; data.len()];
+        Ok(Self {
             initialized: true,
             index: 0,
-            data: s
-                .chars()
-                .map(|c| (char::to_digit(c, 36).unwrap() as usize, 0..0))
-                .collect(),
-        }
+            data,
+            weights,
+            multisets: false,
+            unchanged: 0,
+        })
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> bool {
         if !self.initialized {
             self.initialized = true;
             true
         } else {
             while let Some((mut v, r)) = self.data.pop() {
+                self.weights.pop();
                 v += 1;
                 if v >= r.end {
                     continue;
                 } else {
+                    self.unchanged = self.data.len();
                     self.data.push((v, r));
+                    self.weights.push(1.0);
                     break;
                 }
             }
@@ -59,6 +138,54 @@ impl DeterministicDice {
 
 pub trait Dice {
     fn roll(&mut self, range: std::ops::Range<usize>) -> usize;
+
+    /// Picks an index into `weights`, with probability proportional to its
+    /// weight.  Indices with zero weight are never picked.
+    fn roll_weighted(&mut self, weights: &[usize]) -> usize {
+        let total: usize = weights.iter().sum();
+        assert!(total > 0, "No nonzero weights");
+        let mut v = self.roll(0..total);
+        for (i, w) in weights.iter().enumerate() {
+            if v < *w {
+                return i;
+            }
+            v -= w;
+        }
+        unreachable!()
+    }
+
+    /// Rolls `n` indices into `weights`, each as if by
+    /// [`roll_weighted`](Self::roll_weighted) (or by a uniform roll, which
+    /// keeps dice keys unchanged, if every weight is equal), returning them
+    /// in ascending order
+    fn roll_multiset(&mut self, weights: &[usize], n: usize) -> Vec<usize> {
+        roll_each(self, weights, n)
+    }
+
+    /// Returns the number of rolls so far, if the dice keep track of it
+    fn index(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Rolls each index of a multiset in turn, then sorts them
+fn roll_each<D: Dice + ?Sized>(
+    dice: &mut D,
+    weights: &[usize],
+    n: usize,
+) -> Vec<usize> {
+    let uniform = weights.iter().all(|w| *w == weights[0]);
+    let mut out: Vec<usize> = (0..n)
+        .map(|_| {
+            if uniform {
+                dice.roll(0..weights.len())
+            } else {
+                dice.roll_weighted(weights)
+            }
+        })
+        .collect();
+    out.sort();
+    out
 }
 
 impl<R: rand::Rng> Dice for R {
@@ -80,11 +207,74 @@ impl Dice for DeterministicDice {
             *v
         } else {
             self.data.push((range.start, range.clone()));
+            self.weights.push(1.0);
             range.start
         };
         self.index += 1;
         out
     }
+
+    /// Enumerates one path per nonzero weight (rather than one per unit of
+    /// weight), then scales the [path weight](DeterministicDice::weight)
+    fn roll_weighted(&mut self, weights: &[usize]) -> usize {
+        let options: Vec<usize> =
+            (0..weights.len()).filter(|i| weights[*i] > 0).collect();
+        let total: usize = weights.iter().sum();
+        assert!(total > 0, "No nonzero weights");
+        let i = options[self.roll(0..options.len())];
+        self.weights[self.index - 1] =
+            (weights[i] * options.len()) as f64 / total as f64;
+        i
+    }
+
+    /// With [`DeterministicDice::multisets`], enumerates ascending sequences
+    /// (each of which is rolled against the options from the previous pick
+    /// onwards), then scales the path weight so that its
+    /// [probability](DeterministicDice::probability) is the multiset's
+    fn roll_multiset(&mut self, weights: &[usize], n: usize) -> Vec<usize> {
+        if !self.multisets {
+            return roll_each(self, weights, n);
+        }
+        let options: Vec<usize> =
+            (0..weights.len()).filter(|i| weights[*i] > 0).collect();
+        let total: usize = weights.iter().sum();
+        assert!(total > 0, "No nonzero weights");
+
+        let start = self.index;
+        let mut out = Vec::with_capacity(n);
+        let mut lo = 0;
+        for _ in 0..n {
+            lo = self.roll(lo..options.len());
+            out.push(options[lo]);
+        }
+
+        // The multiset's chance is its number of orderings (n! divided by
+        // the factorial of each value's count), times the chance of each
+        // value, while the path's unweighted chance is 1 / len per roll.
+        let mut exact = 1.0;
+        let mut run = 0;
+        for (i, v) in out.iter().enumerate() {
+            run = if i > 0 && out[i - 1] == *v {
+                run + 1
+            } else {
+                1
+            };
+            exact *= (i + 1) as f64 / run as f64;
+            exact *= weights[*v] as f64 / total as f64;
+        }
+        let uniform: f64 = self.data[start..self.index]
+            .iter()
+            .map(|(_, r)| 1.0 / r.len() as f64)
+            .product();
+        if n > 0 {
+            self.weights[self.index - 1] = exact / uniform;
+        }
+        out
+    }
+
+    fn index(&self) -> Option<usize> {
+        Some(self.index)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -110,3 +300,105 @@ pub fn pick_some<'a, 'b, D: Dice, T: 'a>(
 pub fn pick_one<D: Dice, T>(dice: &mut D, vs: &[Option<T>]) -> Option<usize> {
     pick_some(dice, 1, vs).next()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn keys() {
+        for key in ["", "0", "0z9"] {
+            let dice = DeterministicDice::from_key(key).unwrap();
+            assert_eq!(dice.key(), key);
+        }
+        for bad in ["0-", "1 ", "é"] {
+            assert!(DeterministicDice::from_key(bad).is_err());
+        }
+
+        // Replaying a key makes the same rolls, learning their ranges
+        let mut dice = DeterministicDice::from_key("12").unwrap();
+        assert_eq!((dice.roll(0..2), dice.roll(0..3)), (1, 2));
+        assert_eq!(dice.probability(), 1.0 / 6.0);
+        assert_eq!(dice.roll(0..4), 0);
+        assert_eq!(dice.key(), "120");
+    }
+
+    #[test]
+    fn enumeration() {
+        // Paths are visited in order, changing the last roll first
+        let mut dice = DeterministicDice::new();
+        let mut paths = vec![];
+        while dice.next() {
+            let a = dice.roll(0..2);
+            let b = dice.roll(1..4);
+            assert_eq!(dice.index(), Some(2));
+            assert_eq!(dice.probability(), 1.0 / 6.0);
+            paths.push((a, b, dice.unchanged()));
+        }
+        let expected = [
+            (0, 1, 0),
+            (0, 2, 1),
+            (0, 3, 1),
+            (1, 1, 0),
+            (1, 2, 1),
+            (1, 3, 1),
+        ];
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn weighted_rolls() {
+        // One path per nonzero weight, whose probabilities are exact
+        let weights = [1, 0, 3];
+        let mut dice = DeterministicDice::new();
+        let mut seen = vec![];
+        while dice.next() {
+            seen.push((dice.roll_weighted(&weights), dice.probability()));
+        }
+        assert_eq!(seen, [(0, 0.25), (2, 0.75)]);
+    }
+
+    #[test]
+    fn multiset_rolls() {
+        // Multisets are visited once each, with the same chances as
+        // enumerating every ordering
+        let weights = [1, 1, 2];
+        let tally = |mut dice: DeterministicDice| {
+            let mut out = BTreeMap::new();
+            let mut paths = 0;
+            while dice.next() {
+                let v = dice.roll_multiset(&weights, 2);
+                *out.entry(v).or_insert(0.0) += dice.probability();
+                paths += 1;
+            }
+            (out, paths)
+        };
+        let (ordered, n) = tally(DeterministicDice::new());
+        let (multisets, m) = tally(DeterministicDice::new().multisets());
+        assert_eq!((n, m), (9, 6));
+        assert_eq!(ordered.len(), m);
+        for (v, p) in &ordered {
+            assert!((multisets[v] - p).abs() < 1e-9);
+        }
+        assert!((multisets[&vec![2, 2]] - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn picks() {
+        // Only filled slots are picked, and never twice
+        let vs = [Some(1), None, Some(2), Some(3), None];
+        let mut dice = DeterministicDice::new();
+        let mut paths = 0;
+        while dice.next() {
+            let picked: Vec<usize> = pick_some(&mut dice, 4, &vs).collect();
+            assert_eq!(picked.len(), 3);
+            let mut sorted = picked.clone();
+            sorted.sort();
+            assert_eq!(sorted, [0, 2, 3]);
+            paths += 1;
+        }
+        assert_eq!(paths, 6);
+        assert_eq!(pick_one(&mut dice, &[None::<usize>; 3]), None);
+    }
+}
@@ -0,0 +1,353 @@
+//! Drawing teams and battles as SVG or PNG images.
+//!
+//! Images are built from a handful of simple shapes, so that they can be
+//! rasterized without any font or graphics libraries.  SVG output also
+//! labels each friend with its emoji (or [ASCII](crate::render)
+//! abbreviation) and stats; PNG output has no text, so stats are drawn as
+//! rows of pips instead.
+
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression, Crc};
+
+use crate::{
+    animate::Frames, battle::Battle, friend::Friend, params::TEAM_SIZE, render,
+    species::Species, team::Team,
+};
+
+/// Width and height of a single friend's slot
+const SLOT: (usize, usize) = (64, 96);
+/// Space around and between slots
+const GAP: usize = 8;
+/// Space between the two teams in a battle
+const MIDDLE: usize = 32;
+/// Largest number of pips drawn for a stat in PNG output
+const MAX_PIPS: usize = 10;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    const BACKGROUND: Self = Self(255, 255, 255);
+    const BORDER: Self = Self(64, 64, 64);
+    const HEALTH: Self = Self(220, 40, 40);
+    const ATTACK: Self = Self(110, 110, 110);
+    const PERK: Self = Self(240, 180, 0);
+    const TEXT: Self = Self(0, 0, 0);
+
+    fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// A shape in an image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Shape {
+    Rect {
+        x: usize,
+        y: usize,
+        w: usize,
+        h: usize,
+        fill: Color,
+        stroke: Option<Color>,
+    },
+    /// Text is only drawn in SVG output
+    Text {
+        x: usize,
+        y: usize,
+        size: usize,
+        text: String,
+    },
+}
+
+/// A set of shapes to be drawn onto a white background
+#[derive(Clone, Debug, Default)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub shapes: Vec<Shape>,
+}
+
+fn species_color(s: Species) -> Color {
+    match s {
+        Species::Ant => Color(200, 80, 60),
+        Species::Beaver => Color(150, 100, 60),
+        Species::Cricket | Species::GhostCricket => Color(120, 180, 80),
+        Species::Duck => Color(240, 210, 80),
+        Species::Fish => Color(90, 150, 220),
+        Species::Horse => Color(170, 120, 80),
+        Species::Mosquito => Color(130, 130, 150),
+        Species::Otter => Color(160, 110, 90),
+        Species::Pig => Color(240, 160, 180),
+        Species::Whale => Color(60, 100, 180),
+        Species::Kangaroo => Color(200, 150, 90),
+        Species::Ox => Color(130, 80, 50),
+        Species::Bee => Color(250, 200, 40),
+        Species::Custom(..) => Color(190, 190, 190),
+    }
+}
+
+impl Image {
+    /// Draws a team, front on the right
+    pub fn team(team: &Team) -> Self {
+        let mut out = Self::empty(TEAM_SIZE);
+        out.draw_team(team, GAP, true);
+        out
+    }
+
+    /// Draws a battle, with the teams facing each other in the middle
+    pub fn battle(b: &Battle) -> Self {
+        let mut out = Self::empty(TEAM_SIZE * 2);
+        out.width += MIDDLE - GAP;
+        out.draw_team(&b.0, GAP, true);
+        out.draw_team(&b.1, GAP + Self::team_width() + MIDDLE, false);
+        out
+    }
+
+    /// Draws every frame of a recorded battle
+    pub fn frames(frames: &Frames) -> Vec<Self> {
+        frames
+            .frames()
+            .iter()
+            .map(|f| Self::battle(&f.battle))
+            .collect()
+    }
+
+    fn team_width() -> usize {
+        TEAM_SIZE * (SLOT.0 + GAP) - GAP
+    }
+
+    fn empty(slots: usize) -> Self {
+        Self {
+            width: slots * (SLOT.0 + GAP) + GAP,
+            height: SLOT.1 + 2 * GAP,
+            shapes: vec![],
+        }
+    }
+
+    fn draw_team(&mut self, team: &Team, x0: usize, reverse: bool) {
+        for i in 0..TEAM_SIZE {
+            let slot = if reverse { TEAM_SIZE - 1 - i } else { i };
+            let x = x0 + i * (SLOT.0 + GAP);
+            self.shapes.push(Shape::Rect {
+                x,
+                y: GAP,
+                w: SLOT.0,
+                h: SLOT.1,
+                fill: Color::BACKGROUND,
+                stroke: Some(Color::BORDER),
+            });
+            if let Some(f) = team[slot] {
+                self.draw_friend(&f, x, GAP);
+            }
+        }
+    }
+
+    fn draw_friend(&mut self, f: &Friend, x: usize, y: usize) {
+        self.shapes.push(Shape::Rect {
+            x: x + 16,
+            y: y + 16,
+            w: 32,
+            h: 32,
+            fill: species_color(f.species),
+            stroke: None,
+        });
+        self.shapes.push(Shape::Text {
+            x: x + SLOT.0 / 2,
+            y: y + 40,
+            size: 20,
+            text: format!("{}", f.species),
+        });
+        if let Some(m) = f.modifier {
+            self.shapes.push(Shape::Rect {
+                x: x + SLOT.0 - 14,
+                y: y + 4,
+                w: 10,
+                h: 10,
+                fill: Color::PERK,
+                stroke: None,
+            });
+            self.shapes.push(Shape::Text {
+                x: x + SLOT.0 - 9,
+                y: y + 14,
+                size: 12,
+                text: format!("{}", m),
+            });
+        }
+        let (health, attack) = if render::ascii() {
+            ("hp", "at")
+        } else {
+            ("❤", "⚔")
+        };
+        for (row, n, color, label) in [
+            (0, f.total_health(), Color::HEALTH, health),
+            (1, f.total_attack(), Color::ATTACK, attack),
+        ] {
+            let py = y + 58 + row * 14;
+            for j in 0..n.min(MAX_PIPS) {
+                self.shapes.push(Shape::Rect {
+                    x: x + 4 + j * 6,
+                    y: py,
+                    w: 4,
+                    h: 4,
+                    fill: color,
+                    stroke: None,
+                });
+            }
+            self.shapes.push(Shape::Text {
+                x: x + SLOT.0 / 2,
+                y: py + 12,
+                size: 10,
+                text: format!("{} {}", label, n),
+            });
+        }
+    }
+
+    /// Renders the image as an SVG document
+    pub fn to_svg(&self) -> String {
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+             width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+             <rect width=\"{w}\" height=\"{h}\" fill=\"{}\"/>\n",
+            Color::BACKGROUND.hex(),
+            w = self.width,
+            h = self.height,
+        );
+        for s in &self.shapes {
+            match s {
+                Shape::Rect {
+                    x,
+                    y,
+                    w,
+                    h,
+                    fill,
+                    stroke,
+                } => {
+                    out += &format!(
+                        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                         fill=\"{}\"",
+                        x,
+                        y,
+                        w,
+                        h,
+                        fill.hex()
+                    );
+                    if let Some(c) = stroke {
+                        out += &format!(" stroke=\"{}\"", c.hex());
+                    }
+                    out += "/>\n";
+                }
+                Shape::Text { x, y, size, text } => {
+                    out += &format!(
+                        "<text x=\"{}\" y=\"{}\" font-size=\"{}\" \
+                         text-anchor=\"middle\" fill=\"{}\">{}</text>\n",
+                        x,
+                        y,
+                        size,
+                        Color::TEXT.hex(),
+                        escape(text)
+                    );
+                }
+            }
+        }
+        out += "</svg>\n";
+        out
+    }
+
+    /// Rasterizes the image (without text) as a PNG file
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut pixels = vec![Color::BACKGROUND; self.width * self.height];
+        let mut fill = |x0: usize, y0: usize, w: usize, h: usize, c: Color| {
+            for y in y0..(y0 + h).min(self.height) {
+                for x in x0..(x0 + w).min(self.width) {
+                    pixels[y * self.width + x] = c;
+                }
+            }
+        };
+        for s in &self.shapes {
+            if let Shape::Rect {
+                x,
+                y,
+                w,
+                h,
+                fill: c,
+                stroke,
+            } = *s
+            {
+                fill(x, y, w, h, c);
+                if let Some(c) = stroke {
+                    fill(x, y, w, 1, c);
+                    fill(x, y + h - 1, w, 1, c);
+                    fill(x, y, 1, h, c);
+                    fill(x + w - 1, y, 1, h, c);
+                }
+            }
+        }
+
+        // Each row is prefixed with a filter byte (0 = no filtering)
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in pixels.chunks(self.width) {
+            raw.push(0);
+            raw.extend(row.iter().flat_map(|c| [c.0, c.1, c.2]));
+        }
+        let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+        z.write_all(&raw).unwrap();
+        let idat = z.finish().unwrap();
+
+        let mut ihdr = vec![];
+        ihdr.extend((self.width as u32).to_be_bytes());
+        ihdr.extend((self.height as u32).to_be_bytes());
+        // 8-bit RGB, default compression / filtering, no interlacing
+        ihdr.extend([8, 2, 0, 0, 0]);
+
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut out, b"IHDR", &ihdr);
+        png_chunk(&mut out, b"IDAT", &idat);
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(kind);
+    out.extend(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend(crc.sum().to_be_bytes());
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svg_and_png() {
+        // Every rectangle is drawn in SVG, plus the background, and the PNG
+        // is framed by its signature and end chunk
+        let a: Team = "Ant:2/1+Honey,Fish:3/3:1".parse().unwrap();
+        let b: Team = "Mosquito:2/2".parse().unwrap();
+        let image = Image::battle(&Battle(a, b));
+        let rects = image
+            .shapes
+            .iter()
+            .filter(|s| matches!(s, Shape::Rect { .. }))
+            .count();
+        assert!(rects > 0);
+        assert_eq!(image.to_svg().matches("<rect").count(), rects + 1);
+
+        let png = image.to_png();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &(image.width as u32).to_be_bytes());
+        assert_eq!(&png[20..24], &(image.height as u32).to_be_bytes());
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+}
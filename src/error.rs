@@ -0,0 +1,61 @@
+use thiserror::Error;
+
+/// Errors which library consumers may want to recover from, e.g. bad input
+/// or a corrupt cache.  Violated engine invariants still panic.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid cache data: {0}")]
+    Cache(#[from] bincode::Error),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to install signal handler: {0}")]
+    Signal(#[from] ctrlc::Error),
+    #[error("Invalid species {0:?}")]
+    Species(String),
+    #[error("Invalid modifier {0:?}")]
+    Modifier(String),
+    #[error("Invalid friend: {0}")]
+    Friend(String),
+    #[error("Invalid team: {0}")]
+    Team(String),
+    #[error("Invalid dice key {0:?}")]
+    DiceKey(String),
+    #[error("Invalid pack: {0}")]
+    Pack(String),
+    #[error("Invalid rule set: {0}")]
+    Rules(String),
+    #[error("Invalid custom species: {0}")]
+    Custom(String),
+    #[error("Invalid damage model {0:?}")]
+    DamageModel(String),
+    #[error("Invalid summon placement {0:?}")]
+    SummonPlacement(String),
+    #[error("Invalid challenge rule {0:?}")]
+    Challenge(String),
+    #[error("Invalid pruning rule {0:?}")]
+    Prune(String),
+    #[error("Invalid food {0:?}")]
+    Food(String),
+    #[error("Invalid action: {0}")]
+    Action(String),
+    #[error("Invalid script: {0}")]
+    Script(String),
+    #[error("Invalid policy {0:?}")]
+    Policy(String),
+    #[error("Invalid ranking {0:?}")]
+    Ranking(String),
+    #[error("Invalid meta snapshot: {0}")]
+    Snapshot(String),
+    #[error("Invalid team database: {0}")]
+    Database(String),
+    #[error("Bridge failed: {0}")]
+    Bridge(String),
+    #[error("Invalid matrix file: {0}")]
+    Matrix(String),
+    #[error("Invalid number {0:?}")]
+    Number(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,314 @@
+//! Dry-run cost estimates, which predict how big and slow a run would be
+//! before launching it.
+//!
+//! Generation explores every shop reachable from every starting shop, and
+//! starting shops share most of their states (rerolls lead from any shop to
+//! most others), so the cost isn't proportional to the number of starting
+//! shops.  The estimate explores a random sample of starting shops, first
+//! half of them and then the rest, and extrapolates how the number of
+//! distinct shops and teams grows between the two to every starting shop.
+//!
+//! Growth is extrapolated two ways, which usually bracket the truth:
+//! - Low: each starting shop reaches a random fraction of every state, so
+//!   the count saturates geometrically
+//! - High: the count grows as a power of the number of starting shops
+//!
+//! Scoring battles every pair of teams, so its cost is the square of the
+//! number of teams, times the cost of a matchup, which is measured on random
+//! pairs of the teams found in the sample.
+
+use std::time::Instant;
+
+use hashbrown::{HashMap, HashSet};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    battle::Battle,
+    config::GameConfig,
+    dice::DeterministicDice,
+    filter::Focus,
+    shop::{Shop, Step},
+    team::Team,
+};
+
+/// Settings for [`estimate`]
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Turn of the starting shops, which sets their size and tier
+    pub turn: usize,
+    /// Number of starting shops to explore
+    pub samples: usize,
+    /// Number of matchups to time
+    pub matchups: usize,
+    pub seed: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            turn: 1,
+            samples: 8,
+            matchups: 100,
+            seed: 0,
+        }
+    }
+}
+
+/// What exploring some of the starting shops found
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Sample {
+    /// Number of starting shops explored
+    pub roots: usize,
+    /// Unique shops expanded, ignoring gold
+    pub shops: usize,
+    /// Non-dumb battle-ready teams
+    pub teams: usize,
+    /// Wall-clock time spent exploring, in seconds
+    pub seconds: f64,
+}
+
+/// Low and high estimates of a quantity
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Range {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Range {
+    fn map<F: Fn(f64) -> f64>(self, f: F) -> Self {
+        Self {
+            low: f(self.low),
+            high: f(self.high),
+        }
+    }
+}
+
+/// Predicted size and cost of a run
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Estimate {
+    pub turn: usize,
+    /// Distinct starting shops
+    pub roots: usize,
+    /// Exploration of half the sampled starting shops, then all of them
+    pub half: Sample,
+    pub full: Sample,
+    /// Extrapolated totals for generation
+    pub shops: Range,
+    pub teams: Range,
+    pub generate_seconds: Range,
+    /// Matchups which were timed, and the mean dice paths and seconds for
+    /// each
+    pub matchups: usize,
+    pub paths_per_matchup: f64,
+    pub seconds_per_matchup: f64,
+    /// Extrapolated totals for scoring every pair of teams
+    pub battles: Range,
+    pub score_seconds: Range,
+}
+
+/// A breadth-first exploration, with the same deduplication as full
+/// generation, to which more starting shops can be added
+struct Explorer<'a> {
+    cfg: &'a GameConfig,
+    focus: &'a Focus,
+    /// Most gold with which each shop was expanded, keyed without gold
+    seen_shops: HashMap<Shop, usize>,
+    /// Battle-ready teams, including dumb teams
+    teams: HashSet<Team>,
+    roots: usize,
+    seconds: f64,
+}
+
+impl<'a> Explorer<'a> {
+    fn new(cfg: &'a GameConfig, focus: &'a Focus) -> Self {
+        Self {
+            cfg,
+            focus,
+            seen_shops: HashMap::new(),
+            teams: HashSet::new(),
+            roots: 0,
+            seconds: 0.0,
+        }
+    }
+
+    /// Explores every shop reachable from the given starting shops, which
+    /// haven't been reached already
+    fn add(&mut self, roots: &[Shop]) {
+        let start = Instant::now();
+        let mut active: HashSet<Shop> = roots.iter().copied().collect();
+        let mut actions = 0;
+        while !active.is_empty() {
+            let mut next = HashSet::new();
+            for shop in active {
+                let mut key = shop;
+                key.gold = 0;
+                if self.seen_shops.get(&key).is_some_and(|g| *g >= shop.gold) {
+                    continue;
+                }
+                self.seen_shops.insert(key, shop.gold);
+                let ends = self.cfg.limits.ends(&shop, actions);
+
+                let mut dice = DeterministicDice::new();
+                if self.cfg.multiset_offers {
+                    dice = dice.multisets();
+                }
+                while dice.next() {
+                    let mut shop = shop;
+                    let step = if ends {
+                        shop.end_turn(&mut dice, &mut ());
+                        Step::Ended
+                    } else {
+                        shop.step_pruned(&mut dice, &self.cfg.prune, &mut ())
+                    };
+                    if matches!(step, Step::Impossible | Step::Pruned) {
+                        continue;
+                    }
+                    shop.team.sort();
+                    if next.contains(&shop) || !self.focus.explores(&shop) {
+                        continue;
+                    }
+                    if step == Step::Acted {
+                        if self.cfg.limits.allows(&shop, actions + 1) {
+                            next.insert(shop);
+                        }
+                    } else if self.focus.keeps(&shop.team) {
+                        let teams = shop.team.compact_permutations();
+                        self.teams.extend(teams.map(|t| t.for_battle()));
+                    }
+                }
+            }
+            active = next;
+            actions += 1;
+        }
+        self.roots += roots.len();
+        self.seconds += start.elapsed().as_secs_f64();
+    }
+
+    /// Returns the non-dumb teams found so far, sorted
+    fn teams(&self) -> Vec<Team> {
+        let mut out: Vec<Team> = self
+            .teams
+            .iter()
+            .filter(|t| !t.is_dumb_with(&self.cfg.dumb))
+            .copied()
+            .collect();
+        out.sort();
+        out
+    }
+
+    fn sample(&self) -> Sample {
+        Sample {
+            roots: self.roots,
+            shops: self.seen_shops.len(),
+            teams: self.teams().len(),
+            seconds: self.seconds,
+        }
+    }
+}
+
+/// Extrapolates a count from `k` and `2k` starting shops to `n` of them
+fn extrapolate(k: usize, half: usize, full: usize, n: usize) -> Range {
+    let (full, half) = (full as f64, half as f64);
+    let scale = n as f64 / (2 * k).max(1) as f64;
+    if k == 0 || half == 0.0 || full <= half {
+        // The second half found nothing new, so the count has saturated
+        return Range {
+            low: full,
+            high: full,
+        };
+    }
+    let growth = (full / half).log2().min(1.0);
+    // With U(k) = T (1 - r^k), U(2k) / U(k) = 1 + r^k
+    let rk = (full / half - 1.0).min(1.0);
+    let low = if rk < 1.0 {
+        let total = half / (1.0 - rk);
+        total * (1.0 - rk.powf(n as f64 / k as f64))
+    } else {
+        full * scale
+    };
+    Range {
+        low: low.max(full),
+        high: (full * scale.powf(growth)).max(full),
+    }
+}
+
+/// Estimates the size and cost of generating and scoring every team
+pub fn estimate(
+    cfg: &GameConfig,
+    focus: &Focus,
+    settings: &Settings,
+) -> Estimate {
+    // Starting shops only differ in which offers they have, not in which
+    // slots, so rolling offers as multisets finds every one of them
+    let mut seen = HashSet::new();
+    let mut roots = vec![];
+    let mut dice = DeterministicDice::new().multisets();
+    while dice.next() {
+        let shop =
+            Shop::with_challenge(settings.turn, cfg.challenge, &mut dice);
+        if focus.explores(&shop) && seen.insert(shop) {
+            roots.push(shop);
+        }
+    }
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    roots.shuffle(&mut rng);
+
+    let k = (settings.samples / 2).max(1).min(roots.len() / 2);
+    let mut explorer = Explorer::new(cfg, focus);
+    explorer.add(&roots[..k]);
+    let half = explorer.sample();
+    explorer.add(&roots[k..2 * k]);
+    let full = explorer.sample();
+    let teams = explorer.teams();
+
+    let n = roots.len();
+    let shops = extrapolate(k, half.shops, full.shops, n);
+    let generate_seconds = shops.map(|s| {
+        if full.shops == 0 {
+            0.0
+        } else {
+            full.seconds * s / full.shops as f64
+        }
+    });
+
+    let mut paths = 0;
+    let mut seconds = 0.0;
+    let matchups = if teams.is_empty() {
+        0
+    } else {
+        settings.matchups
+    };
+    for _ in 0..matchups {
+        let a = teams[rng.gen_range(0..teams.len())];
+        let b = teams[rng.gen_range(0..teams.len())];
+        let start = Instant::now();
+        let outcomes = Battle(a, b).outcomes(cfg);
+        paths += outcomes.iter().map(|t| t.paths).sum::<usize>();
+        seconds += start.elapsed().as_secs_f64();
+    }
+    let per = |x: f64| {
+        if matchups == 0 {
+            0.0
+        } else {
+            x / matchups as f64
+        }
+    };
+    let paths_per_matchup = per(paths as f64);
+    let seconds_per_matchup = per(seconds);
+    let teams = extrapolate(k, half.teams, full.teams, n);
+    Estimate {
+        turn: settings.turn,
+        roots: n,
+        half,
+        full,
+        shops,
+        teams,
+        generate_seconds,
+        matchups,
+        paths_per_matchup,
+        seconds_per_matchup,
+        battles: teams.map(|t| t * t * paths_per_matchup),
+        score_seconds: teams.map(|t| t * t * seconds_per_matchup),
+    }
+}
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle::{Battle, Winner},
+    modifier::Modifier,
+    render::Icon,
+    shop::GoldCause,
+    species::Species,
+};
+
+/// A single observable thing that happened during a battle.
+///
+/// Teams are identified by a `bool`, matching [`Battle`](crate::battle::Battle)
+/// indexing: `true` is team A and `false` is team B.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Event {
+    /// A new round of the battle is starting
+    Round(usize),
+    /// The front friends of each team attack each other
+    Clash { a: Species, b: Species },
+    /// A friend takes damage
+    Hurt {
+        team: bool,
+        pos: usize,
+        species: Species,
+        damage: usize,
+    },
+    /// A friend's modifier changes the damage it deals or receives
+    Perk {
+        team: bool,
+        pos: usize,
+        species: Species,
+        modifier: Modifier,
+    },
+    /// A friend knocks out an enemy, i.e. its hit reduced the enemy's health to
+    /// zero and the friend survived
+    KnockOut {
+        team: bool,
+        pos: usize,
+        species: Species,
+        victim: Species,
+    },
+    /// A friend swallows the friend ahead of it, removing it from the team
+    Swallow {
+        team: bool,
+        pos: usize,
+        species: Species,
+        swallowed: Species,
+    },
+    /// A friend is moved within its team, shifting the friends in between
+    Move {
+        team: bool,
+        from: usize,
+        to: usize,
+        species: Species,
+    },
+    /// A friend gains stats
+    Buff {
+        team: bool,
+        pos: usize,
+        species: Species,
+        attack: usize,
+        health: usize,
+    },
+    /// A friend gains a modifier, replacing any it already had
+    Equip {
+        team: bool,
+        pos: usize,
+        species: Species,
+        modifier: Modifier,
+    },
+    /// A friend faints and is removed from the team
+    Faint {
+        team: bool,
+        pos: usize,
+        species: Species,
+    },
+    /// A friend is summoned onto the team
+    Summon {
+        team: bool,
+        pos: usize,
+        species: Species,
+    },
+    /// A friend can't be summoned, because there's no room on the team
+    SummonBlocked {
+        team: bool,
+        pos: usize,
+        species: Species,
+    },
+    /// The battle is over
+    End(Winner),
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let side = |team: bool| if team { 'A' } else { 'B' };
+        match self {
+            Self::Round(i) => write!(f, "Round {}", i),
+            Self::Clash { a, b } => write!(f, "{} clashes with {}", a, b),
+            Self::Hurt {
+                team,
+                pos,
+                species,
+                damage,
+            } => write!(
+                f,
+                "{} at {}{} takes {} damage",
+                species,
+                side(*team),
+                pos,
+                damage
+            ),
+            Self::Perk {
+                team,
+                pos,
+                species,
+                modifier,
+            } => write!(
+                f,
+                "{} at {}{} uses {}",
+                species,
+                side(*team),
+                pos,
+                modifier
+            ),
+            Self::KnockOut {
+                team,
+                pos,
+                species,
+                victim,
+            } => write!(
+                f,
+                "{} at {}{} knocks out {}",
+                species,
+                side(*team),
+                pos,
+                victim
+            ),
+            Self::Swallow {
+                team,
+                pos,
+                species,
+                swallowed,
+            } => write!(
+                f,
+                "{} at {}{} swallows {}",
+                species,
+                side(*team),
+                pos,
+                swallowed
+            ),
+            Self::Move {
+                team,
+                from,
+                to,
+                species,
+            } => write!(
+                f,
+                "{} moves from {}{} to {}{}",
+                species,
+                side(*team),
+                from,
+                side(*team),
+                to
+            ),
+            Self::Buff {
+                team,
+                pos,
+                species,
+                attack,
+                health,
+            } => write!(
+                f,
+                "{} at {}{} gains {} +{}, {} +{}",
+                species,
+                side(*team),
+                pos,
+                Icon::Health,
+                health,
+                Icon::Attack,
+                attack
+            ),
+            Self::Equip {
+                team,
+                pos,
+                species,
+                modifier,
+            } => write!(
+                f,
+                "{} at {}{} gains {}",
+                species,
+                side(*team),
+                pos,
+                modifier
+            ),
+            Self::Faint { team, pos, species } => {
+                write!(f, "{} at {}{} faints", species, side(*team), pos)
+            }
+            Self::Summon { team, pos, species } => {
+                write!(f, "{} is summoned at {}{}", species, side(*team), pos)
+            }
+            Self::SummonBlocked { team, pos, species } => write!(
+                f,
+                "{} can't be summoned at {}{}: no room",
+                species,
+                side(*team),
+                pos
+            ),
+            Self::End(w) => write!(f, "Battle ended: {:?}", w),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What caused an ability to trigger
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Cause {
+    BattleStart,
+    Hurt,
+    KnockOut,
+    Faint,
+    /// The nearest friend ahead attacked
+    AheadAttacks,
+    /// The nearest friend ahead fainted
+    AheadFaints,
+    Summon,
+    Buy,
+    Sell,
+    Sold,
+    LevelUp,
+    StartTurn,
+    EndTurn,
+}
+
+/// A friend's ability triggering, in battle or in the shop (where the team
+/// is always `true`)
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Trigger {
+    pub team: bool,
+    pub pos: usize,
+    pub species: Species,
+    pub cause: Cause,
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {}{} triggers on {:?}",
+            self.species,
+            if self.team { 'A' } else { 'B' },
+            self.pos,
+            self.cause
+        )
+    }
+}
+
+/// A sink for battle events.  This is threaded through the battle engine
+/// alongside the [`Dice`](crate::dice::Dice), so that replays can be recorded
+/// without slowing down bulk scoring (which uses the no-op `()` log).
+///
+/// Triggered abilities are also reported, for debugging with a
+/// [`Timeline`](crate::timeline::Timeline); these are ignored by default.
+pub trait Log {
+    fn log(&mut self, e: Event);
+
+    /// Marks the start of a triggered ability.  Everything until the matching
+    /// [`Log::end_trigger`] is caused by it; triggers may be nested.
+    fn begin_trigger(&mut self, _t: Trigger) {}
+    /// Records the positions (on the given team) targeted by the current
+    /// trigger
+    fn targets(&mut self, _team: bool, _pos: &[usize]) {}
+    /// Marks the end of the current trigger
+    fn end_trigger(&mut self) {}
+    /// Records the state of the battle before it starts and at the start of
+    /// every round, for an [animation](crate::animate::Frames)
+    fn snapshot(&mut self, _b: &Battle) {}
+    /// Records a change in the shop's gold (only in the shop)
+    fn gold(&mut self, _cause: GoldCause, _delta: isize) {}
+}
+
+impl Log for () {
+    fn log(&mut self, _e: Event) {}
+}
+
+impl Log for Vec<Event> {
+    fn log(&mut self, e: Event) {
+        self.push(e)
+    }
+}
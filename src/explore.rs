@@ -0,0 +1,334 @@
+//! An outcome tree explorer, which lists every dice path through a battle
+//! along with its probability and the roll which decided it.
+//!
+//! Dice paths form a tree, where each roll branches into one child per
+//! option.  A path's pivotal roll is the first roll after which every path
+//! through the same choices ends with the same winner; battles with only one
+//! possible winner have no pivotal rolls.
+//!
+//! Conditioning the outcome on each roll also shows which decisions matter
+//! most, i.e. which rolls swing team A's chance of winning the furthest
+//! between their best and worst options.
+
+use hashbrown::HashMap;
+use serde::Serialize;
+
+use crate::{
+    battle::{Battle, Outcome, Winner},
+    config::GameConfig,
+    dice::DeterministicDice,
+    event::Trigger,
+    timeline::{Entry, Roll, Timeline},
+};
+
+/// The roll which decided a path's winner
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Pivot {
+    /// Index of the roll within the path, starting from 0
+    pub index: usize,
+    pub roll: Roll,
+    /// Ability which rolled the dice, if any
+    pub trigger: Option<Trigger>,
+    /// Targets which that ability picked after rolling, as a team and
+    /// positions
+    pub targets: Option<(bool, Vec<usize>)>,
+}
+
+impl std::fmt::Display for Pivot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "roll {} picks {} of {:?}",
+            self.index, self.roll.value, self.roll.range
+        )?;
+        if let Some(t) = &self.trigger {
+            write!(f, " for {}", t)?;
+        }
+        if let Some((team, pos)) = &self.targets {
+            write!(
+                f,
+                ", targeting {:?} on team {}",
+                pos,
+                if *team { 'A' } else { 'B' }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A single dice path through a battle
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Branch {
+    /// Dice key, which can be passed to [`DeterministicDice::from_key`]
+    pub key: String,
+    /// Chance of real dice taking this path
+    pub probability: f64,
+    pub winner: Winner,
+    pub rounds: usize,
+    pub pivot: Option<Pivot>,
+}
+
+/// Finds the ability (and its targets) behind every roll in a timeline
+fn rolls(timeline: &mut Timeline) -> Vec<Pivot> {
+    let entries = timeline.entries();
+    let mut out = vec![];
+    let mut triggers: Vec<Trigger> = vec![];
+    for (i, (depth, e)) in entries.iter().enumerate() {
+        match e {
+            Entry::Trigger(t) => {
+                triggers.truncate(*depth);
+                triggers.push(*t);
+            }
+            Entry::Roll(roll) => {
+                triggers.truncate(*depth);
+                let targets = entries[i + 1..]
+                    .iter()
+                    .take_while(|(d, e)| *d == *depth && *e != Entry::End)
+                    .find_map(|(_, e)| match e {
+                        Entry::Targets { team, pos } => {
+                            Some((*team, pos.clone()))
+                        }
+                        _ => None,
+                    });
+                out.push(Pivot {
+                    index: out.len(),
+                    roll: roll.clone(),
+                    trigger: triggers.last().cloned(),
+                    targets,
+                });
+            }
+            _ => (),
+        }
+    }
+    out
+}
+
+/// A dice path, along with what each roll did
+struct Path {
+    key: String,
+    probability: f64,
+    outcome: Outcome,
+    rolls: Vec<Pivot>,
+}
+
+/// Runs every dice path through the battle, in enumeration order
+fn paths(cfg: &GameConfig, battle: Battle) -> Vec<Path> {
+    let mut out = vec![];
+    let mut dice = DeterministicDice::new();
+    while dice.next() {
+        let mut timeline = Timeline::new();
+        let outcome = battle.run_logged(
+            cfg,
+            &mut timeline.watch(&mut dice),
+            &mut timeline,
+        );
+        out.push(Path {
+            key: dice.key(),
+            probability: dice.probability(),
+            outcome,
+            rolls: rolls(&mut timeline),
+        });
+    }
+    out
+}
+
+/// Runs every dice path through the battle, in enumeration order
+pub fn explore(cfg: &GameConfig, battle: Battle) -> Vec<Branch> {
+    let paths = paths(cfg, battle);
+
+    // Winners reachable below each node in the tree, keyed by the choices
+    // which lead to it
+    let mut below: HashMap<&str, Vec<Winner>> = HashMap::new();
+    for p in &paths {
+        for n in 0..=p.key.len() {
+            let w = below.entry(&p.key[..n]).or_default();
+            if !w.contains(&p.outcome.winner) {
+                w.push(p.outcome.winner);
+            }
+        }
+    }
+    let decided = |key: &str| below[key].len() == 1;
+
+    paths
+        .iter()
+        .map(|p| {
+            let pivot = if decided("") {
+                None
+            } else {
+                (1..=p.key.len())
+                    .find(|n| decided(&p.key[..*n]))
+                    .map(|n| p.rolls[n - 1].clone())
+            };
+            Branch {
+                key: p.key.clone(),
+                probability: p.probability,
+                winner: p.outcome.winner,
+                rounds: p.outcome.rounds,
+                pivot,
+            }
+        })
+        .collect()
+}
+
+/// One option at a [`Decision`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Choice {
+    /// The roll, as picked by this option
+    pub roll: Pivot,
+    /// Chance of real dice picking this option, once the decision is reached
+    pub probability: f64,
+    /// Chance that team A wins, given that this option is picked
+    pub wins: f64,
+}
+
+/// A roll which is made along some dice paths, and how team A's chance of
+/// winning depends on its result
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Decision {
+    /// Dice key of the rolls leading up to this one
+    pub prefix: String,
+    /// Chance of real dice reaching this roll
+    pub reach: f64,
+    /// Chance that team A wins, once this roll is reached
+    pub wins: f64,
+    pub choices: Vec<Choice>,
+}
+
+impl Decision {
+    /// Returns team A's worst and best chance of winning, across every
+    /// option
+    pub fn range(&self) -> (f64, f64) {
+        let wins = self.choices.iter().map(|c| c.wins);
+        (wins.clone().fold(1.0, f64::min), wins.fold(0.0, f64::max))
+    }
+
+    /// Returns how far the decision swings team A's chance of winning
+    pub fn swing(&self) -> f64 {
+        let (worst, best) = self.range();
+        best - worst
+    }
+}
+
+/// Conditions the battle's outcome on every roll with more than one option,
+/// returning the decisions which swing team A's chance of winning the most
+/// first (breaking ties by how likely the decision is to be reached)
+pub fn sensitivity(cfg: &GameConfig, battle: Battle) -> Vec<Decision> {
+    let paths = paths(cfg, battle);
+
+    // Total probability and chance of team A winning below each node
+    let mut below: HashMap<&str, (f64, f64)> = HashMap::new();
+    for p in &paths {
+        let won = (p.outcome.winner == Winner::TeamA) as usize as f64;
+        for n in 0..=p.key.len() {
+            let (total, wins) = below.entry(&p.key[..n]).or_default();
+            *total += p.probability;
+            *wins += p.probability * won;
+        }
+    }
+
+    // Every option for each roll, with a path which picks it
+    let mut options: Vec<(&str, Vec<&Path>)> = vec![];
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for p in &paths {
+        for n in 0..p.key.len() {
+            let prefix = &p.key[..n];
+            let i = *index.entry(prefix).or_insert_with(|| {
+                options.push((prefix, vec![]));
+                options.len() - 1
+            });
+            let picks = &mut options[i].1;
+            if !picks.iter().any(|q| q.key[n..=n] == p.key[n..=n]) {
+                picks.push(p);
+            }
+        }
+    }
+
+    let mut out: Vec<Decision> = options
+        .into_iter()
+        .filter(|(_, picks)| picks.len() > 1)
+        .map(|(prefix, picks)| {
+            let n = prefix.len();
+            let (reach, wins) = below[prefix];
+            let choices = picks
+                .into_iter()
+                .map(|p| {
+                    let (total, wins) = below[&p.key[..=n]];
+                    Choice {
+                        roll: p.rolls[n].clone(),
+                        probability: total / reach,
+                        wins: wins / total,
+                    }
+                })
+                .collect();
+            Decision {
+                prefix: prefix.to_owned(),
+                reach,
+                wins: wins / reach,
+                choices,
+            }
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        b.swing()
+            .total_cmp(&a.swing())
+            .then(b.reach.total_cmp(&a.reach))
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event::Cause, species::Species, team::Team};
+
+    fn battle(a: &str, b: &str) -> Battle {
+        Battle(a.parse::<Team>().unwrap(), b.parse::<Team>().unwrap())
+    }
+
+    #[test]
+    fn mosquito_pivot() {
+        // The Mosquito wins if it snipes the front Fish, and ties if it
+        // snipes the one behind, so its only roll decides every path
+        let cfg = GameConfig::default();
+        let b = battle("Mosquito:2/2", "Fish:1/1,Fish:1/2");
+        let branches = explore(&cfg, b);
+        let winners: Vec<(&str, Winner)> = branches
+            .iter()
+            .map(|b| (b.key.as_str(), b.winner))
+            .collect();
+        assert_eq!(winners, [("0", Winner::TeamA), ("1", Winner::Tied)]);
+        for (i, branch) in branches.iter().enumerate() {
+            assert_eq!(branch.probability, 0.5);
+            let pivot = branch.pivot.as_ref().unwrap();
+            assert_eq!((pivot.index, pivot.roll.value), (0, i));
+            assert_eq!(pivot.targets, Some((false, vec![i])));
+            let t = pivot.trigger.unwrap();
+            assert_eq!(
+                (t.species, t.cause),
+                (Species::Mosquito, Cause::BattleStart)
+            );
+        }
+
+        let decisions = sensitivity(&cfg, b);
+        assert_eq!(decisions.len(), 1);
+        let d = &decisions[0];
+        assert_eq!((d.prefix.as_str(), d.reach, d.wins), ("", 1.0, 0.5));
+        assert_eq!(d.range(), (0.0, 1.0));
+        assert_eq!(d.swing(), 1.0);
+        assert!(d.choices.iter().all(|c| c.probability == 0.5));
+    }
+
+    #[test]
+    fn no_rolls() {
+        // A battle without any rolls has a single path, and nothing to pivot
+        // on or condition
+        let cfg = GameConfig::default();
+        let b = battle("Fish:3/3", "Fish:2/2");
+        let branches = explore(&cfg, b);
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].key, "");
+        assert_eq!(branches[0].winner, Winner::TeamA);
+        assert_eq!(branches[0].pivot, None);
+        assert!(sensitivity(&cfg, b).is_empty());
+    }
+}
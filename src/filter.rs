@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    modifier::Modifier,
+    shop::Shop,
+    species::Species,
+    team::Team,
+};
+
+/// A predicate on teams, for slicing analysis results.  The default filter
+/// matches every team.
+#[derive(Clone, Debug, Default)]
+pub struct TeamFilter {
+    /// Species which must all be on the team
+    pub with: Vec<Species>,
+    /// Species which must not be on the team
+    pub without: Vec<Species>,
+    /// Maximum number of friends on the team
+    pub max_friends: Option<usize>,
+    /// Whether at least one friend must have a honey modifier
+    pub honey: bool,
+}
+
+impl TeamFilter {
+    /// Checks whether the filter matches every team
+    pub fn is_empty(&self) -> bool {
+        self.with.is_empty()
+            && self.without.is_empty()
+            && self.max_friends.is_none()
+            && !self.honey
+    }
+
+    pub fn matches(&self, team: &Team) -> bool {
+        let has = |s: &Species| team.iter().flatten().any(|f| f.species == *s);
+        self.with.iter().all(has)
+            && !self.without.iter().any(has)
+            && self.max_friends.map(|n| team.count() <= n).unwrap_or(true)
+            && (!self.honey
+                || team
+                    .iter()
+                    .flatten()
+                    .any(|f| f.modifier == Some(Modifier::Honey)))
+    }
+
+    /// Parses a comma-separated list of species names, e.g. `Ant,Fish`
+    pub fn parse_species(s: &str) -> Result<Vec<Species>> {
+        s.split(',').map(str::parse).collect()
+    }
+}
+
+/// Rules for ranking teams by their battle results, which differ in how
+/// much a tie is worth
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub enum Ranking {
+    /// Only wins count, so a tie is as bad as a loss
+    #[default]
+    Wins,
+    /// A win is worth a point and a tie half a point
+    Points,
+    /// Ties cost no lives in the real game, so anything but a loss counts
+    Survival,
+}
+
+impl Ranking {
+    /// Returns the score of a matchup, from its win and tie rates
+    pub fn score(self, wins: f32, ties: f32) -> f32 {
+        match self {
+            Self::Wins => wins,
+            Self::Points => wins + ties / 2.0,
+            Self::Survival => wins + ties,
+        }
+    }
+}
+
+impl std::str::FromStr for Ranking {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "wins" => Ok(Self::Wins),
+            "points" => Ok(Self::Points),
+            "survival" => Ok(Self::Survival),
+            _ => Err(Error::Ranking(s.to_owned())),
+        }
+    }
+}
+
+/// Limits on team generation, so that focused questions (e.g. about teams
+/// with a Mosquito) can be answered without exploring every shop.  Unlike a
+/// [`TeamFilter`], this changes which shops are explored, and so also the
+/// economy and provenance recorded for each team.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Focus {
+    /// Species which every generated team must contain
+    pub require: Vec<Species>,
+    /// Species which are never bought
+    pub ban: Vec<Species>,
+}
+
+impl Focus {
+    /// Checks whether the focus allows every team
+    pub fn is_empty(&self) -> bool {
+        self.require.is_empty() && self.ban.is_empty()
+    }
+
+    /// Checks whether a shop is worth exploring: its team has no banned
+    /// species, and it can still afford every required species which the
+    /// team is missing.  Shops which would have to sell a friend to afford
+    /// one are skipped, so this is a focused search rather than an exhaustive
+    /// one.
+    pub fn explores(&self, shop: &Shop) -> bool {
+        let missing = self
+            .require
+            .iter()
+            .filter(|s| !Self::has(&shop.team, s))
+            .count();
+        // Every friend costs 3 gold
+        !self.ban.iter().any(|s| Self::has(&shop.team, s))
+            && shop.gold >= 3 * missing
+    }
+
+    /// Checks whether a generated team should be kept
+    pub fn keeps(&self, team: &Team) -> bool {
+        self.require.iter().all(|s| Self::has(team, s))
+            && !self.ban.iter().any(|s| Self::has(team, s))
+    }
+
+    fn has(team: &Team, s: &Species) -> bool {
+        team.iter().flatten().any(|f| f.species == *s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dice::DeterministicDice, friend::Friend};
+
+    #[test]
+    fn ranking_scores() {
+        for wins in 0..=8 {
+            for ties in 0..=8 {
+                let (wins, ties) = (wins as f32 / 16.0, ties as f32 / 16.0);
+                let [w, p, s] = ["wins", "points", "survival"]
+                    .map(|r| r.parse::<Ranking>().unwrap().score(wins, ties));
+                assert_eq!(w, wins);
+                assert!(w <= p && p <= s);
+                assert_eq!(s, wins + ties);
+            }
+        }
+        assert!("ties".parse::<Ranking>().is_err());
+    }
+
+    #[test]
+    fn filter_matches() {
+        let team: Team = "Ant:2/1+Honey,Fish:2/3,Fish:3/3".parse().unwrap();
+        let species = TeamFilter::parse_species("Ant,Fish").unwrap();
+        assert!(TeamFilter::parse_species("Ant,Dog").is_err());
+        let f = |with, without, max_friends, honey| TeamFilter {
+            with,
+            without,
+            max_friends,
+            honey,
+        };
+        assert!(f(vec![], vec![], None, false).is_empty());
+        assert!(f(species.clone(), vec![], Some(3), true).matches(&team));
+        assert!(!f(species.clone(), vec![], Some(2), false).matches(&team));
+        assert!(!f(vec![], vec![Species::Ant], None, false).matches(&team));
+        assert!(!f(vec![Species::Pig], vec![], None, false).matches(&team));
+        let plain: Team = "Fish:2/3".parse().unwrap();
+        assert!(!f(vec![], vec![], None, true).matches(&plain));
+    }
+
+    #[test]
+    fn focus_explores() {
+        // A focus keeps the same teams as the equivalent filter, and stops
+        // exploring shops which can't afford the missing species
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let mut shop = Shop::new(1, &mut dice);
+        shop.team = "Ant:2/1,Fish:2/3".parse().unwrap();
+        let focus = Focus {
+            require: vec![Species::Fish, Species::Mosquito],
+            ban: vec![Species::Pig],
+        };
+        let filter = TeamFilter {
+            with: focus.require.clone(),
+            without: focus.ban.clone(),
+            ..TeamFilter::default()
+        };
+        assert!(!focus.keeps(&shop.team));
+        assert_eq!(focus.keeps(&shop.team), filter.matches(&shop.team));
+        for (gold, explores) in [(2, false), (3, true)] {
+            shop.gold = gold;
+            assert_eq!(focus.explores(&shop), explores);
+        }
+        shop.team[2] = Some(Friend::new(Species::Mosquito));
+        assert!(focus.keeps(&shop.team) && filter.matches(&shop.team));
+        shop.gold = 0;
+        assert!(focus.explores(&shop));
+        shop.team[3] = Some(Friend::new(Species::Pig));
+        assert!(!focus.keeps(&shop.team) && !focus.explores(&shop));
+        assert!(Focus::default().is_empty());
+        assert!(Focus::default().explores(&shop));
+    }
+}
@@ -0,0 +1,120 @@
+//! Golden replay fixtures.
+//!
+//! Each fixture is a JSON file describing one dice path through one battle:
+//!
+//! ```json
+//! {
+//!   "team_a": [{"species": "Cricket", "attack": 1, "health": 2}],
+//!   "team_b": [{"species": "Fish", "attack": 2, "health": 3}],
+//!   "dice": "",
+//!   "winner": "TeamB",
+//!   "events": [{"Round": 0}, ...]
+//! }
+//! ```
+//!
+//! Teams use the [battle log](crate::battlelog) encoding, and `dice` is a
+//! [`DeterministicDice`] key.  Unlike a battle log, the event list is
+//! complete, and must match the engine's log exactly.
+//!
+//! Fixtures live in `tests/fixtures`; every new ability should come with at
+//! least one.  Running with `--bless` rewrites each fixture's winner and events
+//! from the current engine, which is how new fixtures are authored (after
+//! checking the resulting log by hand!).
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle::{Battle, Winner},
+    battlelog::{build_team, RecordedFriend},
+    config::GameConfig,
+    dice::DeterministicDice,
+    error::Result,
+    event::Event,
+};
+
+pub const FIXTURE_DIR: &str = "tests/fixtures";
+
+#[derive(Deserialize, Serialize)]
+struct Fixture {
+    team_a: Vec<RecordedFriend>,
+    team_b: Vec<RecordedFriend>,
+    #[serde(default)]
+    dice: String,
+    winner: Winner,
+    events: Vec<Event>,
+}
+
+/// Prints a line-by-line diff of two event logs
+fn print_diff(expected: &[Event], actual: &[Event]) {
+    let n = std::cmp::max(expected.len(), actual.len());
+    for i in 0..n {
+        let e = expected.get(i);
+        let a = actual.get(i);
+        let fmt = |v: Option<&Event>| match v {
+            Some(v) => format!("{}", v),
+            None => "(none)".to_owned(),
+        };
+        if e == a {
+            println!("      {}", fmt(e));
+        } else {
+            println!("    - {}", fmt(e));
+            println!("    + {}", fmt(a));
+        }
+    }
+}
+
+/// Runs a single fixture, returning `true` if it passed (or was blessed)
+fn run_fixture(
+    cfg: &GameConfig,
+    path: &std::path::Path,
+    bless: bool,
+) -> Result<bool> {
+    let data = std::fs::read_to_string(path)?;
+    let mut fixture: Fixture = serde_json::from_str(&data)?;
+    let (a, b) =
+        match (build_team(&fixture.team_a), build_team(&fixture.team_b)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Fixture {:?} has an invalid team: {}", path, e);
+                return Ok(false);
+            }
+        };
+
+    let mut dice = DeterministicDice::from_key(&fixture.dice)?;
+    let mut events = vec![];
+    let winner = Battle(a, b).run_logged(cfg, &mut dice, &mut events).winner;
+
+    if bless {
+        fixture.winner = winner;
+        fixture.events = events;
+        let out = serde_json::to_string_pretty(&fixture)?;
+        std::fs::write(path, out + "\n")?;
+        Ok(true)
+    } else if winner != fixture.winner || events != fixture.events {
+        println!("Fixture {:?} failed", path);
+        println!("  Winner: expected {:?}, got {:?}", fixture.winner, winner);
+        print_diff(&fixture.events, &events);
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Runs every fixture in the given directory, returning the number of failures
+pub fn run(cfg: &GameConfig, dir: &str, bless: bool) -> Result<usize> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|p| p.extension().map(|e| e == "json").unwrap_or(false));
+    paths.sort();
+
+    let mut failures = 0;
+    for p in &paths {
+        if !run_fixture(cfg, p, bless)? {
+            failures += 1;
+        }
+    }
+    info!("Ran {} fixtures, {} failed", paths.len(), failures);
+    Ok(failures)
+}
@@ -1,29 +1,75 @@
-use crate::dice::Dice;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+use crate::{dice::Dice, error::Error, pack};
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Deserialize,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+)]
 pub enum Food {
     Apple,
     Honey,
 }
 
 impl Food {
+    /// Returns a two-letter abbreviation, for [ASCII mode](crate::render)
+    pub fn abbrev(&self) -> &'static str {
+        match self {
+            Self::Apple => "Ap",
+            Self::Honey => "Hn",
+        }
+    }
     pub fn emoji(&self) -> char {
         match self {
             Self::Apple => '🍎',
             Self::Honey => '🍯',
         }
     }
-    pub fn sample<R: Dice>(rng: &mut R) -> Self {
-        match rng.roll(0..2) {
-            0 => Food::Apple,
-            1 => Food::Honey,
-            _ => panic!("Invalid random number"),
-        }
+    /// Picks a random food from those sold at or below the given tier in the
+    /// [active pack](crate::pack::active)
+    pub fn sample<R: Dice>(max_tier: usize, rng: &mut R) -> Self {
+        let options: Vec<Food> = pack::active().food(max_tier).collect();
+        options[rng.roll(0..options.len())]
+    }
+
+    /// Picks `n` random foods as if by [`Food::sample`], without regard to
+    /// order (see [`Dice::roll_multiset`])
+    pub fn sample_n<R: Dice>(
+        max_tier: usize,
+        n: usize,
+        rng: &mut R,
+    ) -> Vec<Self> {
+        let options: Vec<Food> = pack::active().food(max_tier).collect();
+        rng.roll_multiset(&vec![1; options.len()], n)
+            .into_iter()
+            .map(|i| options[i])
+            .collect()
+    }
+}
+
+impl std::str::FromStr for Food {
+    type Err = Error;
+
+    /// Parses a food from its name, e.g. `Apple`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned()))
+            .map_err(|_| Error::Food(s.to_owned()))
     }
 }
 
 impl std::fmt::Display for Food {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if crate::render::ascii() {
+            return write!(f, "{}", self.abbrev());
+        }
         write!(f, "{}", self.emoji())
     }
 }
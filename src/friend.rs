@@ -1,5 +1,12 @@
-use crate::{modifier::Modifier, species::Species};
+use crate::{
+    error::{Error, Result},
+    event::Cause,
+    modifier::Modifier,
+    params::{MAX_EXP, MAX_STAT},
+    species::{Species, TriggerLimit},
+};
 use serde::{Deserialize, Serialize};
+use tracing::trace;
 
 /// A [Friend] is an species embodied onto a team (or in the shop)
 #[derive(
@@ -20,9 +27,91 @@ pub struct Friend {
     pub health: usize,
     pub modifier: Option<Modifier>,
     pub exp: usize,
+
+    /// Temporary stats (e.g. from a Horse), which last until the end of the
+    /// turn's battle.  They're folded into the permanent stats at the start of
+    /// battle, since the battle works on a copy of the team.
+    pub temp_attack: usize,
+    pub temp_health: usize,
+
+    /// State used by the friend's ability, e.g. a swallowed friend
+    #[serde(default)]
+    pub state: AbilityState,
+
+    /// How often the friend's ability has triggered, for species with a
+    /// [trigger limit](Species::trigger_limit)
+    #[serde(default)]
+    pub triggers: Triggers,
+}
+
+/// Per-friend ability state, for friends whose behavior depends on what
+/// they've done so far.  This is small and hashable, so teams with different
+/// state are distinct when deduplicating.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+)]
+pub enum AbilityState {
+    #[default]
+    None,
+    /// A friend of the given species was swallowed (e.g. by a Whale), to be
+    /// released when this friend faints
+    Swallowed(Species),
+}
+
+/// Trigger counts for abilities with a limited number of triggers per battle
+/// or per turn.
+///
+/// Like the shop's [`Economy`](crate::shop::Economy), this is bookkeeping
+/// rather than team identity: every `Triggers` compares equal and hashes to
+/// nothing, so that teams and shops are deduplicated regardless of how often
+/// their abilities have fired.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Triggers {
+    /// Triggers since the start of the current battle
+    pub battle: usize,
+    /// Triggers since the start of the current turn
+    pub turn: usize,
+}
+
+impl PartialEq for Triggers {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Triggers {}
+
+impl PartialOrd for Triggers {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Triggers {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl std::hash::Hash for Triggers {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
 }
 
 impl Friend {
+    /// Returns a builder, which checks that the friend is legal in the game
+    pub fn builder() -> FriendBuilder {
+        FriendBuilder::default()
+    }
     pub fn new(species: Species) -> Self {
         let (health, attack) = species.default_power();
         let modifier = species.default_modifier();
@@ -32,17 +121,198 @@ impl Friend {
             attack,
             modifier,
             exp: 0,
+            temp_attack: 0,
+            temp_health: 0,
+            state: AbilityState::None,
+            triggers: Triggers::default(),
+        }
+    }
+    /// Builds a friend with default stats and enough experience to be at the
+    /// given level
+    pub fn with_level(species: Species, level: usize) -> Self {
+        let exp = match level {
+            1 => 0,
+            2 => 3,
+            3 => MAX_EXP,
+            l => panic!("Invalid level: {}", l),
+        };
+        Self {
+            exp,
+            ..Self::new(species)
         }
     }
     pub fn has_default_power(&self) -> bool {
         (self.health, self.attack) == self.species.default_power()
+            && self.temp_attack == 0
+            && self.temp_health == 0
+    }
+    /// Returns attack, including temporary buffs
+    pub fn total_attack(&self) -> usize {
+        self.attack + self.temp_attack
+    }
+    /// Returns health, including temporary buffs
+    pub fn total_health(&self) -> usize {
+        self.health + self.temp_health
+    }
+    /// Makes temporary buffs permanent
+    pub fn apply_temp(&mut self) {
+        self.attack += std::mem::take(&mut self.temp_attack);
+        self.health += std::mem::take(&mut self.temp_health);
+    }
+    /// Checks whether this friend's ability may trigger for the given cause,
+    /// given its species' [trigger limit](Species::trigger_limit), counting
+    /// the trigger if so.  Causes which the species has no scripted ability
+    /// for never count against its limit.
+    pub fn try_trigger(&mut self, cause: Cause) -> bool {
+        let Some(limit) = self.species.trigger_limit() else {
+            return true;
+        };
+        if self.species.scripted(cause, self.level()).next().is_none() {
+            return true;
+        }
+        let (count, n) = match limit {
+            TriggerLimit::PerBattle(n) => (&mut self.triggers.battle, n),
+            TriggerLimit::PerTurn(n) => (&mut self.triggers.turn, n),
+        };
+        if *count < n {
+            *count += 1;
+            true
+        } else {
+            trace!(species = ?self.species, ?cause, "Reached trigger limit");
+            false
+        }
+    }
+    /// Checks whether `other` may be merged into this friend.  Any two
+    /// friends of the same species can merge, whatever their stats, unless
+    /// either is already at the maximum level.
+    pub fn can_merge(&self, other: &Friend) -> bool {
+        self.species == other.species
+            && self.exp < MAX_EXP
+            && other.exp < MAX_EXP
+    }
+    /// Merges `other` into this friend, which takes the best of each stat
+    /// plus one, and the experience of both plus one (capped at the maximum
+    /// level).  Panics if the merge isn't allowed.
+    pub fn merge(&mut self, other: Friend) {
+        assert!(self.can_merge(&other), "Cannot merge {:?}", other);
+        self.health = std::cmp::max(self.health, other.health) + 1;
+        self.attack = std::cmp::max(self.attack, other.attack) + 1;
+        self.temp_health = std::cmp::max(self.temp_health, other.temp_health);
+        self.temp_attack = std::cmp::max(self.temp_attack, other.temp_attack);
+        self.exp = std::cmp::min(self.exp + other.exp + 1, MAX_EXP);
     }
     pub fn level(&self) -> usize {
         match self.exp {
             0..=2 => 1,
-            3..=5 => 1,
+            3..=5 => 2,
             6 => 3,
             exp => panic!("Invalid exp: {}", exp),
         }
     }
+    /// Checks that this friend could appear on a team in the real game: it
+    /// can be bought from the shop, and its stats and experience are in range
+    pub fn check_legal(&self) -> Result<()> {
+        if self.species.tier().is_none() {
+            return Err(Error::Friend(format!(
+                "{:?} is a token",
+                self.species
+            )));
+        }
+        for (name, v) in [
+            ("attack", self.total_attack()),
+            ("health", self.total_health()),
+        ] {
+            if !(1..=MAX_STAT).contains(&v) {
+                return Err(Error::Friend(format!(
+                    "{:?} has {} {} (expected 1-{})",
+                    self.species, name, v, MAX_STAT
+                )));
+            }
+        }
+        if self.exp > MAX_EXP {
+            return Err(Error::Friend(format!(
+                "{:?} has exp {} (expected 0-{})",
+                self.species, self.exp, MAX_EXP
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a [`Friend`], defaulting to its species' stats and modifier
+#[derive(Clone, Debug, Default)]
+pub struct FriendBuilder {
+    species: Option<Species>,
+    attack: Option<usize>,
+    health: Option<usize>,
+    perk: Option<Modifier>,
+    exp: usize,
+}
+
+impl FriendBuilder {
+    pub fn species(mut self, species: Species) -> Self {
+        self.species = Some(species);
+        self
+    }
+    pub fn attack(mut self, attack: usize) -> Self {
+        self.attack = Some(attack);
+        self
+    }
+    pub fn health(mut self, health: usize) -> Self {
+        self.health = Some(health);
+        self
+    }
+    pub fn perk(mut self, perk: Modifier) -> Self {
+        self.perk = Some(perk);
+        self
+    }
+    pub fn exp(mut self, exp: usize) -> Self {
+        self.exp = exp;
+        self
+    }
+    /// Builds the friend, checking that it's [legal](Friend::check_legal)
+    pub fn build(self) -> Result<Friend> {
+        let species = self
+            .species
+            .ok_or_else(|| Error::Friend("missing species".to_owned()))?;
+        let mut f = Friend::new(species);
+        f.attack = self.attack.unwrap_or(f.attack);
+        f.health = self.health.unwrap_or(f.health);
+        f.modifier = self.perk.or(f.modifier);
+        f.exp = self.exp;
+        f.check_legal()?;
+        Ok(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn trigger_limits() {
+        // Causes which the Ox has no ability for don't use up its charge
+        let mut ox = Friend::new(Species::Ox);
+        for cause in [Cause::Hurt, Cause::BattleStart, Cause::EndTurn] {
+            assert!(ox.try_trigger(cause));
+        }
+        assert!(ox.try_trigger(Cause::AheadFaints));
+        assert!(!ox.try_trigger(Cause::AheadFaints));
+        assert_eq!((ox.triggers.battle, ox.triggers.turn), (1, 0));
+
+        // Counts are left out of comparisons and hashes
+        let fresh = Friend::new(Species::Ox);
+        assert_eq!(ox, fresh);
+        let state = std::hash::RandomState::new();
+        assert_eq!(state.hash_one(ox), state.hash_one(fresh));
+
+        // Unlimited species keep their ability state
+        let mut whale = Friend::new(Species::Whale);
+        whale.state = AbilityState::Swallowed(Species::Ant);
+        for _ in 0..3 {
+            assert!(whale.try_trigger(Cause::Faint));
+        }
+        assert_eq!(whale.state, AbilityState::Swallowed(Species::Ant));
+    }
 }
@@ -0,0 +1,215 @@
+//! Export of the shop exploration as a Graphviz DOT graph.
+//!
+//! This runs the same breadth-first search over shop states as team
+//! generation (including its pruning of shops which were already reached
+//! with more gold), but records every state and action along the way.  The
+//! graph grows very quickly, so it's only practical for a few steps.
+
+use hashbrown::HashMap;
+
+use crate::{
+    config::Challenge,
+    dice::DeterministicDice,
+    shop::{Shop, ShopAction, Step},
+};
+
+/// A shop state in the exploration graph
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub shop: Shop,
+    /// The turn ended in this state
+    pub done: bool,
+    /// This state was reached again later with no more gold than before, so
+    /// it wasn't explored further
+    pub pruned: bool,
+}
+
+/// An action taken from one shop state to another
+#[derive(Clone, Debug)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub action: ShopAction,
+    /// Dice key for the step, for use with [`DeterministicDice::from_key`]
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ShopGraph {
+    pub nodes: Vec<Node>,
+    /// Indices of the shops rolled at the start of the turn
+    pub roots: Vec<usize>,
+    pub edges: Vec<Edge>,
+    /// Exploration stopped early because the node limit was reached
+    pub truncated: bool,
+}
+
+impl ShopGraph {
+    /// Returns every shop which may be rolled at the start of the given turn,
+    /// under the given rule changes
+    pub fn starting_shops(turn: usize, challenge: Challenge) -> Vec<Shop> {
+        let mut out = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            out.push(Shop::with_challenge(turn, challenge, &mut dice));
+        }
+        out
+    }
+
+    /// Explores up to `depth` steps from the given starting shops, stopping
+    /// once there are `max_nodes` states
+    pub fn explore(roots: &[Shop], depth: usize, max_nodes: usize) -> Self {
+        let mut out = Self::default();
+        // Finished shops are distinct from active ones, even if the turn ended
+        // without changing anything
+        let mut index: HashMap<(Shop, bool), usize> = HashMap::new();
+        for shop in roots {
+            let n = out.node(&mut index, *shop, false);
+            if !out.roots.contains(&n) {
+                out.roots.push(n);
+            }
+        }
+
+        // Same pruning as team generation: a shop which has been seen with
+        // at least as much gold isn't explored again
+        let mut seen_gold: HashMap<Shop, usize> = HashMap::new();
+        let mut active = out.roots.clone();
+        for _ in 0..depth {
+            let mut next = vec![];
+            for from in active {
+                let shop = out.nodes[from].shop;
+                let mut without_gold = shop;
+                without_gold.gold = 0;
+                if let Some(prev) = seen_gold.get(&without_gold) {
+                    if *prev >= shop.gold {
+                        out.nodes[from].pruned = true;
+                        continue;
+                    }
+                }
+                seen_gold.insert(without_gold, shop.gold);
+
+                let mut dice = DeterministicDice::new();
+                while dice.next() {
+                    if out.nodes.len() >= max_nodes {
+                        out.truncated = true;
+                        return out;
+                    }
+                    let mut shop = shop;
+                    // Impossible actions don't lead anywhere, so they
+                    // aren't drawn
+                    let done = match shop.step(&mut dice) {
+                        Step::Acted => false,
+                        Step::Ended => true,
+                        Step::Impossible | Step::Pruned => continue,
+                    };
+                    shop.team.sort();
+                    let key = dice.key();
+                    let action = key
+                        .chars()
+                        .next()
+                        .and_then(|c| c.to_digit(36))
+                        .and_then(|i| ShopAction::from_index(i as usize))
+                        .expect("Missing shop action");
+                    let is_new = !index.contains_key(&(shop, done));
+                    let to = out.node(&mut index, shop, done);
+                    out.edges.push(Edge {
+                        from,
+                        to,
+                        action,
+                        key,
+                    });
+                    if is_new && !done {
+                        next.push(to);
+                    }
+                }
+            }
+            active = next;
+        }
+        out
+    }
+
+    /// Looks up or inserts a node, returning its index
+    fn node(
+        &mut self,
+        index: &mut HashMap<(Shop, bool), usize>,
+        shop: Shop,
+        done: bool,
+    ) -> usize {
+        *index.entry((shop, done)).or_insert_with(|| {
+            self.nodes.push(Node {
+                shop,
+                done,
+                pruned: false,
+            });
+            self.nodes.len() - 1
+        })
+    }
+
+    /// Renders the graph in Graphviz DOT format.  Starting shops are boxes,
+    /// finished turns have a double border, and pruned states are gray.
+    pub fn to_dot(&self) -> String {
+        let mut out =
+            "digraph shops {\n    node [fontname=monospace];\n".to_owned();
+        for (i, n) in self.nodes.iter().enumerate() {
+            let offers = n.shop.offers().map(|f| format!("{:?}", f.species));
+            let mut attrs = vec![format!(
+                "label=\"{}\\ngold {}, shop {}\"",
+                n.shop.team.code().replace(',', "\\n"),
+                n.shop.gold,
+                offers.collect::<Vec<_>>().join("/")
+            )];
+            if self.roots.contains(&i) {
+                attrs.push("shape=box".to_owned());
+            }
+            if n.done {
+                attrs.push("peripheries=2".to_owned());
+            }
+            if n.pruned {
+                attrs.push("color=gray fontcolor=gray".to_owned());
+            }
+            out += &format!("    n{} [{}];\n", i, attrs.join(" "));
+        }
+        for e in &self.edges {
+            out += &format!(
+                "    n{} -> n{} [label=\"{:?} ({})\"];\n",
+                e.from, e.to, e.action, e.key
+            );
+        }
+        out += "}\n";
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_replay() {
+        // Every edge can be reproduced from its dice key, and the turn ends
+        // along at least one of them
+        let roots = ShopGraph::starting_shops(1, Challenge::default());
+        assert!(roots.len() > 1);
+        let graph = ShopGraph::explore(&roots[..1], 1, usize::MAX);
+        assert_eq!(graph.roots, [0]);
+        assert!(!graph.truncated);
+        for e in &graph.edges {
+            let mut next = graph.nodes[e.from].shop;
+            let mut dice = DeterministicDice::from_key(&e.key).unwrap();
+            let done = next.step(&mut dice) == Step::Ended;
+            next.team.sort();
+            assert_eq!(next, graph.nodes[e.to].shop);
+            assert_eq!(done, graph.nodes[e.to].done);
+        }
+        assert!(graph.edges.iter().any(|e| e.action == ShopAction::EndTurn));
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph shops {"));
+        assert_eq!(dot.matches("->").count(), graph.edges.len());
+        assert_eq!(dot.matches("shape=box").count(), 1);
+
+        let small = ShopGraph::explore(&roots[..1], 2, 4);
+        assert!(small.truncated);
+        assert_eq!(small.nodes.len(), 4);
+    }
+}
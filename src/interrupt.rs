@@ -0,0 +1,32 @@
+//! Graceful interruption of long runs.
+//!
+//! The first Ctrl-C only sets a flag, which long-running work checks at safe
+//! points (e.g. between shops or rows of the score matrix), so that it can
+//! save a checkpoint before exiting.  Work on several threads sees the same
+//! flag, so every thread stops at its next safe point.  A second Ctrl-C
+//! exits immediately, for when no safe point comes soon enough.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Result;
+
+/// Exit code after an interruption, as set by shells for `SIGINT`
+pub const EXIT_CODE: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the Ctrl-C handler
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_CODE);
+        }
+        eprintln!("Interrupted; stopping at the next checkpoint");
+    })?;
+    Ok(())
+}
+
+/// Checks whether long-running work should stop
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,406 @@
+//! Ladder simulation, which plays many complete games at once to measure
+//! what enumerate-then-score can't: how long runs survive, and how many
+//! trophies each opening goes on to win.
+//!
+//! Every run plays its shop turn under a [`Policy`], then battles another
+//! run on the same turn, picked at random (as in the real matchmaking).  If
+//! an odd number of runs are left, the odd one out battles a copy of another
+//! run's team, which doesn't affect that run.  Winning a battle earns a
+//! trophy, and losing costs lives according to the configured
+//! [damage model](crate::config::DamageModel).  A run ends when it reaches
+//! the target number of trophies, runs out of lives, or hits the turn limit.
+//!
+//! The teams fielded on each turn are recorded as [meta snapshots], which
+//! can also be injected: on a turn with an injected snapshot, every run
+//! battles a team drawn from it, rather than another run.
+//!
+//! Each run has its own dice, and each battle is seeded by the matchmaker,
+//! so results don't depend on how many threads play them.
+//!
+//! [meta snapshots]: crate::snapshot
+
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle::{Battle, Outcome, Winner},
+    config::GameConfig,
+    error::Error,
+    opponent::Opponents,
+    params::TEAM_SIZE,
+    shop::{Action, Shop},
+    snapshot::Snapshot,
+    team::Team,
+};
+
+/// How a run plays its shop turns
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Deserialize,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+)]
+pub enum Policy {
+    /// Makes random purchases until it can't afford any more
+    Random,
+    /// Makes whichever purchase adds the most total stats, until none do
+    Greedy,
+}
+
+impl Policy {
+    pub const ALL: [Self; 2] = [Self::Random, Self::Greedy];
+
+    /// Plays one shop turn, up to and including ending it
+    fn play(self, shop: &mut Shop, rng: &mut StdRng) {
+        loop {
+            let before = total_stats(&shop.team);
+            let options = purchases(shop, rng);
+            let pick = match self {
+                _ if options.is_empty() => None,
+                Self::Random => Some(options[rng.gen_range(0..options.len())]),
+                // Ties go to the earliest purchase, so that play doesn't
+                // depend on iteration order
+                Self::Greedy => options
+                    .into_iter()
+                    .filter(|(_, stats)| *stats > before)
+                    .rev()
+                    .max_by_key(|(_, stats)| *stats),
+            };
+            let Some((action, _)) = pick else {
+                break;
+            };
+            shop.apply(action, rng, &mut ()).unwrap();
+        }
+        shop.apply(Action::EndTurn, rng, &mut ()).unwrap();
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Self::Random),
+            "greedy" => Ok(Self::Greedy),
+            _ => Err(Error::Policy(s.to_owned())),
+        }
+    }
+}
+
+/// Returns the team's total attack and health, including temporary buffs
+fn total_stats(team: &Team) -> usize {
+    team.iter()
+        .flatten()
+        .map(|f| f.total_attack() + f.total_health())
+        .sum()
+}
+
+/// Returns every purchase which the shop can make, along with the team's
+/// total stats afterwards.  Purchases are tried on a copy of the shop and
+/// dice, so the real dice are only rolled by the chosen one.
+fn purchases(shop: &Shop, rng: &StdRng) -> Vec<(Action, usize)> {
+    let buy_friend = (0..shop.offers().count()).flat_map(|shop| {
+        (0..TEAM_SIZE).map(move |team| Action::BuyFriend { shop, team })
+    });
+    let buy_food = (0..shop.foods().count()).flat_map(|shop| {
+        (0..TEAM_SIZE).map(move |team| Action::BuyFood { shop, team })
+    });
+    buy_friend
+        .chain(buy_food)
+        .filter_map(|a| {
+            let mut next = *shop;
+            next.apply(a, &mut rng.clone(), &mut ()).ok()?;
+            Some((a, total_stats(&next.team)))
+        })
+        .collect()
+}
+
+/// Settings for [`simulate`]
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Number of runs to play, split evenly between policies
+    pub runs: usize,
+    pub policies: Vec<Policy>,
+    /// Lives at the start of a run
+    pub lives: usize,
+    /// Trophies needed to win a run
+    pub trophies: usize,
+    /// Runs still going after this many turns end where they are
+    pub max_turns: usize,
+    pub seed: u64,
+    /// Injected opponents, by turn, which replace the other runs
+    pub meta: BTreeMap<usize, Opponents>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            runs: 1000,
+            policies: Policy::ALL.to_vec(),
+            lives: 5,
+            trophies: 10,
+            max_turns: 30,
+            seed: 0,
+            meta: BTreeMap::new(),
+        }
+    }
+}
+
+/// A complete game played by one run
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Run {
+    pub policy: Policy,
+    /// Team fielded on turn 1
+    pub opening: Team,
+    pub trophies: usize,
+    pub lives: usize,
+    /// Number of turns played
+    pub turns: usize,
+    /// Rank among every run by trophies, then turns, then lives, starting
+    /// from 1 (tied runs share a rank)
+    pub placement: usize,
+}
+
+/// The result of [`simulate`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Ladder {
+    /// Every run, in the order they were started (cycling through the
+    /// policies)
+    pub runs: Vec<Run>,
+    /// Teams fielded on each turn, weighted by the number of runs which
+    /// fielded them
+    pub snapshots: Vec<Snapshot>,
+}
+
+/// A run in progress
+struct Player {
+    run: Run,
+    shop: Shop,
+    rng: StdRng,
+}
+
+impl Player {
+    fn is_done(&self, settings: &Settings) -> bool {
+        self.run.lives == 0 || self.run.trophies >= settings.trophies
+    }
+}
+
+/// A battle between two runs, or between a run and a team which isn't
+/// another run's (with `b` unset)
+struct Matchup {
+    a: usize,
+    b: Option<usize>,
+    teams: Battle,
+    seed: u64,
+    outcome: Option<Outcome>,
+}
+
+/// Calls `f` on every item, split across the available threads
+fn for_each_parallel<T: Send, F: Fn(&mut T) + Sync>(items: &mut [T], f: F) {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = items.len().div_ceil(threads).max(1);
+    std::thread::scope(|s| {
+        for c in items.chunks_mut(chunk) {
+            let f = &f;
+            s.spawn(move || c.iter_mut().for_each(f));
+        }
+    });
+}
+
+/// Plays every run to completion
+pub fn simulate(cfg: &GameConfig, settings: &Settings) -> Ladder {
+    assert!(!settings.policies.is_empty(), "No policies to simulate");
+    let mut matchmaker = StdRng::seed_from_u64(settings.seed);
+    let mut players: Vec<Player> = (0..settings.runs)
+        .map(|i| {
+            let mut rng = StdRng::seed_from_u64(matchmaker.gen());
+            let policy = settings.policies[i % settings.policies.len()];
+            Player {
+                shop: Shop::with_challenge(1, cfg.challenge, &mut rng),
+                run: Run {
+                    policy,
+                    opening: Team::new(),
+                    trophies: 0,
+                    lives: settings.lives,
+                    turns: 0,
+                    placement: 0,
+                },
+                rng,
+            }
+        })
+        .collect();
+
+    let mut snapshots = vec![];
+    for turn in 1..=settings.max_turns {
+        let mut alive: Vec<usize> = (0..players.len())
+            .filter(|i| !players[*i].is_done(settings))
+            .collect();
+        if alive.is_empty() {
+            break;
+        }
+        let mut playing: Vec<&mut Player> = players
+            .iter_mut()
+            .filter(|p| !p.is_done(settings))
+            .collect();
+        for_each_parallel(&mut playing, |p| {
+            if turn > 1 {
+                p.shop.start_turn(&mut p.rng, &mut ());
+            }
+            p.run.policy.play(&mut p.shop, &mut p.rng);
+            if turn == 1 {
+                p.run.opening = p.shop.team.for_battle();
+            }
+            p.run.turns = turn;
+        });
+
+        let team = |i: usize| players[i].shop.team.for_battle();
+        snapshots
+            .push(Snapshot::new(turn, alive.iter().map(|i| (team(*i), 1.0))));
+
+        let mut matchups: Vec<Matchup> =
+            if let Some(meta) = settings.meta.get(&turn) {
+                alive
+                    .iter()
+                    .map(|a| Matchup {
+                        a: *a,
+                        b: None,
+                        teams: Battle(team(*a), meta.sample(&mut matchmaker)),
+                        seed: matchmaker.gen(),
+                        outcome: None,
+                    })
+                    .collect()
+            } else {
+                alive.shuffle(&mut matchmaker);
+                alive
+                    .chunks(2)
+                    .filter_map(|pair| {
+                        let (a, b) = match *pair {
+                            [a, b] => (a, Some(b)),
+                            [a] if alive.len() > 1 => (a, None),
+                            _ => return None,
+                        };
+                        let other = b.unwrap_or_else(|| alive[0]);
+                        Some(Matchup {
+                            a,
+                            b,
+                            teams: Battle(team(a), team(other)),
+                            seed: matchmaker.gen(),
+                            outcome: None,
+                        })
+                    })
+                    .collect()
+            };
+        for_each_parallel(&mut matchups, |m| {
+            let mut rng = StdRng::seed_from_u64(m.seed);
+            m.outcome = Some(m.teams.run(cfg, &mut rng));
+        });
+
+        for m in &matchups {
+            let outcome = m.outcome.unwrap();
+            let damage = outcome.damage(cfg.damage, turn);
+            let (winner, loser) = match outcome.winner {
+                Winner::TeamA => (Some(m.a), m.b),
+                Winner::TeamB => (m.b, Some(m.a)),
+                Winner::Tied => (None, None),
+            };
+            if let Some(w) = winner {
+                players[w].run.trophies += 1;
+            }
+            if let Some(l) = loser {
+                let lives = &mut players[l].run.lives;
+                *lives = lives.saturating_sub(damage);
+            }
+        }
+    }
+
+    let mut runs: Vec<Run> = players.into_iter().map(|p| p.run).collect();
+    let key = |r: &Run| (r.trophies, r.turns, r.lives);
+    let mut order: Vec<usize> = (0..runs.len()).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(key(&runs[*i])));
+    for (rank, i) in order.iter().enumerate() {
+        runs[*i].placement = match rank {
+            0 => 1,
+            _ if key(&runs[*i]) == key(&runs[order[rank - 1]]) => {
+                runs[order[rank - 1]].placement
+            }
+            _ => rank + 1,
+        };
+    }
+    Ladder { runs, snapshots }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_and_placements() {
+        // Every run ends for a reason, placements follow the final standings,
+        // and results only depend on the seed
+        let cfg = GameConfig::default();
+        for seed in 0..3 {
+            let settings = Settings {
+                runs: 5,
+                lives: 2,
+                trophies: 2,
+                max_turns: 4,
+                seed,
+                ..Settings::default()
+            };
+            let ladder = simulate(&cfg, &settings);
+            let out = &ladder.runs;
+            assert_eq!(out.len(), settings.runs);
+            for r in out {
+                assert!(
+                    r.lives == 0
+                        || r.trophies >= settings.trophies
+                        || r.turns == settings.max_turns
+                );
+                assert!(r.turns >= 1 && r.turns <= settings.max_turns);
+                assert!(!r.opening.is_empty());
+            }
+            let key = |r: &Run| (r.trophies, r.turns, r.lives);
+            for a in out {
+                for b in out {
+                    match key(a).cmp(&key(b)) {
+                        std::cmp::Ordering::Greater => {
+                            assert!(a.placement < b.placement)
+                        }
+                        std::cmp::Ordering::Equal => {
+                            assert_eq!(a.placement, b.placement)
+                        }
+                        std::cmp::Ordering::Less => (),
+                    }
+                }
+            }
+            assert_eq!(ladder.snapshots[0].turn, 1);
+            assert_eq!(ladder, simulate(&cfg, &settings));
+        }
+    }
+
+    #[test]
+    fn injected_meta() {
+        // Every run beats an empty team on turn 1, so a single trophy ends
+        // them all there
+        let cfg = GameConfig::default();
+        let settings = Settings {
+            runs: 4,
+            trophies: 1,
+            meta: [(1, Opponents::fixed(1, Team::new()))].into(),
+            ..Settings::default()
+        };
+        let ladder = simulate(&cfg, &settings);
+        for r in &ladder.runs {
+            assert_eq!((r.trophies, r.turns, r.lives), (1, 1, settings.lives));
+            assert_eq!(r.placement, 1);
+        }
+        assert_eq!(ladder.snapshots.len(), 1);
+    }
+}
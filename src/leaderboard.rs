@@ -0,0 +1,177 @@
+//! Streaming leaderboard, which estimates the strongest teams while scoring
+//! is still running.
+//!
+//! Scoring fills in the score matrix a row (one team against every team) at
+//! a time, so a team's win rate is known exactly once its row is done.  Each
+//! finished row also shows how every other team fared against that one
+//! opponent, so if rows are scored in random order, a team's mean over the
+//! finished rows estimates its win rate.  The estimate's confidence interval
+//! shrinks to nothing as the rows run out, and teams whose interval lies
+//! entirely below the top teams' are out of contention.
+
+use serde::Serialize;
+
+/// Number of standard errors on either side of an estimate, for a 95%
+/// confidence interval
+const Z: f64 = 1.96;
+
+/// A team's place on the [`Leaderboard`]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Standing {
+    /// Index of the team among those being scored
+    pub team: usize,
+    /// Estimated win rate
+    pub mean: f64,
+    /// Bounds of the confidence interval around the estimate
+    pub low: f64,
+    pub high: f64,
+    /// The team's own row is finished, so its win rate is exact
+    pub exact: bool,
+}
+
+/// Running estimates of every team's win rate, from the rows scored so far
+#[derive(Clone, Debug)]
+pub struct Leaderboard {
+    /// Number of teams to rank
+    k: usize,
+    /// Number of finished rows
+    rows: usize,
+    /// Sum of each team's win rate against the opponents whose rows are
+    /// finished, along with the sum of squares
+    sum: Vec<f64>,
+    sum_sq: Vec<f64>,
+    /// Win rates of the teams whose rows are finished
+    exact: Vec<Option<f64>>,
+}
+
+impl Leaderboard {
+    /// Builds an empty leaderboard of the top `k` among `teams` teams
+    pub fn new(teams: usize, k: usize) -> Self {
+        Self {
+            k,
+            rows: 0,
+            sum: vec![0.0; teams],
+            sum_sq: vec![0.0; teams],
+            exact: vec![None; teams],
+        }
+    }
+
+    /// Records a finished row, where `wins[j]` and `loses[j]` are team
+    /// `i`'s chances of beating and losing to team `j`
+    pub fn add_row(&mut self, i: usize, wins: &[f32], loses: &[f32]) {
+        let n = wins.len().max(1) as f64;
+        self.exact[i] = Some(wins.iter().map(|w| *w as f64).sum::<f64>() / n);
+        for (j, l) in loses.iter().enumerate() {
+            let l = *l as f64;
+            self.sum[j] += l;
+            self.sum_sq[j] += l * l;
+        }
+        self.rows += 1;
+    }
+
+    /// Returns the number of finished rows
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the number of teams being scored
+    pub fn teams(&self) -> usize {
+        self.exact.len()
+    }
+
+    /// Returns the estimate for a single team
+    pub fn standing(&self, team: usize) -> Standing {
+        if let Some(mean) = self.exact[team] {
+            return Standing {
+                team,
+                mean,
+                low: mean,
+                high: mean,
+                exact: true,
+            };
+        }
+        let (m, n) = (self.rows as f64, self.teams() as f64);
+        if self.rows == 0 {
+            return Standing {
+                team,
+                mean: 0.0,
+                low: 0.0,
+                high: 1.0,
+                exact: false,
+            };
+        }
+        let mean = self.sum[team] / m;
+        let var = (self.sum_sq[team] / m - mean * mean).max(0.0);
+        // Opponents are drawn without replacement, so the error vanishes
+        // once every row is finished
+        let fpc = if n > 1.0 { (n - m) / (n - 1.0) } else { 0.0 };
+        let err = Z * (var / m * fpc).sqrt();
+        Standing {
+            team,
+            mean,
+            low: (mean - err).max(0.0),
+            high: (mean + err).min(1.0),
+            exact: false,
+        }
+    }
+
+    /// Returns the teams with the best estimates, best first
+    pub fn top(&self) -> Vec<Standing> {
+        let mut all: Vec<Standing> =
+            (0..self.teams()).map(|j| self.standing(j)).collect();
+        all.sort_by(|a, b| b.mean.total_cmp(&a.mean));
+        all.truncate(self.k);
+        all
+    }
+
+    /// Returns the number of teams which may still be among the top teams,
+    /// i.e. whose interval reaches the `k`-th best lower bound
+    pub fn contenders(&self) -> usize {
+        let all: Vec<Standing> =
+            (0..self.teams()).map(|j| self.standing(j)).collect();
+        let mut lows: Vec<f64> = all.iter().map(|s| s.low).collect();
+        lows.sort_by(|a, b| b.total_cmp(a));
+        let Some(bar) = lows.get(self.k.max(1) - 1) else {
+            return all.len();
+        };
+        all.iter().filter(|s| s.high >= *bar).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dominant_row() {
+        // Team 0 beats everyone, and team 1 beats team 2.  Before any rows
+        // are finished, every team is in contention; once team 0's row is,
+        // nobody else can catch it.
+        let rate = [[0.5, 1.0, 1.0], [0.0, 0.5, 1.0], [0.0, 0.0, 0.5f32]];
+        let loses =
+            |i: usize| -> Vec<f32> { (0..3).map(|j| rate[j][i]).collect() };
+        let mut board = Leaderboard::new(3, 1);
+        assert_eq!(board.contenders(), 3);
+        let s = board.standing(1);
+        assert_eq!((s.low, s.high, s.exact), (0.0, 1.0, false));
+
+        board.add_row(0, &rate[0], &loses(0));
+        assert_eq!(board.rows(), 1);
+        let s = board.standing(0);
+        assert!(s.exact && (s.mean - 5.0 / 6.0).abs() < 1e-6);
+        assert_eq!(board.standing(2).high, 0.0);
+        assert_eq!(board.contenders(), 1);
+        assert_eq!(board.top()[0].team, 0);
+
+        // Once every row is done, the leaderboard is exact
+        for i in [2, 1] {
+            board.add_row(i, &rate[i], &loses(i));
+        }
+        for (i, r) in rate.iter().enumerate() {
+            let s = board.standing(i);
+            let mean = r.iter().sum::<f32>() as f64 / 3.0;
+            assert!(s.exact && (s.mean - mean).abs() < 1e-6);
+        }
+        assert_eq!(board.top().len(), 1);
+    }
+}
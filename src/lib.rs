@@ -0,0 +1,49 @@
+pub mod ability;
+pub mod animate;
+pub mod battle;
+pub mod battlelog;
+pub mod config;
+pub mod corpus;
+pub mod crossval;
+pub mod custom;
+pub mod database;
+pub mod dice;
+pub mod draw;
+pub mod error;
+pub mod estimate;
+pub mod event;
+pub mod explore;
+pub mod filter;
+pub mod fixture;
+pub mod food;
+pub mod friend;
+pub mod graph;
+pub mod interrupt;
+pub mod ladder;
+pub mod leaderboard;
+pub mod logfile;
+pub mod matrix;
+pub mod mirror;
+pub mod modifier;
+pub mod opponent;
+pub mod pack;
+pub mod pairing;
+pub mod params;
+#[cfg(all(test, feature = "proptest"))]
+mod proptests;
+pub mod render;
+pub mod replicator;
+pub mod reroll;
+pub mod route;
+pub mod rules;
+pub mod script;
+pub mod shop;
+pub mod similarity;
+pub mod snapshot;
+pub mod species;
+pub mod sweep;
+pub mod target;
+pub mod team;
+pub mod timeline;
+pub mod token;
+pub mod util;
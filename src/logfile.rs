@@ -0,0 +1,136 @@
+//! A size-limited log file, for keeping the verbose log out of the terminal.
+//!
+//! Once the file reaches its size limit, it's renamed to `<path>.1` (pushing
+//! older files back to `<path>.2` and so on) and a fresh file is started.
+//! Only the most recent few files are kept.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::error::Result;
+
+/// Default size at which the log file is rotated
+pub const DEFAULT_MAX_BYTES: u64 = 16 << 20;
+/// Default number of rotated files kept alongside the active one
+pub const DEFAULT_KEEP: usize = 3;
+
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Opens a log file, appending to it if it already exists
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        max_bytes: u64,
+        keep: usize,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            written,
+        })
+    }
+
+    /// Returns the path of the `i`'th rotated file (0 is the active file)
+    pub fn rotated(&self, i: usize) -> PathBuf {
+        if i == 0 {
+            self.path.clone()
+        } else {
+            let mut p = self.path.clone().into_os_string();
+            p.push(format!(".{}", i));
+            p.into()
+        }
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for i in (0..self.keep).rev() {
+                let from = self.rotated(i);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(i + 1))?;
+                }
+            }
+        }
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    /// Writes are never split across files, so a single oversized write may
+    /// push a file past its limit
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation() {
+        // Each file holds two lines, and the files which are kept hold the
+        // last lines written, newest first
+        let dir = std::env::temp_dir()
+            .join(format!("sas-logfile-{}", std::process::id()));
+        for keep in 0..3 {
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut f = RotatingFile::open(dir.join("log"), 16, keep).unwrap();
+            for i in 0..10 {
+                writeln!(f, "line {}", i).unwrap();
+            }
+            f.flush().unwrap();
+            for i in 0..=keep {
+                let data = std::fs::read_to_string(f.rotated(i)).unwrap();
+                let n = 8 - 2 * i;
+                assert_eq!(data, format!("line {}\nline {}\n", n, n + 1));
+            }
+            assert!(!f.rotated(keep + 1).exists());
+
+            // Reopening appends to the active file, and an oversized write
+            // gets a file of its own
+            drop(f);
+            let mut f = RotatingFile::open(dir.join("log"), 16, keep).unwrap();
+            f.write_all(&[b'x'; 20]).unwrap();
+            f.write_all(b"y").unwrap();
+            f.flush().unwrap();
+            assert_eq!(std::fs::read(f.rotated(0)).unwrap(), b"y");
+            if keep > 0 {
+                assert_eq!(std::fs::read(f.rotated(1)).unwrap(), [b'x'; 20]);
+            }
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
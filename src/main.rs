@@ -1,92 +1,491 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
 use hashbrown::{HashMap, HashSet};
-use log::{debug, info, trace, LevelFilter};
+use log::{debug, info, trace, warn, LevelFilter};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use serde::{Deserialize, Serialize};
 
-mod battle;
-mod dice;
-mod food;
-mod friend;
-mod modifier;
-mod params;
-mod shop;
-mod species;
-mod team;
-mod util;
-
-use battle::{Battle, Winner};
-use dice::DeterministicDice;
-use params::TEAM_SIZE;
-use shop::Shop;
-use team::Team;
-use util::{read_compressed, write_compressed};
+use super_auto_sim::{
+    animate::Frames,
+    battle::{Battle, Outcome, Winner},
+    battlelog,
+    config::GameConfig,
+    corpus::{Corpus, Sampling},
+    crossval, custom,
+    database::{self, Database},
+    dice::DeterministicDice,
+    draw::Image,
+    error::Error,
+    estimate::{self, Estimate, Range},
+    explore::{self, Branch, Decision},
+    filter::{Focus, Ranking, TeamFilter},
+    fixture,
+    friend::Friend,
+    graph::ShopGraph,
+    interrupt,
+    ladder::{self, Policy, Run},
+    leaderboard::Leaderboard,
+    logfile::{self, RotatingFile},
+    matrix::{Flat, FlatMatrix, FlatWriter, RowWriter},
+    mirror::{self, Chances},
+    modifier::Modifier,
+    opponent::{Expected, Opponents},
+    pack::{self, Pack},
+    pairing::SpeciesMatrix,
+    params::TEAM_SIZE,
+    render::{self, Icon},
+    replicator::{self, Evolution},
+    reroll::{self, RerollValue},
+    route,
+    rules::{self, RuleSet},
+    script::{self, Command},
+    shop::{Economy, Shop, Step},
+    similarity::{Distance, Index},
+    snapshot::{self, Snapshot},
+    species::Species,
+    sweep::Sweep,
+    team::{Constraints, Team},
+    timeline::Timeline,
+    util::{checksum, open_compressed, read_cache, write_compressed},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 const TEAMS_FILE: &str = "teams.binz";
 const SCORES_FILE: &str = "scores.binz";
+/// Uncompressed copy of the scores, which is memory-mapped with `--mmap`,
+/// and the key and row map which go with it
+const SCORES_FLAT_FILE: &str = "scores.flat";
+const SCORES_INDEX_FILE: &str = "scores-index.binz";
+const ECONOMY_FILE: &str = "economy.binz";
+const METADATA_FILE: &str = "metadata.binz";
+const PROVENANCE_FILE: &str = "provenance.binz";
+/// Checksums of the team and score caches, by file name
+const CHECKSUMS_FILE: &str = "checksums.binz";
+/// Reference teams for quick ratings, saved by `gauntlet save`
+const GAUNTLET_FILE: &str = "gauntlet.binz";
+/// Checkpoints saved when generation or scoring is interrupted, which are
+/// resumed from by the next run with the same settings
+const GENERATE_CHECKPOINT_FILE: &str = "generate-checkpoint.binz";
+const SCORES_CHECKPOINT_FILE: &str = "scores-checkpoint.binz";
+
+/// Details about how the team cache was generated, for `stats`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Metadata {
+    version: String,
+    config: GameConfig,
+    /// Name of the pack which was sold in the shop
+    pack: String,
+    /// Name of the rule set for base stats and ability magnitudes
+    rules: String,
+    /// Limits on which shops were explored
+    focus: Focus,
+    /// From [`custom::checksum`], since teams refer to custom species by
+    /// their index in the registry
+    custom: u64,
+    /// Unique shops explored, ignoring gold
+    shops: usize,
+    /// Shops in which the turn ended
+    finished_shops: usize,
+    /// Unique battle-ready teams, including dumb teams
+    seen_teams: usize,
+    /// Non-dumb teams, which are kept in the cache
+    teams: usize,
+    /// Wall-clock time spent generating teams, in seconds
+    seconds: f32,
+    /// What the exploration did, for evaluating pruning strategies
+    pruning: Pruning,
+}
+
+/// Counters from exploring every shop.  A generation is one breadth-first
+/// step, i.e. every shop which is one action away from the last generation.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Pruning {
+    /// Shops which were stepped along every dice path
+    expanded: usize,
+    /// Shops skipped because the same shop with the same gold was already
+    /// expanded
+    duplicate_shops: usize,
+    /// Shops skipped because the same shop with more gold was already
+    /// expanded
+    gold_dominated: usize,
+    /// Shops which weren't queued, because they were already in the next
+    /// generation
+    duplicate_successors: usize,
+    /// Shops which weren't queued (and whose teams weren't kept), because
+    /// [`Focus::explores`] rejected them
+    unfocused: usize,
+    /// Dice paths dropped because the action they rolled wasn't possible
+    #[serde(default)]
+    impossible: usize,
+    /// Dice paths dropped by the pruning rules in [`GameConfig::prune`]
+    #[serde(default)]
+    pruned: usize,
+    /// Shops which were only allowed to end their turn, or weren't queued,
+    /// because of the limits in [`GameConfig::limits`]
+    #[serde(default)]
+    limited: usize,
+    /// Teams dropped by [`Team::is_dumb_with`]
+    dumb_teams: usize,
+    /// Most shops in a single generation
+    peak_frontier: usize,
+    generations: Vec<Generation>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Generation {
+    /// Shops in this generation, before pruning
+    frontier: usize,
+    expanded: usize,
+    /// Wall-clock time spent on this generation, in seconds
+    seconds: f32,
+}
+
+/// How a generated team was first reached, stored alongside it in the cache
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+struct Provenance {
+    /// Number of shop actions taken before the team first appeared
+    depth: usize,
+    /// Most gold left unspent by any shop which built the team
+    gold: usize,
+    /// Number of distinct shops which built the team.  Shops are
+    /// deduplicated as they're explored, so this counts build paths which
+    /// end in different shop states, not every sequence of actions.
+    paths: usize,
+}
+
+impl Provenance {
+    /// Records another shop which built the team
+    fn add(&mut self, gold: usize) {
+        self.gold = self.gold.max(gold);
+        self.paths += 1;
+    }
+}
+
+/// Prints the average of the given provenances, with a label
+fn print_provenance(label: &str, prov: &[Provenance]) {
+    let n = prov.len().max(1) as f32;
+    let mean = |f: fn(&Provenance) -> usize| {
+        prov.iter().map(f).sum::<usize>() as f32 / n
+    };
+    println!(
+        "{} ({}): first built after {:.2} actions, {} {:.2} unspent, \
+         {:.2} build paths",
+        label,
+        prov.len(),
+        mean(|p| p.depth),
+        Icon::Gold,
+        mean(|p| p.gold),
+        mean(|p| p.paths),
+    );
+}
+
+/// Prints the average of the given economies, with a label
+fn print_economy(label: &str, econ: &[Economy]) {
+    let mut total = Economy::default();
+    for e in econ {
+        total += *e;
+    }
+    print_economy_sum(label, &total, econ.len());
+}
+
+/// Prints the average of `count` economies, given their sum, with a label
+fn print_economy_sum(label: &str, total: &Economy, count: usize) {
+    let n = count.max(1) as f32;
+    println!(
+        "{} ({}): spent {} {:.2} (rerolls {:.2}, friends {:.2}, food {:.2}), \
+         earned {} {:.2} (sold {:.2}, abilities {:.2})",
+        label,
+        count,
+        Icon::Gold,
+        total.spent() as f32 / n,
+        total.rerolls as f32 / n,
+        total.friends as f32 / n,
+        total.food as f32 / n,
+        Icon::Gold,
+        total.earned() as f32 / n,
+        total.sold as f32 / n,
+        total.abilities as f32 / n,
+    );
+}
+
+/// A generated team, with the economy of the first shop which built it and
+/// its provenance
+type Generated = (Team, Economy, Provenance);
+
+/// Returns the settings which generated teams and their scores depend on,
+/// which must match for a checkpoint to be resumed
+fn checkpoint_settings(cfg: &GameConfig, focus: Option<&Focus>) -> String {
+    let pack = &pack::active().name;
+    let rules = &rules::active().name;
+    let custom = custom::checksum();
+    serde_json::to_string(&(cfg, focus, pack, rules, custom)).unwrap()
+}
+
+/// Reads a checkpoint, returning it if its settings match
+fn read_checkpoint<D: serde::de::DeserializeOwned>(
+    file: &str,
+    settings: &str,
+    get: fn(&D) -> &str,
+) -> Option<D> {
+    if !std::path::Path::new(file).exists() {
+        return None;
+    }
+    let c: D = read_cache(file)?;
+    if get(&c) != settings {
+        warn!("Ignoring {}, which was saved with other settings", file);
+        return None;
+    }
+    info!("Resuming from {}", file);
+    Some(c)
+}
+
+/// Saves a checkpoint and exits, explaining how to resume
+fn save_checkpoint<D: Serialize>(d: &D, file: &str, progress: String) -> ! {
+    save_cache(d, file);
+    eprintln!(
+        "Saved a checkpoint to {} after {}; run the same command again to \
+         resume",
+        file, progress
+    );
+    std::process::exit(interrupt::EXIT_CODE)
+}
+
+/// Removes a checkpoint once the work which it saved is done
+fn remove_checkpoint(file: &str) {
+    if std::path::Path::new(file).exists() {
+        if let Err(e) = std::fs::remove_file(file) {
+            warn!("Failed to remove {}: {}", file, e);
+        }
+    }
+}
+
+/// Exploration state saved when generation is interrupted
+#[derive(Deserialize, Serialize)]
+struct GenerateCheckpoint {
+    /// From [`checkpoint_settings`]
+    settings: String,
+    depth: usize,
+    /// Shops left to expand in the current generation
+    pending: Vec<Shop>,
+    /// Shops queued for the next generation
+    next: Vec<Shop>,
+    seen_shops: Vec<(Shop, usize)>,
+    seen_teams: Vec<Generated>,
+    /// Sum of every finished shop's economy
+    finished: Economy,
+    finished_shops: usize,
+    pruning: Pruning,
+    generation: Generation,
+    /// Time spent before the interruption, in seconds
+    seconds: f32,
+}
 
-fn generate_teams() -> Vec<Team> {
+/// Explores every shop (within the focus), returning every non-dumb team
+/// along with the economy of the first shop which built it
+fn generate_teams(
+    cfg: &GameConfig,
+    focus: &Focus,
+) -> (Vec<Generated>, Metadata) {
+    let start = std::time::Instant::now();
     let mut active_shops = HashSet::new();
     let mut seen_shops = HashMap::new();
-    let mut dice = DeterministicDice::new();
-    while dice.next() {
-        active_shops.insert(Shop::new(&mut dice));
+    let mut seen_teams: HashMap<Team, (Economy, Provenance)> = HashMap::new();
+    let mut finished = Economy::default();
+    let mut finished_shops = 0;
+    let mut depth = 0;
+    let mut pruning = Pruning::default();
+    let mut seconds = 0.0;
+    let new_dice = || {
+        let dice = DeterministicDice::new();
+        if cfg.multiset_offers {
+            dice.multisets()
+        } else {
+            dice
+        }
+    };
+
+    let settings = checkpoint_settings(cfg, Some(focus));
+    let mut resume = None;
+    if let Some(c) = read_checkpoint(
+        GENERATE_CHECKPOINT_FILE,
+        &settings,
+        |c: &GenerateCheckpoint| &c.settings,
+    ) {
+        active_shops = c.pending.into_iter().collect();
+        seen_shops = c.seen_shops.into_iter().collect();
+        seen_teams = c
+            .seen_teams
+            .into_iter()
+            .map(|(t, e, p)| (t, (e, p)))
+            .collect();
+        finished = c.finished;
+        finished_shops = c.finished_shops;
+        depth = c.depth;
+        pruning = c.pruning;
+        seconds = c.seconds;
+        resume = Some((c.generation, c.next.into_iter().collect()));
+    } else {
+        let mut dice = new_dice();
+        let mut rolls = 0;
+        while dice.next() {
+            active_shops.insert(Shop::with_challenge(
+                1,
+                cfg.challenge,
+                &mut dice,
+            ));
+            rolls += 1;
+        }
+        info!(
+            "Got {} initial shops from {} rolls",
+            active_shops.len(),
+            rolls
+        );
     }
-    info!("Got {} initial shops", active_shops.len());
 
-    let mut seen_teams = HashSet::new();
     while !active_shops.is_empty() {
+        let gen_start = std::time::Instant::now();
         let num_shops = active_shops.len();
+        // A resumed generation picks up where it was interrupted
+        let (mut gen, mut next) = match resume.take() {
+            Some(r) => r,
+            None => {
+                depth += 1;
+                pruning.peak_frontier = pruning.peak_frontier.max(num_shops);
+                let gen = Generation {
+                    frontier: num_shops,
+                    ..Generation::default()
+                };
+                (gen, HashSet::new())
+            }
+        };
         info!(
             "Got {} active shops, {} teams, {} seen shops",
             num_shops,
             seen_teams.len(),
             seen_shops.len()
         );
-        let mut next = HashSet::new();
-        for (i, shop) in active_shops.into_iter().enumerate() {
-            trace!("Running on shop {} / {}", i + 1, num_shops);
+        let mut queue = active_shops.into_iter().enumerate();
+        while let Some((i, shop)) = queue.next() {
+            if interrupt::requested() {
+                gen.seconds += gen_start.elapsed().as_secs_f32();
+                let c = GenerateCheckpoint {
+                    settings,
+                    depth,
+                    pending: std::iter::once(shop)
+                        .chain(queue.map(|(_, s)| s))
+                        .collect(),
+                    next: next.into_iter().collect(),
+                    seen_shops: seen_shops.into_iter().collect(),
+                    seen_teams: seen_teams
+                        .into_iter()
+                        .map(|(t, (e, p))| (t, e, p))
+                        .collect(),
+                    finished,
+                    finished_shops,
+                    pruning,
+                    generation: gen,
+                    seconds: seconds + start.elapsed().as_secs_f32(),
+                };
+                let progress =
+                    format!("{} shops at depth {}", c.seen_shops.len(), depth);
+                save_checkpoint(&c, GENERATE_CHECKPOINT_FILE, progress);
+            }
+            trace!("Running on shop {} / {}: {:#}", i + 1, num_shops, shop);
             // If we've already seen this shop, and had more gold when we last
             // encountered it, then this branch isn't going to generate anything
             // worthwhile.
             let mut shop_without_gold = shop;
             shop_without_gold.gold = 0;
             if let Some(prev_gold) = seen_shops.get(&shop_without_gold) {
-                if *prev_gold >= shop.gold {
+                if *prev_gold == shop.gold {
                     trace!("Duplicate shop; exiting");
+                    pruning.duplicate_shops += 1;
+                    continue;
+                } else if *prev_gold > shop.gold {
+                    trace!("Shop was seen with more gold; exiting");
+                    pruning.gold_dominated += 1;
                     continue;
                 }
             }
             seen_shops.insert(shop_without_gold, shop.gold);
+            gen.expanded += 1;
 
-            let mut dice = DeterministicDice::new();
+            // Shops in this generation have taken one action fewer than their
+            // successors; past the limits, they may only end their turn
+            let ends = cfg.limits.ends(&shop, depth - 1);
+            pruning.limited += ends as usize;
+
+            let mut dice = new_dice();
             while dice.next() {
                 let mut shop = shop;
-                let done = shop.step(&mut dice);
-
-                // Sort the team for shop state deduplication; we'll generate
-                // every possible team to field in battle later on.
-                shop.team.sort();
+                let step = if ends {
+                    shop.end_turn(&mut dice, &mut ());
+                    Step::Ended
+                } else {
+                    shop.step_pruned(&mut dice, &cfg.prune, &mut ())
+                };
+                match step {
+                    Step::Impossible => {
+                        pruning.impossible += 1;
+                        continue;
+                    }
+                    Step::Pruned => {
+                        pruning.pruned += 1;
+                        continue;
+                    }
+                    Step::Acted | Step::Ended => (),
+                }
 
-                // Do an early check here to make sure we haven't seen this
+                // Shops compare by their canonical form (ignoring team order),
+                // so do an early check here to make sure we haven't seen this
                 // shop before, _before_ building every possible permutation
                 if next.contains(&shop) {
+                    pruning.duplicate_successors += 1;
+                    continue;
+                }
+                if !focus.explores(&shop) {
+                    pruning.unfocused += 1;
                     continue;
                 }
 
-                if !done {
-                    next.insert(shop);
+                if step == Step::Acted {
+                    if cfg.limits.allows(&shop, depth) {
+                        next.insert(shop);
+                    } else {
+                        pruning.limited += 1;
+                    }
+                    continue;
                 }
+                finished += shop.economy;
+                finished_shops += 1;
 
                 // Store all possible compact permutations of this team, to
-                // minimize the amount of exploration required.
+                // minimize the amount of exploration required.  Only teams
+                // whose turn was ended are kept, since that's when a player
+                // fields them (after any end-of-turn abilities).
                 let team = shop.team;
+                if !focus.keeps(&team) {
+                    continue;
+                }
                 for team in team.compact_permutations() {
-                    shop.team = team;
-                    if seen_teams.insert(team.without_exp()) {
+                    let key = team.for_battle();
+                    if let Some((_, p)) = seen_teams.get_mut(&key) {
+                        p.add(shop.gold);
+                    } else {
+                        let mut p = Provenance {
+                            depth,
+                            ..Provenance::default()
+                        };
+                        p.add(shop.gold);
+                        seen_teams.insert(key, (shop.economy, p));
                         debug!(
                             "New {}team ({}):\n{}",
-                            if team.is_dumb() { "(dumb) " } else { "" },
+                            if team.is_dumb_with(&cfg.dumb) {
+                                "(dumb) "
+                            } else {
+                                ""
+                            },
                             seen_teams.len(),
                             team
                         );
@@ -95,45 +494,406 @@ fn generate_teams() -> Vec<Team> {
             }
         }
         active_shops = next;
+        gen.seconds += gen_start.elapsed().as_secs_f32();
+        pruning.expanded += gen.expanded;
+        pruning.generations.push(gen);
     }
-    let mut seen: Vec<Team> =
-        seen_teams.into_iter().filter(|t| !t.is_dumb()).collect();
+    print_economy_sum("Finished shops", &finished, finished_shops);
+    let num_seen = seen_teams.len();
+    let mut seen: Vec<Generated> = seen_teams
+        .into_iter()
+        .filter(|t| !t.0.is_dumb_with(&cfg.dumb))
+        .map(|(t, (e, p))| (t, e, p))
+        .collect();
     info!("Got {} non-dumb teams", seen.len());
-    seen.sort();
-    seen
+    pruning.dumb_teams = num_seen - seen.len();
+    seen.sort_by_key(|t| t.0);
+    let meta = Metadata {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        config: *cfg,
+        pack: pack::active().name.clone(),
+        rules: rules::active().name.clone(),
+        focus: focus.clone(),
+        custom: custom::checksum(),
+        shops: seen_shops.len(),
+        finished_shops,
+        seen_teams: num_seen,
+        teams: seen.len(),
+        seconds: seconds + start.elapsed().as_secs_f32(),
+        pruning,
+    };
+    remove_checkpoint(GENERATE_CHECKPOINT_FILE);
+    info!(
+        "Generation summary: {}",
+        serde_json::to_string(&meta).unwrap()
+    );
+    (seen, meta)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+/// Battle lengths are histogrammed up to this many rounds
+const LENGTH_BUCKETS: usize = 16;
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 struct Record {
     wins: f32,
     loses: f32,
     ties: f32,
+    /// Fraction of battles which hit the round cap (included in `ties`)
+    capped: f32,
+    /// Mean number of rounds of combat
+    rounds: f32,
+    /// Fraction of battles lasting each number of rounds, where the last
+    /// bucket also includes every longer battle
+    length: [f32; LENGTH_BUCKETS],
+    /// Fraction of battles won with each number of surviving friends
+    won_with: [f32; TEAM_SIZE + 1],
+    /// Fraction of battles lost to an opponent with each number of surviving
+    /// friends
+    lost_to: [f32; TEAM_SIZE + 1],
 }
-fn score_teams(teams: &[Team]) -> Vec<Vec<Record>> {
-    let mut results = vec![vec![Record::default(); teams.len()]; teams.len()];
-    for (i, a) in teams.iter().enumerate() {
-        for (j, b) in teams.iter().enumerate() {
-            let mut team_a = 0;
-            let mut team_b = 0;
-            let mut ties = 0;
-            let mut num_battles = 0;
+
+impl Flat for Record {
+    const SIZE: usize = 5 * f32::SIZE
+        + <[f32; LENGTH_BUCKETS]>::SIZE
+        + 2 * <[f32; TEAM_SIZE + 1]>::SIZE;
+    fn encode(&self, out: &mut Vec<u8>) {
+        for f in [self.wins, self.loses, self.ties, self.capped, self.rounds] {
+            f.encode(out);
+        }
+        self.length.encode(out);
+        self.won_with.encode(out);
+        self.lost_to.encode(out);
+    }
+    fn decode(bytes: &[u8]) -> Self {
+        let mut rest = bytes;
+        let mut take = |n: usize| {
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            head
+        };
+        let mut f = || f32::decode(take(f32::SIZE));
+        let (wins, loses, ties, capped, rounds) = (f(), f(), f(), f(), f());
+        Record {
+            wins,
+            loses,
+            ties,
+            capped,
+            rounds,
+            length: Flat::decode(take(<[f32; LENGTH_BUCKETS]>::SIZE)),
+            won_with: Flat::decode(take(<[f32; TEAM_SIZE + 1]>::SIZE)),
+            lost_to: Flat::decode(take(<[f32; TEAM_SIZE + 1]>::SIZE)),
+        }
+    }
+}
+
+/// Row-by-row access to a score matrix, which is either loaded into memory
+/// or mapped from disk (and decoded lazily)
+trait Scores {
+    fn len(&self) -> usize;
+    fn row(&self, i: usize) -> Cow<'_, [Record]>;
+
+    /// Returns the fraction of matchups won by the given team
+    fn win_rate(&self, i: usize) -> f32 {
+        let row = self.row(i);
+        row.iter().map(|r| r.wins).sum::<f32>() / row.len() as f32
+    }
+
+    /// Returns the given team's mean score over every matchup, under the
+    /// given ranking rules
+    fn score(&self, i: usize, ranking: Ranking) -> f32 {
+        let row = self.row(i);
+        row.iter()
+            .map(|r| ranking.score(r.wins, r.ties))
+            .sum::<f32>()
+            / row.len() as f32
+    }
+
+    /// Returns every team's win rate
+    fn win_rates(&self) -> Vec<f32> {
+        (0..self.len()).map(|i| self.win_rate(i)).collect()
+    }
+
+    /// Returns up to `n` teams which beat the given team most often, best
+    /// first, breaking ties by their win rates (from [`Scores::win_rates`])
+    fn counters(&self, i: usize, rates: &[f32], n: usize) -> Vec<usize> {
+        let row = self.row(i);
+        let mut out: Vec<usize> = (0..row.len()).filter(|j| *j != i).collect();
+        out.sort_by(|a, b| {
+            row[*b]
+                .loses
+                .total_cmp(&row[*a].loses)
+                .then(rates[*b].total_cmp(&rates[*a]))
+        });
+        out.truncate(n);
+        out
+    }
+}
+
+/// A square matrix of records, read a cell at a time
+trait Cells {
+    /// Returns the number of rows, or `None` if the matrix isn't square
+    fn size(&self) -> Option<usize>;
+    fn cell(&self, i: usize, j: usize) -> Record;
+}
+
+impl Cells for Vec<Vec<Record>> {
+    fn size(&self) -> Option<usize> {
+        self.iter()
+            .all(|r| r.len() == self.len())
+            .then_some(self.len())
+    }
+    fn cell(&self, i: usize, j: usize) -> Record {
+        self[i][j]
+    }
+}
+
+impl Cells for FlatMatrix<Record> {
+    fn size(&self) -> Option<usize> {
+        (self.rows() == self.cols()).then_some(self.rows())
+    }
+    fn cell(&self, i: usize, j: usize) -> Record {
+        self.get(i, j)
+    }
+}
+
+/// What a score matrix was scored from, so that its scores aren't reused
+/// after the teams or settings change
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct ScoresKey {
+    /// From [`checkpoint_settings`]
+    settings: String,
+    /// Checksum of the teams
+    teams: u64,
+}
+
+impl ScoresKey {
+    fn new(cfg: &GameConfig, teams: &[Team]) -> Self {
+        Self {
+            settings: checkpoint_settings(cfg, None),
+            teams: or_exit(checksum(&teams), "Failed to checksum teams"),
+        }
+    }
+}
+
+/// Scores of the battle-unique teams (see [`battle_unique`]), along with the
+/// row of each team among them.  The flat score matrix is stored as
+/// `UniqueScores<()>` next to the matrix itself.
+#[derive(Deserialize, Serialize)]
+struct UniqueScores<M> {
+    key: ScoresKey,
+    rows: Vec<usize>,
+    /// Last, so that the rest can be read before streaming the matrix
+    scores: M,
+}
+
+impl<M: Cells> UniqueScores<M> {
+    /// Checks that every team's row is in the matrix
+    fn in_range(&self) -> bool {
+        let n = self.scores.size();
+        n.is_some_and(|n| self.rows.iter().all(|r| *r < n))
+    }
+
+    /// Checks that these are the scores for the given key
+    fn matches(&self, key: &ScoresKey) -> bool {
+        self.key == *key && self.in_range()
+    }
+
+    /// Returns the record for a matchup between two teams
+    fn get(&self, i: usize, j: usize) -> Record {
+        self.scores.cell(self.rows[i], self.rows[j])
+    }
+}
+
+impl<M: Cells> Scores for UniqueScores<M> {
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+    fn row(&self, i: usize) -> Cow<'_, [Record]> {
+        Cow::Owned((0..self.rows.len()).map(|j| self.get(i, j)).collect())
+    }
+}
+
+/// Corpus written while scoring, if requested
+type CorpusFile = Corpus<std::io::BufWriter<std::fs::File>>;
+
+/// Runs every dice path through a single matchup
+fn score_matchup(
+    cfg: &GameConfig,
+    a: &Team,
+    b: &Team,
+    corpus: &mut Option<&mut CorpusFile>,
+) -> Record {
+    let mut team_a = 0;
+    let mut team_b = 0;
+    let mut ties = 0;
+    let mut capped = 0;
+    let mut rounds = 0;
+    let mut length = [0; LENGTH_BUCKETS];
+    let mut won_with = [0; TEAM_SIZE + 1];
+    let mut lost_to = [0; TEAM_SIZE + 1];
+    let mut num_battles = 0;
+    let battle = Battle(*a, *b);
+    // The corpus records every path, so it can't skip memoized states
+    let outcomes: Vec<(Outcome, usize)> = match corpus {
+        Some(c) => {
+            let mut out = vec![];
             let mut dice = DeterministicDice::new();
             while dice.next() {
-                let battle = Battle(*a, *b);
-                match battle.run(&mut dice) {
-                    Winner::TeamA => team_a += 1,
-                    Winner::TeamB => team_b += 1,
-                    Winner::Tied => ties += 1,
-                }
-                num_battles += 1;
+                let outcome = battle.run(cfg, &mut dice);
+                or_exit(c.record(&battle, &dice, &outcome), "Corpus error");
+                out.push((outcome, 1));
+            }
+            out
+        }
+        None => battle
+            .outcomes(cfg)
+            .into_iter()
+            .map(|t| (t.outcome, t.paths))
+            .collect(),
+    };
+    for (outcome, n) in outcomes {
+        match outcome.winner {
+            Winner::TeamA => {
+                team_a += n;
+                won_with[outcome.survivors] += n;
+            }
+            Winner::TeamB => {
+                team_b += n;
+                lost_to[outcome.survivors] += n;
+            }
+            Winner::Tied => ties += n,
+        }
+        if outcome.capped {
+            capped += n;
+        }
+        rounds += outcome.rounds * n;
+        length[outcome.rounds.min(LENGTH_BUCKETS - 1)] += n;
+        num_battles += n;
+    }
+    let frac = |n: usize| n as f32 / num_battles as f32;
+    Record {
+        wins: frac(team_a),
+        loses: frac(team_b),
+        ties: frac(ties),
+        capped: frac(capped),
+        rounds: frac(rounds),
+        length: length.map(frac),
+        won_with: won_with.map(frac),
+        lost_to: lost_to.map(frac),
+    }
+}
+
+/// Interval between printing the leaderboard during scoring
+const LEADERBOARD_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(60);
+
+/// Prints the leaderboard's current top teams
+fn print_leaderboard(board: &Leaderboard, teams: &[Team]) {
+    info!(
+        "Leaderboard after {} / {} rows ({} teams in contention):",
+        board.rows(),
+        board.teams(),
+        board.contenders()
+    );
+    for (rank, s) in board.top().iter().enumerate() {
+        let interval = if s.exact {
+            "exact".to_owned()
+        } else {
+            format!("{:.1}-{:.1}%", s.low * 100.0, s.high * 100.0)
+        };
+        info!(
+            "  {:>3}. {:5.1}% ({}) {}",
+            rank + 1,
+            s.mean * 100.0,
+            interval,
+            teams[s.team].code()
+        );
+    }
+}
+
+/// Finished rows of the score matrix, saved when scoring is interrupted
+#[derive(Deserialize, Serialize)]
+struct ScoresCheckpoint {
+    /// From [`checkpoint_settings`]
+    settings: String,
+    teams: Vec<Team>,
+    /// Each finished row, with its index
+    rows: Vec<(usize, Vec<Record>)>,
+}
+
+/// Scores every pair of teams.  With a leaderboard size, rows are scored in
+/// a shuffled order and the running top teams are printed periodically;
+/// the results are the same either way.
+fn score_teams(
+    cfg: &GameConfig,
+    teams: &[Team],
+    mut corpus: Option<&mut CorpusFile>,
+    leaderboard: Option<usize>,
+) -> Vec<Vec<Record>> {
+    let mut results = vec![vec![Record::default(); teams.len()]; teams.len()];
+    let mut order: Vec<usize> = (0..teams.len()).collect();
+    let mut board = leaderboard.map(|k| Leaderboard::new(teams.len(), k));
+    if board.is_some() {
+        order.shuffle(&mut StdRng::seed_from_u64(0));
+    }
+
+    let settings = checkpoint_settings(cfg, None);
+    let mut done = vec![false; teams.len()];
+    if let Some(c) = read_checkpoint(
+        SCORES_CHECKPOINT_FILE,
+        &settings,
+        |c: &ScoresCheckpoint| &c.settings,
+    )
+    .filter(|c| c.teams == teams)
+    {
+        if corpus.is_some() {
+            warn!("Resumed rows won't be written to the corpus");
+        }
+        for (i, row) in c.rows {
+            if let Some(board) = &mut board {
+                let wins: Vec<f32> = row.iter().map(|r| r.wins).collect();
+                let loses: Vec<f32> = row.iter().map(|r| r.loses).collect();
+                board.add_row(i, &wins, &loses);
             }
-            results[i][j] = Record {
-                wins: team_a as f32 / num_battles as f32,
-                loses: team_b as f32 / num_battles as f32,
-                ties: ties as f32 / num_battles as f32,
+            results[i] = row;
+            done[i] = true;
+        }
+    }
+
+    let mut printed = std::time::Instant::now();
+    for i in order {
+        if done[i] {
+            continue;
+        }
+        if interrupt::requested() {
+            let c = ScoresCheckpoint {
+                settings,
+                teams: teams.to_vec(),
+                rows: (0..teams.len())
+                    .filter(|i| done[*i])
+                    .map(|i| (i, std::mem::take(&mut results[i])))
+                    .collect(),
             };
+            let progress = format!("{} / {} rows", c.rows.len(), teams.len());
+            save_checkpoint(&c, SCORES_CHECKPOINT_FILE, progress);
+        }
+        let a = &teams[i];
+        for (j, b) in teams.iter().enumerate() {
+            if let Some(c) = &mut corpus {
+                c.start_matchup(i, j);
+            }
+            results[i][j] = score_matchup(cfg, a, b, &mut corpus);
+        }
+        if let Some(board) = &mut board {
+            let wins: Vec<f32> = results[i].iter().map(|r| r.wins).collect();
+            let loses: Vec<f32> = results[i].iter().map(|r| r.loses).collect();
+            board.add_row(i, &wins, &loses);
+            if printed.elapsed() >= LEADERBOARD_INTERVAL {
+                print_leaderboard(board, teams);
+                printed = std::time::Instant::now();
+            }
         }
         let mut num_wins = 0.0;
         let mut num_ties = 0.0;
@@ -150,117 +910,3884 @@ fn score_teams(teams: &[Team]) -> Vec<Vec<Record>> {
             num_ties / count * 100.0,
             teams[i]
         );
-        println!("{}", num_wins / count);
+        done[i] = true;
     }
+    if let Some(board) = &board {
+        print_leaderboard(board, teams);
+    }
+    remove_checkpoint(SCORES_CHECKPOINT_FILE);
     results
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-fn analyze_scores(teams: &[Team], results: Vec<Vec<Record>>) {
+/// Sorts per-team data by the team's win rate, strongest first, returning
+/// it along with the number of teams in the top 10%
+fn by_strength<T: Copy>(results: &dyn Scores, data: &[T]) -> (Vec<T>, usize) {
+    let mut order: Vec<(f32, T)> = (0..results.len())
+        .map(|i| results.win_rate(i))
+        .zip(data.iter().cloned())
+        .collect();
+    order.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    let data: Vec<T> = order.into_iter().map(|e| e.1).collect();
+    let n = std::cmp::max(data.len() / 10, 1).min(data.len());
+    (data, n)
+}
+
+/// Aggregates the score matrix into species-vs-species statistics
+fn species_pairings(teams: &[Team], results: &dyn Scores) -> SpeciesMatrix {
+    let mut matrix = SpeciesMatrix::new();
+    for (i, a) in teams.iter().enumerate() {
+        let row = results.row(i);
+        let chances = |f: fn(&Record) -> f32| row.iter().map(f).collect();
+        let wins: Vec<f32> = chances(|r| r.wins);
+        let loses: Vec<f32> = chances(|r| r.loses);
+        let ties: Vec<f32> = chances(|r| r.ties);
+        matrix.add_row(a, teams, &wins, &loses, &ties);
+    }
+    matrix
+}
+
+/// Prints each species' win rate against each other species, as a table
+/// with a row per species
+fn print_pairings(matrix: &SpeciesMatrix) {
+    let species = matrix.species();
+    print!("{:<14}", "Win rate vs.");
+    for o in &species {
+        print!("{:>6}", o.abbrev());
+    }
+    println!();
+    for s in &species {
+        print!("{:<14}", format!("{:?}", s));
+        for o in &species {
+            match matrix.get(*s, *o) {
+                Some(p) => print!("{:>6.1}", p.win_rate() * 100.0),
+                None => print!("{:>6}", "-"),
+            }
+        }
+        println!();
+    }
+}
+
+/// Largest difference in an outcome's chance which `mirror` ignores, to
+/// allow for rounding
+const MIRROR_TOLERANCE: f64 = 1e-9;
+
+/// A matchup which disagrees with its mirror image, for `mirror`
+#[derive(Serialize)]
+struct MirrorPair {
+    a: String,
+    b: String,
+    forward: Chances,
+    mirrored: Chances,
+    difference: f64,
+}
+
+/// Result of `mirror`
+#[derive(Serialize)]
+struct MirrorReport {
+    /// Matchups which were run both ways
+    matchups: usize,
+    /// Matchups whose mirror images disagree, most different first
+    asymmetric: Vec<MirrorPair>,
+}
+
+/// Runs random matchups between the given teams with the teams on either
+/// side, looking for battles which depend on which side a team is on
+fn audit_mirrors(
+    cfg: &GameConfig,
+    teams: &[Team],
+    matchups: usize,
+    seed: u64,
+) -> MirrorReport {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut asymmetric = vec![];
+    let matchups = if teams.is_empty() { 0 } else { matchups };
+    for _ in 0..matchups {
+        let a = teams.choose(&mut rng).unwrap();
+        let b = teams.choose(&mut rng).unwrap();
+        if let Some(m) = mirror::audit(cfg, a, b, MIRROR_TOLERANCE) {
+            asymmetric.push(MirrorPair {
+                a: m.a.code(),
+                b: m.b.code(),
+                forward: m.forward,
+                mirrored: m.mirrored,
+                difference: m.difference,
+            });
+        }
+    }
+    asymmetric.sort_by(|a, b| b.difference.total_cmp(&a.difference));
+    MirrorReport {
+        matchups,
+        asymmetric,
+    }
+}
+
+fn print_mirrors(report: &MirrorReport, count: usize) {
+    println!(
+        "{} of {} matchups disagree with their mirror image",
+        report.asymmetric.len(),
+        report.matchups
+    );
+    let pct = |c: &Chances| {
+        format!(
+            "{:.2}% / {:.2}% / {:.2}%",
+            c.wins * 100.0,
+            c.loses * 100.0,
+            c.ties * 100.0
+        )
+    };
+    for m in report.asymmetric.iter().take(count) {
+        println!("\n{} vs. {}", m.a, m.b);
+        println!("    Forward (win / lose / tie):  {}", pct(&m.forward));
+        println!("    Mirrored (win / lose / tie): {}", pct(&m.mirrored));
+        println!(
+            "    Largest outcome difference: {:.2}%",
+            m.difference * 100.0
+        );
+    }
+}
+
+/// Compares the economy of the strongest teams against every team, to see
+/// how gold allocation correlates with strength
+fn analyze_economy(results: &dyn Scores, econ: &[Economy]) {
+    let (econ, n) = by_strength(results, econ);
+    print_economy("Top 10% of teams", &econ[..n]);
+    print_economy("All teams", &econ);
+}
+
+/// Compares how the strongest teams were built against every team, to see
+/// whether strong teams are harder to reach
+fn analyze_provenance(results: &dyn Scores, prov: &[Provenance]) {
+    let (prov, n) = by_strength(results, prov);
+    print_provenance("Top 10% of teams", &prov[..n]);
+    print_provenance("All teams", &prov);
+}
+
+/// Teams whose cheapest build path cost the same amount of gold, for `cost`
+#[derive(Serialize)]
+struct CostBucket {
+    gold: usize,
+    teams: usize,
+    mean_wins: f32,
+    /// Strongest team at this cost, if any
+    best: Option<String>,
+    best_wins: f32,
+    /// How much this gold raises the best win rate among teams costing at
+    /// most this much, i.e. the marginal value of this gold
+    marginal: f32,
+}
+
+/// The team which wins the most per gold spent, for `cost`
+#[derive(Serialize)]
+struct CostValue {
+    code: String,
+    gold: usize,
+    wins: f32,
+}
+
+/// Result of `cost`, with one bucket per amount of gold
+#[derive(Serialize)]
+struct CostReport {
+    buckets: Vec<CostBucket>,
+    best_value: Option<CostValue>,
+}
+
+/// Buckets teams matching the filter by the net gold spent on their
+/// cheapest build path, which is the starting gold minus the most gold any
+/// shop had left after building them
+fn analyze_cost(
+    gold: usize,
+    teams: &[Team],
+    results: &dyn Scores,
+    prov: &[Provenance],
+    filter: &TeamFilter,
+) -> CostReport {
+    let rated: Vec<(usize, f32, &Team)> = teams
+        .iter()
+        .zip(prov)
+        .enumerate()
+        .filter(|(_, (t, _))| filter.matches(t))
+        .map(|(i, (t, p))| {
+            (gold.saturating_sub(p.gold), results.win_rate(i), t)
+        })
+        .collect();
+    let max_gold = rated.iter().map(|r| r.0).max().unwrap_or(0);
+
+    let mut frontier = 0.0;
+    let buckets = (0..=max_gold)
+        .map(|gold| {
+            let here: Vec<_> = rated.iter().filter(|r| r.0 == gold).collect();
+            let best = here.iter().max_by(|a, b| a.1.total_cmp(&b.1));
+            let best_wins = best.map_or(0.0, |b| b.1);
+            let marginal = (best_wins - frontier).max(0.0);
+            frontier += marginal;
+            let total: f32 = here.iter().map(|r| r.1).sum();
+            CostBucket {
+                gold,
+                teams: here.len(),
+                mean_wins: if here.is_empty() {
+                    0.0
+                } else {
+                    total / here.len() as f32
+                },
+                best: best.map(|b| b.2.code()),
+                best_wins,
+                marginal,
+            }
+        })
+        .collect();
+    let best_value = rated
+        .iter()
+        .filter(|r| r.0 > 0)
+        .max_by(|a, b| (a.1 / a.0 as f32).total_cmp(&(b.1 / b.0 as f32)))
+        .map(|r| CostValue {
+            code: r.2.code(),
+            gold: r.0,
+            wins: r.1,
+        });
+    CostReport {
+        buckets,
+        best_value,
+    }
+}
+
+/// Prints win rates by cost, and the team with the best value
+fn print_cost(report: &CostReport) {
+    println!("Win rates by gold spent on the cheapest build path:");
+    for b in &report.buckets {
+        if let Some(best) = &b.best {
+            println!(
+                "  {} {:2}: {:5} teams, mean {:5.2}%, best {:5.2}% \
+                 ({:+.2}% marginal) {}",
+                Icon::Gold,
+                b.gold,
+                b.teams,
+                b.mean_wins * 100.0,
+                b.best_wins * 100.0,
+                b.marginal * 100.0,
+                best
+            );
+        }
+    }
+    if let Some(v) = &report.best_value {
+        println!(
+            "Best value: {} wins {:.2}% for {} {} ({:.2}% per gold)",
+            v.code,
+            v.wins * 100.0,
+            Icon::Gold,
+            v.gold,
+            v.wins / v.gold as f32 * 100.0
+        );
+    }
+}
+
+/// Width of each bucket in the honey report's histogram, in percent
+const HONEY_BUCKET: f32 = 10.0;
+
+/// A honey-carrying team and the same team without honey, for `honey`
+#[derive(Serialize)]
+struct HoneyPair {
+    honey: String,
+    plain: String,
+    /// Change in win rate from adding the honey
+    delta: f32,
+}
+
+/// Result of `honey`, with pairs sorted from the biggest gain to the
+/// biggest loss
+#[derive(Serialize)]
+struct HoneyReport {
+    /// Teams carrying honey, whether or not they have a counterpart
+    teams: usize,
+    /// Pairs in which the honey helped
+    helped: usize,
+    mean_delta: f32,
+    /// Number of pairs in each bucket of win rate deltas, keyed by the
+    /// bucket's lower bound in percent
+    histogram: Vec<(f32, usize)>,
+    pairs: Vec<HoneyPair>,
+}
+
+/// Pairs each honey-carrying team matching the filter with its honey-less
+/// counterpart (if that team was also generated), comparing their win rates
+fn analyze_honey(
+    teams: &[Team],
+    results: &dyn Scores,
+    filter: &TeamFilter,
+) -> HoneyReport {
+    let honey = |t: &Team| {
+        t.iter()
+            .flatten()
+            .any(|f| f.modifier == Some(Modifier::Honey))
+    };
+    let with: Vec<usize> = (0..teams.len())
+        .filter(|i| honey(&teams[*i]) && filter.matches(&teams[*i]))
+        .collect();
+    let mut pairs: Vec<HoneyPair> = with
+        .iter()
+        .filter_map(|i| {
+            let mut plain = teams[*i];
+            for k in 0..TEAM_SIZE {
+                if let Some(f) = plain[k].as_mut() {
+                    if f.modifier == Some(Modifier::Honey) {
+                        f.modifier = f.species.default_modifier();
+                    }
+                }
+            }
+            // Cached teams are sorted and stored in their battle-ready form
+            let j = teams.binary_search(&plain.for_battle()).ok()?;
+            Some(HoneyPair {
+                honey: teams[*i].code(),
+                plain: teams[j].code(),
+                delta: results.win_rate(*i) - results.win_rate(j),
+            })
+        })
+        .collect();
+    pairs.sort_by(|a, b| b.delta.total_cmp(&a.delta));
+
+    let n = (200.0 / HONEY_BUCKET) as usize;
+    let mut histogram: Vec<(f32, usize)> = (0..n)
+        .map(|k| (k as f32 * HONEY_BUCKET - 100.0, 0))
+        .collect();
+    for p in &pairs {
+        let k = ((p.delta * 100.0 + 100.0) / HONEY_BUCKET) as usize;
+        histogram[k.min(n - 1)].1 += 1;
+    }
+    let total: f32 = pairs.iter().map(|p| p.delta).sum();
+    HoneyReport {
+        teams: with.len(),
+        helped: pairs.iter().filter(|p| p.delta > 0.0).count(),
+        mean_delta: if pairs.is_empty() {
+            0.0
+        } else {
+            total / pairs.len() as f32
+        },
+        histogram,
+        pairs,
+    }
+}
+
+/// Prints the distribution of win rate deltas from honey, and the pairs in
+/// which it helped the most and least
+fn print_honey(report: &HoneyReport, count: usize) {
+    println!(
+        "{} of {} honey teams have a honey-less counterpart; honey helped \
+         {} of them, by {:+.2}% on average",
+        report.pairs.len(),
+        report.teams,
+        report.helped,
+        report.mean_delta * 100.0
+    );
+    if report.pairs.is_empty() {
+        return;
+    }
+    println!("\nWin rate deltas:");
+    for (lo, n) in report.histogram.iter().filter(|(_, n)| *n > 0) {
+        println!("  {:+4.0}% to {:+4.0}%: {:5}", lo, lo + HONEY_BUCKET, n);
+    }
+    let line = |p: &HoneyPair| {
+        println!("  {:+6.2}% {} vs {}", p.delta * 100.0, p.honey, p.plain)
+    };
+    println!("\nHoney helped the most:");
+    report.pairs.iter().take(count).for_each(line);
+    println!("\nHoney helped the least:");
+    report.pairs.iter().rev().take(count).for_each(line);
+}
+
+/// Prints reports about teams which match the given filter, ranked by
+/// their scores under the ranking rules.  Scores are always against every
+/// team, not just those matching the filter.
+fn analyze_scores(
+    teams: &[Team],
+    results: &dyn Scores,
+    filter: &TeamFilter,
+    ranking: Ranking,
+) {
     let mut most_wins = 0.0;
     let mut best_team = 0;
 
     let mut win_percent = vec![];
-    for (k, v) in results.iter().enumerate() {
-        let mut num_wins = 0.0;
-        let mut count = 0.0;
-        for (_j, r) in v.iter().enumerate() {
-            num_wins += r.wins;
-            count += 1.0;
+    for (k, t) in teams.iter().enumerate() {
+        if !filter.matches(t) {
+            continue;
         }
-        if num_wins / count > most_wins {
+        let rate = results.score(k, ranking);
+        if rate > most_wins {
             best_team = k;
-            most_wins = num_wins / count;
+            most_wins = rate;
         }
-        win_percent.push((num_wins / count, teams[k]));
+        win_percent.push((rate, *t));
+    }
+    if !filter.is_empty() {
+        println!("{} teams match {:?}\n", win_percent.len(), filter);
+    }
+    if win_percent.is_empty() {
+        return;
     }
     win_percent.sort_by_key(|k| (-k.0 * 1000000.0) as i32);
 
+    let (label, most) = match ranking {
+        Ranking::Wins => ("Win percent", "the most wins"),
+        Ranking::Points => ("Points", "the most points"),
+        Ranking::Survival => ("Survival", "the fewest losses"),
+    };
     for i in win_percent.iter().take(10) {
-        println!("Win percent: {}\n{}\n", i.0, i.1);
+        println!("{}: {} ({})\n{}\n", label, i.0, i.1.code(), i.1);
     }
     println!(
-        "The team with the most wins ({:.2}%) [{}]:\n{}",
+        "The team with {} ({:.2}%) [{}]:\n{}",
+        most,
         most_wins * 100.0,
         best_team,
         teams[best_team]
     );
 
-    for (k, t) in win_percent.iter().rev() {
-        let mut count = 0;
-        for i in 0..TEAM_SIZE {
-            if t[i].is_some() {
-                count += 1;
-                if t[i].unwrap().modifier.is_some() {
-                    count += 1;
-                }
-            }
-        }
-        if count == 3 {
-            println!(
-                "The worst team with three friends ({:.2}%):\n{}",
-                k * 100.0,
-                t,
-            );
-            break;
+    println!("\nTeams by number of friends and perks:");
+    print_strata(&analyze_strata(teams, results, filter, ranking));
+}
+
+/// A team in a [`Stratum`]
+#[derive(Serialize)]
+struct StratumTeam {
+    index: usize,
+    code: String,
+    /// Mean score under the ranking rules (by default, the win rate)
+    score: f32,
+}
+
+/// Teams with the same number of friends and perks, for `strata`
+#[derive(Serialize)]
+struct Stratum {
+    friends: usize,
+    /// Friends carrying a perk
+    perks: usize,
+    teams: usize,
+    best: StratumTeam,
+    median: StratumTeam,
+    worst: StratumTeam,
+}
+
+/// Splits teams matching the filter by their number of friends and perks,
+/// returning the best, median and worst team of each stratum (ordered by
+/// friends, then perks)
+fn analyze_strata(
+    teams: &[Team],
+    results: &dyn Scores,
+    filter: &TeamFilter,
+    ranking: Ranking,
+) -> Vec<Stratum> {
+    let mut strata: BTreeMap<(usize, usize), Vec<(f32, usize)>> =
+        BTreeMap::new();
+    for (i, t) in teams.iter().enumerate() {
+        if !filter.matches(t) {
+            continue;
         }
+        let friends = t.iter().flatten().count();
+        let perks = t.iter().flatten().filter(|f| f.modifier.is_some()).count();
+        strata
+            .entry((friends, perks))
+            .or_default()
+            .push((results.score(i, ranking), i));
+    }
+    strata
+        .into_iter()
+        .map(|((friends, perks), mut ranked)| {
+            ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+            let team = |(score, index): (f32, usize)| StratumTeam {
+                index,
+                code: teams[index].code(),
+                score,
+            };
+            Stratum {
+                friends,
+                perks,
+                teams: ranked.len(),
+                best: team(ranked[0]),
+                median: team(ranked[ranked.len() / 2]),
+                worst: team(ranked[ranked.len() - 1]),
+            }
+        })
+        .collect()
+}
+
+/// Prints a CSV table of the best, median and worst team of each stratum
+fn print_strata(strata: &[Stratum]) {
+    println!(
+        "friends,perks,teams,best,best_score,median,median_score,\
+         worst,worst_score"
+    );
+    for s in strata {
+        println!(
+            "{},{},{},\"{}\",{:.4},\"{}\",{:.4},\"{}\",{:.4}",
+            s.friends,
+            s.perks,
+            s.teams,
+            s.best.code,
+            s.best.score,
+            s.median.code,
+            s.median.score,
+            s.worst.code,
+            s.worst.score
+        );
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-fn main() {
-    use env_logger::Builder;
+/// Unwraps a result, or prints the error and exits
+fn or_exit<T>(r: Result<T, Error>, what: &str) -> T {
+    r.unwrap_or_else(|e| {
+        eprintln!("{}: {}", what, e);
+        std::process::exit(1)
+    })
+}
 
-    let mut log = Builder::new();
+/// Returns a command-line argument, exiting with the given message if it's
+/// missing
+fn required_arg<'a>(args: &'a [String], i: usize, what: &str) -> &'a String {
+    args.get(i).unwrap_or_else(|| {
+        eprintln!("{}", what);
+        std::process::exit(1)
+    })
+}
 
-    let args = std::env::args();
-    match args.len() {
-        1 => {
-            log.filter_level(LevelFilter::Debug);
-            log.parse_env("RUST_LOG");
-            log.init();
-
-            let teams = match read_compressed(TEAMS_FILE) {
-                Some(teams) => {
-                    info!("Loading teams from cache");
-                    teams
-                }
-                None => {
-                    info!("Generating teams");
-                    let teams = generate_teams();
-                    write_compressed(&teams, TEAMS_FILE);
-                    teams
-                }
-            };
-            let scores = match read_compressed(SCORES_FILE) {
-                Some(scores) => {
-                    info!("Loading scores from cache");
-                    scores
-                }
-                None => {
-                    info!("Scoring teams");
-                    let scores = score_teams(&teams);
-                    write_compressed(&scores, SCORES_FILE);
-                    scores
+/// Parses a number from the command line, exiting with an error message if
+/// it's invalid
+fn parse_arg<T: std::str::FromStr>(s: &str, what: &str) -> T {
+    or_exit(s.parse().map_err(|_| Error::Number(s.to_owned())), what)
+}
+
+/// Saves a cache file, warning (but continuing) on failure
+fn save_cache<D: Serialize>(d: &D, f: &str) {
+    if let Err(e) = write_compressed(d, f) {
+        warn!("Failed to save cache {}: {}", f, e);
+    }
+}
+
+/// Checksums of cache files, by file name, written alongside them so that
+/// `verify` can tell whether a file was changed or replaced on its own
+type Checksums = BTreeMap<String, u64>;
+
+/// Saves a cache file along with its checksum
+fn save_checked<D: Serialize>(d: &D, f: &str) {
+    save_cache(d, f);
+    let mut sums: Checksums = read_cache(CHECKSUMS_FILE).unwrap_or_default();
+    match checksum(d) {
+        Ok(c) => {
+            sums.insert(f.to_owned(), c);
+            save_cache(&sums, CHECKSUMS_FILE);
+        }
+        Err(e) => warn!("Failed to checksum {}: {}", f, e),
+    }
+}
+
+/// Removes `name` and the argument following it from the argument list,
+/// returning that argument.
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    if i + 1 >= args.len() {
+        panic!("Missing value for {}", name);
+    }
+    let out = args.remove(i + 1);
+    args.remove(i);
+    Some(out)
+}
+
+/// Removes `name` from the argument list, returning whether it was present
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Exits if a cache which refers to custom species was written with other
+/// custom species registered, since it would now refer to the wrong ones
+fn check_custom(file: &str, custom: Option<u64>) {
+    if custom != Some(custom::checksum()) {
+        eprintln!(
+            "{} was written with other custom species; use the same \
+             --species files, or delete it",
+            file
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Loads teams from the cache, generating (and caching) them if necessary.
+/// The cache doesn't record the pruning rules, so it must be deleted after
+/// changing them.  Cached teams are still loaded under a different rule set,
+/// so that old results can be compared against a new patch.
+fn load_teams(cfg: &GameConfig, focus: &Focus) -> Vec<Team> {
+    match read_cache::<Vec<Team>>(TEAMS_FILE) {
+        Some(teams) => {
+            info!("Loading teams from cache");
+            let meta: Option<Metadata> = read_cache(METADATA_FILE);
+            // Without metadata, teams can only be trusted if neither they
+            // nor this run have custom species
+            let custom = teams
+                .iter()
+                .flat_map(|t| t.iter().flatten())
+                .any(|f| f.species.is_custom());
+            if meta.is_some() || custom || !custom::registered().is_empty() {
+                check_custom(TEAMS_FILE, meta.as_ref().map(|m| m.custom));
+            }
+            let active = &rules::active().name;
+            match meta {
+                Some(m) if m.rules != *active => warn!(
+                    "Cached teams were generated under the {} rules, \
+                     but the {} rules are active",
+                    m.rules, active
+                ),
+                Some(m) if m.focus != *focus => warn!(
+                    "Cached teams were generated with focus {:?}, \
+                     not {:?}",
+                    m.focus, focus
+                ),
+                Some(m) if m.config.challenge != cfg.challenge => warn!(
+                    "Cached teams were generated with challenge {:?}, \
+                     not {:?}",
+                    m.config.challenge, cfg.challenge
+                ),
+                _ => (),
+            }
+            teams
+        }
+        None => {
+            info!("Generating teams");
+            let (generated, meta) = generate_teams(cfg, focus);
+            let teams: Vec<Team> = generated.iter().map(|g| g.0).collect();
+            let econ: Vec<Economy> = generated.iter().map(|g| g.1).collect();
+            let prov: Vec<Provenance> = generated.iter().map(|g| g.2).collect();
+            save_checked(&teams, TEAMS_FILE);
+            save_cache(&econ, ECONOMY_FILE);
+            save_cache(&prov, PROVENANCE_FILE);
+            save_cache(&meta, METADATA_FILE);
+            teams
+        }
+    }
+}
+
+/// Deduplicates teams which are identical in battle (e.g. differing only in
+/// experience), returning the unique teams and the row of each input team
+/// among them.  Scoring only the unique teams shrinks the score matrix
+/// quadratically.
+fn battle_unique(teams: &[Team]) -> (Vec<Team>, Vec<usize>) {
+    let mut unique = vec![];
+    let mut seen = HashMap::new();
+    let rows = teams
+        .iter()
+        .map(|t| {
+            *seen.entry(t.for_battle()).or_insert_with(|| {
+                unique.push(*t);
+                unique.len() - 1
+            })
+        })
+        .collect();
+    (unique, rows)
+}
+
+/// Streams the scores cache into the flat score matrix a row at a time,
+/// returning the cache's key and row map, or `None` if there's no cache or
+/// it was scored for another key
+fn flatten_scores(key: &ScoresKey) -> Result<Option<UniqueScores<()>>, Error> {
+    use bincode::Options;
+    use serde::de::DeserializeSeed;
+    let Some(r) = open_compressed(SCORES_FILE)? else {
+        return Ok(None);
+    };
+    // Same encoding as `bincode::deserialize`, which writes a struct's
+    // fields one after the other, so the matrix follows the row map
+    let opts = bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes();
+    let mut de = bincode::Deserializer::with_reader(r, opts);
+    let index = UniqueScores::<()>::deserialize(&mut de)?;
+    if index.key != *key {
+        return Ok(None);
+    }
+    let mut out = FlatWriter::<Record>::create(SCORES_FLAT_FILE)?;
+    RowWriter(&mut out).deserialize(&mut de)?;
+    out.finish()?;
+    Ok(Some(index))
+}
+
+/// Maps the flat score matrix from disk, writing it from the scores cache
+/// (which is loaded or scored as usual) if it's missing or stale
+fn map_scores(
+    cfg: &GameConfig,
+    teams: &[Team],
+    corpus: Option<&mut CorpusFile>,
+    leaderboard: Option<usize>,
+) -> UniqueScores<FlatMatrix<Record>> {
+    let key = ScoresKey::new(cfg, teams);
+    let open = || -> Result<_, Error> {
+        let index: UniqueScores<()> = read_cache(SCORES_INDEX_FILE)
+            .ok_or_else(|| Error::Matrix("no index".to_owned()))?;
+        Ok(UniqueScores {
+            key: index.key,
+            rows: index.rows,
+            scores: FlatMatrix::open(SCORES_FLAT_FILE)?,
+        })
+    };
+    match open() {
+        Ok(m) if m.matches(&key) => {
+            info!("Mapping scores from {}", SCORES_FLAT_FILE);
+            return m;
+        }
+        Ok(_) => info!("{} is stale; rewriting it", SCORES_FLAT_FILE),
+        Err(e) => info!("Can't map {}: {}", SCORES_FLAT_FILE, e),
+    }
+
+    // Converts the scores cache if it matches, and otherwise scores the
+    // teams, writing the flat matrix from the scores in memory
+    let index = match flatten_scores(&key) {
+        Ok(index) => index,
+        Err(e) => {
+            warn!("Can't convert {}: {}", SCORES_FILE, e);
+            None
+        }
+    };
+    let index = index.unwrap_or_else(|| {
+        let s = load_scores(cfg, teams, corpus, leaderboard);
+        or_exit(
+            FlatMatrix::write(SCORES_FLAT_FILE, &s.scores),
+            "Failed to write flat scores",
+        );
+        UniqueScores {
+            key: s.key,
+            rows: s.rows,
+            scores: (),
+        }
+    });
+    save_cache(&index, SCORES_INDEX_FILE);
+    let m = UniqueScores {
+        key: index.key,
+        rows: index.rows,
+        scores: or_exit(
+            FlatMatrix::open(SCORES_FLAT_FILE),
+            "Failed to map scores",
+        ),
+    };
+    if !m.matches(&key) {
+        eprintln!("{} doesn't match its index", SCORES_FLAT_FILE);
+        std::process::exit(1);
+    }
+    m
+}
+
+/// Loads scores, either into memory or (with `--mmap`) by mapping them from
+/// disk
+fn open_scores(
+    cfg: &GameConfig,
+    teams: &[Team],
+    corpus: Option<&mut CorpusFile>,
+    mmap: bool,
+    leaderboard: Option<usize>,
+) -> Box<dyn Scores> {
+    if mmap {
+        Box::new(map_scores(cfg, teams, corpus, leaderboard))
+    } else {
+        Box::new(load_scores(cfg, teams, corpus, leaderboard))
+    }
+}
+
+/// Loads scores from the cache, scoring (and caching) them if necessary or
+/// if the cache was scored from other teams or settings.  Battles are only
+/// written to the corpus if they're actually run.
+fn load_scores(
+    cfg: &GameConfig,
+    teams: &[Team],
+    corpus: Option<&mut CorpusFile>,
+    leaderboard: Option<usize>,
+) -> UniqueScores<Vec<Vec<Record>>> {
+    let key = ScoresKey::new(cfg, teams);
+    match read_cache::<UniqueScores<Vec<Vec<Record>>>>(SCORES_FILE) {
+        Some(scores) if scores.matches(&key) => {
+            info!("Loading scores from cache");
+            if corpus.is_some() {
+                warn!("Scores are cached, so no battles will be recorded");
+            }
+            return scores;
+        }
+        Some(_) => warn!("{} is stale; rescoring", SCORES_FILE),
+        None => (),
+    }
+    let (unique, rows) = battle_unique(teams);
+    info!(
+        "Scoring {} teams ({} duplicates skipped)",
+        unique.len(),
+        teams.len() - unique.len()
+    );
+    let scores = UniqueScores {
+        key,
+        rows,
+        scores: score_teams(cfg, &unique, corpus, leaderboard),
+    };
+    save_checked(&scores, SCORES_FILE);
+    scores
+}
+
+/// A single matchup in [`Query`] output
+#[derive(Serialize)]
+struct Matchup {
+    index: usize,
+    code: String,
+    wins: f32,
+    loses: f32,
+    ties: f32,
+    rounds: f32,
+}
+
+/// Result of `query --json`, for use in scripts
+#[derive(Serialize)]
+struct Query {
+    code: String,
+    /// Index in the team cache, or `None` if the team isn't in it
+    index: Option<usize>,
+    wins: f32,
+    rank: usize,
+    teams: usize,
+    /// How the team was first built, if provenance data is cached
+    provenance: Option<Provenance>,
+    best: Vec<Matchup>,
+    worst: Vec<Matchup>,
+}
+
+/// Result of `build --json`
+#[derive(Serialize)]
+struct Build {
+    code: String,
+    turn: usize,
+    gold: usize,
+}
+
+/// Runs a shop script (read from stdin if the path is missing or `-`),
+/// starting from the turn 1 shop rolled by the given dice key
+fn scripted_shop(cfg: &GameConfig, path: Option<&str>, key: &str) -> Shop {
+    use std::io::Read;
+
+    let text = match path {
+        None | Some("-") => {
+            let mut s = String::new();
+            let r = std::io::stdin().read_to_string(&mut s);
+            or_exit(r.map_err(Error::from), "Failed to read script");
+            s
+        }
+        Some(p) => or_exit(
+            std::fs::read_to_string(p).map_err(Error::from),
+            "Failed to read script",
+        ),
+    };
+    let commands = or_exit(script::parse(&text), "Failed to parse script");
+    let mut dice =
+        or_exit(DeterministicDice::from_key(key), "Invalid dice key");
+    let mut shop = Shop::with_challenge(1, cfg.challenge, &mut dice).audited();
+    info!("Starting shop:\n{}", shop);
+    let mut timeline = Timeline::new();
+    let r = script::run(&mut shop, &commands, &mut dice, &mut timeline);
+    debug!("Script timeline:\n{}", timeline);
+    or_exit(r, "Failed to run script");
+    shop
+}
+
+/// Runs a scripted shop turn and prints the resulting team code
+fn build_team(cfg: &GameConfig, path: Option<&str>, key: &str, json: bool) {
+    let shop = scripted_shop(cfg, path, key);
+    info!("Final shop:\n{}", shop);
+    let code = shop.team.code();
+    if json {
+        let b = Build {
+            code,
+            turn: shop.turn,
+            gold: shop.gold,
+        };
+        println!("{}", serde_json::to_string(&b).unwrap());
+    } else {
+        println!("{}", code);
+    }
+}
+
+/// Result of `route --json`
+#[derive(Serialize)]
+struct RouteReport {
+    code: String,
+    /// Dice key which rolls the starting shop
+    key: String,
+    /// The route as a shop script, which can be run by `build`
+    script: String,
+    /// Gold left at the end of the route
+    gold: usize,
+}
+
+/// Most shops to explore while searching for a route
+const MAX_ROUTE_STATES: usize = 1_000_000;
+
+/// Finds the shortest route to the team with the given code, starting from
+/// the turn 1 shop rolled by the dice key, or (without a key) from every
+/// turn 1 shop which offers each species on the team
+fn route_team(cfg: &GameConfig, code: &str, key: Option<&str>, json: bool) {
+    let team: Team = or_exit(code.parse(), "Invalid team code");
+    let roots: Vec<(String, Shop)> = match key {
+        Some(k) => {
+            let mut dice =
+                or_exit(DeterministicDice::from_key(k), "Invalid dice key");
+            let shop = Shop::with_challenge(1, cfg.challenge, &mut dice);
+            vec![(k.to_owned(), shop)]
+        }
+        None => {
+            let species: Vec<Species> =
+                team.iter().flatten().map(|f| f.species).collect();
+            let mut seen = HashSet::new();
+            let mut out = vec![];
+            let mut dice = DeterministicDice::new();
+            while dice.next() {
+                let shop = Shop::with_challenge(1, cfg.challenge, &mut dice);
+                if seen.insert(shop)
+                    && species
+                        .iter()
+                        .all(|s| shop.offers().any(|f| f.species == *s))
+                {
+                    out.push((dice.key(), shop));
+                }
+            }
+            out
+        }
+    };
+    info!("Searching from {} starting shops", roots.len());
+    let shops: Vec<Shop> = roots.iter().map(|r| r.1).collect();
+    let Some(r) = route::shortest(&shops, &team, MAX_ROUTE_STATES) else {
+        eprintln!("No route found to {}", team.code());
+        std::process::exit(1);
+    };
+    let (key, start) = &roots[r.root];
+    let script = r
+        .actions
+        .iter()
+        .map(|a| Command::Action(*a).to_string())
+        .collect::<Vec<_>>()
+        .join("; ");
+    info!("Final shop:\n{}", r.shop);
+    if json {
+        let out = RouteReport {
+            code: team.code(),
+            key: key.clone(),
+            script,
+            gold: r.shop.gold,
+        };
+        println!("{}", serde_json::to_string(&out).unwrap());
+    } else {
+        println!("Starting shop (dice key {:?}):\n{}", key, start);
+        println!("{}", script);
+    }
+}
+
+/// Result of `reroll --json`
+#[derive(Serialize)]
+struct Reroll {
+    code: String,
+    gold: usize,
+    #[serde(flatten)]
+    value: RerollValue,
+    gain: f64,
+}
+
+/// Computes the expected value of rerolling a scripted shop, rating each
+/// final team by its best win rate (in any order) among the cached teams
+fn reroll_shop(teams: &[Team], results: &dyn Scores, shop: &Shop, json: bool) {
+    let rates: Vec<f32> =
+        (0..teams.len()).map(|i| results.win_rate(i)).collect();
+    // Cached teams are sorted and stored in their battle-ready form, and
+    // teams which aren't cached (e.g. dumb teams) are rated 0
+    let rate = |t: &Team| {
+        t.compact_permutations()
+            .filter_map(|p| teams.binary_search(&p.for_battle()).ok())
+            .map(|i| rates[i] as f64)
+            .fold(0.0, f64::max)
+    };
+    info!("Solving shop:\n{}", shop);
+    let Some(value) = reroll::reroll_value(shop, rate) else {
+        eprintln!("Not enough gold to reroll");
+        std::process::exit(1);
+    };
+    if json {
+        let r = Reroll {
+            code: shop.team.code(),
+            gold: shop.gold,
+            value,
+            gain: value.gain(),
+        };
+        println!("{}", serde_json::to_string(&r).unwrap());
+    } else {
+        println!(
+            "Best expected win rate without rerolling: {:.2}%",
+            value.stay * 100.0
+        );
+        println!(
+            "Expected best win rate after rerolling:   {:.2}%",
+            value.reroll * 100.0
+        );
+        println!(
+            "Rerolling gains {:+.2}%, and improves on staying {:.1}% of the \
+             time ({} possible shops)",
+            value.gain() * 100.0,
+            value.improves * 100.0,
+            value.outcomes
+        );
+    }
+}
+
+/// Prints the rating and rank of the team with the given code, along with
+/// its best and worst matchups
+fn query_team(teams: &[Team], results: &dyn Scores, code: &str, json: bool) {
+    let team: Team = or_exit(code.parse(), "Invalid team code");
+    // Cached teams are sorted and stored in their battle-ready form
+    let i = match teams.binary_search(&team.for_battle()) {
+        Ok(i) => i,
+        Err(_) if json => {
+            let q = Query {
+                code: team.code(),
+                index: None,
+                wins: 0.0,
+                rank: 0,
+                teams: teams.len(),
+                provenance: None,
+                best: vec![],
+                worst: vec![],
+            };
+            println!("{}", serde_json::to_string(&q).unwrap());
+            return;
+        }
+        Err(_) => {
+            println!("Team {} is not in the cache", team.code());
+            return;
+        }
+    };
+    let ours = results.win_rate(i);
+    let rank = (0..teams.len())
+        .filter(|k| results.win_rate(*k) > ours)
+        .count()
+        + 1;
+
+    let mut matchups: Vec<(usize, Record)> =
+        results.row(i).iter().cloned().enumerate().collect();
+    matchups.sort_by(|a, b| {
+        (b.1.wins - b.1.loses).total_cmp(&(a.1.wins - a.1.loses))
+    });
+    let best = &matchups[..matchups.len().min(3)];
+    let worst = &matchups[matchups.len().saturating_sub(3)..];
+    let provenance = read_cache::<Vec<Provenance>>(PROVENANCE_FILE)
+        .filter(|p| p.len() == teams.len())
+        .map(|p| p[i]);
+
+    if json {
+        let to_json = |ms: &[(usize, Record)]| {
+            ms.iter()
+                .map(|(j, r)| Matchup {
+                    index: *j,
+                    code: teams[*j].code(),
+                    wins: r.wins,
+                    loses: r.loses,
+                    ties: r.ties,
+                    rounds: r.rounds,
+                })
+                .collect()
+        };
+        let q = Query {
+            code: teams[i].code(),
+            index: Some(i),
+            wins: ours,
+            rank,
+            teams: teams.len(),
+            provenance,
+            best: to_json(best),
+            worst: to_json(worst),
+        };
+        println!("{}", serde_json::to_string(&q).unwrap());
+        return;
+    }
+
+    println!(
+        "Team [{}] {}:
+{}",
+        i,
+        teams[i].code(),
+        teams[i]
+    );
+    println!(
+        "Wins {:.2}% of matchups, ranked {} / {}",
+        ours * 100.0,
+        rank,
+        teams.len()
+    );
+    if let Some(p) = provenance {
+        println!(
+            "First built after {} actions; up to {} {} left unspent, \
+             {} build paths",
+            p.depth,
+            Icon::Gold,
+            p.gold,
+            p.paths
+        );
+    }
+
+    for (label, ms) in [("Best matchups", best), ("Worst matchups", worst)] {
+        println!("\n{}:", label);
+        for (j, r) in ms {
+            println!(
+                "  [{}] {}: wins {:.1}%, loses {:.1}%, ties {:.1}% \
+                 ({:.1} rounds on average)",
+                j,
+                teams[*j].code(),
+                r.wins * 100.0,
+                r.loses * 100.0,
+                r.ties * 100.0,
+                r.rounds
+            );
+        }
+    }
+}
+
+/// Looks up a team in the cache by its code, exiting if it isn't there
+fn cached_team(teams: &[Team], code: &str) -> usize {
+    let team: Team = or_exit(code.parse(), "Invalid team code");
+    // Cached teams are sorted and stored in their battle-ready form
+    teams.binary_search(&team.for_battle()).unwrap_or_else(|_| {
+        eprintln!("Team {} is not in the cache", team.code());
+        std::process::exit(1)
+    })
+}
+
+/// A team which beats another, for `counters` and `cycle`
+#[derive(Serialize)]
+struct Counter {
+    index: usize,
+    code: String,
+    /// Fraction of battles which it wins against the team it counters
+    beats: f32,
+    /// Its win rate against every team
+    wins: f32,
+}
+
+/// A team which is similar to another, for `similar`
+#[derive(Serialize)]
+struct Similar {
+    index: usize,
+    code: String,
+    distance: Distance,
+    wins: f32,
+    /// Its win rate minus that of the team it's similar to
+    delta: f32,
+}
+
+/// Lists the teams nearest to the given team (other than itself)
+fn find_similar(
+    teams: &[Team],
+    results: &dyn Scores,
+    i: usize,
+    n: usize,
+) -> Vec<Similar> {
+    let ours = results.win_rate(i);
+    Index::new(teams)
+        .nearest(&teams[i], n + 1)
+        .into_iter()
+        .filter(|(j, _)| *j != i)
+        .take(n)
+        .map(|(j, distance)| {
+            let wins = results.win_rate(j);
+            Similar {
+                index: j,
+                code: teams[j].code(),
+                distance,
+                wins,
+                delta: wins - ours,
+            }
+        })
+        .collect()
+}
+
+/// Prints similar teams, with how they differ and how their win rates
+/// compare
+fn print_similar(similar: &[Similar]) {
+    for s in similar {
+        let d = s.distance;
+        println!(
+            "  {:6.2}% ({:+6.2}%) [{} species, {} perks, {} stats, {} \
+             order] {} {}",
+            s.wins * 100.0,
+            s.delta * 100.0,
+            d.species,
+            d.perks,
+            d.stats,
+            d.order,
+            s.index,
+            s.code
+        );
+    }
+}
+
+/// A best-response chain, for `cycle`
+#[derive(Serialize)]
+struct Chain {
+    start: Counter,
+    /// Teams in the chain, each the best counter to the one before
+    counters: Vec<Counter>,
+    /// Number of teams in the cycle which ends the chain, if there is one
+    cycle: Option<usize>,
+}
+
+/// Lists the teams which beat the given team most often
+fn find_counters(
+    teams: &[Team],
+    results: &dyn Scores,
+    rates: &[f32],
+    i: usize,
+    n: usize,
+) -> Vec<Counter> {
+    let row = results.row(i);
+    results
+        .counters(i, rates, n)
+        .into_iter()
+        .map(|j| Counter {
+            index: j,
+            code: teams[j].code(),
+            beats: row[j].loses,
+            wins: rates[j],
+        })
+        .collect()
+}
+
+/// Follows best counters from the given team (or the strongest team) until
+/// a team repeats, closing a cycle, or the chain reaches `max` counters
+fn best_response_chain(
+    teams: &[Team],
+    results: &dyn Scores,
+    start: Option<usize>,
+    max: usize,
+) -> Chain {
+    let rates = results.win_rates();
+    let start = start.unwrap_or_else(|| {
+        (0..rates.len())
+            .max_by(|a, b| rates[*a].total_cmp(&rates[*b]))
+            .unwrap()
+    });
+    let mut seen = vec![start];
+    let mut counters = vec![];
+    let mut cycle = None;
+    while counters.len() < max {
+        let i = *seen.last().unwrap();
+        let Some(c) = find_counters(teams, results, &rates, i, 1).pop() else {
+            break;
+        };
+        let repeat = seen.iter().position(|k| *k == c.index);
+        seen.push(c.index);
+        counters.push(c);
+        if let Some(k) = repeat {
+            cycle = Some(seen.len() - 1 - k);
+            break;
+        }
+    }
+    Chain {
+        start: Counter {
+            index: start,
+            code: teams[start].code(),
+            beats: 0.0,
+            wins: rates[start],
+        },
+        counters,
+        cycle,
+    }
+}
+
+/// Prints a list of counters, one per line
+fn print_counters(counters: &[Counter]) {
+    for c in counters {
+        println!(
+            "  beats it {:5.1}% ({:5.2}% overall) [{}] {}",
+            c.beats * 100.0,
+            c.wins * 100.0,
+            c.index,
+            c.code
+        );
+    }
+}
+
+/// Prints a best-response chain, and the cycle at its end
+fn print_chain(chain: &Chain) {
+    println!(
+        "Best-response chain from [{}] {} ({:.2}% overall):",
+        chain.start.index,
+        chain.start.code,
+        chain.start.wins * 100.0
+    );
+    print_counters(&chain.counters);
+    match chain.cycle {
+        Some(1) => println!("The last team is its own best counter"),
+        Some(n) => {
+            println!(
+                "The last {} teams form a cycle, where each beats the one \
+                 before it (and the first beats the last):",
+                n
+            );
+            let cycle = &chain.counters[chain.counters.len() - n..];
+            for c in cycle {
+                println!("  [{}] {}", c.index, c.code);
+            }
+        }
+        None => println!("No cycle after {} counters", chain.counters.len()),
+    }
+}
+
+/// A team which survived replicator dynamics, for `evolve`
+#[derive(Serialize)]
+struct Survivor {
+    index: usize,
+    code: String,
+    share: f64,
+    /// Its win rate against every team
+    wins: f32,
+}
+
+/// Result of `evolve`, with survivors sorted by share
+#[derive(Serialize)]
+struct Metagame {
+    generations: usize,
+    converged: bool,
+    survivors: Vec<Survivor>,
+    /// Share of the final population fielding each species
+    species: Vec<(Species, f64)>,
+}
+
+/// Evolves a population of the teams matching the filter under replicator
+/// dynamics, where each team's payoff is its wins plus half its ties
+fn evolve_metagame(
+    teams: &[Team],
+    results: &dyn Scores,
+    filter: &TeamFilter,
+    settings: &replicator::Settings,
+) -> (Metagame, Evolution) {
+    let payoff: Vec<Vec<f32>> = (0..teams.len())
+        .map(|i| {
+            results
+                .row(i)
+                .iter()
+                .map(|r| r.wins + r.ties / 2.0)
+                .collect()
+        })
+        .collect();
+    let initial: Vec<f64> = teams
+        .iter()
+        .map(|t| filter.matches(t) as usize as f64)
+        .collect();
+    info!(
+        "Evolving {} teams for up to {} generations",
+        initial.iter().filter(|w| **w > 0.0).count(),
+        settings.generations
+    );
+    let evo = replicator::evolve(&payoff, &initial, settings);
+
+    let mut survivors: Vec<Survivor> = (0..teams.len())
+        .filter(|i| evo.shares[*i] > 0.0)
+        .map(|i| Survivor {
+            index: i,
+            code: teams[i].code(),
+            share: evo.shares[i],
+            wins: results.win_rate(i),
+        })
+        .collect();
+    survivors.sort_by(|a, b| b.share.total_cmp(&a.share));
+    let mut species: Vec<(Species, f64)> = Species::all()
+        .map(|s| {
+            let share = survivors
+                .iter()
+                .filter(|v| {
+                    teams[v.index].iter().flatten().any(|f| f.species == s)
+                })
+                .map(|v| v.share)
+                .sum();
+            (s, share)
+        })
+        .filter(|(_, share)| *share > 0.0)
+        .collect();
+    species.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let m = Metagame {
+        generations: evo.survivors.len(),
+        converged: evo.converged,
+        survivors,
+        species,
+    };
+    (m, evo)
+}
+
+/// Prints how the population narrowed, and which teams and species survived
+fn print_metagame(m: &Metagame, evo: &Evolution, count: usize) {
+    println!("Survivors by generation:");
+    let mut g = 1;
+    while g <= evo.survivors.len() {
+        println!("  {:5}: {}", g, evo.survivors[g - 1]);
+        g *= 10;
+    }
+    println!(
+        "\n{} teams survive after {} generations ({}):",
+        m.survivors.len(),
+        m.generations,
+        if m.converged {
+            "converged"
+        } else {
+            "still changing"
+        }
+    );
+    for v in m.survivors.iter().take(count) {
+        println!(
+            "  {:6.2}% of the population ({:5.2}% overall) [{}] {}",
+            v.share * 100.0,
+            v.wins * 100.0,
+            v.index,
+            v.code
+        );
+    }
+    println!("\nShare of the population fielding each species:");
+    for (s, share) in &m.species {
+        println!("  {:6.2}% {} {:?}", share * 100.0, s, s);
+    }
+}
+
+/// A plausible opponent, for `opponents`
+#[derive(Clone, Serialize)]
+struct Plausible {
+    /// Index in the cache, unless the team came from a meta snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<usize>,
+    code: String,
+    /// Chance of facing this team, based on how many shops built it (or its
+    /// weight in a meta snapshot)
+    probability: f64,
+    result: Expected,
+}
+
+/// A team's expected result against plausible opponents, for `opponents`
+#[derive(Serialize)]
+struct OpponentReport {
+    code: String,
+    turn: usize,
+    /// Result against a team drawn from the distribution
+    expected: Expected,
+    /// The single most likely opponent, for comparison
+    likeliest: Plausible,
+    /// Every opponent, most likely first
+    opponents: Vec<Plausible>,
+}
+
+/// Models the turn 1 opponent as every cached team which matches the
+/// filter, weighted by its number of build paths, returning the model and
+/// each opponent's index in the cache
+fn cached_opponents(
+    teams: &[Team],
+    prov: &[Provenance],
+    filter: &TeamFilter,
+) -> (Opponents, Vec<Option<usize>>) {
+    let index: Vec<usize> = (0..teams.len())
+        .filter(|i| filter.matches(&teams[*i]))
+        .collect();
+    let weights = index.iter().map(|i| (teams[*i], prov[*i].paths as f64));
+    let model = Opponents::new(1, weights).unwrap_or_else(|| {
+        eprintln!("No teams match the filter");
+        std::process::exit(1)
+    });
+    (model, index.into_iter().map(Some).collect())
+}
+
+/// Battles the given team against the whole distribution of opponents,
+/// where `index` lists each opponent's index in the cache (if any)
+fn model_opponents(
+    cfg: &GameConfig,
+    model: &Opponents,
+    index: &[Option<usize>],
+    team: &Team,
+) -> OpponentReport {
+    let mut opponents: Vec<Plausible> = index
+        .iter()
+        .zip(model.teams())
+        .zip(model.matchups(cfg, team))
+        .map(|((i, (t, p)), result)| Plausible {
+            index: *i,
+            code: t.code(),
+            probability: *p,
+            result,
+        })
+        .collect();
+    let expected =
+        Expected::mix(opponents.iter().map(|o| (o.probability, o.result)));
+    opponents.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+    OpponentReport {
+        code: team.code(),
+        turn: model.turn,
+        expected,
+        likeliest: opponents[0].clone(),
+        opponents,
+    }
+}
+
+/// Prints the expected result against the distribution, then against the
+/// most likely opponents
+fn print_opponents(r: &OpponentReport, count: usize) {
+    let line = |e: &Expected| {
+        format!(
+            "{:5.1}% wins, {:5.1}% losses, {:5.1}% ties; \
+             takes {:.2} lives, deals {:.2}",
+            e.wins * 100.0,
+            e.loses * 100.0,
+            e.ties * 100.0,
+            e.damage_taken,
+            e.damage_dealt
+        )
+    };
+    println!(
+        "{} against {} plausible opponents on turn {}:",
+        r.code,
+        r.opponents.len(),
+        r.turn
+    );
+    println!("  Distribution: {}", line(&r.expected));
+    println!(
+        "  Likeliest:    {} (vs {})",
+        line(&r.likeliest.result),
+        r.likeliest.code
+    );
+    println!("\nMost likely opponents:");
+    for o in r.opponents.iter().take(count) {
+        let index = o.index.map(|i| format!("[{}] ", i)).unwrap_or_default();
+        println!(
+            "  {:5.2}% {}{}: {}",
+            o.probability * 100.0,
+            index,
+            o.code,
+            line(&o.result)
+        );
+    }
+}
+
+/// Writes one file per snapshot into the given directory, creating it if
+/// needed
+fn save_snapshots(dir: &str, snapshots: &[Snapshot]) -> Result<(), Error> {
+    std::fs::create_dir_all(dir)?;
+    for s in snapshots {
+        let path = std::path::Path::new(dir).join(snapshot::file_name(s.turn));
+        s.save(&path.to_string_lossy())?;
+    }
+    info!("Wrote {} snapshots to {}", snapshots.len(), dir);
+    Ok(())
+}
+
+/// Prints a CSV table of the fraction of each policy's runs which are still
+/// playing on each turn, and which have been eliminated or won by the end
+/// of it
+fn print_ladder_survival(runs: &[Run], settings: &ladder::Settings) {
+    println!("turn,policy,playing,eliminated,won");
+    let last = runs.iter().map(|r| r.turns).max().unwrap_or(0);
+    for turn in 1..=last {
+        for policy in &settings.policies {
+            let mine: Vec<&Run> =
+                runs.iter().filter(|r| r.policy == *policy).collect();
+            let frac = |f: &dyn Fn(&Run) -> bool| {
+                mine.iter().filter(|r| f(r)).count() as f64
+                    / mine.len().max(1) as f64
+            };
+            println!(
+                "{},{:?},{:.4},{:.4},{:.4}",
+                turn,
+                policy,
+                frac(&|r| r.turns >= turn),
+                frac(&|r| r.lives == 0 && r.turns <= turn),
+                frac(&|r| r.trophies >= settings.trophies && r.turns <= turn),
+            );
+        }
+    }
+}
+
+/// Prints a CSV table of how each policy's runs did with each opening (the
+/// team fielded on turn 1), most common first
+fn print_ladder_openings(runs: &[Run], settings: &ladder::Settings) {
+    let mut openings: HashMap<(Policy, Team), Vec<&Run>> = HashMap::new();
+    for r in runs {
+        openings.entry((r.policy, r.opening)).or_default().push(r);
+    }
+    let mut openings: Vec<_> = openings.into_iter().collect();
+    openings
+        .sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+    println!("policy,opening,runs,mean_trophies,mean_turns,won");
+    for ((policy, team), mine) in openings {
+        let n = mine.len() as f64;
+        let mean = |f: fn(&Run) -> usize| {
+            mine.iter().map(|r| f(r)).sum::<usize>() as f64 / n
+        };
+        let won = mine
+            .iter()
+            .filter(|r| r.trophies >= settings.trophies)
+            .count();
+        println!(
+            "{:?},\"{}\",{},{:.4},{:.4},{:.4}",
+            policy,
+            team.code(),
+            mine.len(),
+            mean(|r| r.trophies),
+            mean(|r| r.turns),
+            won as f64 / n
+        );
+    }
+}
+
+/// Prints a CSV table of how each policy's runs are spread across final
+/// placements, in tenths of the ladder (1 is the top tenth)
+fn print_ladder_placements(runs: &[Run], settings: &ladder::Settings) {
+    println!("policy,decile,runs,fraction");
+    for policy in &settings.policies {
+        let mut deciles = [0; 10];
+        for r in runs.iter().filter(|r| r.policy == *policy) {
+            deciles[(r.placement - 1) * 10 / runs.len()] += 1;
+        }
+        let total: usize = deciles.iter().sum();
+        for (i, n) in deciles.iter().enumerate() {
+            println!(
+                "{:?},{},{},{:.4}",
+                policy,
+                i + 1,
+                n,
+                *n as f64 / total.max(1) as f64
+            );
+        }
+    }
+}
+
+/// Result of `diff --json`, for use in scripts
+#[derive(Serialize)]
+struct HeadToHead {
+    left: String,
+    right: String,
+    paths: usize,
+    left_wins: f32,
+    right_wins: f32,
+    ties: f32,
+    /// Expected lives lost by the left and right teams on a given turn
+    damage: Vec<(usize, f32, f32)>,
+}
+
+/// Prints two teams side by side, their per-position differences, and the
+/// outcomes of every dice path through a battle between them
+fn diff_teams(cfg: &GameConfig, a: &str, b: &str, json: bool) {
+    let a: Team = or_exit(a.parse(), "Invalid team code");
+    let b: Team = or_exit(b.parse(), "Invalid team code");
+    if !json {
+        print_differences(&a, &b);
+    }
+
+    let mut outcomes = vec![];
+    let mut dice = DeterministicDice::new();
+    while dice.next() {
+        outcomes.push(Battle(a, b).run(cfg, &mut dice));
+    }
+    let n = outcomes.len() as f32;
+    let count =
+        |w: Winner| outcomes.iter().filter(|o| o.winner == w).count() as f32;
+
+    // Expected lives lost by each side, averaged over every dice path
+    let damage = |w: Winner, turn: usize| {
+        outcomes
+            .iter()
+            .filter(|o| o.winner == w)
+            .map(|o| o.damage(cfg.damage, turn))
+            .sum::<usize>() as f32
+            / n
+    };
+    let damage: Vec<(usize, f32, f32)> = [1, 3, 5]
+        .into_iter()
+        .map(|t| (t, damage(Winner::TeamB, t), damage(Winner::TeamA, t)))
+        .collect();
+
+    if json {
+        let h = HeadToHead {
+            left: a.code(),
+            right: b.code(),
+            paths: outcomes.len(),
+            left_wins: count(Winner::TeamA) / n,
+            right_wins: count(Winner::TeamB) / n,
+            ties: count(Winner::Tied) / n,
+            damage,
+        };
+        println!("{}", serde_json::to_string(&h).unwrap());
+        return;
+    }
+
+    println!(
+        "\nHead to head over {} dice paths: \
+         left wins {:.1}%, right wins {:.1}%, ties {:.1}%",
+        outcomes.len(),
+        count(Winner::TeamA) / n * 100.0,
+        count(Winner::TeamB) / n * 100.0,
+        count(Winner::Tied) / n * 100.0
+    );
+    for (turn, left, right) in damage {
+        println!(
+            "Expected damage on turn {}: left takes {:.2}, right takes {:.2}",
+            turn, left, right
+        );
+    }
+}
+
+/// Prints every dice path through a battle, then the chance of each winner
+fn print_branches(branches: &[Branch]) {
+    let width = branches.iter().map(|b| b.key.len()).max().unwrap_or(0);
+    let name = |w: Winner| match w {
+        Winner::TeamA => "A wins",
+        Winner::TeamB => "B wins",
+        Winner::Tied => "tie",
+    };
+    for b in branches {
+        print!(
+            "[{:width$}] {:>7.3}% {:>6} in {} rounds",
+            b.key,
+            b.probability * 100.0,
+            name(b.winner),
+            b.rounds,
+            width = width
+        );
+        match &b.pivot {
+            Some(p) => println!(": {}", p),
+            None => println!(),
+        }
+    }
+    let chance = |w: Winner| {
+        branches
+            .iter()
+            .filter(|b| b.winner == w)
+            .fold(0.0, |sum, b| sum + b.probability)
+    };
+    println!(
+        "\n{} dice paths: A wins {:.1}%, B wins {:.1}%, ties {:.1}%",
+        branches.len(),
+        chance(Winner::TeamA) * 100.0,
+        chance(Winner::TeamB) * 100.0,
+        chance(Winner::Tied) * 100.0
+    );
+}
+
+/// Prints each decision, along with team A's chance of winning after each
+/// of its options
+fn print_decisions(decisions: &[Decision]) {
+    if decisions.is_empty() {
+        println!("No roll changes the chance of winning");
+    }
+    for d in decisions {
+        let roll = &d.choices[0].roll;
+        print!(
+            "[{}] roll {} in {:?}",
+            d.prefix, roll.index, roll.roll.range
+        );
+        if let Some(t) = &roll.trigger {
+            print!(" for {}", t);
+        }
+        let (worst, best) = d.range();
+        if d.swing() > 0.0 {
+            print!(
+                ": swings team A from {:.1}% to {:.1}%",
+                worst * 100.0,
+                best * 100.0
+            );
+        } else {
+            print!(": doesn't change team A's chance");
+        }
+        println!(
+            " (reached {:.1}% of the time, {:.1}% to win)",
+            d.reach * 100.0,
+            d.wins * 100.0
+        );
+        for c in &d.choices {
+            print!("    picks {}", c.roll.roll.value);
+            if let Some((team, pos)) = &c.roll.targets {
+                print!(
+                    ", targeting {:?} on team {}",
+                    pos,
+                    if *team { 'A' } else { 'B' }
+                );
+            }
+            println!(
+                " ({:.1}% likely): wins {:.1}%",
+                c.probability * 100.0,
+                c.wins * 100.0
+            );
+        }
+    }
+}
+
+/// Looks up a built-in rule set by name, or loads one from a JSON file
+fn load_rules(r: &str) -> Result<RuleSet, Error> {
+    if std::path::Path::new(r).exists() {
+        RuleSet::load(r)
+    } else {
+        r.parse()
+    }
+}
+
+/// A single team in [`PatchDiff`] output
+#[derive(Serialize)]
+struct PatchTeam {
+    /// Team code under the first rule set
+    code: String,
+    before: f32,
+    after: f32,
+}
+
+/// A single species in [`PatchDiff`] output, averaged over every team which
+/// fields it
+#[derive(Serialize)]
+struct PatchSpecies {
+    species: Species,
+    teams: usize,
+    before: f32,
+    after: f32,
+}
+
+/// Result of `patch-diff`, with teams and species sorted from the biggest
+/// gain in win rate to the biggest loss
+#[derive(Serialize)]
+struct PatchDiff {
+    before: String,
+    after: String,
+    teams: Vec<PatchTeam>,
+    species: Vec<PatchSpecies>,
+}
+
+/// Scores teams against each other under the given rule set (which is left
+/// active), returning each team's win rate
+fn win_rates_under(
+    cfg: &GameConfig,
+    rules: &RuleSet,
+    teams: &[Team],
+) -> Vec<f32> {
+    or_exit(rules::set_active(rules.clone()), "Invalid rule set");
+    let (unique, rows) = battle_unique(teams);
+    info!(
+        "Scoring {} teams under the {} rules",
+        unique.len(),
+        rules.name
+    );
+    let scores = score_teams(cfg, &unique, None, None);
+    rows.iter()
+        .map(|i| {
+            rows.iter().map(|j| scores[*i][*j].wins).sum::<f32>()
+                / rows.len() as f32
+        })
+        .collect()
+}
+
+/// Re-scores a fixed set of teams (built under the first rule set) under
+/// both rule sets, comparing each team's win rate
+fn patch_diff(
+    cfg: &GameConfig,
+    teams: &[Team],
+    before: RuleSet,
+    after: RuleSet,
+) -> PatchDiff {
+    let patched: Vec<Team> =
+        teams.iter().map(|t| before.translate(&after, t)).collect();
+    let old = win_rates_under(cfg, &before, teams);
+    let new = win_rates_under(cfg, &after, &patched);
+
+    let mut out: Vec<PatchTeam> = teams
+        .iter()
+        .zip(old.iter().zip(&new))
+        .map(|(t, (before, after))| PatchTeam {
+            code: t.code(),
+            before: *before,
+            after: *after,
+        })
+        .collect();
+    out.sort_by(|a, b| (b.after - b.before).total_cmp(&(a.after - a.before)));
+
+    let mut species: Vec<PatchSpecies> = Species::all()
+        .filter_map(|s| {
+            let fielded: Vec<usize> = (0..teams.len())
+                .filter(|i| teams[*i].iter().flatten().any(|f| f.species == s))
+                .collect();
+            let n = fielded.len() as f32;
+            (!fielded.is_empty()).then(|| PatchSpecies {
+                species: s,
+                teams: fielded.len(),
+                before: fielded.iter().map(|i| old[*i]).sum::<f32>() / n,
+                after: fielded.iter().map(|i| new[*i]).sum::<f32>() / n,
+            })
+        })
+        .collect();
+    species
+        .sort_by(|a, b| (b.after - b.before).total_cmp(&(a.after - a.before)));
+    PatchDiff {
+        before: before.name,
+        after: after.name,
+        teams: out,
+        species,
+    }
+}
+
+/// Prints the species changes and the teams which gained or lost the most
+fn print_patch_diff(diff: &PatchDiff, count: usize) {
+    let line = |code: &str, before: f32, after: f32| {
+        println!(
+            "  {:+6.2}% ({:.2}% → {:.2}%) {}",
+            (after - before) * 100.0,
+            before * 100.0,
+            after * 100.0,
+            code
+        )
+    };
+    println!(
+        "Win rates from the {} rules to the {} rules, by species:",
+        diff.before, diff.after
+    );
+    for s in &diff.species {
+        line(
+            &format!("{} {:?} ({} teams)", s.species, s.species, s.teams),
+            s.before,
+            s.after,
+        );
+    }
+    println!("\nTeams which gained the most:");
+    for t in diff.teams.iter().take(count) {
+        line(&t.code, t.before, t.after);
+    }
+    println!("\nTeams which lost the most:");
+    for t in diff.teams.iter().rev().take(count) {
+        line(&t.code, t.before, t.after);
+    }
+}
+
+/// A team's rating against the gauntlet, for `gauntlet`
+#[derive(Serialize)]
+struct GauntletTeam {
+    code: String,
+    wins: f32,
+}
+
+/// Result of `gauntlet`, with teams sorted from strongest to weakest
+#[derive(Serialize)]
+struct Gauntlet {
+    /// Number of reference teams in the gauntlet
+    size: usize,
+    teams: Vec<GauntletTeam>,
+}
+
+/// Reference teams saved by `gauntlet save` or `gauntlet random`
+#[derive(Deserialize, Serialize)]
+struct SavedGauntlet {
+    /// From [`custom::checksum`]
+    custom: u64,
+    teams: Vec<Team>,
+}
+
+/// Saves a gauntlet for later runs
+fn save_gauntlet(gauntlet: &[Team]) {
+    info!("Saving {} teams to {}", gauntlet.len(), GAUNTLET_FILE);
+    let saved = SavedGauntlet {
+        custom: custom::checksum(),
+        teams: gauntlet.to_vec(),
+    };
+    or_exit(
+        write_compressed(&saved, GAUNTLET_FILE),
+        "Failed to save gauntlet",
+    );
+}
+
+/// Rates each team by its win rate against the gauntlet alone, which is an
+/// approximation of its full rating that scales linearly (rather than
+/// quadratically) with the number of teams
+fn run_gauntlet(
+    cfg: &GameConfig,
+    teams: &[Team],
+    gauntlet: &[Team],
+) -> Gauntlet {
+    let (unique, rows) = battle_unique(teams);
+    info!(
+        "Scoring {} teams against a gauntlet of {}",
+        unique.len(),
+        gauntlet.len()
+    );
+    let wins: Vec<f32> = unique
+        .iter()
+        .map(|a| {
+            gauntlet
+                .iter()
+                .map(|b| score_matchup(cfg, a, b, &mut None).wins)
+                .sum::<f32>()
+                / gauntlet.len() as f32
+        })
+        .collect();
+    let mut out: Vec<GauntletTeam> = teams
+        .iter()
+        .zip(&rows)
+        .map(|(t, i)| GauntletTeam {
+            code: t.code(),
+            wins: wins[*i],
+        })
+        .collect();
+    out.sort_by(|a, b| b.wins.total_cmp(&a.wins));
+    Gauntlet {
+        size: gauntlet.len(),
+        teams: out,
+    }
+}
+
+/// Prints the strongest teams against the gauntlet
+fn print_gauntlet(g: &Gauntlet, count: usize) {
+    println!(
+        "Strongest of {} teams against a gauntlet of {}:",
+        g.teams.len(),
+        g.size
+    );
+    for t in g.teams.iter().take(count) {
+        println!("  {:6.2}% {}", t.wins * 100.0, t.code);
+    }
+}
+
+/// How the active rule set changes a single species, for `rules`
+#[derive(Serialize)]
+struct RuleChange {
+    species: Species,
+    /// Base `[attack, health]` under the current and active rules, if they
+    /// differ
+    stats: Option<([usize; 2], [usize; 2])>,
+    /// Ability text at each level under the current and active rules, for
+    /// levels where they differ
+    abilities: Vec<(usize, String, String)>,
+}
+
+/// Compares the active rule set against the current rules
+fn rule_changes() -> Vec<RuleChange> {
+    Species::all()
+        .filter_map(|s| {
+            let (h0, a0) = s.current_power();
+            let (h1, a1) = s.default_power();
+            let stats = ((h0, a0) != (h1, a1)).then_some(([a0, h0], [a1, h1]));
+            let abilities: Vec<_> = (1..=3)
+                .filter_map(|level| {
+                    let old = s.current_ability_params(level);
+                    let new = s.ability_params(level);
+                    (old != new).then(|| {
+                        (
+                            level,
+                            s.describe_ability(level, old),
+                            s.describe_ability(level, new),
+                        )
+                    })
+                })
+                .collect();
+            (stats.is_some() || !abilities.is_empty()).then_some(RuleChange {
+                species: s,
+                stats,
+                abilities,
+            })
+        })
+        .collect()
+}
+
+fn print_rule_changes(diff: &[RuleChange]) {
+    let name = &rules::active().name;
+    if diff.is_empty() {
+        println!("The {} rules match the current rules", name);
+    } else {
+        println!("Changes in the {} rules:", name);
+    }
+    for c in diff {
+        println!("{} {:?}", c.species, c.species);
+        if let Some(([a0, h0], [a1, h1])) = c.stats {
+            println!("    stats: {}/{} → {}/{}", a0, h0, a1, h1);
+        }
+        for (level, old, new) in &c.abilities {
+            println!("    L{}: {}\n     → {}", level, old, new);
+        }
+    }
+}
+
+/// Prints a seed sweep as a table, warning if the unweighted average looks
+/// misleading
+fn print_sweep(sweep: &Sweep) {
+    println!(
+        "Sampled {} seeds (from {}) against {} dice paths",
+        sweep.seeds, sweep.first_seed, sweep.paths
+    );
+    println!(
+        "{:>8} {:>9} {:>9} {:>9} {:>9} {:>7}",
+        "", "sampled", "std err", "paths", "weighted", "z"
+    );
+    for s in &sweep.spread {
+        let label = match s.winner {
+            Winner::TeamA => "wins",
+            Winner::TeamB => "loses",
+            Winner::Tied => "ties",
+        };
+        println!(
+            "{:>8} {:>8.2}% {:>8.2}% {:>8.2}% {:>8.2}% {:>7.2}",
+            label,
+            sweep.sampled.rate(s.winner) * 100.0,
+            s.std_error * 100.0,
+            sweep.unweighted.rate(s.winner) * 100.0,
+            sweep.weighted.rate(s.winner) * 100.0,
+            s.z
+        );
+    }
+    println!(
+        "{:>8} {:>9.2} {:>9} {:>9.2} {:>9.2}",
+        "rounds",
+        sweep.sampled.rounds,
+        "",
+        sweep.unweighted.rounds,
+        sweep.weighted.rounds
+    );
+    println!(
+        "{:>8} {:>9.2} {:>9} {:>9.2} {:>9.2}",
+        "(var)",
+        sweep.sampled.rounds_variance,
+        "",
+        sweep.unweighted.rounds_variance,
+        sweep.weighted.rounds_variance
+    );
+    if sweep.is_suspicious() {
+        warn!(
+            "Sampled rates are {:.1} standard errors from the unweighted \
+             average over dice paths, which may be misleading here",
+            sweep.max_z()
+        );
+    }
+}
+
+/// Prints two teams side by side, then their per-position differences
+fn print_differences(a: &Team, b: &Team) {
+    let left = format!("{}", a);
+    let right = format!("{}", b);
+    for (l, r) in left.split('\n').zip(right.split('\n')) {
+        println!("{}   {}", l, r);
+    }
+
+    println!();
+    let mut same = true;
+    for i in 0..TEAM_SIZE {
+        let (f, g) = match (a[i], b[i]) {
+            (Some(f), Some(g)) => (f, g),
+            (None, None) => continue,
+            (f, g) => {
+                let name = |f: Option<Friend>| match f {
+                    Some(f) => format!("{:?}", f.species),
+                    None => "nothing".to_owned(),
+                };
+                println!("{}: {} vs. {}", i, name(f), name(g));
+                same = false;
+                continue;
+            }
+        };
+        let mut diffs = vec![];
+        if f.species != g.species {
+            diffs.push(format!("{:?} vs. {:?}", f.species, g.species));
+        }
+        let delta = |x: usize, y: usize| y as i64 - x as i64;
+        if f.total_attack() != g.total_attack() {
+            let d = delta(f.total_attack(), g.total_attack());
+            diffs.push(format!("{} {:+}", Icon::Attack, d));
+        }
+        if f.total_health() != g.total_health() {
+            let d = delta(f.total_health(), g.total_health());
+            diffs.push(format!("{} {:+}", Icon::Health, d));
+        }
+        if f.exp != g.exp {
+            diffs.push(format!("exp {:+}", delta(f.exp, g.exp)));
+        }
+        if f.modifier != g.modifier {
+            let perk = |m: Option<Modifier>| match m {
+                Some(m) => format!("{:?}", m),
+                None => "no perk".to_owned(),
+            };
+            diffs.push(format!(
+                "{} vs. {}",
+                perk(f.modifier),
+                perk(g.modifier)
+            ));
+        }
+        if !diffs.is_empty() {
+            println!("{}: {}", i, diffs.join(", "));
+            same = false;
+        }
+    }
+    if same {
+        println!("Teams are identical");
+    }
+}
+
+/// Size of a cache file on disk and its estimated size once loaded
+#[derive(Serialize)]
+struct CacheSize {
+    file: &'static str,
+    /// `None` if the file doesn't exist
+    disk: Option<u64>,
+    memory: Option<usize>,
+}
+
+/// Result of `stats --json`, for use in scripts
+#[derive(Serialize)]
+struct Stats {
+    teams: Option<usize>,
+    /// Rows and columns in the score matrix, which only has a row for each
+    /// battle-unique team
+    scores: Option<(usize, usize)>,
+    /// Fraction of the teams × teams matchups which have been scored
+    density: Option<f32>,
+    /// Fraction of scored battle-unique matchups which aren't always tied
+    decisive: Option<f32>,
+    caches: Vec<CacheSize>,
+    metadata: Option<Metadata>,
+}
+
+/// Reports on the cache files, without generating anything that's missing
+fn cache_stats() -> Stats {
+    let disk = |f: &str| std::fs::metadata(f).ok().map(|m| m.len());
+    let vec_size =
+        |n: usize, item: usize| std::mem::size_of::<Vec<()>>() + n * item;
+
+    let teams: Option<Vec<Team>> = read_cache(TEAMS_FILE);
+    let scores: Option<UniqueScores<Vec<Vec<Record>>>> =
+        read_cache(SCORES_FILE);
+    let index: Option<UniqueScores<()>> = read_cache(SCORES_INDEX_FILE);
+    let econ: Option<Vec<Economy>> = read_cache(ECONOMY_FILE);
+    let metadata: Option<Metadata> = read_cache(METADATA_FILE);
+    let prov: Option<Vec<Provenance>> = read_cache(PROVENANCE_FILE);
+    let gauntlet: Option<SavedGauntlet> = read_cache(GAUNTLET_FILE);
+    let sums: Option<Checksums> = read_cache(CHECKSUMS_FILE);
+
+    let num_teams = teams.as_ref().map(Vec::len);
+    // Every team with a row in the matrix has been scored against every
+    // other such team
+    let scored = scores.as_ref().map(|s| {
+        let n = s.scores.len();
+        s.rows.iter().filter(|r| **r < n).count()
+    });
+    let density = match (num_teams, scored) {
+        (Some(0), _) => None,
+        (Some(n), Some(m)) => Some((m * m) as f32 / (n * n) as f32),
+        _ => None,
+    };
+    let matchups = scores
+        .as_ref()
+        .map(|s| s.scores.iter().map(Vec::len).sum::<usize>());
+    let decisive = match (&scores, matchups) {
+        (Some(s), Some(m)) if m > 0 => {
+            let n = s.scores.iter().flatten().filter(|r| r.ties < 1.0).count();
+            Some(n as f32 / m as f32)
+        }
+        _ => None,
+    };
+
+    let caches = vec![
+        CacheSize {
+            file: TEAMS_FILE,
+            disk: disk(TEAMS_FILE),
+            memory: num_teams.map(|n| vec_size(n, std::mem::size_of::<Team>())),
+        },
+        CacheSize {
+            file: SCORES_FILE,
+            disk: disk(SCORES_FILE),
+            memory: scores.as_ref().map(|s| {
+                let row = |r: &Vec<Record>| {
+                    vec_size(r.len(), std::mem::size_of::<Record>())
+                };
+                vec_size(s.rows.len(), std::mem::size_of::<usize>())
+                    + vec_size(0, 0)
+                    + s.scores.iter().map(row).sum::<usize>()
+            }),
+        },
+        CacheSize {
+            file: SCORES_FLAT_FILE,
+            disk: disk(SCORES_FLAT_FILE),
+            // Mapped rather than loaded
+            memory: FlatMatrix::<Record>::open(SCORES_FLAT_FILE)
+                .ok()
+                .map(|_| std::mem::size_of::<FlatMatrix<Record>>()),
+        },
+        CacheSize {
+            file: SCORES_INDEX_FILE,
+            disk: disk(SCORES_INDEX_FILE),
+            memory: index
+                .as_ref()
+                .map(|i| vec_size(i.rows.len(), std::mem::size_of::<usize>())),
+        },
+        CacheSize {
+            file: ECONOMY_FILE,
+            disk: disk(ECONOMY_FILE),
+            memory: econ
+                .as_ref()
+                .map(|e| vec_size(e.len(), std::mem::size_of::<Economy>())),
+        },
+        CacheSize {
+            file: PROVENANCE_FILE,
+            disk: disk(PROVENANCE_FILE),
+            memory: prov
+                .as_ref()
+                .map(|p| vec_size(p.len(), std::mem::size_of::<Provenance>())),
+        },
+        CacheSize {
+            file: METADATA_FILE,
+            disk: disk(METADATA_FILE),
+            memory: metadata.as_ref().map(|_| std::mem::size_of::<Metadata>()),
+        },
+        CacheSize {
+            file: GAUNTLET_FILE,
+            disk: disk(GAUNTLET_FILE),
+            memory: gauntlet
+                .as_ref()
+                .map(|g| vec_size(g.teams.len(), std::mem::size_of::<Team>())),
+        },
+        CacheSize {
+            file: CHECKSUMS_FILE,
+            disk: disk(CHECKSUMS_FILE),
+            memory: sums.as_ref().map(|_| std::mem::size_of::<Checksums>()),
+        },
+    ];
+    Stats {
+        teams: num_teams,
+        scores: scores
+            .as_ref()
+            .map(|s| (s.scores.len(), s.scores.first().map_or(0, Vec::len))),
+        density,
+        decisive,
+        caches,
+        metadata,
+    }
+}
+
+/// Result of `estimate`, with the space which the scores would take
+#[derive(Serialize)]
+struct EstimateReport {
+    #[serde(flatten)]
+    estimate: Estimate,
+    /// Size of the flat score matrix on disk, and of the score matrix when
+    /// loaded into memory
+    scores_flat_bytes: Range,
+    scores_memory_bytes: Range,
+}
+
+fn estimate_run(
+    cfg: &GameConfig,
+    focus: &Focus,
+    settings: &estimate::Settings,
+) -> EstimateReport {
+    let estimate = estimate::estimate(cfg, focus, settings);
+    let cells = |t: f64| t * t;
+    let bytes = |size: usize| Range {
+        low: cells(estimate.teams.low) * size as f64,
+        high: cells(estimate.teams.high) * size as f64,
+    };
+    EstimateReport {
+        scores_flat_bytes: bytes(Record::SIZE),
+        scores_memory_bytes: bytes(std::mem::size_of::<Record>()),
+        estimate,
+    }
+}
+
+/// Formats a duration in seconds for humans, e.g. `3h 25m`
+fn human_seconds(s: f64) -> String {
+    let s = s.round() as u64;
+    match s {
+        0..=59 => format!("{}s", s),
+        60..=3599 => format!("{}m {}s", s / 60, s % 60),
+        _ => format!("{}h {}m", s / 3600, s / 60 % 60),
+    }
+}
+
+fn print_estimate(report: &EstimateReport) {
+    let e = &report.estimate;
+    let count = |r: Range| format!("{:.0} to {:.0}", r.low, r.high);
+    let time = |r: Range| {
+        format!("{} to {}", human_seconds(r.low), human_seconds(r.high))
+    };
+    let mb = |r: Range| format!("{:.1} to {:.1} MB", r.low / 1e6, r.high / 1e6);
+    println!(
+        "Turn {}: {} starting shops, of which {} were explored",
+        e.turn, e.roots, e.full.roots
+    );
+    for sample in [&e.half, &e.full] {
+        println!(
+            "  {:>4} starting shops: {} shops, {} teams in {:.1}s",
+            sample.roots, sample.shops, sample.teams, sample.seconds
+        );
+    }
+    println!(
+        "Generation: {} shops and {} teams, taking {}",
+        count(e.shops),
+        count(e.teams),
+        time(e.generate_seconds)
+    );
+    println!(
+        "Scoring: {} battles ({:.1} dice paths per matchup, from {} timed), \
+         taking {}",
+        count(e.battles),
+        e.paths_per_matchup,
+        e.matchups,
+        time(e.score_seconds)
+    );
+    println!(
+        "Space: {} for {}, {} in memory",
+        mb(report.scores_flat_bytes),
+        SCORES_FLAT_FILE,
+        mb(report.scores_memory_bytes)
+    );
+}
+
+fn print_stats(stats: &Stats) {
+    let or_missing = |v: Option<String>| v.unwrap_or_else(|| "-".to_owned());
+    println!("Teams: {}", or_missing(stats.teams.map(|n| n.to_string())));
+    match stats.scores {
+        Some((rows, cols)) => println!("Scores: {} × {}", rows, cols),
+        None => println!("Scores: -"),
+    }
+    println!(
+        "Matrix density: {}, decisive: {}",
+        or_missing(stats.density.map(|d| format!("{:.1}%", d * 100.0))),
+        or_missing(stats.decisive.map(|d| format!("{:.1}%", d * 100.0)))
+    );
+    println!("\n{:<16}{:>14}{:>14}", "File", "On disk", "In memory");
+    for c in &stats.caches {
+        println!(
+            "{:<16}{:>14}{:>14}",
+            c.file,
+            or_missing(c.disk.map(|d| d.to_string())),
+            or_missing(c.memory.map(|m| m.to_string()))
+        );
+    }
+    match &stats.metadata {
+        Some(m) => {
+            println!(
+                "\nGenerated by version {} in {:.1}s",
+                m.version, m.seconds
+            );
+            println!(
+                "Explored {} shops ({} finished), found {} teams \
+                 ({} after pruning)",
+                m.shops, m.finished_shops, m.seen_teams, m.teams
+            );
+            println!(
+                "Pack: {}, rules: {}, config: {:?}",
+                m.pack, m.rules, m.config
+            );
+            if !m.focus.is_empty() {
+                println!(
+                    "Focus: requiring {:?}, banning {:?} ({} shops skipped)",
+                    m.focus.require, m.focus.ban, m.pruning.unfocused
+                );
+            }
+
+            let p = &m.pruning;
+            println!(
+                "\nExpanded {} shops (peak frontier {}); skipped {} \
+                 duplicate, {} gold-dominated, and {} duplicate successor \
+                 shops; dropped {} dumb teams",
+                p.expanded,
+                p.peak_frontier,
+                p.duplicate_shops,
+                p.gold_dominated,
+                p.duplicate_successors,
+                p.dumb_teams
+            );
+            println!(
+                "Dropped {} impossible actions and {} pruned by {:?}",
+                p.impossible, p.pruned, m.config.prune
+            );
+            println!(
+                "Cut {} shops short with {:?}",
+                p.limited, m.config.limits
+            );
+            println!("Generation   Frontier   Expanded    Seconds");
+            for (i, g) in p.generations.iter().enumerate() {
+                println!(
+                    "{:<10} {:>10} {:>10} {:>10.2}",
+                    i + 1,
+                    g.frontier,
+                    g.expanded,
+                    g.seconds
+                );
+            }
+        }
+        None => println!("\nNo generation metadata"),
+    }
+}
+
+/// Returns the path of a cache file in a run's directory
+fn cache_path(dir: &str, file: &str) -> String {
+    std::path::Path::new(dir)
+        .join(file)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Result of `verify`
+#[derive(Serialize)]
+struct Verify {
+    teams: Option<usize>,
+    /// Cache files whose checksums match the ones recorded when they were
+    /// written, and those with no recorded checksum
+    checked: Vec<String>,
+    unchecked: Vec<String>,
+    /// Ways in which the cache files disagree, which is fine if empty
+    problems: Vec<String>,
+}
+
+/// Checks that the cache files in a run's directory agree with each other
+/// and with their checksums, without generating anything that's missing
+fn verify_caches(dir: &str) -> Verify {
+    let path = |f: &str| cache_path(dir, f);
+    let teams: Option<Vec<Team>> = read_cache(&path(TEAMS_FILE));
+    let scores: Option<UniqueScores<Vec<Vec<Record>>>> =
+        read_cache(&path(SCORES_FILE));
+    let sums: Checksums = read_cache(&path(CHECKSUMS_FILE)).unwrap_or_default();
+    let mut out = Verify {
+        teams: teams.as_ref().map(Vec::len),
+        checked: vec![],
+        unchecked: vec![],
+        problems: vec![],
+    };
+
+    let actual = [
+        (TEAMS_FILE, teams.as_ref().map(checksum)),
+        (SCORES_FILE, scores.as_ref().map(checksum)),
+    ];
+    for (file, sum) in actual {
+        match (sum, sums.get(file)) {
+            (None, _) => (),
+            (Some(Err(e)), _) => {
+                out.problems.push(format!("Can't checksum {}: {}", file, e))
+            }
+            (Some(Ok(_)), None) => out.unchecked.push(file.to_owned()),
+            (Some(Ok(a)), Some(b)) if a == *b => {
+                out.checked.push(file.to_owned())
+            }
+            (Some(Ok(_)), Some(_)) => out
+                .problems
+                .push(format!("{} doesn't match its checksum", file)),
+        }
+    }
+
+    let Some(n) = out.teams else {
+        out.problems.push(format!("No teams in {}", TEAMS_FILE));
+        return out;
+    };
+    if let Some(m) = read_cache::<Metadata>(&path(METADATA_FILE)) {
+        if m.teams != n {
+            out.problems.push(format!(
+                "{} has {} teams, but {} expects {}",
+                TEAMS_FILE, n, METADATA_FILE, m.teams
+            ));
+        }
+    }
+    let econ: Option<Vec<Economy>> = read_cache(&path(ECONOMY_FILE));
+    let prov: Option<Vec<Provenance>> = read_cache(&path(PROVENANCE_FILE));
+    for (file, len) in [
+        (ECONOMY_FILE, econ.map(|e| e.len())),
+        (PROVENANCE_FILE, prov.map(|p| p.len())),
+    ] {
+        if let Some(len) = len.filter(|len| *len != n) {
+            out.problems
+                .push(format!("{} has {} entries for {} teams", file, len, n));
+        }
+    }
+
+    // Every matchup's chances of winning, losing and tying add up to one
+    let valid = |r: &Record| {
+        let chances = [r.wins, r.loses, r.ties];
+        chances.iter().all(|c| (0.0..=1.0).contains(c))
+            && (chances.iter().sum::<f32>() - 1.0).abs() < 1e-3
+    };
+    let scores = scores.filter(|s| {
+        let key = teams.as_ref().map(checksum).and_then(Result::ok);
+        let problem = if key != Some(s.key.teams) {
+            Some(format!("{} was scored from other teams", SCORES_FILE))
+        } else if s.rows.len() != n || s.scores.size().is_none() {
+            Some(format!("{} doesn't cover {} teams", SCORES_FILE, n))
+        } else if s.rows.iter().any(|r| *r >= s.scores.len()) {
+            Some(format!("{} has rows outside its matrix", SCORES_FILE))
+        } else {
+            None
+        };
+        out.problems.extend(problem.clone());
+        problem.is_none()
+    });
+    if let Some(s) = &scores {
+        let invalid = s.scores.iter().flatten().filter(|r| !valid(r)).count();
+        if invalid > 0 {
+            out.problems.push(format!(
+                "{} has {} matchups whose chances don't add up",
+                SCORES_FILE, invalid
+            ));
+        }
+    }
+    if std::path::Path::new(&path(SCORES_FLAT_FILE)).exists() {
+        let index: Option<UniqueScores<()>> =
+            read_cache(&path(SCORES_INDEX_FILE));
+        match (FlatMatrix::<Record>::open(path(SCORES_FLAT_FILE)), index) {
+            (Ok(_), None) => out.problems.push(format!(
+                "{} has no {}",
+                SCORES_FLAT_FILE, SCORES_INDEX_FILE
+            )),
+            (Ok(m), Some(index)) => {
+                let m = UniqueScores {
+                    key: index.key,
+                    rows: index.rows,
+                    scores: m,
+                };
+                let differ = match &scores {
+                    Some(s) if m.key != s.key || m.rows != s.rows => {
+                        out.problems.push(format!(
+                            "{} doesn't match {}",
+                            SCORES_INDEX_FILE, SCORES_FILE
+                        ));
+                        0
+                    }
+                    Some(s) if m.scores.size() != s.scores.size() => {
+                        out.problems.push(format!(
+                            "{} is {} × {}, not {} × {}",
+                            SCORES_FLAT_FILE,
+                            m.scores.rows(),
+                            m.scores.cols(),
+                            s.scores.len(),
+                            s.scores.len()
+                        ));
+                        0
+                    }
+                    Some(s) => (0..s.scores.len())
+                        .filter(|i| m.scores.row(*i) != s.scores[*i])
+                        .count(),
+                    None => 0,
+                };
+                if differ > 0 {
+                    out.problems.push(format!(
+                        "{} rows of {} differ from {}",
+                        differ, SCORES_FLAT_FILE, SCORES_FILE
+                    ));
                 }
-            };
+            }
+            (Err(e), _) => out
+                .problems
+                .push(format!("Can't map {}: {}", SCORES_FLAT_FILE, e)),
+        }
+    }
+    out
+}
+
+fn print_verify(v: &Verify) {
+    match v.teams {
+        Some(n) => println!("Teams: {}", n),
+        None => println!("Teams: -"),
+    }
+    if !v.checked.is_empty() {
+        println!("Checksums match for {}", v.checked.join(", "));
+    }
+    if !v.unchecked.is_empty() {
+        println!("No checksums recorded for {}", v.unchecked.join(", "));
+    }
+    if v.problems.is_empty() {
+        println!("Caches are consistent");
+    }
+    for p in &v.problems {
+        println!("Problem: {}", p);
+    }
+}
+
+/// A team whose scores changed between two runs, for `verify`
+#[derive(Serialize)]
+struct TeamChange {
+    code: String,
+    /// Matchups against teams in both runs which changed
+    matchups: usize,
+    /// Win rate against teams in both runs, in the other run and this one
+    before: f32,
+    after: f32,
+}
+
+/// Differences between this run and another, for `verify`
+#[derive(Serialize)]
+struct RunDiff {
+    /// Battle-ready teams which are only in this run, or only in the other
+    added: Vec<String>,
+    removed: Vec<String>,
+    /// Teams in both runs
+    shared: usize,
+    /// Teams in both runs whose scores against each other changed, most
+    /// changed first, or `None` if either run has no scores
+    changed: Option<Vec<TeamChange>>,
+}
+
+impl RunDiff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.as_ref().is_none_or(Vec::is_empty)
+    }
+}
+
+/// Compares the teams and scores of the run in the current directory
+/// against another run's, returning `None` if either has no teams
+fn diff_runs(other: &str) -> Option<RunDiff> {
+    let load = |dir: &str| {
+        let teams: Vec<Team> = read_cache(&cache_path(dir, TEAMS_FILE))?;
+        let scores: Option<UniqueScores<Vec<Vec<Record>>>> =
+            read_cache(&cache_path(dir, SCORES_FILE));
+        let key = checksum(&teams).ok();
+        let scores = scores.filter(|s| {
+            Some(s.key.teams) == key
+                && s.rows.len() == teams.len()
+                && s.in_range()
+        });
+        let mut index = HashMap::new();
+        for (i, t) in teams.iter().enumerate() {
+            index.entry(t.for_battle()).or_insert(i);
+        }
+        Some((teams, scores, index))
+    };
+    let (teams, scores, index) = load(".")?;
+    let (old_teams, old_scores, old_index) = load(other)?;
+
+    let only = |teams: &[Team], index: &HashMap<Team, usize>| {
+        let mut out: Vec<String> = teams
+            .iter()
+            .filter(|t| !index.contains_key(&t.for_battle()))
+            .map(Team::code)
+            .collect();
+        out.sort();
+        out.dedup();
+        out
+    };
+    // Pairs of indices of the same team, in this run and the other
+    let mut shared: Vec<(usize, usize)> = index
+        .iter()
+        .filter_map(|(t, i)| old_index.get(t).map(|j| (*i, *j)))
+        .collect();
+    shared.sort();
+
+    let changed = scores.zip(old_scores).map(|(s, old)| {
+        let same = |a: &Record, b: &Record| {
+            (a.wins - b.wins).abs() < 1e-5
+                && (a.loses - b.loses).abs() < 1e-5
+                && (a.ties - b.ties).abs() < 1e-5
+        };
+        let n = shared.len().max(1) as f32;
+        let mut out: Vec<TeamChange> = shared
+            .iter()
+            .map(|(i, j)| TeamChange {
+                code: teams[*i].code(),
+                matchups: shared
+                    .iter()
+                    .filter(|(k, l)| !same(&s.get(*i, *k), &old.get(*j, *l)))
+                    .count(),
+                before: shared
+                    .iter()
+                    .map(|(_, l)| old.get(*j, *l).wins)
+                    .sum::<f32>()
+                    / n,
+                after: shared
+                    .iter()
+                    .map(|(k, _)| s.get(*i, *k).wins)
+                    .sum::<f32>()
+                    / n,
+            })
+            .filter(|c| c.matchups > 0)
+            .collect();
+        out.sort_by(|a, b| {
+            let delta = |c: &TeamChange| (c.after - c.before).abs();
+            b.matchups
+                .cmp(&a.matchups)
+                .then(delta(b).total_cmp(&delta(a)))
+        });
+        out
+    });
+    Some(RunDiff {
+        added: only(&teams, &old_index),
+        removed: only(&old_teams, &index),
+        shared: shared.len(),
+        changed,
+    })
+}
+
+fn print_run_diff(diff: &RunDiff, count: usize) {
+    println!(
+        "\n{} teams in both runs, {} only in this run, {} only in the other",
+        diff.shared,
+        diff.added.len(),
+        diff.removed.len()
+    );
+    for (label, codes) in [("Added", &diff.added), ("Removed", &diff.removed)] {
+        for c in codes.iter().take(count) {
+            println!("    {}: {}", label, c);
+        }
+    }
+    match &diff.changed {
+        None => println!("Scores weren't compared, since a run has none"),
+        Some(c) if c.is_empty() => println!("Scores are unchanged"),
+        Some(changed) => {
+            println!("{} teams have changed scores:", changed.len());
+            for c in changed.iter().take(count) {
+                println!(
+                    "    {} ({} matchups): {:.2}% → {:.2}%",
+                    c.code,
+                    c.matchups,
+                    c.before * 100.0,
+                    c.after * 100.0
+                );
+            }
+        }
+    }
+}
+
+fn main() {
+    use env_logger::{Builder, Target, WriteStyle};
+
+    let mut log = Builder::new();
+
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut cfg = GameConfig::default();
+    if let Some(m) = take_flag(&mut args, "--max-rounds") {
+        cfg.max_rounds = parse_arg(&m, "Invalid --max-rounds");
+    }
+    if let Some(m) = take_flag(&mut args, "--damage-model") {
+        cfg.damage = or_exit(m.parse(), "Invalid --damage-model");
+    }
+    if let Some(m) = take_flag(&mut args, "--summon-placement") {
+        cfg.summon = or_exit(m.parse(), "Invalid --summon-placement");
+    }
+    // Custom mode rules, e.g. --challenge no-food,team-size=4
+    if let Some(c) = take_flag(&mut args, "--challenge") {
+        cfg.challenge = or_exit(c.parse(), "Invalid --challenge");
+    }
+    // Comma-separated pruning rules for generation, or "all"
+    if let Some(p) = take_flag(&mut args, "--prune") {
+        cfg.prune = or_exit(p.parse(), "Invalid --prune");
+    }
+    // Limits on how far each turn is explored, trading completeness for time
+    if let Some(m) = take_flag(&mut args, "--max-actions") {
+        cfg.limits.max_actions = Some(parse_arg(&m, "Invalid --max-actions"));
+    }
+    if let Some(m) = take_flag(&mut args, "--max-rerolls") {
+        cfg.limits.max_rerolls = Some(parse_arg(&m, "Invalid --max-rerolls"));
+    }
+    if let Some(m) = take_flag(&mut args, "--min-gold") {
+        cfg.limits.min_gold = parse_arg(&m, "Invalid --min-gold");
+    }
+    if take_switch(&mut args, "--multiset-offers") {
+        cfg.multiset_offers = true;
+    }
+    if take_switch(&mut args, "--memo-battles") {
+        cfg.memo_battles = true;
+    }
+    if let Some(m) = take_flag(&mut args, "--dumb-min-friends") {
+        cfg.dumb.min_friends = parse_arg(&m, "Invalid --dumb-min-friends");
+    }
+    if take_switch(&mut args, "--dumb-any-stats") {
+        cfg.dumb.require_stock = false;
+    }
+
+    // A built-in pack by name, or a custom pack from a JSON file
+    if let Some(p) = take_flag(&mut args, "--pack") {
+        let pack = if std::path::Path::new(&p).exists() {
+            Pack::load(&p)
+        } else {
+            p.parse()
+        };
+        let pack = or_exit(pack, "Invalid --pack");
+        or_exit(pack::set_active(pack), "Invalid --pack");
+    }
+
+    // Custom species from a JSON file, which are sold by the active pack
+    if let Some(f) = take_flag(&mut args, "--species") {
+        let species = or_exit(custom::load(&f), "Invalid --species");
+        let mut pack = pack::active().clone();
+        for s in species {
+            pack.sell(s);
+        }
+        or_exit(pack::set_active(pack), "Invalid --species");
+    }
+
+    // Limits on team generation, as comma-separated species lists
+    let mut focus = Focus::default();
+    if let Some(s) = take_flag(&mut args, "--require") {
+        focus.require =
+            or_exit(TeamFilter::parse_species(&s), "Invalid --require");
+    }
+    if let Some(s) = take_flag(&mut args, "--ban") {
+        focus.ban = or_exit(TeamFilter::parse_species(&s), "Invalid --ban");
+    }
+
+    // A built-in rule set by name, or balance changes from a JSON file
+    if let Some(r) = take_flag(&mut args, "--rules") {
+        let rules = or_exit(load_rules(&r), "Invalid --rules");
+        or_exit(rules::set_active(rules), "Invalid --rules");
+    }
+
+    // Output settings
+    render::init_from_env();
+    if take_switch(&mut args, "--ascii") {
+        render::set_ascii(true);
+    }
+    if take_switch(&mut args, "--color") {
+        render::set_color(true);
+    }
+    if take_switch(&mut args, "--no-color") {
+        render::set_color(false);
+    }
+    let json = take_switch(&mut args, "--json");
+    let mmap = take_switch(&mut args, "--mmap");
+    let leaderboard = take_flag(&mut args, "--leaderboard")
+        .map(|k| parse_arg::<usize>(&k, "Invalid --leaderboard"));
+    let draw_frames = take_switch(&mut args, "--frames");
+    let delay = match take_flag(&mut args, "--delay") {
+        Some(d) => parse_arg(&d, "Invalid --delay"),
+        None => 500,
+    };
+
+    // Analysis filters
+    let mut filter = TeamFilter::default();
+    let ranking: Ranking = match take_flag(&mut args, "--rank") {
+        Some(r) => or_exit(r.parse(), "Invalid --rank"),
+        None => Ranking::default(),
+    };
+    if let Some(s) = take_flag(&mut args, "--with") {
+        filter.with = or_exit(TeamFilter::parse_species(&s), "Invalid --with");
+    }
+    if let Some(s) = take_flag(&mut args, "--without") {
+        filter.without =
+            or_exit(TeamFilter::parse_species(&s), "Invalid --without");
+    }
+    if let Some(n) = take_flag(&mut args, "--max-friends") {
+        filter.max_friends = Some(parse_arg(&n, "Invalid --max-friends"));
+    }
+    filter.honey = take_switch(&mut args, "--honey");
+
+    // Training corpus, written while scoring
+    let corpus_path = take_flag(&mut args, "--corpus");
+    let mut sampling = Sampling::default();
+    if let Some(r) = take_flag(&mut args, "--corpus-rate") {
+        sampling.rate = parse_arg(&r, "Invalid --corpus-rate");
+    }
+    if let Some(m) = take_flag(&mut args, "--corpus-max") {
+        sampling.max = Some(parse_arg(&m, "Invalid --corpus-max"));
+    }
+    if let Some(s) = take_flag(&mut args, "--corpus-seed") {
+        sampling.seed = parse_arg(&s, "Invalid --corpus-seed");
+    }
+
+    // Ladder simulation
+    let mut ladder = ladder::Settings::default();
+    if let Some(p) = take_flag(&mut args, "--policies") {
+        ladder.policies = or_exit(
+            p.split(',').map(str::parse).collect(),
+            "Invalid --policies",
+        );
+    }
+    if let Some(n) = take_flag(&mut args, "--lives") {
+        ladder.lives = parse_arg(&n, "Invalid --lives");
+    }
+    if let Some(n) = take_flag(&mut args, "--trophies") {
+        ladder.trophies = parse_arg(&n, "Invalid --trophies");
+    }
+    if let Some(n) = take_flag(&mut args, "--max-turns") {
+        ladder.max_turns = parse_arg(&n, "Invalid --max-turns");
+    }
+
+    // Meta snapshots, as directories of per-turn files: injected snapshots
+    // are read from --meta, and the ladder writes its own to --snapshots
+    let meta = take_flag(&mut args, "--meta").map(|dir| {
+        or_exit(snapshot::load_dir(&dir, ladder.max_turns), "Invalid --meta")
+    });
+    let snapshot_dir = take_flag(&mut args, "--snapshots");
+
+    // Logging goes to stderr by default; with a log file, the verbose log
+    // goes there instead, so stdout and stderr stay quiet
+    let log_file = take_flag(&mut args, "--log-file");
+    let max_bytes = match take_flag(&mut args, "--log-max-bytes") {
+        Some(m) => parse_arg(&m, "Invalid --log-max-bytes"),
+        None => logfile::DEFAULT_MAX_BYTES,
+    };
+    let keep = match take_flag(&mut args, "--log-keep") {
+        Some(k) => parse_arg(&k, "Invalid --log-keep"),
+        None => logfile::DEFAULT_KEEP,
+    };
+    if let Some(path) = &log_file {
+        let file = or_exit(
+            RotatingFile::open(path, max_bytes, keep),
+            "Failed to open log file",
+        );
+        log.target(Target::Pipe(Box::new(file)));
+        log.write_style(WriteStyle::Never);
+    }
+    or_exit(interrupt::install(), "Failed to handle Ctrl-C");
+    let mut init_log = move |level: LevelFilter| {
+        let level = if log_file.is_some() {
+            level.max(LevelFilter::Debug)
+        } else {
+            level
+        };
+        log.filter_level(level);
+        log.parse_env("RUST_LOG");
+        log.init();
+    };
+
+    match args.get(1).map(String::as_str) {
+        None => {
+            init_log(LevelFilter::Debug);
+
+            let mut corpus = corpus_path.map(|p| {
+                or_exit(CorpusFile::create(&p, sampling), "Corpus error")
+            });
+            let teams = load_teams(&cfg, &focus);
+            let scores =
+                open_scores(&cfg, &teams, corpus.as_mut(), mmap, leaderboard);
+            if let Some(c) = corpus {
+                info!("Wrote {} battles to corpus", c.written());
+                or_exit(c.finish(), "Corpus error");
+            }
             info!("Analyzing scores");
-            analyze_scores(&teams, scores);
+            let econ: Option<Vec<Economy>> = read_cache(ECONOMY_FILE);
+            match econ {
+                Some(econ) if econ.len() == teams.len() => {
+                    analyze_economy(&*scores, &econ)
+                }
+                _ => info!("No economy data; skipping economy analysis"),
+            }
+            let prov: Option<Vec<Provenance>> = read_cache(PROVENANCE_FILE);
+            match prov {
+                Some(prov) if prov.len() == teams.len() => {
+                    analyze_provenance(&*scores, &prov);
+                    print_cost(&analyze_cost(
+                        cfg.challenge.gold(),
+                        &teams,
+                        &*scores,
+                        &prov,
+                        &filter,
+                    ));
+                }
+                _ => info!("No provenance data; skipping provenance analysis"),
+            }
+            analyze_scores(&teams, &*scores, &filter, ranking);
+        }
+        Some("crossval") => {
+            // crossval [count] [bridge command...]
+            init_log(LevelFilter::Info);
+
+            let count = match args.get(2) {
+                Some(c) => parse_arg(c, "Invalid matchup count"),
+                None => 100,
+            };
+            let bridge: Vec<String> = if args.len() > 3 {
+                args[3..].to_vec()
+            } else {
+                crossval::DEFAULT_BRIDGE
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            };
+            let teams = load_teams(&cfg, &focus);
+            let disagreements = or_exit(
+                crossval::run(&cfg, &teams, count, &bridge),
+                "Cross-validation failed",
+            );
+            if disagreements > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some("import") => {
+            // import <battle log>...
+            init_log(LevelFilter::Info);
+
+            let divergences: usize = args[2..]
+                .iter()
+                .map(|f| or_exit(battlelog::run(&cfg, f), "Invalid battle log"))
+                .sum();
+            if divergences > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some("fixtures") => {
+            // fixtures [--bless] [directory]
+            init_log(LevelFilter::Info);
+
+            let bless = args.iter().any(|a| a == "--bless");
+            let dir = args[2..]
+                .iter()
+                .find(|a| *a != "--bless")
+                .map(String::as_str)
+                .unwrap_or(fixture::FIXTURE_DIR);
+            if or_exit(fixture::run(&cfg, dir, bless), "Invalid fixtures") > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some("diff") => {
+            // diff <team code> <team code>
+            init_log(LevelFilter::Info);
+
+            let a = required_arg(&args, 2, "Missing team code");
+            let b = required_arg(&args, 3, "Missing team code");
+            diff_teams(&cfg, a, b, json);
+        }
+        Some("explore") => {
+            // explore <team code> <team code>
+            init_log(LevelFilter::Info);
+
+            let a = required_arg(&args, 2, "Missing team code");
+            let b = required_arg(&args, 3, "Missing team code");
+            let a: Team = or_exit(a.parse(), "Invalid team code");
+            let b: Team = or_exit(b.parse(), "Invalid team code");
+            let branches = explore::explore(&cfg, Battle(a, b));
+            if json {
+                println!("{}", serde_json::to_string(&branches).unwrap());
+            } else {
+                println!("{}", Battle(a, b));
+                print_branches(&branches);
+            }
+        }
+        Some("sensitivity") => {
+            // sensitivity <team code> <team code> [count]
+            init_log(LevelFilter::Info);
+
+            let a = required_arg(&args, 2, "Missing team code");
+            let b = required_arg(&args, 3, "Missing team code");
+            let a: Team = or_exit(a.parse(), "Invalid team code");
+            let b: Team = or_exit(b.parse(), "Invalid team code");
+            let count = match args.get(4) {
+                Some(c) => parse_arg(c, "Invalid decision count"),
+                None => 10,
+            };
+            let mut decisions = explore::sensitivity(&cfg, Battle(a, b));
+            decisions.truncate(count);
+            if json {
+                println!("{}", serde_json::to_string(&decisions).unwrap());
+            } else {
+                print_decisions(&decisions);
+            }
+        }
+        Some("sweep") => {
+            // sweep <team code> <team code> [seeds] [first seed]
+            init_log(LevelFilter::Info);
+
+            let a = required_arg(&args, 2, "Missing team code");
+            let b = required_arg(&args, 3, "Missing team code");
+            let a: Team = or_exit(a.parse(), "Invalid team code");
+            let b: Team = or_exit(b.parse(), "Invalid team code");
+            let seeds = match args.get(4) {
+                Some(s) => parse_arg(s, "Invalid seed count"),
+                None => 10_000,
+            };
+            let first_seed = match args.get(5) {
+                Some(s) => parse_arg(s, "Invalid first seed"),
+                None => 0,
+            };
+            let sweep = Sweep::run(&cfg, Battle(a, b), seeds, first_seed);
+            if json {
+                println!("{}", serde_json::to_string(&sweep).unwrap());
+            } else {
+                print_sweep(&sweep);
+            }
+        }
+        Some("replay") => {
+            // replay <team code> <team code> [dice key] [--delay <ms>]
+            init_log(LevelFilter::Info);
+
+            let a = required_arg(&args, 2, "Missing team code");
+            let b = required_arg(&args, 3, "Missing team code");
+            let a: Team = or_exit(a.parse(), "Invalid team code");
+            let b: Team = or_exit(b.parse(), "Invalid team code");
+            let key = args.get(4).map(String::as_str).unwrap_or("");
+            let mut dice =
+                or_exit(DeterministicDice::from_key(key), "Invalid dice key");
+            let mut frames = Frames::new();
+            Battle(a, b).run_logged(&cfg, &mut dice, &mut frames);
+            frames
+                .play(
+                    &mut std::io::stdout(),
+                    std::time::Duration::from_millis(delay),
+                )
+                .expect("Failed to write replay");
+        }
+        Some("draw") => {
+            // draw <file> <team code> [team code [dice key]] [--frames]
+            init_log(LevelFilter::Info);
+
+            let path = required_arg(&args, 2, "Missing output file");
+            let a: Team = or_exit(
+                required_arg(&args, 3, "Missing team code").parse(),
+                "Invalid team code",
+            );
+            let images = match args.get(4) {
+                None => vec![Image::team(&a)],
+                Some(b) => {
+                    let b: Team = or_exit(b.parse(), "Invalid team code");
+                    let battle = Battle(a, b);
+                    if draw_frames {
+                        let key = args.get(5).map(String::as_str).unwrap_or("");
+                        let mut dice = or_exit(
+                            DeterministicDice::from_key(key),
+                            "Invalid dice key",
+                        );
+                        let mut frames = Frames::new();
+                        battle.run_logged(&cfg, &mut dice, &mut frames);
+                        Image::frames(&frames)
+                    } else {
+                        vec![Image::battle(&battle)]
+                    }
+                }
+            };
+            let path = std::path::Path::new(path);
+            let png = path.extension().is_some_and(|e| e == "png");
+            for (i, image) in images.iter().enumerate() {
+                // Frames are numbered, e.g. battle-00.svg, battle-01.svg
+                let p = if images.len() > 1 {
+                    let stem = path.file_stem().unwrap().to_string_lossy();
+                    let ext = path.extension().unwrap_or_default();
+                    let name = format!("{}-{:02}", stem, i);
+                    path.with_file_name(name).with_extension(ext)
+                } else {
+                    path.to_owned()
+                };
+                let data = if png {
+                    image.to_png()
+                } else {
+                    image.to_svg().into_bytes()
+                };
+                std::fs::write(&p, data).expect("Failed to write image");
+                info!("Wrote {:?}", p);
+            }
+        }
+        Some("graph") => {
+            // graph <file> [depth] [max nodes] [shop dice key]
+            init_log(LevelFilter::Info);
+
+            let path = required_arg(&args, 2, "Missing output file");
+            let arg = |i: usize, default: usize| match args.get(i) {
+                Some(s) => parse_arg(s, "Invalid graph argument"),
+                None => default,
+            };
+            // Without a dice key, start from every turn 1 shop
+            let roots = match args.get(5) {
+                Some(key) => {
+                    let mut dice = or_exit(
+                        DeterministicDice::from_key(key),
+                        "Invalid dice key",
+                    );
+                    vec![Shop::with_challenge(1, cfg.challenge, &mut dice)]
+                }
+                None => ShopGraph::starting_shops(1, cfg.challenge),
+            };
+            let graph = ShopGraph::explore(&roots, arg(3, 2), arg(4, 1000));
+            if graph.truncated {
+                warn!("Stopped exploring after {} shops", graph.nodes.len());
+            }
+            info!(
+                "Got {} shops and {} actions",
+                graph.nodes.len(),
+                graph.edges.len()
+            );
+            std::fs::write(path, graph.to_dot())
+                .expect("Failed to write graph");
+        }
+        Some("estimate") => {
+            // estimate [turn] [samples]
+            init_log(LevelFilter::Info);
+
+            let mut settings = estimate::Settings::default();
+            if let Some(t) = args.get(2) {
+                settings.turn = parse_arg(t, "Invalid turn");
+            }
+            if let Some(n) = args.get(3) {
+                settings.samples = parse_arg(n, "Invalid sample count");
+            }
+            let report = estimate_run(&cfg, &focus, &settings);
+            if json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                print_estimate(&report);
+            }
+        }
+        Some("stats") => {
+            // stats
+            init_log(LevelFilter::Info);
+
+            let stats = cache_stats();
+            if json {
+                println!("{}", serde_json::to_string(&stats).unwrap());
+            } else {
+                print_stats(&stats);
+            }
+        }
+        Some("verify") => {
+            // verify [other run] [count]
+            init_log(LevelFilter::Info);
+
+            let v = verify_caches(".");
+            let count = match args.get(3) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let diff = args.get(2).map(|other| match diff_runs(other) {
+                Some(d) => d,
+                None => {
+                    eprintln!("Both runs need teams in {}", TEAMS_FILE);
+                    std::process::exit(1);
+                }
+            });
+            if json {
+                let out = serde_json::json!({ "verify": v, "diff": diff });
+                println!("{}", out);
+            } else {
+                print_verify(&v);
+                if let Some(d) = &diff {
+                    print_run_diff(d, count);
+                }
+            }
+            if !v.problems.is_empty() || diff.is_some_and(|d| !d.is_empty()) {
+                std::process::exit(1);
+            }
+        }
+        Some("species") => {
+            // species
+            init_log(LevelFilter::Info);
+
+            for s in Species::all() {
+                let (health, attack) = s.default_power();
+                let tier = match s.tier() {
+                    Some(t) => format!("tier {}", t),
+                    None => "token".to_owned(),
+                };
+                let packs: Vec<&str> =
+                    s.packs().map(|p| p.name.as_str()).collect();
+                println!(
+                    "{} {:?} ({}, {}/{}, packs: {})",
+                    s,
+                    s,
+                    tier,
+                    attack,
+                    health,
+                    if packs.is_empty() {
+                        "-".to_owned()
+                    } else {
+                        packs.join(", ")
+                    }
+                );
+                for level in 1..=3 {
+                    println!("    L{}: {}", level, s.ability_text(level));
+                }
+            }
+        }
+        Some("patch-diff") => {
+            // patch-diff <rule set> <rule set> [count]
+            init_log(LevelFilter::Info);
+
+            let rules = |i: usize| {
+                let r = required_arg(&args, i, "Missing rule set");
+                or_exit(load_rules(r), "Invalid rule set")
+            };
+            let (before, after) = (rules(2), rules(3));
+            let count = match args.get(4) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let teams: Vec<Team> = load_teams(&cfg, &focus)
+                .into_iter()
+                .filter(|t| filter.matches(t))
+                .collect();
+            if !filter.is_empty() {
+                info!("{} teams match {:?}", teams.len(), filter);
+            }
+            let diff = patch_diff(&cfg, &teams, before, after);
+            if json {
+                println!("{}", serde_json::to_string(&diff).unwrap());
+            } else {
+                print_patch_diff(&diff, count);
+            }
+        }
+        Some("gauntlet") if args.get(2).map(String::as_str) == Some("save") => {
+            // gauntlet save [size]
+            init_log(LevelFilter::Info);
+
+            let size = match args.get(3) {
+                Some(s) => parse_arg(s, "Invalid gauntlet size"),
+                None => 100,
+            };
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let (mut strongest, _) = by_strength(&*scores, &teams);
+            strongest.truncate(size);
+            save_gauntlet(&strongest);
+        }
+        Some("gauntlet")
+            if args.get(2).map(String::as_str) == Some("random") =>
+        {
+            // gauntlet random [size] [seed]
+            init_log(LevelFilter::Info);
+
+            let size = match args.get(3) {
+                Some(s) => parse_arg(s, "Invalid gauntlet size"),
+                None => 100,
+            };
+            let seed = match args.get(4) {
+                Some(s) => parse_arg(s, "Invalid seed"),
+                None => 0,
+            };
+            let mut rng = StdRng::seed_from_u64(seed);
+            let constraints = Constraints::default();
+            let gauntlet: Vec<Team> = (0..size)
+                .map(|_| {
+                    let t = Team::random(&mut rng, &constraints);
+                    or_exit(t, "Invalid constraints").for_battle()
+                })
+                .collect();
+            save_gauntlet(&gauntlet);
+        }
+        Some("gauntlet") => {
+            // gauntlet [count]
+            init_log(LevelFilter::Info);
+
+            let count = match args.get(2) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let gauntlet = match read_cache::<SavedGauntlet>(GAUNTLET_FILE) {
+                Some(g) => {
+                    check_custom(GAUNTLET_FILE, Some(g.custom));
+                    g.teams
+                }
+                None => {
+                    eprintln!(
+                        "No gauntlet in {}; run `gauntlet save` or \
+                         `gauntlet random` first",
+                        GAUNTLET_FILE
+                    );
+                    std::process::exit(1);
+                }
+            };
+            let teams: Vec<Team> = load_teams(&cfg, &focus)
+                .into_iter()
+                .filter(|t| filter.matches(t))
+                .collect();
+            if !filter.is_empty() {
+                info!("{} teams match {:?}", teams.len(), filter);
+            }
+            let g = run_gauntlet(&cfg, &teams, &gauntlet);
+            if json {
+                println!("{}", serde_json::to_string(&g).unwrap());
+            } else {
+                print_gauntlet(&g, count);
+            }
+        }
+        Some("cost") => {
+            // cost
+            init_log(LevelFilter::Info);
+
+            let teams = load_teams(&cfg, &focus);
+            let prov = read_cache::<Vec<Provenance>>(PROVENANCE_FILE);
+            let prov = match prov {
+                Some(p) if p.len() == teams.len() => p,
+                _ => {
+                    eprintln!("No provenance data in {}", PROVENANCE_FILE);
+                    std::process::exit(1);
+                }
+            };
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let gold = cfg.challenge.gold();
+            let report = analyze_cost(gold, &teams, &*scores, &prov, &filter);
+            if json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                print_cost(&report);
+            }
+        }
+        Some("honey") => {
+            // honey [count]
+            init_log(LevelFilter::Info);
+
+            let count = match args.get(2) {
+                Some(c) => parse_arg(c, "Invalid pair count"),
+                None => 10,
+            };
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let report = analyze_honey(&teams, &*scores, &filter);
+            if json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                print_honey(&report, count);
+            }
+        }
+        Some("database") => {
+            // database [path]
+            init_log(LevelFilter::Info);
+
+            let path = args.get(2).map(String::as_str).unwrap_or("teams.json");
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+
+            // Rate the first of each team that's identical in battle (which
+            // is the one the database keeps) against the first of every other
+            let mut firsts: Vec<usize> = vec![];
+            let mut seen = HashSet::new();
+            for (i, t) in teams.iter().enumerate() {
+                if seen.insert(database::key(t)) {
+                    firsts.push(i);
+                }
+            }
+            let rated = firsts.iter().map(|i| {
+                let row = scores.row(*i);
+                let wins: f32 = firsts.iter().map(|j| row[*j].wins).sum();
+                (teams[*i], wins / firsts.len() as f32)
+            });
+            let command = std::iter::once("super-auto-sim".to_owned())
+                .chain(std::env::args().skip(1))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let db = Database::new(
+                &command,
+                &rules::active().name,
+                &pack::active().name,
+                &cfg,
+                rated,
+            );
+            or_exit(db.save(path), "Failed to write team database");
+            info!("Wrote {} teams to {}", db.teams.len(), path);
+        }
+        Some("mirror") => {
+            // mirror [matchups] [seed] [count]
+            init_log(LevelFilter::Info);
+
+            let arg = |i: usize, default: usize, what: &str| match args.get(i) {
+                Some(s) => parse_arg(s, what),
+                None => default,
+            };
+            let matchups = arg(2, 1000, "Invalid matchup count");
+            let seed = arg(3, 0, "Invalid seed") as u64;
+            let count = arg(4, 10, "Invalid matchup count");
+            let teams: Vec<Team> = load_teams(&cfg, &focus)
+                .into_iter()
+                .filter(|t| filter.matches(t))
+                .collect();
+            if !filter.is_empty() {
+                info!("{} teams match {:?}", teams.len(), filter);
+            }
+            let report = audit_mirrors(&cfg, &teams, matchups, seed);
+            if json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                print_mirrors(&report, count);
+            }
+        }
+        Some("pairings") => {
+            // pairings
+            init_log(LevelFilter::Info);
+
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let matrix = species_pairings(&teams, &*scores);
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&matrix.pairings()).unwrap()
+                );
+            } else {
+                print_pairings(&matrix);
+            }
+        }
+        Some("strata") => {
+            // strata
+            init_log(LevelFilter::Info);
+
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let strata = analyze_strata(&teams, &*scores, &filter, ranking);
+            if json {
+                println!("{}", serde_json::to_string(&strata).unwrap());
+            } else {
+                print_strata(&strata);
+            }
+        }
+        Some("rules") => {
+            // rules
+            init_log(LevelFilter::Info);
+
+            let diff = rule_changes();
+            if json {
+                println!("{}", serde_json::to_string(&diff).unwrap());
+            } else {
+                print_rule_changes(&diff);
+            }
+        }
+        Some("query") => {
+            // query <team code>
+            init_log(LevelFilter::Info);
+
+            let code = required_arg(&args, 2, "Missing team code");
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            query_team(&teams, &*scores, code, json);
+        }
+        Some("counters") => {
+            // counters <team code> [count]
+            init_log(LevelFilter::Info);
+
+            let code = required_arg(&args, 2, "Missing team code");
+            let count = match args.get(3) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let i = cached_team(&teams, code);
+            let rates = scores.win_rates();
+            let counters = find_counters(&teams, &*scores, &rates, i, count);
+            if json {
+                println!("{}", serde_json::to_string(&counters).unwrap());
+            } else {
+                println!("Best counters to [{}] {}:", i, teams[i].code());
+                print_counters(&counters);
+            }
+        }
+        Some("similar") => {
+            // similar <team code> [count]
+            init_log(LevelFilter::Info);
+
+            let code = required_arg(&args, 2, "Missing team code");
+            let count = match args.get(3) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let i = cached_team(&teams, code);
+            let similar = find_similar(&teams, &*scores, i, count);
+            if json {
+                println!("{}", serde_json::to_string(&similar).unwrap());
+            } else {
+                println!(
+                    "Teams most similar to [{}] {} ({:.2}%):",
+                    i,
+                    teams[i].code(),
+                    scores.win_rate(i) * 100.0
+                );
+                print_similar(&similar);
+            }
+        }
+        Some("cycle") => {
+            // cycle [team code] [max steps]
+            init_log(LevelFilter::Info);
+
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let start = args.get(2).map(|c| cached_team(&teams, c));
+            let max = match args.get(3) {
+                Some(m) => parse_arg(m, "Invalid step count"),
+                None => 100,
+            };
+            let chain = best_response_chain(&teams, &*scores, start, max);
+            if json {
+                println!("{}", serde_json::to_string(&chain).unwrap());
+            } else {
+                print_chain(&chain);
+            }
+        }
+        Some("evolve") => {
+            // evolve [generations] [count]
+            init_log(LevelFilter::Info);
+
+            let mut settings = replicator::Settings::default();
+            if let Some(g) = args.get(2) {
+                settings.generations = parse_arg(g, "Invalid generations");
+            }
+            let count = match args.get(3) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            let (m, evo) =
+                evolve_metagame(&teams, &*scores, &filter, &settings);
+            if json {
+                println!("{}", serde_json::to_string(&m).unwrap());
+            } else {
+                print_metagame(&m, &evo, count);
+            }
+        }
+        Some("opponents") => {
+            // opponents <team code> [count] [turn]
+            init_log(LevelFilter::Info);
+
+            let code = required_arg(&args, 2, "Missing team code");
+            let team: Team = or_exit(code.parse(), "Invalid team code");
+            let count = match args.get(3) {
+                Some(c) => parse_arg(c, "Invalid team count"),
+                None => 10,
+            };
+            let turn = match args.get(4) {
+                Some(t) => parse_arg(t, "Invalid turn"),
+                None => 1,
+            };
+            // Injected snapshots take the place of the enumerated teams
+            let (model, index) = if let Some(meta) = &meta {
+                let Some(s) = meta.get(&turn) else {
+                    eprintln!("No meta snapshot for turn {}", turn);
+                    std::process::exit(1);
+                };
+                let model = or_exit(s.opponents(), "Invalid --meta");
+                let index = vec![None; model.teams().len()];
+                (model, index)
+            } else if turn == 1 {
+                let teams = load_teams(&cfg, &focus);
+                let prov = match read_cache(PROVENANCE_FILE) {
+                    Some(p) if Vec::len(&p) == teams.len() => p,
+                    _ => {
+                        eprintln!("No provenance data in {}", PROVENANCE_FILE);
+                        std::process::exit(1);
+                    }
+                };
+                cached_opponents(&teams, &prov, &filter)
+            } else {
+                eprintln!("Teams are only enumerated for turn 1; use --meta");
+                std::process::exit(1);
+            };
+            let report = model_opponents(&cfg, &model, &index, &team);
+            if json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                print_opponents(&report, count);
+            }
+        }
+        Some("ladder") => {
+            // ladder <survival|openings|placements> [runs] [seed]
+            init_log(LevelFilter::Info);
+
+            let table = args.get(2).map(String::as_str);
+            if let Some(n) = args.get(3) {
+                ladder.runs = parse_arg(n, "Invalid run count");
+            }
+            if let Some(s) = args.get(4) {
+                ladder.seed = parse_arg(s, "Invalid seed");
+            }
+            for s in meta.iter().flat_map(|m| m.values()) {
+                info!("Using injected meta snapshot for turn {}", s.turn);
+                let opponents = or_exit(s.opponents(), "Invalid --meta");
+                ladder.meta.insert(s.turn, opponents);
+            }
+            info!("Simulating {} runs", ladder.runs);
+            let out = ladder::simulate(&cfg, &ladder);
+            if let Some(dir) = &snapshot_dir {
+                or_exit(save_snapshots(dir, &out.snapshots), "Snapshot error");
+            }
+            if json {
+                println!("{}", serde_json::to_string(&out).unwrap());
+                return;
+            }
+            let runs = &out.runs;
+            match table {
+                Some("survival") => print_ladder_survival(runs, &ladder),
+                Some("openings") => print_ladder_openings(runs, &ladder),
+                Some("placements") => print_ladder_placements(runs, &ladder),
+                _ => {
+                    eprintln!(
+                        "Usage: ladder <survival|openings|placements> \
+                         [runs] [seed]"
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("route") => {
+            // route <team code> [dice key]
+            init_log(LevelFilter::Info);
+
+            let code = required_arg(&args, 2, "Missing team code");
+            route_team(&cfg, code, args.get(3).map(String::as_str), json);
+        }
+        Some("reroll") => {
+            // reroll [script file] [dice key]
+            init_log(LevelFilter::Info);
+
+            let path = args.get(2).map(String::as_str);
+            let key = args.get(3).map(String::as_str).unwrap_or("");
+            let shop = scripted_shop(&cfg, path, key);
+            let teams = load_teams(&cfg, &focus);
+            let scores = open_scores(&cfg, &teams, None, mmap, leaderboard);
+            reroll_shop(&teams, &*scores, &shop, json);
+        }
+        Some("build") => {
+            // build [script file] [dice key]
+            init_log(LevelFilter::Info);
+
+            let path = args.get(2).map(String::as_str);
+            let key = args.get(3).map(String::as_str).unwrap_or("");
+            build_team(&cfg, path, key, json);
+        }
+        Some("timeline") => {
+            // timeline shop <dice key>
+            // timeline battle <team index> <team index> [dice key]
+            init_log(LevelFilter::Info);
+
+            let key = |i: usize| args.get(i).map(String::as_str).unwrap_or("");
+            let mut timeline = Timeline::new();
+            match args.get(2).map(String::as_str) {
+                Some("shop") => {
+                    let mut dice = or_exit(
+                        DeterministicDice::from_key(key(3)),
+                        "Invalid dice key",
+                    );
+                    let mut dice = timeline.watch(&mut dice);
+                    let mut shop =
+                        Shop::with_challenge(1, cfg.challenge, &mut dice)
+                            .audited();
+                    println!("Starting shop:\n{}", shop);
+                    let step = loop {
+                        let s = shop.step_logged(&mut dice, &mut timeline);
+                        if s != Step::Acted {
+                            break s;
+                        }
+                    };
+                    print!("{}", timeline);
+                    if step == Step::Impossible {
+                        println!("The last action wasn't possible");
+                    }
+                    println!("Final shop:\n{}", shop);
+                }
+                Some("battle") => {
+                    let teams = load_teams(&cfg, &focus);
+                    let team = |i: usize| -> Team {
+                        let n: usize = parse_arg(key(i), "Invalid team index");
+                        *teams.get(n).unwrap_or_else(|| {
+                            eprintln!("Invalid team index: {}", n);
+                            std::process::exit(1)
+                        })
+                    };
+                    let battle = Battle(team(3), team(4));
+                    println!("{}", battle);
+                    let mut dice = or_exit(
+                        DeterministicDice::from_key(key(5)),
+                        "Invalid dice key",
+                    );
+                    let mut dice = timeline.watch(&mut dice);
+                    battle.run_logged(&cfg, &mut dice, &mut timeline);
+                    print!("{}", timeline);
+                }
+                _ => panic!("Invalid timeline arguments {:?}", &args[2..]),
+            }
         }
-        2 => {
+        Some(_) if args.len() == 2 => {
             // By default, when asked to generate a team, print the verbose
             // team generation log.
-            log.filter_level(LevelFilter::Trace);
-            log.parse_env("RUST_LOG");
-            log.init();
+            init_log(LevelFilter::Trace);
 
             //let team = random_team(seed);
             //debug!("Got team [{}]:\n{}", seed, team);
         }
-        i => {
-            panic!("Invalid argument count {}", i);
+        _ => {
+            panic!("Invalid arguments {:?}", &args[1..]);
         }
     }
 }
@@ -0,0 +1,273 @@
+//! A flat on-disk matrix, which is memory-mapped and decoded one cell at a
+//! time, so that score matrices larger than RAM can still be analyzed.
+//!
+//! The file is a header (magic bytes, then the row count, column count, and
+//! cell size as little-endian `u64`s) followed by every cell, row by row.
+
+use std::{
+    io::{BufWriter, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use serde::de::{self, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+
+use crate::error::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"SASFLAT1";
+const HEADER_SIZE: usize = MAGIC.len() + 3 * 8;
+
+/// A fixed-size value which can be stored in a [`FlatMatrix`]
+pub trait Flat: Sized {
+    /// Encoded size, in bytes
+    const SIZE: usize;
+    /// Appends exactly [`Flat::SIZE`] bytes to the output
+    fn encode(&self, out: &mut Vec<u8>);
+    /// Decodes a value from exactly [`Flat::SIZE`] bytes
+    fn decode(bytes: &[u8]) -> Self;
+}
+
+impl Flat for f32 {
+    const SIZE: usize = 4;
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes())
+    }
+    fn decode(bytes: &[u8]) -> Self {
+        f32::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl<T: Flat, const N: usize> Flat for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+    fn encode(&self, out: &mut Vec<u8>) {
+        for t in self {
+            t.encode(out);
+        }
+    }
+    fn decode(bytes: &[u8]) -> Self {
+        std::array::from_fn(|i| T::decode(&bytes[i * T::SIZE..][..T::SIZE]))
+    }
+}
+
+/// A read-only matrix, mapped from a file written by [`FlatMatrix::write`]
+pub struct FlatMatrix<T> {
+    map: memmap2::Mmap,
+    rows: usize,
+    cols: usize,
+    _t: PhantomData<T>,
+}
+
+/// Writes a [`FlatMatrix`] a row at a time, so that the whole matrix never
+/// needs to be in memory
+pub struct FlatWriter<T> {
+    out: BufWriter<std::fs::File>,
+    rows: usize,
+    /// Length of every row, once the first has been written
+    cols: Option<usize>,
+    buf: Vec<u8>,
+    _t: PhantomData<T>,
+}
+
+impl<T: Flat> FlatWriter<T> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut out = BufWriter::new(std::fs::File::create(path)?);
+        // The header is filled in by `finish`, once the size is known
+        out.write_all(&[0; HEADER_SIZE])?;
+        Ok(Self {
+            out,
+            rows: 0,
+            cols: None,
+            buf: vec![],
+            _t: PhantomData,
+        })
+    }
+
+    /// Appends a row, which must be the same length as every other row
+    pub fn push(&mut self, row: &[T]) -> Result<()> {
+        if *self.cols.get_or_insert(row.len()) != row.len() {
+            return Err(Error::Matrix("rows have different lengths".into()));
+        }
+        self.buf.clear();
+        for t in row {
+            t.encode(&mut self.buf);
+        }
+        self.out.write_all(&self.buf)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Writes the header, finishing the file
+    pub fn finish(mut self) -> Result<()> {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(MAGIC)?;
+        for n in [self.rows, self.cols.unwrap_or(0), T::SIZE] {
+            self.out.write_all(&(n as u64).to_le_bytes())?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Deserializes a sequence of rows (e.g. a serialized `Vec<Vec<T>>`) into a
+/// [`FlatWriter`], writing each row as soon as it's decoded
+pub struct RowWriter<'a, T>(pub &'a mut FlatWriter<T>);
+
+impl<'de, T: Flat + de::Deserialize<'de>> DeserializeSeed<'de>
+    for RowWriter<'_, T>
+{
+    type Value = ();
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        d: D,
+    ) -> std::result::Result<(), D::Error> {
+        d.deserialize_seq(self)
+    }
+}
+
+impl<'de, T: Flat + de::Deserialize<'de>> Visitor<'de> for RowWriter<'_, T> {
+    type Value = ();
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a sequence of rows")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<(), A::Error> {
+        while let Some(row) = seq.next_element::<Vec<T>>()? {
+            self.0.push(&row).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Flat> FlatMatrix<T> {
+    /// Writes a matrix, whose rows must all be the same length
+    pub fn write<P: AsRef<Path>>(path: P, rows: &[Vec<T>]) -> Result<()> {
+        let mut out = FlatWriter::create(path)?;
+        for r in rows {
+            out.push(r)?;
+        }
+        out.finish()
+    }
+
+    /// Maps a matrix from disk, checking its header and size
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the cache files are only written whole, by this program;
+        // if the file is modified while mapped, we may read garbage (but
+        // every cell is plain data, so garbage is still a valid value).
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        if map.len() < HEADER_SIZE || &map[..MAGIC.len()] != MAGIC {
+            return Err(Error::Matrix("bad header".into()));
+        }
+        let field = |i: usize| {
+            let start = MAGIC.len() + i * 8;
+            u64::from_le_bytes(map[start..start + 8].try_into().unwrap())
+                as usize
+        };
+        let (rows, cols, size) = (field(0), field(1), field(2));
+        if size != T::SIZE {
+            return Err(Error::Matrix(format!(
+                "expected {} byte cells, found {}",
+                T::SIZE,
+                size
+            )));
+        } else if map.len() != HEADER_SIZE + rows * cols * size {
+            return Err(Error::Matrix(format!(
+                "expected {} × {} cells, found {} bytes",
+                rows,
+                cols,
+                map.len()
+            )));
+        }
+        Ok(Self {
+            map,
+            rows,
+            cols,
+            _t: PhantomData,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Decodes a single cell
+    pub fn get(&self, row: usize, col: usize) -> T {
+        assert!(row < self.rows && col < self.cols, "Out of bounds");
+        let start = HEADER_SIZE + (row * self.cols + col) * T::SIZE;
+        T::decode(&self.map[start..start + T::SIZE])
+    }
+
+    /// Decodes a whole row
+    pub fn row(&self, row: usize) -> Vec<T> {
+        (0..self.cols).map(|col| self.get(row, col)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::Options;
+
+    #[test]
+    fn stream_rows() {
+        let path = std::env::temp_dir()
+            .join(format!("sas-stream-{}.flat", std::process::id()));
+        let m = vec![vec![1.0f32, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]];
+        let data = bincode::serialize(&m).unwrap();
+        let mut out = FlatWriter::<f32>::create(&path).unwrap();
+        let opts = bincode::options().with_fixint_encoding();
+        let mut de = bincode::Deserializer::from_slice(&data, opts);
+        RowWriter(&mut out).deserialize(&mut de).unwrap();
+        out.finish().unwrap();
+        let f = FlatMatrix::<f32>::open(&path).unwrap();
+        assert_eq!((f.rows(), f.cols()), (3, 2));
+        assert_eq!(f.row(2), vec![5.0, 6.0]);
+
+        let mut out = FlatWriter::<f32>::create(&path).unwrap();
+        out.push(&[1.0, 2.0]).unwrap();
+        assert!(out.push(&[3.0]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("sas-round-trip-{}.flat", std::process::id()));
+        for (rows, cols) in [(0, 3), (1, 0), (1, 1), (3, 2)] {
+            let m: Vec<Vec<[f32; 2]>> = (0..rows)
+                .map(|i| {
+                    (0..cols).map(|j| [i as f32, -(j as f32) / 3.0]).collect()
+                })
+                .collect();
+            FlatMatrix::write(&path, &m).unwrap();
+            let f = FlatMatrix::<[f32; 2]>::open(&path).unwrap();
+            let cols = if rows == 0 { 0 } else { cols };
+            assert_eq!((f.rows(), f.cols()), (rows, cols));
+            for (i, r) in m.iter().enumerate() {
+                assert_eq!(f.row(i), *r);
+                for (j, c) in r.iter().enumerate() {
+                    assert_eq!(f.get(i, j), *c);
+                }
+            }
+            drop(f);
+
+            // Truncated files and mismatched cell sizes are rejected
+            let data = std::fs::read(&path).unwrap();
+            if rows * cols > 0 {
+                for n in [1, 4, data.len()] {
+                    std::fs::write(&path, &data[..data.len() - n]).unwrap();
+                    assert!(FlatMatrix::<[f32; 2]>::open(&path).is_err());
+                }
+            }
+            std::fs::write(&path, &data).unwrap();
+            assert!(FlatMatrix::<f32>::open(&path).is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+//! Mirror audits, which check that battles don't depend on which side each
+//! team fights from.
+//!
+//! Battle rules are symmetric, so `Battle(a, b)` and `Battle(b, a)` should
+//! have the same outcomes with the winner swapped, and with the same
+//! chances.  Trigger resolution which (for example) always handles team A's
+//! abilities before team B's can break that symmetry, so disagreements point
+//! at ordering bugs.  Outcomes are compared by their chances rather than by
+//! their dice paths, since swapping sides may change the order in which dice
+//! are rolled.
+
+use hashbrown::HashMap;
+use serde::Serialize;
+
+use crate::{
+    battle::{Battle, Outcome, Winner},
+    config::GameConfig,
+    team::Team,
+};
+
+/// Chances of a battle's results, from the first team's point of view
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Chances {
+    pub wins: f64,
+    pub loses: f64,
+    pub ties: f64,
+}
+
+/// A matchup whose battles disagree when the teams swap sides
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Asymmetry {
+    pub a: Team,
+    pub b: Team,
+    /// Chances for team `a`, in `Battle(a, b)` and in `Battle(b, a)`
+    pub forward: Chances,
+    pub mirrored: Chances,
+    /// Largest difference between the two battles in the chance of any
+    /// outcome (including its length and survivors, not just its winner)
+    pub difference: f64,
+}
+
+/// Returns the outcome as seen from the other side
+fn mirror(outcome: Outcome) -> Outcome {
+    let winner = match outcome.winner {
+        Winner::TeamA => Winner::TeamB,
+        Winner::TeamB => Winner::TeamA,
+        Winner::Tied => Winner::Tied,
+    };
+    Outcome { winner, ..outcome }
+}
+
+/// Returns the chance of each outcome of a battle, from the first team's
+/// point of view (so that the outcomes of a mirrored battle are mirrored)
+fn chances(
+    cfg: &GameConfig,
+    a: &Team,
+    b: &Team,
+    mirrored: bool,
+) -> HashMap<Outcome, f64> {
+    let mut out = HashMap::new();
+    let battle = if mirrored {
+        Battle(*b, *a)
+    } else {
+        Battle(*a, *b)
+    };
+    for t in battle.outcomes(cfg) {
+        let o = if mirrored {
+            mirror(t.outcome)
+        } else {
+            t.outcome
+        };
+        *out.entry(o).or_default() += t.probability;
+    }
+    out
+}
+
+/// Totals the chances of each winner
+fn totals(outcomes: &HashMap<Outcome, f64>) -> Chances {
+    let mut out = Chances::default();
+    for (o, p) in outcomes {
+        match o.winner {
+            Winner::TeamA => out.wins += p,
+            Winner::TeamB => out.loses += p,
+            Winner::Tied => out.ties += p,
+        }
+    }
+    out
+}
+
+/// Runs a matchup with the teams on either side, returning how they differ
+/// if the chance of any outcome differs by more than `tolerance`
+pub fn audit(
+    cfg: &GameConfig,
+    a: &Team,
+    b: &Team,
+    tolerance: f64,
+) -> Option<Asymmetry> {
+    let forward = chances(cfg, a, b, false);
+    let mirrored = chances(cfg, a, b, true);
+    let chance =
+        |m: &HashMap<Outcome, f64>, o| m.get(o).copied().unwrap_or(0.0);
+    let difference = forward
+        .keys()
+        .chain(mirrored.keys())
+        .map(|o| (chance(&forward, o) - chance(&mirrored, o)).abs())
+        .fold(0.0, f64::max);
+    (difference > tolerance).then(|| Asymmetry {
+        a: *a,
+        b: *b,
+        forward: totals(&forward),
+        mirrored: totals(&mirrored),
+        difference,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetric_matchups() {
+        // Random targets, faint triggers and summons resolve the same way
+        // from either side
+        let cfg = GameConfig::default();
+        let team = |s: &str| s.parse::<Team>().unwrap();
+        let matchups = [
+            ("Ant:2/1,Fish:2/3", "Mosquito:2/2,Cricket:1/2"),
+            ("Mosquito:2/2:1,Horse:2/1", "Ant:2/1+Honey,Duck:2/3"),
+            ("Fish:3/3", "Fish:3/3"),
+        ];
+        for (a, b) in matchups {
+            let (a, b) = (team(a), team(b));
+            assert_eq!(audit(&cfg, &a, &b, 1e-9), None);
+
+            // With a negative tolerance, even a symmetric matchup is
+            // reported, with the same chances from either side
+            let r = audit(&cfg, &a, &b, -1.0).unwrap();
+            assert_eq!(r.difference, 0.0);
+            let close = |x: f64, y: f64| (x - y).abs() < 1e-9;
+            assert!(close(r.forward.wins, r.mirrored.wins));
+            assert!(close(r.forward.loses, r.mirrored.loses));
+            let c = r.forward;
+            assert!(close(c.wins + c.loses + c.ties, 1.0));
+
+            let s = audit(&cfg, &b, &a, -1.0).unwrap();
+            assert!(close(r.forward.wins, s.forward.loses));
+            assert!(close(r.forward.ties, s.forward.ties));
+        }
+    }
+}
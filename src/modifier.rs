@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+
 #[derive(
     Copy,
     Clone,
@@ -14,18 +16,71 @@ use serde::{Deserialize, Serialize};
 )]
 pub enum Modifier {
     Honey,
+    MeatBone,
+    Steak,
+    Melon,
+    Garlic,
+    Coconut,
+    Chili,
 }
 
 impl Modifier {
+    /// Every modifier, in declaration order
+    pub const ALL: [Modifier; 7] = [
+        Self::Honey,
+        Self::MeatBone,
+        Self::Steak,
+        Self::Melon,
+        Self::Garlic,
+        Self::Coconut,
+        Self::Chili,
+    ];
+
+    /// Returns a two-letter abbreviation, for [ASCII mode](crate::render)
+    pub fn abbrev(&self) -> &'static str {
+        match self {
+            Self::Honey => "Hn",
+            Self::MeatBone => "Mb",
+            Self::Steak => "St",
+            Self::Melon => "Ml",
+            Self::Garlic => "Ga",
+            Self::Coconut => "Cc",
+            Self::Chili => "Ch",
+        }
+    }
     pub fn emoji(&self) -> char {
         match self {
             Self::Honey => '🍯',
+            Self::MeatBone => '🍖',
+            Self::Steak => '🥩',
+            Self::Melon => '🍈',
+            Self::Garlic => '🧄',
+            Self::Coconut => '🥥',
+            Self::Chili => '🌶',
         }
     }
+
+    /// Checks whether the modifier is removed after its first use
+    pub fn is_single_use(&self) -> bool {
+        matches!(self, Self::Steak | Self::Melon | Self::Coconut)
+    }
+}
+
+impl std::str::FromStr for Modifier {
+    type Err = Error;
+
+    /// Parses a modifier from its name, e.g. `Honey`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned()))
+            .map_err(|_| Error::Modifier(s.to_owned()))
+    }
 }
 
 impl std::fmt::Display for Modifier {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if crate::render::ascii() {
+            return write!(f, "{}", self.abbrev());
+        }
         write!(f, "{}", self.emoji())
     }
 }
@@ -0,0 +1,181 @@
+//! Opponent models, which describe the opponent on a given turn as a
+//! distribution over plausible teams rather than a single fixed team.
+//!
+//! A team is plausible in proportion to how easily it's built, e.g. the
+//! number of distinct shops which reached it during enumeration.  Expected
+//! outcomes average each matchup over every dice path (as in scoring), then
+//! over the distribution by weight.
+
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    battle::{Battle, Winner},
+    config::GameConfig,
+    team::Team,
+};
+
+/// A distribution over the teams which an opponent may field on one turn
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opponents {
+    /// Turn on which the teams are fielded (starting from 1), used to work
+    /// out damage
+    pub turn: usize,
+    /// Battle-ready teams, each with its probability
+    teams: Vec<(Team, f64)>,
+}
+
+/// The expected result of battling an opponent
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Expected {
+    pub wins: f64,
+    pub loses: f64,
+    pub ties: f64,
+    /// Expected lives lost by the team
+    pub damage_taken: f64,
+    /// Expected lives lost by the opponent
+    pub damage_dealt: f64,
+}
+
+impl Opponents {
+    /// Builds a distribution from teams and their relative weights, dropping
+    /// teams with no weight.  Returns `None` if no team has any weight.
+    pub fn new<I: IntoIterator<Item = (Team, f64)>>(
+        turn: usize,
+        teams: I,
+    ) -> Option<Self> {
+        let mut teams: Vec<(Team, f64)> = teams
+            .into_iter()
+            .filter(|(_, w)| *w > 0.0)
+            .map(|(t, w)| (t.for_battle(), w))
+            .collect();
+        let total: f64 = teams.iter().map(|(_, w)| w).sum();
+        if teams.is_empty() || !total.is_finite() {
+            return None;
+        }
+        for (_, w) in &mut teams {
+            *w /= total;
+        }
+        Some(Self { turn, teams })
+    }
+
+    /// Returns a distribution which always fields the same team
+    pub fn fixed(turn: usize, team: Team) -> Self {
+        Self {
+            turn,
+            teams: vec![(team.for_battle(), 1.0)],
+        }
+    }
+
+    /// Returns every team along with its probability
+    pub fn teams(&self) -> &[(Team, f64)] {
+        &self.teams
+    }
+
+    /// Draws a team from the distribution
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Team {
+        let mut x: f64 = rng.gen();
+        for (t, p) in &self.teams {
+            if x < *p {
+                return *t;
+            }
+            x -= p;
+        }
+        // Rounding may leave a sliver past the last team
+        self.teams.last().unwrap().0
+    }
+
+    /// Returns the result of battling each team in the distribution, in the
+    /// same order as [`Opponents::teams`]
+    pub fn matchups(&self, cfg: &GameConfig, team: &Team) -> Vec<Expected> {
+        let team = team.for_battle();
+        self.teams
+            .iter()
+            .map(|(opponent, _)| matchup(cfg, &team, opponent, self.turn))
+            .collect()
+    }
+
+    /// Returns the expected result of battling a team drawn from the
+    /// distribution
+    pub fn expect(&self, cfg: &GameConfig, team: &Team) -> Expected {
+        let ps = self.teams.iter().map(|(_, p)| *p);
+        Expected::mix(ps.zip(self.matchups(cfg, team)))
+    }
+}
+
+impl Expected {
+    /// Mixes results by probability
+    pub fn mix<I: IntoIterator<Item = (f64, Expected)>>(results: I) -> Self {
+        let mut out = Self::default();
+        for (p, e) in results {
+            out.wins += p * e.wins;
+            out.loses += p * e.loses;
+            out.ties += p * e.ties;
+            out.damage_taken += p * e.damage_taken;
+            out.damage_dealt += p * e.damage_dealt;
+        }
+        out
+    }
+}
+
+/// Returns the result of a single matchup, averaged over every dice path
+fn matchup(cfg: &GameConfig, a: &Team, b: &Team, turn: usize) -> Expected {
+    let mut out = Expected::default();
+    let mut n = 0;
+    for t in Battle(*a, *b).outcomes(cfg) {
+        let paths = t.paths as f64;
+        let damage = t.outcome.damage(cfg.damage, turn) as f64 * paths;
+        match t.outcome.winner {
+            Winner::TeamA => {
+                out.wins += paths;
+                out.damage_dealt += damage;
+            }
+            Winner::TeamB => {
+                out.loses += paths;
+                out.damage_taken += damage;
+            }
+            Winner::Tied => out.ties += paths,
+        }
+        n += t.paths;
+    }
+    let n = n as f64;
+    Expected {
+        wins: out.wins / n,
+        loses: out.loses / n,
+        ties: out.ties / n,
+        damage_taken: out.damage_taken / n,
+        damage_dealt: out.damage_dealt / n,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn expected_mix() {
+        // A team which beats the empty team and loses to a huge Fish, on
+        // turn 3 (where a loss costs 2 lives under the default damage model)
+        let cfg = GameConfig::default();
+        let fish: Team = "Fish:50/50".parse().unwrap();
+        let team: Team = "Ant:2/1".parse().unwrap();
+        assert!(Opponents::new(3, [(fish, 0.0)]).is_none());
+        let model =
+            Opponents::new(3, [(Team::new(), 1.0), (fish, 3.0), (team, 0.0)])
+                .unwrap();
+        assert_eq!(model.teams(), [(Team::new(), 0.25), (fish, 0.75)]);
+
+        let e = model.expect(&cfg, &team);
+        assert_eq!((e.wins, e.loses, e.ties), (0.25, 0.75, 0.0));
+        assert_eq!((e.damage_dealt, e.damage_taken), (0.5, 1.5));
+        let m = model.matchups(&cfg, &team);
+        assert_eq!(m[1], Opponents::fixed(3, fish).expect(&cfg, &team));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..16 {
+            let t = model.sample(&mut rng);
+            assert!(t == fish || t.is_empty());
+        }
+    }
+}
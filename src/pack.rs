@@ -0,0 +1,475 @@
+//! Packs, which decide the species and food sold at each shop tier.
+//!
+//! The active pack is a global setting (like [ASCII mode](crate::render)), so
+//! that shops can sample from it without threading it through every call.
+//! It defaults to the Turtle pack, and is normally only changed at startup.
+//!
+//! Only a handful of species are implemented, so the built-in packs only
+//! contain the implemented species which they actually sell.  Custom packs
+//! are loaded from a JSON [`PackFile`], e.g.
+//!
+//! ```json
+//! {
+//!     "name": "whale-rush",
+//!     "base": "turtle",
+//!     "remove": ["Pig"],
+//!     "species": { "Whale": 2, "Kangaroo": 1 },
+//!     "food": { "Honey": 2 },
+//!     "weights": { "Whale": 3 },
+//!     "odds": { "2": [70, 30] }
+//! }
+//! ```
+//!
+//! In the real game, each shop slot first rolls a tier against a table which
+//! depends on the turn, then a species within that tier.  A pack's `odds`
+//! give that table for each shop tier (which is what the turn decides, see
+//! [`SHOP_TABLE`](crate::params::SHOP_TABLE)); shops without a row roll
+//! every species on offer together, by weight alone.
+
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    food::Food,
+    species::Species,
+};
+
+/// Species and food sold at a single shop tier
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Tier {
+    #[serde(default)]
+    pub species: Vec<Species>,
+    #[serde(default)]
+    pub food: Vec<Food>,
+}
+
+/// A mapping from shop tiers (starting at 1) to what's sold there
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Pack {
+    pub name: String,
+    pub tiers: Vec<Tier>,
+    /// Relative chance of each species appearing in the shop, compared to
+    /// others on offer at the same time.  Species which aren't listed have
+    /// a weight of [`DEFAULT_WEIGHT`]; a weight of zero means that the
+    /// species is never offered.
+    #[serde(default)]
+    pub weights: BTreeMap<Species, usize>,
+    /// Relative chance of each tier (starting at 1) being rolled for a shop
+    /// slot, keyed by the shop's highest tier.  Tiers past the end of a row
+    /// are never rolled, and a tier which sells nothing is skipped (so its
+    /// chance is shared among the others).
+    #[serde(default)]
+    pub odds: BTreeMap<usize, Vec<usize>>,
+}
+
+/// Weight of species without an explicit entry in [`Pack::weights`]
+pub const DEFAULT_WEIGHT: usize = 1;
+
+/// The Turtle pack sells every built-in species at its [`Species::tier`]
+static TURTLE: LazyLock<Pack> = LazyLock::new(|| {
+    let max = Species::builtin()
+        .filter_map(|s| s.tier())
+        .max()
+        .unwrap_or(1);
+    let mut tiers: Vec<Tier> = (1..=max)
+        .map(|t| Tier {
+            species: Species::builtin()
+                .filter(|s| s.tier() == Some(t))
+                .collect(),
+            food: vec![],
+        })
+        .collect();
+    tiers[0].food = vec![Food::Apple, Food::Honey];
+    Pack {
+        name: "turtle".to_owned(),
+        tiers,
+        weights: BTreeMap::new(),
+        odds: BTreeMap::new(),
+    }
+});
+
+/// The Puppy and Star packs share the Turtle pack's Tier 1 species and
+/// foods; none of their own species are implemented.
+fn tier_1_only(name: &str) -> Pack {
+    Pack {
+        name: name.to_owned(),
+        tiers: vec![TURTLE.tiers[0].clone()],
+        weights: BTreeMap::new(),
+        odds: BTreeMap::new(),
+    }
+}
+
+static PUPPY: LazyLock<Pack> = LazyLock::new(|| tier_1_only("puppy"));
+static STAR: LazyLock<Pack> = LazyLock::new(|| tier_1_only("star"));
+
+static ACTIVE: RwLock<Option<&'static Pack>> = RwLock::new(None);
+
+/// Returns the active pack
+pub fn active() -> &'static Pack {
+    ACTIVE.read().unwrap().unwrap_or(&TURTLE)
+}
+
+/// Sets the active pack.  Packs are small and rarely changed, so the old one
+/// is leaked rather than tracking who's still using it.
+pub fn set_active(pack: Pack) -> Result<()> {
+    pack.check()?;
+    *ACTIVE.write().unwrap() = Some(Box::leak(Box::new(pack)));
+    Ok(())
+}
+
+impl Pack {
+    /// Every built-in pack
+    pub fn builtin() -> [&'static Pack; 3] {
+        [&TURTLE, &PUPPY, &STAR]
+    }
+
+    /// Loads a custom pack from a JSON [`PackFile`]
+    pub fn load(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let file: PackFile = serde_json::from_str(&data)?;
+        file.build()
+    }
+
+    /// Checks that the first tier sells both species (with nonzero weight)
+    /// and food, since both are offered on turn 1, that no tokens are sold,
+    /// and that every row of [`Pack::odds`] can roll some species
+    pub fn check(&self) -> Result<()> {
+        match self.tiers.first() {
+            Some(t)
+                if t.species.iter().any(|s| self.weight(*s) > 0)
+                    && !t.food.is_empty() => {}
+            _ => {
+                return Err(Error::Pack(format!(
+                    "{}: Tier 1 must sell species and food",
+                    self.name
+                )))
+            }
+        }
+        for s in self.tiers.iter().flat_map(|t| &t.species) {
+            if s.tier().is_none() {
+                return Err(Error::Pack(format!(
+                    "{}: {:?} can't be sold",
+                    self.name, s
+                )));
+            }
+        }
+        for (tier, row) in &self.odds {
+            if *tier == 0 || row.len() > *tier {
+                return Err(Error::Pack(format!(
+                    "{}: odds for tier {} shops list {} tiers",
+                    self.name,
+                    tier,
+                    row.len()
+                )));
+            }
+            if self.chances(*tier).iter().all(|w| *w == 0) {
+                return Err(Error::Pack(format!(
+                    "{}: odds for tier {} shops never roll a species",
+                    self.name, tier
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sells a species at its [`Species::tier`] (e.g. a
+    /// [custom species](crate::custom)), adding empty tiers if needed.
+    /// Panics if the species is a token.
+    pub fn sell(&mut self, s: Species) {
+        let tier = s.tier().expect("Tokens can't be sold");
+        if self.tiers.len() < tier {
+            self.tiers.resize_with(tier, Tier::default);
+        }
+        if self.tier(s).is_none() {
+            self.tiers[tier - 1].species.push(s);
+        }
+    }
+
+    /// Returns the tier at which the pack sells the given species
+    pub fn tier(&self, s: Species) -> Option<usize> {
+        self.tiers
+            .iter()
+            .position(|t| t.species.contains(&s))
+            .map(|i| i + 1)
+    }
+
+    /// Returns the relative chance of the species appearing in the shop
+    pub fn weight(&self, s: Species) -> usize {
+        self.weights.get(&s).cloned().unwrap_or(DEFAULT_WEIGHT)
+    }
+
+    /// Returns every species sold at or below the given tier, in sampling
+    /// order
+    pub fn species(
+        &self,
+        max_tier: usize,
+    ) -> impl Iterator<Item = Species> + '_ {
+        self.tiers
+            .iter()
+            .take(max_tier)
+            .flat_map(|t| t.species.iter().cloned())
+    }
+
+    /// Returns the relative chance of rolling each species sold at or below
+    /// the given tier, in the same order as [`Pack::species`].  Without a row
+    /// of [`Pack::odds`] for the tier, these are the species' weights;
+    /// otherwise, each tier's odds are split among its species by weight.
+    pub fn chances(&self, max_tier: usize) -> Vec<usize> {
+        let weights = |t: &Tier| -> Vec<usize> {
+            t.species.iter().map(|s| self.weight(*s)).collect()
+        };
+        let tiers = self.tiers.iter().take(max_tier);
+        let Some(row) = self.odds.get(&max_tier) else {
+            return tiers.flat_map(weights).collect();
+        };
+        // Scale every tier to a common total weight, so that a species'
+        // chance is its tier's odds times its share of the tier
+        let totals: Vec<usize> =
+            tiers.clone().map(|t| weights(t).iter().sum()).collect();
+        let common = totals
+            .iter()
+            .filter(|w| **w > 0)
+            .fold(1, |a, b| a / gcd(a, *b) * b);
+        let mut out: Vec<usize> = tiers
+            .zip(&totals)
+            .enumerate()
+            .flat_map(|(i, (t, total))| {
+                let odds = row.get(i).copied().unwrap_or(0);
+                let scale = if *total == 0 { 0 } else { common / total };
+                weights(t).into_iter().map(move |w| w * scale * odds)
+            })
+            .collect();
+        // Keep the numbers small, since they set the range of each roll
+        let g = out.iter().fold(0, |a, b| gcd(a, *b));
+        if g > 1 {
+            out.iter_mut().for_each(|w| *w /= g);
+        }
+        out
+    }
+
+    /// Returns every food sold at or below the given tier, in sampling order
+    pub fn food(&self, max_tier: usize) -> impl Iterator<Item = Food> + '_ {
+        self.tiers
+            .iter()
+            .take(max_tier)
+            .flat_map(|t| t.food.iter().cloned())
+    }
+
+    /// Returns the species sold at exactly the given tier, or at the highest
+    /// tier below it which sells any (with nonzero weight)
+    pub fn species_at(&self, tier: usize) -> &[Species] {
+        self.tiers
+            .iter()
+            .take(tier)
+            .rev()
+            .map(|t| t.species.as_slice())
+            .find(|s| s.iter().any(|s| self.weight(*s) > 0))
+            .expect("Tier 1 must sell species")
+    }
+}
+
+/// Returns the greatest common divisor, where `gcd(a, 0)` is `a`
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl std::str::FromStr for Pack {
+    type Err = Error;
+
+    /// Looks up a built-in pack by name, e.g. `turtle`
+    fn from_str(s: &str) -> Result<Self> {
+        Self::builtin()
+            .into_iter()
+            .find(|p| p.name.eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| Error::Pack(format!("Unknown pack {:?}", s)))
+    }
+}
+
+/// A custom pack definition.  The pack starts as a copy of the built-in
+/// `base` pack (or empty), then drops everything in `remove` and
+/// `remove_food`, then sells each entry in `species` and `food` at the given
+/// tier, moving it if it was already sold at another tier.  Entries in
+/// `weights` and `odds` replace those from the base pack.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackFile {
+    pub name: String,
+    #[serde(default)]
+    pub base: Option<String>,
+    #[serde(default)]
+    pub remove: Vec<Species>,
+    #[serde(default)]
+    pub remove_food: Vec<Food>,
+    #[serde(default)]
+    pub species: BTreeMap<Species, usize>,
+    #[serde(default)]
+    pub food: BTreeMap<Food, usize>,
+    #[serde(default)]
+    pub weights: BTreeMap<Species, usize>,
+    #[serde(default)]
+    pub odds: BTreeMap<usize, Vec<usize>>,
+}
+
+impl PackFile {
+    /// Builds and checks the pack
+    pub fn build(&self) -> Result<Pack> {
+        let (mut tiers, mut weights, mut odds) = match &self.base {
+            Some(b) => {
+                let base: Pack = b.parse()?;
+                (base.tiers, base.weights, base.odds)
+            }
+            None => (vec![], BTreeMap::new(), BTreeMap::new()),
+        };
+        weights.extend(self.weights.iter().map(|(s, w)| (*s, *w)));
+        odds.extend(self.odds.iter().map(|(t, o)| (*t, o.clone())));
+        for t in tiers.iter_mut() {
+            t.species.retain(|s| {
+                !self.remove.contains(s) && !self.species.contains_key(s)
+            });
+            t.food.retain(|f| {
+                !self.remove_food.contains(f) && !self.food.contains_key(f)
+            });
+        }
+        let assigned = self.species.values().chain(self.food.values());
+        if assigned.clone().any(|t| *t == 0) {
+            return Err(Error::Pack(format!(
+                "{}: tiers start at 1",
+                self.name
+            )));
+        }
+        if let Some(max) = assigned.max() {
+            if tiers.len() < *max {
+                tiers.resize_with(*max, Tier::default);
+            }
+        }
+        for (s, t) in &self.species {
+            tiers[t - 1].species.push(*s);
+        }
+        for (f, t) in &self.food {
+            tiers[t - 1].food.push(*f);
+        }
+        let pack = Pack {
+            name: self.name.clone(),
+            tiers,
+            weights,
+            odds,
+        };
+        pack.check()?;
+        Ok(pack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_tiers() {
+        // Built-in packs are valid, round-trip through JSON, and only offer
+        // species from the requested tiers
+        for p in Pack::builtin() {
+            assert!(p.check().is_ok());
+            let json = serde_json::to_string(p).unwrap();
+            assert_eq!(&serde_json::from_str::<Pack>(&json).unwrap(), p);
+            assert_eq!(p.name.parse::<Pack>().unwrap(), p.clone());
+            for tier in 1..8 {
+                for s in p.species(tier) {
+                    assert!(p.tier(s).unwrap() <= tier);
+                }
+                assert!(p.food(tier).count() > 0);
+                let at = p.species_at(tier);
+                assert!(!at.is_empty());
+                let t = p.tier(at[0]).unwrap();
+                assert!(at.iter().all(|s| p.tier(*s) == Some(t)));
+                assert!(t <= tier);
+            }
+        }
+    }
+
+    #[test]
+    fn pack_file_moves() {
+        // Moving or removing one species leaves every other species where it
+        // was; only tokens and tier 0 are rejected, and tier 1 always keeps
+        // more than one species
+        let turtle: Pack = "turtle".parse().unwrap();
+        for s in Species::builtin() {
+            for tier in 0..6 {
+                for remove in [false, true] {
+                    let mut file = PackFile {
+                        name: "custom".to_owned(),
+                        base: Some("turtle".to_owned()),
+                        ..PackFile::default()
+                    };
+                    if remove {
+                        file.remove.push(s);
+                    } else {
+                        file.species.insert(s, tier);
+                    }
+                    let Ok(pack) = file.build() else {
+                        assert!(!remove);
+                        assert!(s.tier().is_none() || tier == 0);
+                        continue;
+                    };
+                    let expected = if remove { None } else { Some(tier) };
+                    assert_eq!(pack.tier(s), expected);
+                    for t in turtle.species(usize::MAX).filter(|t| *t != s) {
+                        assert_eq!(pack.tier(t), turtle.tier(t));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tier_odds() {
+        // Like the pack from the module docs (but leaving the Kangaroo at
+        // tier 2), tier 2 shops are split 70/30 between the two tiers, and
+        // roll a Whale three times as often as a Kangaroo
+        let file: PackFile = serde_json::from_str(
+            r#"{
+                "name": "whale-rush",
+                "base": "turtle",
+                "remove": ["Pig"],
+                "species": { "Whale": 2 },
+                "food": { "Honey": 2 },
+                "weights": { "Whale": 3 },
+                "odds": { "2": [70, 30] }
+            }"#,
+        )
+        .unwrap();
+        let pack = file.build().unwrap();
+        assert_eq!(pack.tier(Species::Pig), None);
+        assert_eq!(pack.species_at(2), [Species::Kangaroo, Species::Whale]);
+        assert_eq!(pack.food(1).count(), 1);
+
+        let species: Vec<Species> = pack.species(2).collect();
+        let chances = pack.chances(2);
+        let total = |tier| -> usize {
+            species
+                .iter()
+                .zip(&chances)
+                .filter(|(s, _)| pack.tier(**s) == Some(tier))
+                .map(|(_, c)| c)
+                .sum()
+        };
+        assert_eq!(total(1) * 30, total(2) * 70);
+        let chance = |s| chances[species.iter().position(|t| *t == s).unwrap()];
+        assert_eq!(chance(Species::Kangaroo) * 3, chance(Species::Whale));
+        assert!(pack.species_at(1).iter().all(|s| chance(*s) == chances[0]));
+
+        // Without a row, every species is rolled by its weight alone
+        let plain: Vec<usize> =
+            species.iter().map(|s| pack.weight(*s)).collect();
+        assert_eq!(pack.chances(1), plain[..pack.species(1).count()]);
+    }
+}
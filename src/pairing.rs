@@ -0,0 +1,161 @@
+//! Species-level head-to-head statistics, which summarize the score matrix
+//! as how often a team fielding one species beats a team fielding another.
+//!
+//! Every matchup counts towards each pairing of a species on the first team
+//! with a species on the second, once per pairing however many copies of
+//! each species are on the teams.  A team with several species counts towards
+//! several pairings, so pairings aren't independent: they show which species
+//! tend to be found on winning teams against which others, not the outcome of
+//! the two species fighting alone.
+
+use hashbrown::HashMap;
+use serde::Serialize;
+
+use crate::{species::Species, team::Team};
+
+/// Head-to-head totals for one species against another
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Pairing {
+    pub species: Species,
+    pub opponent: Species,
+    /// Matchups between a team fielding `species` and a team fielding
+    /// `opponent`
+    pub matchups: usize,
+    /// Expected wins, losses and ties over those matchups
+    pub wins: f64,
+    pub loses: f64,
+    pub ties: f64,
+}
+
+impl Pairing {
+    /// Returns the fraction of matchups won
+    pub fn win_rate(&self) -> f64 {
+        if self.matchups == 0 {
+            0.0
+        } else {
+            self.wins / self.matchups as f64
+        }
+    }
+}
+
+/// Running head-to-head totals for every pair of species, built up a row of
+/// the score matrix at a time
+#[derive(Clone, Debug, Default)]
+pub struct SpeciesMatrix {
+    pairs: HashMap<(Species, Species), Pairing>,
+}
+
+/// Returns the distinct species on a team, sorted
+fn species(team: &Team) -> Vec<Species> {
+    let mut out: Vec<Species> =
+        team.iter().flatten().map(|f| f.species).collect();
+    out.sort();
+    out.dedup();
+    out
+}
+
+impl SpeciesMatrix {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a finished row of the score matrix, where `wins[j]`,
+    /// `loses[j]` and `ties[j]` are team `a`'s chances against
+    /// `opponents[j]`
+    pub fn add_row(
+        &mut self,
+        a: &Team,
+        opponents: &[Team],
+        wins: &[f32],
+        loses: &[f32],
+        ties: &[f32],
+    ) {
+        // Totals against each opposing species, which are then shared out
+        // among the row team's species
+        let mut row: HashMap<Species, (usize, f64, f64, f64)> = HashMap::new();
+        for (j, b) in opponents.iter().enumerate() {
+            for s in species(b) {
+                let t = row.entry(s).or_default();
+                t.0 += 1;
+                t.1 += wins[j] as f64;
+                t.2 += loses[j] as f64;
+                t.3 += ties[j] as f64;
+            }
+        }
+        for s in species(a) {
+            for (o, (n, w, l, t)) in &row {
+                let p = self.pairs.entry((s, *o)).or_insert(Pairing {
+                    species: s,
+                    opponent: *o,
+                    matchups: 0,
+                    wins: 0.0,
+                    loses: 0.0,
+                    ties: 0.0,
+                });
+                p.matchups += n;
+                p.wins += w;
+                p.loses += l;
+                p.ties += t;
+            }
+        }
+    }
+
+    /// Returns the totals for one species against another, if any matchups
+    /// between them have been recorded
+    pub fn get(&self, species: Species, opponent: Species) -> Option<Pairing> {
+        self.pairs.get(&(species, opponent)).copied()
+    }
+
+    /// Returns every species which has been seen, on either side, sorted
+    pub fn species(&self) -> Vec<Species> {
+        let mut out: Vec<Species> =
+            self.pairs.keys().flat_map(|(s, o)| [*s, *o]).collect();
+        out.sort();
+        out.dedup();
+        out
+    }
+
+    /// Returns every pairing with recorded matchups, sorted by species and
+    /// then by opponent
+    pub fn pairings(&self) -> Vec<Pairing> {
+        let mut out: Vec<Pairing> = self.pairs.values().copied().collect();
+        out.sort_by_key(|p| (p.species, p.opponent));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_totals() {
+        // Each matchup counts once towards every pairing of species on the
+        // two teams, however many copies of each species they field
+        let team = |s: &str| s.parse::<Team>().unwrap();
+        let a = team("Ant:2/1,Fish:2/3");
+        let opponents = [team("Fish:2/3"), team("Ant:2/1,Ant:2/1")];
+        let mut matrix = SpeciesMatrix::new();
+        matrix.add_row(&a, &opponents, &[1.0, 0.5], &[0.0, 0.25], &[0.0, 0.25]);
+        assert_eq!(matrix.species(), [Species::Ant, Species::Fish]);
+        assert_eq!(matrix.pairings().len(), 4);
+        for s in [Species::Ant, Species::Fish] {
+            let p = matrix.get(s, Species::Fish).unwrap();
+            assert_eq!((p.matchups, p.wins, p.loses), (1, 1.0, 0.0));
+            let p = matrix.get(s, Species::Ant).unwrap();
+            assert_eq!((p.matchups, p.wins, p.ties), (1, 0.5, 0.25));
+        }
+
+        // A second row adds to the same pairings
+        matrix.add_row(
+            &team("Fish:3/3"),
+            &opponents[..1],
+            &[0.0],
+            &[1.0],
+            &[0.0],
+        );
+        let p = matrix.get(Species::Fish, Species::Fish).unwrap();
+        assert_eq!((p.matchups, p.win_rate()), (2, 0.5));
+        assert_eq!(matrix.get(Species::Pig, Species::Fish), None);
+    }
+}
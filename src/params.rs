@@ -1,6 +1,28 @@
-// XXX these change depending on round number
-pub const SHOP_ANIMAL_COUNT: usize = 3;
-pub const SHOP_FOOD_COUNT: usize = 1;
+/// Shop size and the highest tier on offer, which grow every other turn.
+/// Each row is `(animal slots, food slots, max tier)`, starting at turn 1;
+/// the last row applies to every later turn.
+pub const SHOP_TABLE: [(usize, usize, usize); 6] = [
+    (3, 1, 1),
+    (3, 2, 2),
+    (4, 2, 3),
+    (4, 2, 4),
+    (5, 2, 5),
+    (5, 2, 6),
+];
+/// Largest number of animal slots in the shop, on any turn, including bonus
+/// slots from leveling up
+pub const MAX_SHOP_ANIMALS: usize = 7;
+/// Largest number of food slots in the shop, on any turn
+pub const MAX_SHOP_FOODS: usize = 2;
 
 pub const TEAM_SIZE: usize = 5;
 pub const DEFAULT_GOLD: usize = 10;
+
+/// Effects which may be scheduled for the next turn, i.e. one per friend
+pub const MAX_PENDING: usize = TEAM_SIZE;
+
+/// Largest attack or health a friend may have
+pub const MAX_STAT: usize = 50;
+
+/// Experience at which a friend reaches level 3 and can't be combined further
+pub const MAX_EXP: usize = 6;
@@ -0,0 +1,1582 @@
+//! Property tests for engine invariants, enabled with `--features proptest`:
+//!
+//! ```sh
+//! cargo test --features proptest
+//! ```
+//!
+//! Checks which need no generated input are unit tests in their own module,
+//! and run with a plain `cargo test`.
+//!
+//! Dice sequences are generated as a list of raw choices, which are reduced
+//! into whatever range the engine asks for; this lets proptest shrink failing
+//! cases down to a short, readable sequence of decisions.
+
+use std::{collections::HashSet, hash::BuildHasher};
+
+use proptest::prelude::*;
+
+use crate::{
+    ability::Ability,
+    animate::Frames,
+    battle::{Battle, Outcome, Winner},
+    config::{
+        Challenge, DamageModel, ExploreLimits, GameConfig, PruneConfig,
+        SummonPlacement,
+    },
+    corpus::{BattleLine, Corpus, Sampling},
+    custom::{self, CustomSpecies},
+    dice::{DeterministicDice, Dice},
+    draw::{Image, Shape},
+    event::{Cause, Event, Log},
+    explore,
+    filter::{Focus, TeamFilter},
+    food::Food,
+    friend::{AbilityState, Friend, Triggers},
+    graph::ShopGraph,
+    leaderboard::Leaderboard,
+    mirror,
+    modifier::Modifier,
+    opponent::{Expected, Opponents},
+    pack::{self, PackFile},
+    pairing::SpeciesMatrix,
+    params::{DEFAULT_GOLD, MAX_EXP, MAX_STAT, TEAM_SIZE},
+    replicator, route,
+    rules::RuleSet,
+    script::{self, Command},
+    shop::{Action, Pending, Shop, ShopAction, Step},
+    similarity::{self, Distance, Index},
+    species::Species,
+    sweep::Sweep,
+    target::Target,
+    team::{Constraints, Team},
+    timeline::{Entry, Timeline},
+};
+
+/// Dice which replay a generated list of choices, then roll the lowest value
+/// in each range once the list runs out.
+#[derive(Clone, Debug)]
+struct SequenceDice {
+    choices: Vec<usize>,
+    index: usize,
+}
+
+impl Dice for SequenceDice {
+    fn roll(&mut self, range: std::ops::Range<usize>) -> usize {
+        let c = self.choices.get(self.index).cloned().unwrap_or(0);
+        self.index += 1;
+        range.start + c % range.len()
+    }
+}
+
+fn dice() -> impl Strategy<Value = SequenceDice> {
+    prop::collection::vec(any::<usize>(), 0..64)
+        .prop_map(|choices| SequenceDice { choices, index: 0 })
+}
+
+fn species() -> impl Strategy<Value = Species> {
+    prop_oneof![
+        Just(Species::Ant),
+        Just(Species::Beaver),
+        Just(Species::Cricket),
+        Just(Species::Duck),
+        Just(Species::Fish),
+        Just(Species::Horse),
+        Just(Species::Mosquito),
+        Just(Species::Otter),
+        Just(Species::Pig),
+        Just(Species::Whale),
+        Just(Species::Kangaroo),
+        Just(Species::Ox),
+    ]
+}
+
+fn modifier() -> impl Strategy<Value = Option<Modifier>> {
+    prop_oneof![
+        Just(None),
+        Just(Some(Modifier::Honey)),
+        Just(Some(Modifier::MeatBone)),
+        Just(Some(Modifier::Steak)),
+        Just(Some(Modifier::Melon)),
+        Just(Some(Modifier::Garlic)),
+        Just(Some(Modifier::Coconut)),
+        Just(Some(Modifier::Chili)),
+    ]
+}
+
+fn friend() -> impl Strategy<Value = Friend> {
+    (
+        (species(), 1..20usize, 1..20usize, modifier(), 0..=MAX_EXP),
+        (0..3usize, 0..3usize),
+    )
+        .prop_map(
+            |((species, attack, health, modifier, exp), (ta, th))| Friend {
+                species,
+                attack,
+                health,
+                modifier,
+                exp,
+                temp_attack: ta,
+                temp_health: th,
+                state: AbilityState::None,
+                triggers: Triggers::default(),
+            },
+        )
+}
+
+/// Generates a compact team, i.e. one that's ready for battle
+fn team() -> impl Strategy<Value = Team> {
+    team_of(TEAM_SIZE)
+}
+
+/// Generates a team of at most `max` friends, for tests which look at every
+/// dice path in detail (the number of paths grows quickly with team size)
+fn team_of(max: usize) -> impl Strategy<Value = Team> {
+    prop::collection::vec(friend(), 0..=max).prop_map(|fs| {
+        let mut team = Team::new();
+        for (i, f) in fs.into_iter().enumerate() {
+            team[i] = Some(f);
+        }
+        team
+    })
+}
+
+/// Custom species which copy each sellable built-in species, registered on
+/// first use
+static MIMICS: std::sync::LazyLock<Vec<(Species, Species)>> =
+    std::sync::LazyLock::new(|| {
+        Species::builtin()
+            .filter(|s| s.tier().is_some())
+            .map(|s| {
+                let (health, attack) = s.default_power();
+                let mimic = CustomSpecies {
+                    name: format!("Mimic{:?}", s),
+                    emoji: s.emoji(),
+                    abbrev: None,
+                    tier: s.tier().unwrap(),
+                    stats: [attack, health],
+                    templates: vec![s],
+                    params: Default::default(),
+                    abilities: vec![],
+                    limit: None,
+                };
+                (s, custom::register(mimic).unwrap())
+            })
+            .collect()
+    });
+
+/// Custom species which spell out each scripted built-in species' abilities
+/// (with magnitudes), rather than using it as a template
+static SCRIPTS: std::sync::LazyLock<Vec<(Species, Species)>> =
+    std::sync::LazyLock::new(|| {
+        Species::builtin()
+            .filter(|s| s.tier().is_some() && !s.abilities().is_empty())
+            .map(|s| {
+                let (health, attack) = s.default_power();
+                let levels = [1, 2, 3].map(|level| s.ability_params(level));
+                let script = CustomSpecies {
+                    name: format!("Script{:?}", s),
+                    emoji: s.emoji(),
+                    abbrev: None,
+                    tier: s.tier().unwrap(),
+                    stats: [attack, health],
+                    templates: vec![],
+                    params: Default::default(),
+                    abilities: s
+                        .abilities()
+                        .iter()
+                        .map(|a| Ability {
+                            levels: Some(levels),
+                            ..*a
+                        })
+                        .collect(),
+                    limit: s.trigger_limit(),
+                };
+                (s, custom::register(script).unwrap())
+            })
+            .collect()
+    });
+
+/// Swaps every friend's species according to the given table
+fn swap_species(mut t: Team, table: &[(Species, Species)]) -> Team {
+    for i in 0..TEAM_SIZE {
+        if let Some(f) = t[i].as_mut() {
+            if let Some((_, m)) = table.iter().find(|(s, _)| *s == f.species) {
+                f.species = *m;
+            }
+        }
+    }
+    t
+}
+
+/// Generates a shop by rolling a fresh shop then taking a few actions
+fn shop() -> impl Strategy<Value = Shop> {
+    (dice(), 1..16usize, 0..16usize).prop_map(|(mut dice, turn, steps)| {
+        let mut shop = Shop::new(turn, &mut dice);
+        for _ in 0..steps {
+            if shop.step(&mut dice) == Step::Ended {
+                break;
+            }
+        }
+        shop
+    })
+}
+
+fn target() -> impl Strategy<Value = Target> {
+    prop_oneof![
+        Just(Target::NearestAhead),
+        Just(Target::NearestBehind),
+        Just(Target::LowestHealth),
+        Just(Target::HighestAttack),
+        (0..=TEAM_SIZE).prop_map(Target::RandomEnemy),
+        (0..=TEAM_SIZE).prop_map(Target::RandomFriend),
+        Just(Target::AllFriends),
+        Just(Target::AllEnemies),
+    ]
+}
+
+/// Log which checks that battles respect the round cap and stay in bounds
+#[derive(Default)]
+struct CheckLog {
+    ended: bool,
+}
+
+impl Log for CheckLog {
+    fn log(&mut self, e: Event) {
+        assert!(!self.ended, "Event {:?} after end of battle", e);
+        match e {
+            Event::Round(i) => {
+                assert!(i < GameConfig::default().max_rounds, "Missed cap")
+            }
+            Event::Hurt { pos, .. }
+            | Event::Perk { pos, .. }
+            | Event::KnockOut { pos, .. }
+            | Event::Move { to: pos, .. }
+            | Event::Swallow { pos, .. }
+            | Event::Buff { pos, .. }
+            | Event::Equip { pos, .. }
+            | Event::Faint { pos, .. }
+            | Event::Summon { pos, .. }
+            | Event::SummonBlocked { pos, .. } => assert!(pos < TEAM_SIZE),
+            Event::Clash { .. } => (),
+            Event::End(..) => self.ended = true,
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn battle_invariants(a in team(), b in team(), mut dice in dice()) {
+        let battle = Battle(a, b);
+        prop_assert_eq!(battle.check(), Ok(()));
+        let mut log = CheckLog::default();
+        let cfg = GameConfig::default();
+        let out = battle.run_logged(&cfg, &mut dice, &mut log);
+        prop_assert!(log.ended);
+
+        // Only ties are free, and nobody loses more than three lives
+        for model in [DamageModel::Turn, DamageModel::Survivors] {
+            for turn in 1..8 {
+                let d = out.damage(model, turn);
+                prop_assert_eq!(d == 0, out.winner == Winner::Tied);
+                prop_assert!(d <= 3);
+            }
+        }
+    }
+
+    #[test]
+    fn battle_replays_terminate(a in team(), b in team()) {
+        let replays = Battle(a, b).replays(&GameConfig::default());
+        prop_assert!(!replays.is_empty());
+    }
+
+    #[test]
+    fn target_selection(
+        t in target(),
+        own in team(),
+        enemy in team(),
+        pos in 0..TEAM_SIZE,
+        mut dice in dice(),
+    ) {
+        let pos = own[pos].map(|_| pos);
+        let picked = t.select(&own, pos, Some(&enemy), &mut dice);
+        let side = if t.is_enemy() { &enemy } else { &own };
+        for (i, p) in picked.iter().enumerate() {
+            prop_assert!(side[*p].is_some());
+            prop_assert!(!picked[..i].contains(p));
+            if !t.is_enemy() {
+                prop_assert!(Some(*p) != pos);
+            }
+        }
+        match t {
+            Target::RandomEnemy(n) | Target::RandomFriend(n) => {
+                prop_assert!(picked.len() <= n)
+            }
+            Target::AllFriends | Target::AllEnemies => (),
+            _ => prop_assert!(picked.len() <= 1),
+        }
+
+        // Enemy selectors find nothing outside of battle
+        if t.is_enemy() {
+            prop_assert!(t.select(&own, pos, None, &mut dice).is_empty());
+        }
+    }
+
+    #[test]
+    fn team_moves(
+        mut team in team(),
+        pos in 0..TEAM_SIZE,
+        n in 0..=TEAM_SIZE,
+        forward in any::<bool>(),
+    ) {
+        prop_assume!(team[pos].is_some());
+        let before = team;
+        let to = if forward {
+            team.push_forward(pos, n)
+        } else {
+            team.push_back(pos, n)
+        };
+        prop_assert!(team.is_compact());
+        prop_assert_eq!(team.count(), before.count());
+        for i in 0..TEAM_SIZE {
+            prop_assert_eq!(team[Team::reindex(pos, to, i)], before[i]);
+        }
+
+        // Making space never loses a friend
+        let mut t = before;
+        if t.make_space_at(pos) {
+            prop_assert!(t[pos].is_none());
+            prop_assert_eq!(t.count(), before.count());
+        } else {
+            prop_assert_eq!(before.count(), TEAM_SIZE);
+        }
+    }
+
+    #[test]
+    fn team_code_roundtrip(team in team()) {
+        // Codes don't include temporary stats
+        let mut expected = team;
+        for i in 0..TEAM_SIZE {
+            if let Some(f) = expected[i].as_mut() {
+                f.temp_attack = 0;
+                f.temp_health = 0;
+            }
+        }
+        prop_assert_eq!(team.code().parse::<Team>().ok(), Some(expected));
+    }
+
+    #[test]
+    fn dice_key_roundtrip(key in "[0-9a-z]{0,16}", junk in "[^0-9a-zA-Z]") {
+        let dice = DeterministicDice::from_key(&key).unwrap();
+        prop_assert_eq!(dice.key(), key.clone());
+        let bad = format!("{}{}", key, junk);
+        prop_assert!(DeterministicDice::from_key(&bad).is_err());
+    }
+
+    #[test]
+    fn friend_builder(
+        species in species(),
+        attack in 0..60usize,
+        health in 0..60usize,
+        exp in 0..=MAX_EXP + 1,
+    ) {
+        let f = Friend::builder()
+            .species(species)
+            .attack(attack)
+            .health(health)
+            .exp(exp)
+            .build();
+        let legal = species.tier().is_some()
+            && (1..=MAX_STAT).contains(&attack)
+            && (1..=MAX_STAT).contains(&health)
+            && exp <= MAX_EXP;
+        prop_assert_eq!(f.is_ok(), legal);
+        if let Ok(f) = f {
+            prop_assert_eq!((f.attack, f.health, f.exp), (attack, health, exp));
+        }
+    }
+
+    #[test]
+    fn team_builder(fs in prop::collection::vec(friend(), 0..=TEAM_SIZE + 1)) {
+        let legal = fs.len() <= TEAM_SIZE
+            && fs.iter().all(|f| f.check_legal().is_ok());
+        let team = fs
+            .iter()
+            .fold(Team::builder(), |b, f| b.friend(*f))
+            .build();
+        prop_assert_eq!(team.is_ok(), legal);
+        if let Ok(team) = team {
+            prop_assert_eq!(team.count(), fs.len());
+            prop_assert!(team.check().is_ok());
+        }
+    }
+
+    #[test]
+    fn shop_serde_roundtrip(shop in shop()) {
+        let json = serde_json::to_string(&shop).unwrap();
+        let out: Shop = serde_json::from_str(&json).unwrap();
+        prop_assert_eq!(out, shop);
+        prop_assert!(out.check().is_ok());
+    }
+
+    #[test]
+    fn shop_invariants(mut shop in shop(), mut dice in dice()) {
+        prop_assert_eq!(shop.check(), Ok(()));
+        // Every gold change since the start of the turn is accounted for
+        let e = shop.economy;
+        prop_assert_eq!(shop.gold + e.spent(), DEFAULT_GOLD + e.earned());
+        for _ in 0..16 {
+            let gold = shop.gold;
+            let done = shop.step(&mut dice) != Step::Acted;
+            prop_assert_eq!(shop.check(), Ok(()));
+            prop_assert!(shop.team.count() <= TEAM_SIZE);
+            if done {
+                prop_assert!(shop.gold == gold);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn shop_canonical(shop in shop(), rotate in 0..8usize) {
+        // Restocking the same offers in any order gives the same shop, down
+        // to its hash (bonus friends are left out, since restocking drops
+        // them)
+        let (animals, _, tier) = shop.limits();
+        let mut friends: Vec<Species> = shop
+            .offers()
+            .map(|f| f.species)
+            .filter(|s| pack::active().tier(*s).is_some_and(|t| t <= tier))
+            .take(animals)
+            .collect();
+        let mut foods: Vec<Food> = shop.foods().cloned().collect();
+        let (mut a, mut b) = (shop, shop);
+        a.stock(&friends, &foods).unwrap();
+        friends.reverse();
+        foods.reverse();
+        let n = friends.len().max(1);
+        friends.rotate_left(rotate % n);
+        b.stock(&friends, &foods).unwrap();
+        prop_assert_eq!(a, b);
+        let state = std::hash::RandomState::new();
+        prop_assert_eq!(state.hash_one(a), state.hash_one(b));
+    }
+
+    #[test]
+    fn shop_pending(
+        mut shop in shop(),
+        mut dice in dice(),
+        gold in 0..4usize,
+        s in species(),
+    ) {
+        // Scheduled effects are applied once, at the start of the next turn
+        let count = shop.team.count();
+        shop.schedule(Pending::Summon(Friend::new(s)));
+        shop.schedule(Pending::Gold(gold));
+        prop_assert_eq!(shop.pending().count(), 2);
+        shop.start_turn(&mut dice, &mut ());
+        prop_assert_eq!(shop.check(), Ok(()));
+        prop_assert_eq!(shop.pending().count(), 0);
+        prop_assert_eq!(shop.gold, DEFAULT_GOLD + gold);
+        prop_assert_eq!(shop.team.count(), std::cmp::min(count + 1, TEAM_SIZE));
+    }
+
+    #[test]
+    fn timeline_balanced(
+        a in team(),
+        b in team(),
+        mut shop in shop(),
+        mut dice in dice(),
+    ) {
+        // Every trigger is closed, and no dice roll is lost
+        let mut timeline = Timeline::new();
+        let mut watched = timeline.watch(&mut dice);
+        let cfg = GameConfig::default();
+        Battle(a, b).run_logged(&cfg, &mut watched, &mut timeline);
+        while shop.step_logged(&mut watched, &mut timeline) == Step::Acted {}
+        let entries = timeline.entries();
+        prop_assert_eq!(entries.last().map(|e| e.0), Some(0));
+        let opened = entries
+            .iter()
+            .filter(|e| matches!(e.1, Entry::Trigger(..)))
+            .count();
+        let closed = entries.iter().filter(|e| e.1 == Entry::End).count();
+        prop_assert_eq!(opened, closed);
+    }
+
+    #[test]
+    fn draw_battle(a in team(), b in team()) {
+        // Every rectangle is drawn, plus the background
+        let image = Image::battle(&Battle(a, b));
+        let rects = image
+            .shapes
+            .iter()
+            .filter(|s| matches!(s, Shape::Rect { .. }))
+            .count();
+        prop_assert_eq!(image.to_svg().matches("<rect").count(), rects + 1);
+    }
+
+    #[test]
+    fn step_outcomes(mut dice in dice(), steps in 0..8usize) {
+        // Impossible actions leave the shop unchanged, and only an explicit
+        // EndTurn ends the turn, as if by the EndTurn action.  Later turns
+        // have many more paths, so this only steps from turn 1 shops.
+        let mut shop = Shop::new(1, &mut dice);
+        for _ in 0..steps {
+            if shop.step(&mut dice) == Step::Ended {
+                break;
+            }
+        }
+        let shop = shop;
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let mut next = shop;
+            let step = next.step(&mut dice);
+            let key = dice.key();
+            let action = key
+                .chars()
+                .next()
+                .and_then(|c| c.to_digit(36))
+                .and_then(|i| ShopAction::from_index(i as usize));
+            match step {
+                Step::Impossible | Step::Pruned => {
+                    prop_assert_eq!(next, shop)
+                }
+                Step::Ended => {
+                    prop_assert_eq!(action, Some(ShopAction::EndTurn));
+                    let mut expected = shop;
+                    let mut dice = DeterministicDice::from_key(&key[1..])
+                        .unwrap();
+                    expected.apply(Action::EndTurn, &mut dice, &mut ())
+                        .unwrap();
+                    prop_assert_eq!(next, expected);
+                }
+                Step::Acted => {
+                    prop_assert_ne!(action, Some(ShopAction::EndTurn))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prune_rules(
+        mut dice in dice(),
+        team in team_of(1),
+        reroll in any::<bool>(),
+    ) {
+        // Over two steps from a new shop, the pruning rules only drop moves
+        // which are also made in the other order, and friends which are
+        // bought then sold (leaving the starting team with less gold).
+        // Rerolls are pruned by choice, so they may drop anything.
+        let mut start = Shop::new(1, &mut dice);
+        start.team = team;
+        start.team.sort();
+        let prune = PruneConfig {
+            reroll_duplicates: reroll,
+            ..PruneConfig::all()
+        };
+        let two_steps = |prune: &PruneConfig| {
+            let step = |shop: &Shop| {
+                let mut acted = HashSet::new();
+                let mut ended = HashSet::new();
+                let mut dice = DeterministicDice::new().multisets();
+                while dice.next() {
+                    let mut next = *shop;
+                    let step = next.step_pruned(&mut dice, prune, &mut ());
+                    next.team.sort();
+                    match step {
+                        Step::Acted => acted.insert(next),
+                        Step::Ended => ended.insert(next),
+                        Step::Impossible | Step::Pruned => false,
+                    };
+                }
+                (acted, ended)
+            };
+            let (first, mut out) = step(&start);
+            for shop in &first {
+                let (acted, ended) = step(shop);
+                out.extend(acted);
+                out.extend(ended);
+            }
+            // Pruning rules only remember the last step along the way
+            out.into_iter()
+                .map(|mut s| {
+                    s.forget_recent();
+                    s
+                })
+                .collect::<HashSet<Shop>>()
+        };
+        let all = two_steps(&PruneConfig::default());
+        let pruned = two_steps(&prune);
+        prop_assert!(pruned.is_subset(&all));
+        if !reroll {
+            for shop in all.difference(&pruned) {
+                prop_assert_eq!(shop.team, start.team);
+                prop_assert!(shop.gold < start.gold);
+            }
+        }
+    }
+
+    #[test]
+    fn explore_limits(
+        mut dice in dice(),
+        max_actions in 0..2usize,
+        max_rerolls in proptest::option::of(0..2usize),
+        min_gold in 0..12usize,
+    ) {
+        // Limits only drop teams, and ended shops respect them
+        let start = Shop::new(1, &mut dice);
+        let explore = |limits: &ExploreLimits| {
+            let mut active = HashSet::from([start]);
+            let mut seen = HashSet::new();
+            let mut ended = vec![];
+            let mut actions = 0;
+            while !active.is_empty() {
+                let mut next = HashSet::new();
+                for shop in active {
+                    if !seen.insert(shop) {
+                        continue;
+                    }
+                    let ends = limits.ends(&shop, actions);
+                    let mut dice = DeterministicDice::new().multisets();
+                    while dice.next() {
+                        let mut shop = shop;
+                        let step = if ends {
+                            shop.end_turn(&mut dice, &mut ());
+                            Step::Ended
+                        } else {
+                            shop.step(&mut dice)
+                        };
+                        shop.team.sort();
+                        match step {
+                            Step::Acted => {
+                                if limits.allows(&shop, actions + 1) {
+                                    next.insert(shop);
+                                }
+                            }
+                            Step::Ended => ended.push(shop),
+                            Step::Impossible | Step::Pruned => (),
+                        }
+                    }
+                }
+                active = next;
+                actions += 1;
+            }
+            ended
+        };
+        let limits = ExploreLimits {
+            max_actions: Some(max_actions),
+            max_rerolls,
+            min_gold,
+        };
+        let ended = explore(&limits);
+        let looser = explore(&ExploreLimits {
+            max_actions: Some(max_actions + 1),
+            ..ExploreLimits::default()
+        });
+        let looser: HashSet<Shop> = looser.into_iter().collect();
+        for shop in &ended {
+            prop_assert!(looser.contains(shop));
+            let rerolls = shop.economy.rerolls;
+            prop_assert!(max_rerolls.is_none_or(|m| rerolls <= m));
+        }
+        if min_gold > start.gold {
+            let mut team = start.team;
+            team.sort();
+            prop_assert!(ended.iter().all(|s| s.team == team));
+        }
+    }
+
+    #[test]
+    fn graph_edges_replay(mut dice in dice()) {
+        // Every edge can be reproduced from its dice key.  Later turns have
+        // many more paths, so this only explores from a turn 1 shop.
+        let shop = Shop::new(1, &mut dice);
+        let graph = ShopGraph::explore(&[shop], 1, usize::MAX);
+        for e in &graph.edges {
+            let mut next = graph.nodes[e.from].shop;
+            let mut dice = DeterministicDice::from_key(&e.key).unwrap();
+            let done = next.step(&mut dice) == Step::Ended;
+            next.team.sort();
+            prop_assert_eq!(next, graph.nodes[e.to].shop);
+            prop_assert_eq!(done, graph.nodes[e.to].done);
+        }
+    }
+
+    #[test]
+    fn corpus_lines(a in team(), b in team(), max in 0..4usize) {
+        // Every dice path is written (up to the limit), and each line parses
+        // back into the battle that was run
+        let sampling = Sampling {
+            max: Some(max),
+            ..Sampling::default()
+        };
+        let mut corpus = Corpus::new(vec![], sampling);
+        let cfg = GameConfig::default();
+        let mut outcomes = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let battle = Battle(a, b);
+            let out = battle.run(&cfg, &mut dice);
+            corpus.record(&battle, &dice, &out).unwrap();
+            outcomes.push(out);
+        }
+        let data = String::from_utf8(corpus.finish().unwrap()).unwrap();
+        let lines: Vec<BattleLine> = data
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        prop_assert_eq!(lines.len(), outcomes.len().min(max));
+        for (line, out) in lines.iter().zip(&outcomes) {
+            prop_assert_eq!((line.team_a, line.team_b), (a, b));
+            prop_assert_eq!(line.winner, out.winner);
+            prop_assert_eq!(line.rounds, out.rounds);
+            prop_assert_eq!(line.seed, sampling.seed);
+        }
+    }
+
+    #[test]
+    fn corpus_order(
+        teams in prop::collection::vec(team(), 1..4),
+        seed in any::<u64>(),
+    ) {
+        // Each matchup samples the same battles, whatever order the
+        // matchups are run in
+        let sampling = Sampling {
+            rate: 0.5,
+            seed,
+            ..Sampling::default()
+        };
+        let cfg = GameConfig::default();
+        let n = teams.len();
+        let pairs: Vec<(usize, usize)> =
+            (0..n).flat_map(|i| (0..n).map(move |j| (i, j))).collect();
+        let sample = |pairs: &mut dyn Iterator<Item = &(usize, usize)>| {
+            let mut corpus = Corpus::new(vec![], sampling);
+            for (i, j) in pairs {
+                corpus.start_matchup(*i, *j);
+                let battle = Battle(teams[*i], teams[*j]);
+                // A few dice paths are enough to sample from
+                let mut dice = DeterministicDice::new();
+                for _ in 0..8 {
+                    if !dice.next() {
+                        break;
+                    }
+                    let out = battle.run(&cfg, &mut dice);
+                    corpus.record(&battle, &dice, &out).unwrap();
+                }
+            }
+            let data = String::from_utf8(corpus.finish().unwrap()).unwrap();
+            let mut lines: Vec<String> =
+                data.lines().map(str::to_owned).collect();
+            lines.sort();
+            lines
+        };
+        prop_assert_eq!(
+            sample(&mut pairs.iter()),
+            sample(&mut pairs.iter().rev())
+        );
+    }
+
+    #[test]
+    fn rule_translate(
+        t in team(),
+        s in species(),
+        attack in 0..=3usize,
+        health in 1..=3usize,
+    ) {
+        // Translating a team shifts only the patched species, and
+        // translating back undoes it (unless stats were clamped)
+        let current: RuleSet = "current".parse().unwrap();
+        prop_assert_eq!(current.translate(&current, &t), t);
+        let mut patch = RuleSet {
+            name: "patch".to_owned(),
+            ..RuleSet::default()
+        };
+        patch.stats.insert(s, [attack, health]);
+        let u = current.translate(&patch, &t);
+        let (h0, a0) = s.current_power();
+        let mut clamped = false;
+        for (f, g) in t.iter().zip(u.iter()) {
+            match (f, g) {
+                (Some(f), Some(g)) if f.species == s => {
+                    clamped |= f.health + health <= h0
+                        || f.attack + attack < a0
+                        || f.health + health - h0 > MAX_STAT
+                        || f.attack + attack - a0 > MAX_STAT;
+                    prop_assert_eq!(g.species, s);
+                }
+                (f, g) => prop_assert_eq!(f, g),
+            }
+        }
+        if !clamped {
+            prop_assert_eq!(patch.translate(&current, &u), t);
+        }
+    }
+
+    #[test]
+    fn tier_odds(
+        weights in prop::collection::btree_map(species(), 0..4usize, 0..4),
+        odds in prop::collection::vec(0..4usize, 1..4),
+    ) {
+        // Each slot rolls a tier by its odds (among tiers which sell
+        // anything), then a species within it by weight
+        let file = PackFile {
+            name: "odds".to_owned(),
+            base: Some("turtle".to_owned()),
+            weights: weights.into_iter().collect(),
+            ..PackFile::default()
+        };
+        let Ok(plain) = file.build() else {
+            return Ok(());
+        };
+        let tier = odds.len();
+        let species: Vec<Species> = plain.species(tier).collect();
+        let weights: Vec<usize> =
+            species.iter().map(|s| plain.weight(*s)).collect();
+        prop_assert_eq!(&plain.chances(tier), &weights);
+
+        let mut file = file;
+        file.odds.insert(tier, odds.clone());
+        let Ok(pack) = file.build() else {
+            prop_assert!(plain.chances(tier).iter().zip(&species).all(
+                |(w, s)| *w == 0 || odds[plain.tier(*s).unwrap() - 1] == 0
+            ));
+            return Ok(());
+        };
+        let chances = pack.chances(tier);
+        prop_assert_eq!(chances.len(), species.len());
+        let tier_weight = |t: usize| -> usize {
+            species
+                .iter()
+                .zip(&weights)
+                .filter(|(s, _)| pack.tier(**s) == Some(t))
+                .map(|(_, w)| *w)
+                .sum()
+        };
+        let total_odds: usize = (1..=tier)
+            .filter(|t| tier_weight(*t) > 0)
+            .map(|t| odds[t - 1])
+            .sum();
+
+        let nonzero = chances.iter().filter(|w| **w > 0).count();
+        let mut seen = vec![0.0; chances.len()];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let i = dice.roll_weighted(&chances);
+            seen[i] += dice.weight() / nonzero as f64;
+        }
+        for ((s, w), p) in species.iter().zip(&weights).zip(&seen) {
+            let t = pack.tier(*s).unwrap();
+            let expected = if *w == 0 {
+                0.0
+            } else {
+                odds[t - 1] as f64 / total_odds as f64 * *w as f64
+                    / tier_weight(t) as f64
+            };
+            prop_assert!((expected - p).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn weighted_rolls(
+        weights in prop::collection::vec(0..5usize, 1..6),
+        mut seq in dice(),
+    ) {
+        prop_assume!(weights.iter().any(|w| *w > 0));
+        let total: usize = weights.iter().sum();
+        prop_assert!(weights[seq.roll_weighted(&weights)] > 0);
+
+        // Exhaustive enumeration visits each nonzero choice once, and the
+        // path weights recover the exact probabilities
+        let nonzero = weights.iter().filter(|w| **w > 0).count();
+        let mut seen = vec![0.0; weights.len()];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let i = dice.roll_weighted(&weights);
+            seen[i] += dice.weight() / nonzero as f64;
+        }
+        for (w, p) in weights.iter().zip(&seen) {
+            prop_assert!((*w as f64 / total as f64 - p).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn pre_battle_settles(a in team(), b in team(), mut dice in dice()) {
+        // Battle-start snipes and their faint triggers are fully resolved
+        // before the first round, leaving only healthy, compact teams
+        let mut frames = Frames::new();
+        let cfg = GameConfig::default();
+        Battle(a, b).run_logged(&cfg, &mut dice, &mut frames);
+        let first = frames.frames()[1].battle;
+        prop_assert_eq!(first.check(), Ok(()));
+    }
+
+    #[test]
+    fn summon_placement(
+        team in team(),
+        pos in 0..TEAM_SIZE,
+        dice in dice(),
+    ) {
+        // Pushing only moves friends at or behind the summon slot, and fails
+        // exactly when they fill the back of the team; otherwise, it agrees
+        // with shoving
+        let mut pushed = team;
+        let mut shoved = team;
+        let room = (pos..TEAM_SIZE).any(|i| team[i].is_none());
+        prop_assert_eq!(pushed.push_at(pos), room);
+        if room {
+            prop_assert!(shoved.make_space_at(pos));
+            prop_assert_eq!(pushed, shoved);
+        } else {
+            prop_assert_eq!(pushed, team);
+        }
+        for i in 0..pos {
+            prop_assert_eq!(pushed[i], team[i]);
+        }
+
+        // Both rules give valid battles
+        let mut cfg = GameConfig::default();
+        for summon in [SummonPlacement::Shove, SummonPlacement::Push] {
+            cfg.summon = summon;
+            let mut dice = dice.clone();
+            let mut log = CheckLog::default();
+            Battle(team, team).run_logged(&cfg, &mut dice, &mut log);
+            prop_assert!(log.ended);
+        }
+    }
+
+    #[test]
+    fn gold_audit(shop in shop(), mut dice in dice()) {
+        // The timeline explains every change in an audited shop's gold
+        let mut shop = shop.audited();
+        let mut timeline = Timeline::new();
+        let gold = shop.gold;
+        for _ in 0..16 {
+            if shop.step_logged(&mut dice, &mut timeline) == Step::Ended {
+                break;
+            }
+        }
+        let delta: isize = timeline
+            .entries()
+            .iter()
+            .filter_map(|(_, e)| match e {
+                Entry::Gold { delta, .. } => Some(*delta),
+                _ => None,
+            })
+            .sum();
+        prop_assert_eq!(shop.gold as isize, gold as isize + delta);
+        prop_assert_eq!(shop.check_gold(), Ok(()));
+    }
+
+    #[test]
+    fn scripted_actions(
+        shop in shop(),
+        actions in prop::collection::vec(
+            (0..6usize, 0..8usize, 0..6usize),
+            0..8,
+        ),
+    ) {
+        // Scripted actions either run cleanly or are rejected without
+        // touching the shop
+        let script: Vec<String> = actions
+            .iter()
+            .map(|(kind, i, j)| match kind {
+                0 => format!("buy a{} t{}", i, j),
+                1 => format!("buy f{} t{}", i, j),
+                2 => format!("sell t{}", j),
+                3 => format!("merge t{} t{}", i, j),
+                4 => "roll".to_owned(),
+                _ => "end".to_owned(),
+            })
+            .collect();
+        let commands = script::parse(&script.join("; ")).unwrap();
+        prop_assert_eq!(commands.len(), actions.len());
+        let mut shop = shop.audited();
+        let mut dice = DeterministicDice::new();
+        for c in commands {
+            let Command::Action(a) = c else {
+                unreachable!();
+            };
+            let before = shop;
+            if shop.apply(a, &mut dice, &mut ()).is_err() {
+                prop_assert_eq!(shop, before);
+            }
+            prop_assert_eq!(shop.check(), Ok(()));
+            prop_assert_eq!(shop.check_gold(), Ok(()));
+        }
+        prop_assert!(shop.team.count() <= TEAM_SIZE);
+    }
+
+    #[test]
+    fn seed_sweep(a in team(), b in team(), first_seed in any::<u64>()) {
+        let cfg = GameConfig::default();
+        let sweep = Sweep::run(&cfg, Battle(a, b), 20, first_seed);
+        for r in [&sweep.sampled, &sweep.unweighted, &sweep.weighted] {
+            prop_assert!((r.wins + r.loses + r.ties - 1.0).abs() < 1e-9);
+        }
+        for s in &sweep.spread {
+            // Real dice can only reach outcomes which some dice path reaches
+            if sweep.sampled.rate(s.winner) > 0.0 {
+                prop_assert!(sweep.unweighted.rate(s.winner) > 0.0);
+            }
+        }
+        // With a single dice path, there's nothing to average
+        if sweep.paths == 1 {
+            prop_assert_eq!(&sweep.sampled, &sweep.unweighted);
+            prop_assert_eq!(sweep.max_z(), 0.0);
+        }
+    }
+
+    #[test]
+    fn outcome_tree(a in team_of(3), b in team_of(3)) {
+        let cfg = GameConfig::default();
+        let branches = explore::explore(&cfg, Battle(a, b));
+        let total: f64 = branches.iter().map(|b| b.probability).sum();
+        prop_assert!((total - 1.0).abs() < 1e-9);
+
+        // Coin-flip battles have a pivotal roll on every path, which is one
+        // of the path's own rolls; one-sided battles have none
+        let one_sided = branches.iter().all(|b| b.winner == branches[0].winner);
+        for b in &branches {
+            prop_assert_eq!(b.pivot.is_none(), one_sided);
+            if let Some(p) = &b.pivot {
+                prop_assert!(p.index < b.key.len());
+                let key = b.key.as_bytes()[p.index] as char;
+                prop_assert_eq!(key.to_digit(36), Some(p.roll.value as u32));
+            }
+        }
+    }
+
+    #[test]
+    fn roll_sensitivity(a in team_of(3), b in team_of(3)) {
+        let cfg = GameConfig::default();
+        let decisions = explore::sensitivity(&cfg, Battle(a, b));
+        for d in &decisions {
+            // Each decision's options cover it exactly, and conditioning on
+            // them averages back to the decision's own chance of winning
+            let total: f64 = d.choices.iter().map(|c| c.probability).sum();
+            prop_assert!((total - 1.0).abs() < 1e-9);
+            let wins: f64 =
+                d.choices.iter().map(|c| c.probability * c.wins).sum();
+            prop_assert!((wins - d.wins).abs() < 1e-9);
+            for c in &d.choices {
+                prop_assert_eq!(c.roll.index, d.prefix.len());
+            }
+        }
+        for w in decisions.windows(2) {
+            prop_assert!(w[0].swing() >= w[1].swing());
+        }
+    }
+
+    #[test]
+    fn custom_mimics(a in team_of(3), b in team_of(3), level in 1..=3usize) {
+        // A custom species with a single template behaves exactly like the
+        // template, along every dice path
+        for (s, m) in MIMICS.iter() {
+            prop_assert_eq!(m.default_power(), s.default_power());
+            prop_assert_eq!(m.ability_text(level), s.ability_text(level));
+            prop_assert_eq!(format!("{:?}", m).parse::<Species>().unwrap(), *m);
+        }
+        let cfg = GameConfig::default();
+        let (c, d) = (swap_species(a, &MIMICS), swap_species(b, &MIMICS));
+        prop_assert_eq!(c.code().parse::<Team>().unwrap().code(), c.code());
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let expected = Battle(a, b).run(&cfg, &mut dice);
+            let key = dice.key();
+            let mut replay = DeterministicDice::from_key(&key).unwrap();
+            prop_assert_eq!(Battle(c, d).run(&cfg, &mut replay), expected);
+        }
+    }
+
+    #[test]
+    fn scripted_abilities(
+        a in team_of(3),
+        b in team_of(3),
+        level in 1..=3usize,
+    ) {
+        // Spelling out a built-in species' scripted abilities gives a custom
+        // species which behaves exactly like it, along every dice path
+        for (s, m) in SCRIPTS.iter() {
+            for x in m.abilities() {
+                prop_assert_eq!(x.check(), Ok(()));
+                let json = serde_json::to_string(x).unwrap();
+                let parsed: Ability = serde_json::from_str(&json).unwrap();
+                prop_assert_eq!(parsed, *x);
+            }
+            let mut causes: Vec<Cause> =
+                m.abilities().iter().map(|x| x.trigger).collect();
+            causes.dedup();
+            let found: Vec<_> = causes
+                .into_iter()
+                .flat_map(|c| m.scripted(c, level).map(|(_, p)| p))
+                .collect();
+            prop_assert!(found.iter().all(|p| *p == s.ability_params(level)));
+            prop_assert_eq!(found.len(), s.abilities().len());
+        }
+        let cfg = GameConfig::default();
+        let (c, d) = (swap_species(a, &SCRIPTS), swap_species(b, &SCRIPTS));
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let expected = Battle(a, b).run(&cfg, &mut dice);
+            let key = dice.key();
+            let mut replay = DeterministicDice::from_key(&key).unwrap();
+            prop_assert_eq!(Battle(c, d).run(&cfg, &mut replay), expected);
+        }
+    }
+
+    #[test]
+    fn random_teams(
+        mut dice in dice(),
+        pool in prop::collection::vec(species(), 0..3),
+        min_friends in 0..=TEAM_SIZE,
+        low in 1..=MAX_STAT,
+        span in 0..10usize,
+        perk_chance in 0..=100usize,
+    ) {
+        // Random teams are always legal and within their constraints
+        let stats = low..=(low + span).min(MAX_STAT);
+        let c = Constraints {
+            species: pool.clone(),
+            friends: min_friends..=TEAM_SIZE,
+            attack: stats.clone(),
+            health: stats.clone(),
+            perk_chance,
+            perks: vec![Modifier::Garlic, Modifier::Chili],
+        };
+        let team = Team::random(&mut dice, &c).unwrap();
+        prop_assert!(team.is_compact());
+        prop_assert!(c.friends.contains(&team.count()));
+        for f in team.iter().flatten() {
+            prop_assert!(f.check_legal().is_ok());
+            prop_assert!(pool.is_empty() || pool.contains(&f.species));
+            prop_assert!(stats.contains(&f.attack));
+            prop_assert!(stats.contains(&f.health));
+            prop_assert!(
+                f.modifier == f.species.default_modifier()
+                    || c.perks.contains(&f.modifier.unwrap())
+            );
+        }
+
+        let bad = Constraints {
+            attack: 0..=low,
+            ..c.clone()
+        };
+        prop_assert!(Team::random(&mut dice, &bad).is_err());
+    }
+
+    #[test]
+    fn replicator_dynamics(
+        upper in prop::collection::vec(0.0..=1.0f32, 28),
+        n in 2..=8usize,
+        dominant in 0..8usize,
+    ) {
+        // Shares stay a distribution and survivors only go extinct.  In a
+        // constant-sum game, a team which beats every other team takes over.
+        let mut payoff = vec![vec![0.5; n]; n];
+        let pairs = (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j)));
+        for ((i, j), u) in pairs.zip(&upper) {
+            payoff[i][j] = *u;
+            payoff[j][i] = 1.0 - u;
+        }
+        let settings = replicator::Settings::default();
+        let evo = replicator::evolve(&payoff, &vec![1.0; n], &settings);
+        prop_assert!((evo.shares.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        prop_assert!(evo.shares.iter().all(|s| *s >= 0.0));
+        prop_assert!(evo.survivors.windows(2).all(|w| w[0] >= w[1]));
+
+        let d = dominant % n;
+        for j in (0..n).filter(|j| *j != d) {
+            payoff[d][j] = 1.0;
+            payoff[j][d] = 0.0;
+        }
+        let evo = replicator::evolve(&payoff, &vec![1.0; n], &settings);
+        prop_assert!(evo.converged);
+        prop_assert_eq!(evo.shares[d], 1.0);
+    }
+
+    #[test]
+    fn focus_matches_filter(
+        shop in shop(),
+        require in prop::collection::vec(species(), 0..3),
+        ban in prop::collection::vec(species(), 0..3),
+    ) {
+        // A focus keeps the same teams as the equivalent filter, and only
+        // explores shops which could still lead to one
+        let focus = Focus {
+            require: require.clone(),
+            ban: ban.clone(),
+        };
+        let filter = TeamFilter {
+            with: require,
+            without: ban,
+            ..TeamFilter::default()
+        };
+        prop_assert_eq!(focus.keeps(&shop.team), filter.matches(&shop.team));
+        if focus.keeps(&shop.team) {
+            prop_assert!(focus.explores(&shop));
+        }
+        prop_assert!(Focus::default().explores(&shop));
+    }
+
+    #[test]
+    fn opponent_distribution(
+        team in team_of(3),
+        opponents in prop::collection::vec((team_of(3), 0..4usize), 1..4),
+    ) {
+        // The expected result against a distribution is the mix of results
+        // against each team on its own, and is itself a distribution
+        let cfg = GameConfig::default();
+        let weights = opponents.iter().map(|(t, w)| (*t, *w as f64));
+        let Some(model) = Opponents::new(3, weights) else {
+            prop_assert!(opponents.iter().all(|(_, w)| *w == 0));
+            return Ok(());
+        };
+        let total: f64 = model.teams().iter().map(|(_, p)| p).sum();
+        prop_assert!((total - 1.0).abs() < 1e-9);
+
+        let e = model.expect(&cfg, &team);
+        prop_assert!((e.wins + e.loses + e.ties - 1.0).abs() < 1e-9);
+        let fixed = model.teams().iter().map(|(t, p)| {
+            (*p, Opponents::fixed(3, *t).expect(&cfg, &team))
+        });
+        let mixed = Expected::mix(fixed);
+        prop_assert!((e.wins - mixed.wins).abs() < 1e-9);
+        prop_assert!((e.damage_taken - mixed.damage_taken).abs() < 1e-9);
+        // Losing on turn 3 costs 2 lives under the default damage model
+        prop_assert!((e.damage_taken - 2.0 * e.loses).abs() < 1e-9);
+        prop_assert!((e.damage_dealt - 2.0 * e.wins).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mirrored_battles(a in team(), b in team()) {
+        // Auditing a matchup from either side finds the same asymmetry, with
+        // chances which add up (and nothing at all with no tolerance left)
+        let cfg = GameConfig::default();
+        let ab = mirror::audit(&cfg, &a, &b, 1e-9);
+        let ba = mirror::audit(&cfg, &b, &a, 1e-9);
+        prop_assert_eq!(ab.is_some(), ba.is_some());
+        if let Some((ab, ba)) = ab.zip(ba) {
+            let close = |x: f64, y: f64| (x - y).abs() < 1e-9;
+            prop_assert!(close(ab.forward.wins, ba.mirrored.loses));
+            prop_assert!(close(ab.forward.ties, ba.mirrored.ties));
+            prop_assert!(close(ab.mirrored.wins, ba.forward.loses));
+            prop_assert!(close(ab.difference, ba.difference));
+            for c in [ab.forward, ab.mirrored] {
+                prop_assert!(close(c.wins + c.loses + c.ties, 1.0));
+            }
+            prop_assert!(mirror::audit(&cfg, &a, &b, 1.0).is_none());
+        }
+    }
+
+    #[test]
+    fn species_pairings(
+        teams in prop::collection::vec(team(), 1..5),
+        chances in prop::collection::vec((0..=4usize, 0..=4usize), 25),
+    ) {
+        // Aggregating a row at a time matches counting every matchup in
+        // which both species are present
+        let n = teams.len();
+        let cell = |i: usize, j: usize| {
+            let (w, l) = chances[i * 5 + j];
+            let l = l.min(4 - w);
+            (w as f32 / 4.0, l as f32 / 4.0, (4 - w - l) as f32 / 4.0)
+        };
+        let mut matrix = SpeciesMatrix::new();
+        for (i, a) in teams.iter().enumerate() {
+            let row: Vec<_> = (0..n).map(|j| cell(i, j)).collect();
+            let wins: Vec<f32> = row.iter().map(|c| c.0).collect();
+            let loses: Vec<f32> = row.iter().map(|c| c.1).collect();
+            let ties: Vec<f32> = row.iter().map(|c| c.2).collect();
+            matrix.add_row(a, &teams, &wins, &loses, &ties);
+        }
+        let has = |t: &Team, s: Species| {
+            t.iter().flatten().any(|f| f.species == s)
+        };
+        for s in matrix.species() {
+            for o in matrix.species() {
+                let pairs: Vec<(usize, usize)> = (0..n)
+                    .flat_map(|i| (0..n).map(move |j| (i, j)))
+                    .filter(|(i, j)| has(&teams[*i], s) && has(&teams[*j], o))
+                    .collect();
+                match matrix.get(s, o) {
+                    None => prop_assert!(pairs.is_empty()),
+                    Some(p) => {
+                        prop_assert_eq!(p.matchups, pairs.len());
+                        let sum = |f: fn((f32, f32, f32)) -> f32| {
+                            pairs
+                                .iter()
+                                .map(|(i, j)| f(cell(*i, *j)) as f64)
+                                .sum::<f64>()
+                        };
+                        prop_assert!((p.wins - sum(|c| c.0)).abs() < 1e-6);
+                        prop_assert!((p.loses - sum(|c| c.1)).abs() < 1e-6);
+                        prop_assert!((p.ties - sum(|c| c.2)).abs() < 1e-6);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn challenge_rules(
+        mut dice in dice(),
+        rules in (any::<bool>(), any::<bool>(), any::<bool>()),
+        team_size in 1..=TEAM_SIZE,
+        b in team(),
+    ) {
+        // Shops follow the challenge's gold, food, honey and team size, and
+        // summons in battle don't grow a team past its size either
+        let (no_food, double_gold, honey) = rules;
+        let challenge = Challenge {
+            no_food,
+            double_gold,
+            honey,
+            team_size: Some(team_size),
+        };
+        let mut shop = Shop::with_challenge(1, challenge, &mut dice);
+        prop_assert_eq!(shop.gold, challenge.gold());
+        for _ in 0..24 {
+            let done = shop.step(&mut dice) == Step::Ended;
+            prop_assert_eq!(shop.check(), Ok(()));
+            prop_assert!(shop.team.count() <= team_size);
+            if no_food {
+                prop_assert_eq!(shop.foods().count(), 0);
+                if honey {
+                    prop_assert!(shop
+                        .team
+                        .iter()
+                        .flatten()
+                        .all(|f| f.modifier == Some(Modifier::Honey)));
+                }
+            }
+            if done {
+                break;
+            }
+        }
+
+        let mut a = Team::new();
+        for i in 0..team_size {
+            a[i] = Some(Friend {
+                modifier: Some(Modifier::Honey),
+                ..Friend::new(Species::Cricket)
+            });
+        }
+        let cfg = GameConfig {
+            challenge,
+            ..GameConfig::default()
+        };
+        let mut events: Vec<Event> = vec![];
+        Battle(a, b).run_logged(&cfg, &mut dice, &mut events);
+        let mut size = team_size;
+        for e in &events {
+            match e {
+                Event::Summon { team: true, .. } => size += 1,
+                Event::Faint { team: true, .. } => size -= 1,
+                _ => (),
+            }
+            prop_assert!(size <= team_size);
+        }
+    }
+
+    #[test]
+    fn multiset_rolls(
+        weights in prop::collection::vec(0usize..4, 1..5),
+        n in 0usize..4,
+    ) {
+        prop_assume!(weights.iter().any(|w| *w > 0));
+        let tally = |mut dice: DeterministicDice| {
+            let mut out = std::collections::BTreeMap::new();
+            while dice.next() {
+                let v = dice.roll_multiset(&weights, n);
+                *out.entry(v).or_insert(0.0) += dice.probability();
+            }
+            out
+        };
+        let ordered = tally(DeterministicDice::new());
+        let multisets = tally(DeterministicDice::new().multisets());
+        prop_assert_eq!(ordered.len(), multisets.len());
+        for (v, p) in &ordered {
+            prop_assert!(v.windows(2).all(|w| w[0] <= w[1]));
+            prop_assert!(v.iter().all(|i| weights[*i] > 0));
+            prop_assert!((multisets[v] - p).abs() < 1e-9);
+        }
+        let total: f64 = multisets.values().sum();
+        prop_assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn route_rebuilds_team(
+        mut dice in dice(),
+        buys in prop::collection::vec((0usize..3, 0usize..TEAM_SIZE), 0..3),
+    ) {
+        let root = Shop::new(1, &mut dice);
+        let mut shop = root;
+        let mut taken = 0;
+        let mut fixed = true;
+        for (s, t) in buys {
+            let a = Action::BuyFriend { shop: s, team: t };
+            let mut results = std::collections::HashSet::new();
+            let mut paths = DeterministicDice::new();
+            while paths.next() {
+                let mut next = shop;
+                if next.apply(a, &mut paths, &mut ()).is_ok() {
+                    results.insert(next);
+                }
+            }
+            if shop.apply(a, &mut dice, &mut ()).is_ok() {
+                taken += 1;
+                fixed &= results.len() == 1;
+            }
+        }
+        let canonical = |t: &Team| {
+            let mut t = t.for_battle();
+            t.sort();
+            t
+        };
+        let r = route::shortest(&[root], &shop.team, 100_000);
+        // If the purchases didn't depend on the dice, they're a route
+        // themselves, so the shortest can't be any longer
+        let len = r.as_ref().map(|r| r.actions.len());
+        prop_assert!(!fixed || len.is_some_and(|n| n <= taken));
+        if let Some(r) = r {
+            prop_assert_eq!(r.root, 0);
+            prop_assert_eq!(canonical(&r.shop.team), canonical(&shop.team));
+            let mut replay = root;
+            for a in &r.actions {
+                prop_assert!(replay.apply(*a, &mut dice, &mut ()).is_ok());
+            }
+            prop_assert_eq!(replay, r.shop);
+        }
+    }
+
+    #[test]
+    fn plain_battles(
+        mut a in team(),
+        mut b in team(),
+        max_rounds in 1..20usize,
+    ) {
+        // Strip anything which acts in battle, so that the fast path runs
+        for i in 0..TEAM_SIZE {
+            for f in [&mut a[i], &mut b[i]].into_iter().flatten() {
+                f.modifier = None;
+                if f.species.acts_in_battle() {
+                    f.species = Species::Fish;
+                }
+            }
+        }
+        let battle = Battle(a, b);
+        prop_assert!(battle.is_plain());
+        let cfg = GameConfig {
+            max_rounds,
+            ..GameConfig::default()
+        };
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let full = battle.run_logged(&cfg, &mut dice, &mut ());
+        prop_assert_eq!(dice.index(), Some(0));
+        prop_assert_eq!(battle.run(&cfg, &mut dice), full);
+    }
+
+    #[test]
+    fn memoized_outcomes(a in team_of(3), b in team_of(3)) {
+        let cfg = GameConfig {
+            memo_battles: true,
+            ..GameConfig::default()
+        };
+        let battle = Battle(a, b);
+        let mut expected: Vec<(Outcome, usize, f64)> = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let o = battle.run(&cfg, &mut dice);
+            match expected.iter_mut().find(|e| e.0 == o) {
+                Some(e) => {
+                    e.1 += 1;
+                    e.2 += dice.probability();
+                }
+                None => expected.push((o, 1, dice.probability())),
+            }
+        }
+        let tallies = battle.outcomes(&cfg);
+        prop_assert_eq!(tallies.len(), expected.len());
+        for (o, paths, p) in expected {
+            let t = tallies.iter().find(|t| t.outcome == o).unwrap();
+            prop_assert_eq!(t.paths, paths);
+            prop_assert!((t.probability - p).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn leaderboard_converges(
+        wins in prop::collection::vec(
+            prop::collection::vec(0u8..=4, 8), 1..8),
+        order in Just((0..8).collect::<Vec<usize>>()).prop_shuffle(),
+        k in 1usize..4,
+    ) {
+        let n = wins.len();
+        let rate = |i: usize, j: usize| wins[i][j] as f32 / 4.0;
+        let mut board = Leaderboard::new(n, k);
+        for i in order.into_iter().filter(|i| *i < n) {
+            let w: Vec<f32> = (0..n).map(|j| rate(i, j)).collect();
+            let l: Vec<f32> = (0..n).map(|j| rate(j, i)).collect();
+            board.add_row(i, &w, &l);
+            for j in 0..n {
+                let s = board.standing(j);
+                prop_assert!(0.0 <= s.low && s.low <= s.mean);
+                prop_assert!(s.mean <= s.high && s.high <= 1.0);
+            }
+            prop_assert!(board.contenders() >= k.min(n));
+        }
+        prop_assert_eq!(board.rows(), n);
+
+        // Once every row is done, the leaderboard is exact
+        let mut means: Vec<f64> = (0..n)
+            .map(|i| (0..n).map(|j| rate(i, j) as f64).sum::<f64>() / n as f64)
+            .collect();
+        for (i, m) in means.iter().enumerate() {
+            let s = board.standing(i);
+            prop_assert!(s.exact);
+            prop_assert!((s.mean - m).abs() < 1e-9);
+        }
+        means.sort_by(|a, b| b.total_cmp(a));
+        let top: Vec<f64> = board.top().iter().map(|s| s.mean).collect();
+        prop_assert_eq!(top.len(), k.min(n));
+        for (t, m) in top.iter().zip(&means) {
+            prop_assert!((t - m).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn similar_teams(
+        teams in prop::collection::vec(team(), 1..20),
+        q in team(),
+        n in 0usize..6,
+    ) {
+        prop_assert_eq!(similarity::distance(&q, &q), Distance::default());
+        let mut brute: Vec<(usize, Distance)> = teams
+            .iter()
+            .map(|t| similarity::distance(&q, t))
+            .enumerate()
+            .collect();
+        for (i, d) in &brute {
+            prop_assert_eq!(*d, similarity::distance(&teams[*i], &q));
+        }
+        brute.sort_by_key(|(i, d)| (*d, *i));
+        brute.truncate(n);
+        prop_assert_eq!(Index::new(&teams).nearest(&q, n), brute);
+    }
+
+    #[test]
+    fn frames_per_round(a in team(), b in team(), mut dice in dice()) {
+        // One frame before the battle and one at the start of every round,
+        // including the final check for a winner
+        let mut frames = Frames::new();
+        let cfg = GameConfig::default();
+        let out = Battle(a, b).run_logged(&cfg, &mut dice, &mut frames);
+        prop_assert_eq!(frames.frames().len(), out.rounds + 2);
+        let last = frames.frames().last().unwrap().battle;
+        if !out.capped {
+            prop_assert!(last.0.is_empty() || last.1.is_empty());
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Global settings for how game state is printed.
+//!
+//! By default, species, modifiers, and stats are drawn as emoji.  These come
+//! out garbled in some terminals and log systems, so there's also an ASCII
+//! mode, which uses two-letter abbreviations instead (keeping the same width
+//! as an emoji, so that box art still lines up).
+//!
+//! Team box art may also be drawn with ANSI colors, which are only enabled
+//! by default when printing to a terminal.
+
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Environment variable which enables ASCII mode when set to anything other
+/// than an empty string or `0`
+pub const ASCII_ENV: &str = "SAP_ASCII";
+
+static ASCII: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables ASCII mode
+pub fn set_ascii(on: bool) {
+    ASCII.store(on, Ordering::Relaxed);
+}
+
+/// Checks whether ASCII mode is enabled
+pub fn ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// Enables or disables ANSI colors
+pub fn set_color(on: bool) {
+    COLOR.store(on, Ordering::Relaxed);
+}
+
+/// Checks whether ANSI colors are enabled
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Enables ASCII mode if requested by [`ASCII_ENV`], and colors if stdout is
+/// a terminal (unless `NO_COLOR` is set)
+pub fn init_from_env() {
+    if let Ok(v) = std::env::var(ASCII_ENV) {
+        if !v.is_empty() && v != "0" {
+            set_ascii(true);
+        }
+    }
+    set_color(
+        std::io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none(),
+    );
+}
+
+/// How to color part of a team's box art
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Style {
+    Plain,
+    /// A stat above its species' base value
+    Buffed,
+    /// A stat below its species' base value
+    Debuffed,
+    /// A held modifier
+    Perk,
+    /// A friend with no health left, which is about to faint
+    Dim,
+}
+
+impl Style {
+    /// Picks a style for a stat, based on its species' base value
+    pub fn for_stat(value: usize, base: usize) -> Self {
+        match value.cmp(&base) {
+            std::cmp::Ordering::Greater => Self::Buffed,
+            std::cmp::Ordering::Less => Self::Debuffed,
+            std::cmp::Ordering::Equal => Self::Plain,
+        }
+    }
+    fn code(&self) -> Option<&'static str> {
+        match self {
+            Self::Plain => None,
+            Self::Buffed => Some("32"),
+            Self::Debuffed => Some("31"),
+            Self::Perk => Some("1;33"),
+            Self::Dim => Some("2"),
+        }
+    }
+}
+
+/// Prints a value in the given style, if colors are enabled
+pub struct Paint<T>(pub Style, pub T);
+
+impl<T: std::fmt::Display> std::fmt::Display for Paint<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0.code() {
+            Some(c) if color() => write!(f, "\x1b[{}m{}\x1b[0m", c, self.1),
+            _ => write!(f, "{}", self.1),
+        }
+    }
+}
+
+/// Symbols for stats and resources
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Icon {
+    Health,
+    Attack,
+    Gold,
+    Dice,
+}
+
+impl std::fmt::Display for Icon {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // The heart and swords are narrower than other emoji in most
+        // terminals, so they're padded with an extra space
+        let s = match (self, ascii()) {
+            (Self::Health, false) => "❤️ ",
+            (Self::Attack, false) => "⚔️ ",
+            (Self::Gold, false) => "🪙",
+            (Self::Dice, false) => "🎲",
+            (Self::Health, true) => "hp",
+            (Self::Attack, true) => "atk",
+            (Self::Gold, true) => "gold",
+            (Self::Dice, true) => "roll",
+        };
+        write!(f, "{}", s)
+    }
+}
@@ -0,0 +1,156 @@
+//! Replicator dynamics, which evolve a population of teams on a payoff
+//! matrix to show which compositions survive in the metagame.
+//!
+//! Each generation, a team's share of the population grows in proportion to
+//! its payoff against the current population, relative to the population's
+//! mean payoff.  Teams whose share falls below a threshold go extinct, so
+//! the population narrows to the survivors (which may keep cycling, rather
+//! than converging, when there's rock-paper-scissors structure).
+
+use serde::Serialize;
+
+/// Settings for [`evolve`]
+#[derive(Copy, Clone, Debug)]
+pub struct Settings {
+    /// Most generations to run
+    pub generations: usize,
+    /// Share below which a team goes extinct
+    pub extinction: f64,
+    /// The population has converged once no share changes by more than this
+    /// in a generation
+    pub tolerance: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            generations: 1000,
+            extinction: 1e-6,
+            tolerance: 1e-9,
+        }
+    }
+}
+
+/// The final population, along with how it got there
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Evolution {
+    /// Share of the population held by each team, which sums to 1
+    pub shares: Vec<f64>,
+    /// Number of surviving teams after each generation
+    pub survivors: Vec<usize>,
+    pub converged: bool,
+}
+
+/// Evolves a population under discrete replicator dynamics, where
+/// `payoff[i][j]` is team `i`'s payoff against team `j` (which must not be
+/// negative).  The initial population is given as relative weights, e.g. 1
+/// for each team which takes part and 0 for the rest.
+pub fn evolve(
+    payoff: &[Vec<f32>],
+    initial: &[f64],
+    settings: &Settings,
+) -> Evolution {
+    let total: f64 = initial.iter().sum();
+    let mut shares: Vec<f64> = initial.iter().map(|w| w / total).collect();
+    let mut alive: Vec<usize> =
+        (0..shares.len()).filter(|i| shares[*i] > 0.0).collect();
+    let mut survivors = vec![];
+    let mut converged = false;
+    for _ in 0..settings.generations {
+        let fitness: Vec<f64> = alive
+            .iter()
+            .map(|i| {
+                let row = &payoff[*i];
+                alive.iter().map(|j| row[*j] as f64 * shares[*j]).sum()
+            })
+            .collect();
+        let mean: f64 = alive
+            .iter()
+            .zip(&fitness)
+            .map(|(i, f)| shares[*i] * f)
+            .sum();
+        if mean <= 0.0 {
+            break;
+        }
+
+        let mut next = shares.clone();
+        for (i, f) in alive.iter().zip(&fitness) {
+            next[*i] = shares[*i] * f / mean;
+            if next[*i] < settings.extinction {
+                next[*i] = 0.0;
+            }
+        }
+        let total: f64 = alive.iter().map(|i| next[*i]).sum();
+        alive.retain(|i| next[*i] > 0.0);
+        for i in &alive {
+            next[*i] /= total;
+        }
+        let change = (0..shares.len())
+            .map(|i| (next[i] - shares[i]).abs())
+            .fold(0.0, f64::max);
+        shares = next;
+        survivors.push(alive.len());
+        if change <= settings.tolerance {
+            converged = true;
+            break;
+        }
+    }
+    Evolution {
+        shares,
+        survivors,
+        converged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dominant_team() {
+        // A team which beats every other team takes over, and the others go
+        // extinct one generation at a time
+        let payoff = vec![
+            vec![0.5, 1.0, 1.0],
+            vec![0.0, 0.5, 0.75],
+            vec![0.0, 0.25, 0.5],
+        ];
+        let evo = evolve(&payoff, &[1.0, 1.0, 1.0], &Settings::default());
+        assert!(evo.converged);
+        assert_eq!(evo.shares, [1.0, 0.0, 0.0]);
+        assert!(evo.survivors.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(evo.survivors.last(), Some(&1));
+
+        // Teams which start with no share never grow one
+        let evo = evolve(&payoff, &[0.0, 2.0, 1.0], &Settings::default());
+        assert_eq!(evo.shares, [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn rock_paper_scissors() {
+        // An even split is a fixed point, so nothing changes; starting off
+        // balance, the discrete dynamics spiral outwards, until only one
+        // team is left
+        let payoff = vec![
+            vec![0.5, 1.0, 0.0],
+            vec![0.0, 0.5, 1.0],
+            vec![1.0, 0.0, 0.5],
+        ];
+        let settings = Settings {
+            generations: 50,
+            ..Settings::default()
+        };
+        let evo = evolve(&payoff, &[1.0; 3], &settings);
+        assert!(evo.converged);
+        assert_eq!(evo.survivors, [3]);
+        for s in &evo.shares {
+            assert!((s - 1.0 / 3.0).abs() < 1e-12);
+        }
+
+        let evo = evolve(&payoff, &[2.0, 1.0, 1.0], &settings);
+        assert!(evo.converged);
+        assert_eq!(evo.survivors[..3], [3; 3]);
+        assert_eq!(evo.survivors.last(), Some(&1));
+        assert_eq!(evo.shares.iter().filter(|s| **s > 0.0).count(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+//! Expected value of a reroll, i.e. how much spending a gold to reroll the
+//! shop improves the best team which can be built by the end of the turn.
+//!
+//! Play is solved by expectimax: picking an [`Action`] is a choice, while
+//! the dice rolled by that action (for the new offers, or a Beaver's target)
+//! are chance, averaged by their probability.  After the reroll being
+//! evaluated, play continues without rerolling again, since the search
+//! would otherwise branch over every possible shop at every step.
+
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+
+use crate::{
+    dice::DeterministicDice,
+    params::TEAM_SIZE,
+    shop::{Action, Shop},
+    team::Team,
+};
+
+/// The value of rerolling a shop, compared to playing out the turn from it
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct RerollValue {
+    /// Best expected rating of the final team, without rerolling
+    pub stay: f64,
+    /// Expected best rating of the final team, after rerolling once
+    pub reroll: f64,
+    /// Number of distinct shops which the reroll may produce
+    pub outcomes: usize,
+    /// Chance that the reroll produces a shop with a better best rating
+    /// than staying
+    pub improves: f64,
+}
+
+impl RerollValue {
+    /// Returns the expected improvement in rating from rerolling
+    pub fn gain(&self) -> f64 {
+        self.reroll - self.stay
+    }
+}
+
+/// Solves for the best play from each shop, memoizing results
+struct Solver<F> {
+    rate: F,
+    memo: HashMap<Shop, f64>,
+}
+
+impl<F: Fn(&Team) -> f64> Solver<F> {
+    /// Returns the best expected rating reachable from the shop this turn,
+    /// without rerolling
+    fn value(&mut self, shop: &Shop) -> f64 {
+        if let Some(v) = self.memo.get(shop) {
+            return *v;
+        }
+        let v = actions(shop)
+            .into_iter()
+            .filter_map(|a| self.expect(shop, a))
+            .fold(f64::NEG_INFINITY, f64::max);
+        self.memo.insert(*shop, v);
+        v
+    }
+
+    /// Averages the value after the given action over every dice path, or
+    /// returns `None` if the action isn't possible
+    fn expect(&mut self, shop: &Shop, action: Action) -> Option<f64> {
+        let mut total = 0.0;
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let mut next = *shop;
+            next.apply(action, &mut dice, &mut ()).ok()?;
+            let v = if action == Action::EndTurn {
+                (self.rate)(&next.team)
+            } else {
+                // Same deduplication as team generation
+                next.team.sort();
+                self.value(&next)
+            };
+            total += dice.probability() * v;
+        }
+        Some(total)
+    }
+}
+
+/// Lists actions other than rerolling, including some which aren't possible
+/// (these are rejected by [`Shop::apply`])
+fn actions(shop: &Shop) -> Vec<Action> {
+    let friends: Vec<usize> =
+        (0..TEAM_SIZE).filter(|i| shop.team[*i].is_some()).collect();
+    // Teams are sorted after every action, so buying into any empty slot
+    // leads to the same shop
+    let empty = (0..TEAM_SIZE).find(|i| shop.team[*i].is_none());
+    let mut out = vec![Action::EndTurn];
+    for shop in 0..shop.offers().count() {
+        for team in friends.iter().chain(&empty) {
+            out.push(Action::BuyFriend { shop, team: *team });
+        }
+    }
+    for shop in 0..shop.foods().count() {
+        out.extend(
+            friends
+                .iter()
+                .map(|team| Action::BuyFood { shop, team: *team }),
+        );
+    }
+    for from in &friends {
+        out.push(Action::Sell { team: *from });
+        out.extend(friends.iter().map(|to| Action::Combine {
+            from: *from,
+            to: *to,
+        }));
+    }
+    out
+}
+
+/// Compares rerolling the shop once against playing out the turn from it,
+/// where `rate` gives the rating of a team at the end of the turn.  Returns
+/// `None` if the shop can't afford a reroll.
+pub fn reroll_value<F: Fn(&Team) -> f64>(
+    shop: &Shop,
+    rate: F,
+) -> Option<RerollValue> {
+    let mut solver = Solver {
+        rate,
+        memo: HashMap::new(),
+    };
+    let stay = solver.value(shop);
+
+    let mut reroll = 0.0;
+    let mut improves = 0.0;
+    let mut outcomes = HashSet::new();
+    let mut dice = DeterministicDice::new();
+    while dice.next() {
+        let mut next = *shop;
+        next.apply(Action::Reroll, &mut dice, &mut ()).ok()?;
+        let v = solver.value(&next);
+        let p = dice.probability();
+        reroll += p * v;
+        if v > stay {
+            improves += p;
+        }
+        outcomes.insert(next);
+    }
+    Some(RerollValue {
+        stay,
+        reroll,
+        outcomes: outcomes.len(),
+        improves,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pack, species::Species};
+
+    #[test]
+    fn reroll_values() {
+        // Rated by size, rerolling spends the gold which would have bought a
+        // friend, and never leads to a better shop
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let mut shop = Shop::new(1, &mut dice);
+        let size = |t: &Team| 0.5 + t.count() as f64;
+        shop.gold = 0;
+        assert_eq!(reroll_value(&shop, size), None);
+        shop.gold = 3;
+        let v = reroll_value(&shop, size).unwrap();
+        assert_eq!(v.stay, 1.5);
+        assert!((v.reroll - 0.5).abs() < 1e-9);
+        assert_eq!(v.improves, 0.0);
+        assert!(v.outcomes > 1);
+
+        // Rated by a species which isn't on offer, only a reroll can help
+        let missing: Species = pack::active()
+            .species(1)
+            .find(|s| shop.offers().all(|f| f.species != *s))
+            .unwrap();
+        let wanted =
+            |t: &Team| t.iter().flatten().any(|f| f.species == missing) as u8;
+        shop.gold = 4;
+        let v = reroll_value(&shop, |t| wanted(t) as f64).unwrap();
+        assert_eq!(v.stay, 0.0);
+        assert!(v.improves > 0.0 && v.improves < 1.0);
+        assert!((v.gain() - v.improves).abs() < 1e-9);
+    }
+}
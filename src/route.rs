@@ -0,0 +1,205 @@
+//! Shortest build routes, which turn a generated team back into a sequence
+//! of shop actions that can be followed by hand.
+//!
+//! Generation reaches a team along many paths, most of which wander (e.g.
+//! buying a friend only to sell it).  A route is found by breadth-first
+//! search from the starting shops, over actions whose result doesn't depend
+//! on the dice; rerolls, and abilities which pick random targets, can't be
+//! followed reliably, so they're skipped.  The route is therefore the fewest
+//! such actions which build the team, leaving the most gold among those.
+//!
+//! Friends can be rearranged freely before battle, so a route only has to
+//! build the same friends as the team, in any order.
+
+use hashbrown::HashSet;
+
+use crate::{
+    dice::DeterministicDice,
+    params::TEAM_SIZE,
+    shop::{Action, Shop},
+    team::Team,
+};
+
+/// A sequence of actions which builds a team
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    /// Index of the starting shop which the route begins from
+    pub root: usize,
+    pub actions: Vec<Action>,
+    /// The shop after taking every action
+    pub shop: Shop,
+}
+
+/// A shop explored by the search
+struct State {
+    shop: Shop,
+    /// Index of the starting shop which it was reached from
+    root: usize,
+    /// Index of the previous state and the action taken from it, if this
+    /// isn't a starting shop
+    parent: Option<(usize, Action)>,
+}
+
+/// Returns a team in battle-ready form, with its friends sorted, so that
+/// teams which only differ in order compare equal
+fn canonical(team: &Team) -> Team {
+    let mut out = team.for_battle();
+    out.sort();
+    out
+}
+
+/// Lists actions other than rerolling or ending the turn, including some
+/// which aren't possible (these are rejected by [`Shop::apply`])
+fn actions(shop: &Shop) -> Vec<Action> {
+    let friends: Vec<usize> =
+        (0..TEAM_SIZE).filter(|i| shop.team[*i].is_some()).collect();
+    let mut out = vec![];
+    for s in 0..shop.offers().count() {
+        out.extend(
+            (0..TEAM_SIZE).map(|team| Action::BuyFriend { shop: s, team }),
+        );
+    }
+    for s in 0..shop.foods().count() {
+        out.extend(friends.iter().map(|team| Action::BuyFood {
+            shop: s,
+            team: *team,
+        }));
+    }
+    for from in &friends {
+        out.push(Action::Sell { team: *from });
+        out.extend(friends.iter().filter(|to| *to != from).map(|to| {
+            Action::Combine {
+                from: *from,
+                to: *to,
+            }
+        }));
+    }
+    out
+}
+
+/// Applies an action, returning the resulting shop if it's possible and
+/// every dice path leads to the same shop
+fn apply(shop: &Shop, action: Action) -> Option<Shop> {
+    let mut out = None;
+    let mut dice = DeterministicDice::new();
+    while dice.next() {
+        let mut next = *shop;
+        next.apply(action, &mut dice, &mut ()).ok()?;
+        match out {
+            None => out = Some(next),
+            Some(prev) if prev != next => return None,
+            Some(_) => (),
+        }
+    }
+    out
+}
+
+/// Finds the shortest route from any of the given shops to the team,
+/// breaking ties by the most gold left.  Returns `None` if there's no route
+/// or if more than `max_states` shops are explored.
+pub fn shortest(
+    roots: &[Shop],
+    team: &Team,
+    max_states: usize,
+) -> Option<Route> {
+    let target = canonical(team);
+    let mut states = vec![];
+    let mut seen = HashSet::new();
+    for (root, shop) in roots.iter().enumerate() {
+        if seen.insert(*shop) {
+            states.push(State {
+                shop: *shop,
+                root,
+                parent: None,
+            });
+        }
+    }
+
+    let mut active: Vec<usize> = (0..states.len()).collect();
+    while !active.is_empty() {
+        let best = active
+            .iter()
+            .filter(|i| canonical(&states[**i].shop.team) == target)
+            .rev()
+            .max_by_key(|i| states[**i].shop.gold);
+        if let Some(i) = best {
+            return Some(route(&states, *i));
+        }
+
+        let mut next = vec![];
+        for i in active {
+            let State { shop, root, .. } = states[i];
+            for a in actions(&shop) {
+                let Some(s) = apply(&shop, a) else {
+                    continue;
+                };
+                if seen.insert(s) {
+                    if states.len() >= max_states {
+                        return None;
+                    }
+                    states.push(State {
+                        shop: s,
+                        root,
+                        parent: Some((i, a)),
+                    });
+                    next.push(states.len() - 1);
+                }
+            }
+        }
+        active = next;
+    }
+    None
+}
+
+/// Walks back from a state to its starting shop
+fn route(states: &[State], mut i: usize) -> Route {
+    let State { shop, root, .. } = states[i];
+    let mut actions = vec![];
+    while let Some((parent, a)) = states[i].parent {
+        actions.push(a);
+        i = parent;
+    }
+    actions.reverse();
+    Route {
+        root,
+        actions,
+        shop,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script;
+
+    #[test]
+    fn shortest_route() {
+        // Buying two friends (in either order) is the shortest route to a
+        // team of them, and a starting shop which already has the team needs
+        // no actions at all
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let root = Shop::new(1, &mut dice);
+        let mut built = root;
+        let commands = script::parse("buy a0 t3; buy a1 t1").unwrap();
+        script::run(&mut built, &commands, &mut dice, &mut ()).unwrap();
+
+        let r = shortest(&[root], &built.team, 100_000).unwrap();
+        assert_eq!(r.root, 0);
+        assert_eq!(r.actions.len(), 2);
+        assert_eq!(r.shop.gold, built.gold);
+        assert_eq!(canonical(&r.shop.team), canonical(&built.team));
+        let mut replay = root;
+        for a in &r.actions {
+            replay.apply(*a, &mut dice, &mut ()).unwrap();
+        }
+        assert_eq!(replay, r.shop);
+
+        let r = shortest(&[root, built], &built.team, 100_000).unwrap();
+        assert_eq!((r.root, r.actions.len()), (1, 0));
+
+        // A Whale isn't sold on turn 1, and the search gives up quickly
+        let whale: Team = "Whale:3/8".parse().unwrap();
+        assert_eq!(shortest(&[root], &whale, 100), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Rule sets, which capture balance changes between versions of the game.
+//!
+//! Like the [active pack](crate::pack), the active rule set is a global
+//! setting, since base stats and ability magnitudes are looked up all over
+//! the engine.  The default `current` rule set uses the stats and magnitudes
+//! in [`Species`]; other rule sets override them per species, and are loaded
+//! from JSON, e.g.
+//!
+//! ```json
+//! {
+//!     "name": "old-otter",
+//!     "stats": { "Otter": [1, 2] },
+//!     "abilities": {
+//!         "Ant": [
+//!             { "targets": 1, "attack": 1, "health": 1 },
+//!             { "targets": 1, "attack": 2, "health": 2 },
+//!             { "targets": 1, "attack": 3, "health": 3 }
+//!         ]
+//!     }
+//! }
+//! ```
+//!
+//! Stats are written as `[attack, health]`, like team codes; abilities have
+//! one entry per level, with unused magnitudes left out.
+
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    params::{MAX_STAT, TEAM_SIZE},
+    species::{AbilityParams, Species},
+    team::Team,
+};
+
+/// A set of balance changes, relative to the current rules
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    pub name: String,
+    /// Base `[attack, health]` of each species
+    #[serde(default)]
+    pub stats: BTreeMap<Species, [usize; 2]>,
+    /// Ability magnitudes of each species, at levels 1-3
+    #[serde(default)]
+    pub abilities: BTreeMap<Species, [AbilityParams; 3]>,
+}
+
+static CURRENT: LazyLock<RuleSet> = LazyLock::new(|| RuleSet {
+    name: "current".to_owned(),
+    ..RuleSet::default()
+});
+
+static ACTIVE: RwLock<Option<&'static RuleSet>> = RwLock::new(None);
+
+/// Returns the active rule set
+pub fn active() -> &'static RuleSet {
+    ACTIVE.read().unwrap().unwrap_or(&CURRENT)
+}
+
+/// Sets the active rule set, leaking the old one (as with packs)
+pub fn set_active(rules: RuleSet) -> Result<()> {
+    rules.check()?;
+    *ACTIVE.write().unwrap() = Some(Box::leak(Box::new(rules)));
+    Ok(())
+}
+
+impl RuleSet {
+    /// Every built-in rule set
+    pub fn builtin() -> [&'static RuleSet; 1] {
+        [&CURRENT]
+    }
+
+    /// Loads a rule set from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let rules: Self = serde_json::from_str(&data)?;
+        rules.check()?;
+        Ok(rules)
+    }
+
+    /// Checks that stats are in range, and that tokens aren't overridden
+    /// (since their stats and abilities come from the
+    /// [token registry](crate::token))
+    pub fn check(&self) -> Result<()> {
+        let species = self.stats.keys().chain(self.abilities.keys());
+        for s in species {
+            if s.tier().is_none() {
+                return Err(Error::Rules(format!(
+                    "{}: {:?} is a token",
+                    self.name, s
+                )));
+            }
+        }
+        for (s, [attack, health]) in &self.stats {
+            if *health == 0 || *attack > MAX_STAT || *health > MAX_STAT {
+                return Err(Error::Rules(format!(
+                    "{}: {:?} can't have {}/{} stats",
+                    self.name, s, attack, health
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the species' base `(health, attack)`, if overridden
+    pub fn power(&self, s: Species) -> Option<(usize, usize)> {
+        self.stats
+            .get(&s)
+            .map(|[attack, health]| (*health, *attack))
+    }
+
+    /// Returns the species' base `(health, attack)` under this rule set
+    pub fn base_power(&self, s: Species) -> (usize, usize) {
+        self.power(s).unwrap_or_else(|| s.current_power())
+    }
+
+    /// Moves a team built under this rule set to another one, by shifting
+    /// each friend's stats by the change in its species' base stats (so
+    /// that buffs are kept).  Stats stay in `1..=MAX_STAT` for health and
+    /// `0..=MAX_STAT` for attack.
+    pub fn translate(&self, to: &RuleSet, team: &Team) -> Team {
+        let mut out = *team;
+        for i in 0..TEAM_SIZE {
+            if let Some(f) = out[i].as_mut() {
+                let (h0, a0) = self.base_power(f.species);
+                let (h1, a1) = to.base_power(f.species);
+                let shift = |v: usize, from: usize, to: usize, min: usize| {
+                    (v + to).saturating_sub(from).clamp(min, MAX_STAT)
+                };
+                f.health = shift(f.health, h0, h1, 1);
+                f.attack = shift(f.attack, a0, a1, 0);
+            }
+        }
+        out
+    }
+
+    /// Returns the species' ability magnitudes at the given level (1-3), if
+    /// overridden
+    pub fn ability_params(
+        &self,
+        s: Species,
+        level: usize,
+    ) -> Option<AbilityParams> {
+        self.abilities.get(&s).map(|a| a[level.clamp(1, 3) - 1])
+    }
+}
+
+impl std::str::FromStr for RuleSet {
+    type Err = Error;
+
+    /// Looks up a built-in rule set by name, e.g. `current`
+    fn from_str(s: &str) -> Result<Self> {
+        Self::builtin()
+            .into_iter()
+            .find(|r| r.name.eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| Error::Rules(format!("Unknown rule set {:?}", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides() {
+        // Overrides replace exactly what they name, and round-trip through
+        // JSON; tokens and friends with no health are rejected
+        let current: RuleSet = "current".parse().unwrap();
+        assert!("old".parse::<RuleSet>().is_err());
+        for s in Species::builtin() {
+            assert_eq!(current.power(s), None);
+            assert_eq!(current.base_power(s), s.current_power());
+            for [attack, health] in [[0, 1], [3, 4], [MAX_STAT, 0], [1, 51]] {
+                let mut rules = RuleSet {
+                    name: "patch".to_owned(),
+                    ..RuleSet::default()
+                };
+                rules.stats.insert(s, [attack, health]);
+                let p = |level| AbilityParams {
+                    targets: 2,
+                    attack: level,
+                    ..AbilityParams::default()
+                };
+                rules.abilities.insert(s, [p(1), p(2), p(3)]);
+                assert_eq!(
+                    rules.check().is_ok(),
+                    s.tier().is_some() && health > 0 && health <= MAX_STAT
+                );
+                assert_eq!(rules.power(s), Some((health, attack)));
+                for level in 0..5 {
+                    assert_eq!(current.ability_params(s, level), None);
+                    assert_eq!(
+                        rules.ability_params(s, level),
+                        Some(p(level.clamp(1, 3)))
+                    );
+                }
+                let json = serde_json::to_string(&rules).unwrap();
+                let parsed: RuleSet = serde_json::from_str(&json).unwrap();
+                assert_eq!(parsed, rules);
+            }
+        }
+    }
+
+    #[test]
+    fn translate_keeps_buffs() {
+        // The Otter from the module docs is 1/2 rather than 2/1, so every
+        // Otter loses a point of attack (but not below zero) and gains a
+        // point of health, and nothing else changes
+        let current: RuleSet = "current".parse().unwrap();
+        let old: RuleSet = serde_json::from_str(
+            r#"{ "name": "old-otter", "stats": { "Otter": [1, 2] } }"#,
+        )
+        .unwrap();
+        assert!(old.check().is_ok());
+        let team = |s: &str| s.parse::<Team>().unwrap();
+        let t = team("Otter:3/5:1,Ant:2/1+Honey,Otter:0/1");
+        let u = current.translate(&old, &t);
+        assert_eq!(u, team("Otter:2/6:1,Ant:2/1+Honey,Otter:0/2"));
+        assert_eq!(current.translate(&current, &t), t);
+
+        // Translating back undoes it, except where attack was clamped
+        let back = old.translate(&current, &u);
+        assert_eq!(back, team("Otter:3/5:1,Ant:2/1+Honey,Otter:1/1"));
+    }
+}
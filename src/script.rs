@@ -0,0 +1,236 @@
+//! Scripted shop turns, for replaying a turn from the real game.
+//!
+//! A script is a list of commands, separated by semicolons or newlines, e.g.
+//!
+//! ```text
+//! shop Ant Fish Pig; food Apple
+//! buy a0 t1; buy f0 t1; sell t0; end
+//! ```
+//!
+//! Positions are written as a letter and an index: `a` for friends in the
+//! shop, `f` for food in the shop, and `t` for the team (where `t0` is the
+//! front).  The commands are
+//!
+//! - `shop <species>...` and `food <food>...`, which replace the offers in the
+//!   shop, so that it matches the real game
+//! - `buy aN tM`, which buys a friend and places it at (or merges it onto)
+//!   the given team position
+//! - `buy fN tM`, which buys food for a friend on the team
+//! - `sell tN`
+//! - `merge tN tM`, which merges the first friend onto the second
+//! - `roll`, which rerolls the shop
+//! - `end`, which ends the turn.  If any commands follow, they're run in the
+//!   next turn.
+//!
+//! Everything after a `#` on a line is a comment.
+
+use crate::{
+    dice::Dice,
+    error::{Error, Result},
+    event::Log,
+    food::Food,
+    shop::{Action, Shop},
+    species::Species,
+};
+
+/// A single command in a script
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Replaces the friends on offer in the shop
+    Shop(Vec<Species>),
+    /// Replaces the food on offer in the shop
+    Food(Vec<Food>),
+    Action(Action),
+}
+
+/// Parses a position like `t0`, with the given prefix
+fn position(s: Option<&str>, prefix: char) -> Result<usize> {
+    let bad = || Error::Script(format!("expected {}<index>", prefix));
+    let s = s.ok_or_else(bad)?;
+    s.strip_prefix(prefix)
+        .and_then(|i| i.parse().ok())
+        .ok_or_else(bad)
+}
+
+impl std::str::FromStr for Command {
+    type Err = Error;
+
+    /// Parses a single command, e.g. `buy a0 t1`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let out = match cmd {
+            "shop" => Self::Shop(
+                words.by_ref().map(str::parse).collect::<Result<_>>()?,
+            ),
+            "food" => Self::Food(
+                words.by_ref().map(str::parse).collect::<Result<_>>()?,
+            ),
+            "buy" => {
+                let what = words.next().unwrap_or("");
+                let team = position(words.next(), 't')?;
+                let action = if what.starts_with('a') {
+                    Action::BuyFriend {
+                        shop: position(Some(what), 'a')?,
+                        team,
+                    }
+                } else {
+                    Action::BuyFood {
+                        shop: position(Some(what), 'f')?,
+                        team,
+                    }
+                };
+                Self::Action(action)
+            }
+            "sell" => Self::Action(Action::Sell {
+                team: position(words.next(), 't')?,
+            }),
+            "merge" => Self::Action(Action::Combine {
+                from: position(words.next(), 't')?,
+                to: position(words.next(), 't')?,
+            }),
+            "roll" => Self::Action(Action::Reroll),
+            "end" => Self::Action(Action::EndTurn),
+            _ => {
+                return Err(Error::Script(format!("unknown command {:?}", cmd)))
+            }
+        };
+        match words.next() {
+            Some(w) => Err(Error::Script(format!("unexpected {:?}", w))),
+            None => Ok(out),
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    /// Writes the command as it would be parsed, e.g. `buy a0 t1`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names = |v: Vec<String>| v.join(" ");
+        match self {
+            Self::Shop(s) => write!(
+                f,
+                "shop {}",
+                names(s.iter().map(|s| s.name().to_owned()).collect())
+            ),
+            Self::Food(v) => write!(
+                f,
+                "food {}",
+                names(v.iter().map(|v| format!("{:?}", v)).collect())
+            ),
+            Self::Action(a) => match a {
+                Action::BuyFriend { shop, team } => {
+                    write!(f, "buy a{} t{}", shop, team)
+                }
+                Action::BuyFood { shop, team } => {
+                    write!(f, "buy f{} t{}", shop, team)
+                }
+                Action::Sell { team } => write!(f, "sell t{}", team),
+                Action::Combine { from, to } => {
+                    write!(f, "merge t{} t{}", from, to)
+                }
+                Action::Reroll => write!(f, "roll"),
+                Action::EndTurn => write!(f, "end"),
+            },
+        }
+    }
+}
+
+/// Parses a script into commands, skipping comments and blank commands
+pub fn parse(script: &str) -> Result<Vec<Command>> {
+    script
+        .lines()
+        .map(|line| line.split('#').next().unwrap())
+        .flat_map(|line| line.split(';'))
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            c.parse().map_err(|e| match e {
+                Error::Script(s) => Error::Script(format!("{:?}: {}", c, s)),
+                e => e,
+            })
+        })
+        .collect()
+}
+
+/// Runs a script against the given shop, returning an error which names the
+/// first command that couldn't be run
+pub fn run<R: Dice, L: Log>(
+    shop: &mut Shop,
+    commands: &[Command],
+    rng: &mut R,
+    log: &mut L,
+) -> Result<()> {
+    let mut ended = false;
+    for (i, c) in commands.iter().enumerate() {
+        if ended {
+            shop.start_turn(rng, log);
+            ended = false;
+        }
+        let r = match c {
+            Command::Shop(s) => {
+                let foods: Vec<Food> = shop.foods().cloned().collect();
+                shop.stock(s, &foods)
+            }
+            Command::Food(f) => {
+                let friends: Vec<Species> =
+                    shop.offers().map(|f| f.species).collect();
+                shop.stock(&friends, f)
+            }
+            Command::Action(a) => {
+                ended = *a == Action::EndTurn;
+                shop.apply(*a, rng, log)
+            }
+        };
+        r.map_err(|e| Error::Script(format!("command {}: {}", i, e)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_round_trip() {
+        // Comments and blank commands are skipped, and every command prints
+        // as it was written
+        let script = "shop Ant Fish; food Apple Honey # restock\n\
+                      buy a0 t1;; buy f1 t0\n\
+                      sell t2; merge t0 t3; roll; end";
+        let commands = parse(script).unwrap();
+        let printed: Vec<String> =
+            commands.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            printed,
+            [
+                "shop Ant Fish",
+                "food Apple Honey",
+                "buy a0 t1",
+                "buy f1 t0",
+                "sell t2",
+                "merge t0 t3",
+                "roll",
+                "end",
+            ]
+        );
+        for (c, s) in commands.iter().zip(&printed) {
+            assert_eq!(&s.parse::<Command>().unwrap(), c);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for bad in [
+            "buy t0 a0",
+            "sell t0 t1",
+            "sell a0",
+            "merge t0",
+            "buy a0",
+            "shop Dragon",
+            "food Steak Sushi",
+            "jump",
+        ] {
+            assert!(parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -1,69 +1,599 @@
 use crate::{
+    ability::{Effect, Selector},
+    config::{Challenge, PruneConfig},
     dice::Dice,
+    error::Error,
+    event::{Cause, Log, Trigger},
     food::Food,
     friend::Friend,
     modifier::Modifier,
+    pack,
     params::TEAM_SIZE,
-    params::{DEFAULT_GOLD, SHOP_ANIMAL_COUNT, SHOP_FOOD_COUNT},
+    params::{MAX_PENDING, MAX_SHOP_ANIMALS, MAX_SHOP_FOODS, SHOP_TABLE},
+    render::Icon,
     species::Species,
-    team::Team,
+    team::{fmt_friends, Team},
 };
-use log::trace;
+use serde::{Deserialize, Serialize};
+use tracing::{trace, trace_span};
 
-#[derive(Copy, Clone, Debug)]
-enum ShopAction {
+/// An action taken by [`Shop::step`], which is the first thing it rolls
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShopAction {
     BuyFriend,
     BuyCombineFriend,
     SellFriend,
     BuyFood,
     CombineFriends,
     Reroll,
+    EndTurn,
 }
 
 impl ShopAction {
     pub fn sample<R: Dice>(rng: &mut R) -> Self {
-        match rng.roll(0..6) {
-            0 => Self::BuyFriend,
-            1 => Self::BuyCombineFriend,
-            2 => Self::SellFriend,
-            3 => Self::BuyFood,
-            4 => Self::CombineFriends,
-            5 => Self::Reroll,
-            i => panic!("Invalid ShopAction {}", i),
+        let i = rng.roll(0..7);
+        Self::from_index(i)
+            .unwrap_or_else(|| panic!("Invalid ShopAction {}", i))
+    }
+
+    /// Returns the action chosen by the given roll
+    pub fn from_index(i: usize) -> Option<Self> {
+        match i {
+            0 => Some(Self::BuyFriend),
+            1 => Some(Self::BuyCombineFriend),
+            2 => Some(Self::SellFriend),
+            3 => Some(Self::BuyFood),
+            4 => Some(Self::CombineFriends),
+            5 => Some(Self::Reroll),
+            6 => Some(Self::EndTurn),
+            _ => None,
+        }
+    }
+}
+
+/// The result of [`Shop::step`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// The action was taken, and the turn goes on
+    Acted,
+    /// The turn was ended on purpose, running end-of-turn abilities, so the
+    /// team is ready for battle
+    Ended,
+    /// The action wasn't possible (e.g. buying without enough gold), so the
+    /// shop is unchanged and this dice path leads nowhere.  Rerolling a full
+    /// shop also counts, since other dice paths already roll every shop.
+    Impossible,
+    /// A [pruning rule](PruneConfig) skipped the action, so the shop is
+    /// unchanged
+    Pruned,
+}
+
+/// A specific action in the shop, e.g. one played in the real game, rather
+/// than a random one picked by [`Shop::step`].  Positions in the shop count
+/// only what's on offer, in the order it's printed (see [`Shop::offers`] and
+/// [`Shop::foods`]), and shift as friends and food are bought.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Action {
+    /// Buys the friend at the given shop position.  If the team position
+    /// holds a friend which it can merge with, they're combined; otherwise,
+    /// space is made at that position.
+    BuyFriend {
+        shop: usize,
+        team: usize,
+    },
+    /// Buys the food at the given shop position for a friend on the team
+    BuyFood {
+        shop: usize,
+        team: usize,
+    },
+    /// Sells the friend at the given team position
+    Sell {
+        team: usize,
+    },
+    /// Merges the friend at `from` onto the friend at `to`
+    Combine {
+        from: usize,
+        to: usize,
+    },
+    Reroll,
+    EndTurn,
+}
+
+/// An effect scheduled during one turn, which is applied at the start of the
+/// next one
+#[derive(
+    Copy,
+    Clone,
+    Hash,
+    Debug,
+    Deserialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+)]
+pub enum Pending {
+    /// Gain extra gold
+    Gold(usize),
+    /// Summon a friend into the frontmost empty slot, if there is one
+    Summon(Friend),
+}
+
+/// Where a shop's gold went, and where any extra gold came from, since it
+/// was created.
+///
+/// This is bookkeeping rather than game state: every `Economy` compares equal
+/// and hashes to nothing, so that shops with the same team and offers are
+/// still deduplicated regardless of how they got there.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Economy {
+    /// Gold spent on rerolls
+    pub rerolls: usize,
+    /// Gold spent on friends, including those merged onto the team
+    pub friends: usize,
+    /// Gold spent on food
+    pub food: usize,
+    /// Gold earned by selling friends
+    pub sold: usize,
+    /// Gold earned from abilities (e.g. a Pig), including scheduled effects
+    pub abilities: usize,
+}
+
+impl Economy {
+    pub fn spent(&self) -> usize {
+        self.rerolls + self.friends + self.food
+    }
+    pub fn earned(&self) -> usize {
+        self.sold + self.abilities
+    }
+}
+
+/// Why a shop's gold changed.  Every change is reported to the
+/// [`Log`](crate::event::Log), so that a
+/// [`Timeline`](crate::timeline::Timeline) records an audit trail.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum GoldCause {
+    /// Gold is reset at the start of each turn; leftover gold is lost
+    Turn,
+    Reroll,
+    Friend,
+    Food,
+    Sold,
+    Ability,
+}
+
+impl Economy {
+    /// Returns the running total for the given cause, if it's tracked
+    fn total(&mut self, cause: GoldCause) -> Option<&mut usize> {
+        match cause {
+            GoldCause::Turn => None,
+            GoldCause::Reroll => Some(&mut self.rerolls),
+            GoldCause::Friend => Some(&mut self.friends),
+            GoldCause::Food => Some(&mut self.food),
+            GoldCause::Sold => Some(&mut self.sold),
+            GoldCause::Ability => Some(&mut self.abilities),
         }
     }
 }
 
-#[derive(Copy, Clone, Hash, Debug, Eq, PartialEq)]
+/// Bookkeeping for a shop in [audit mode](Shop::audited): the gold and
+/// economy at the start of the current turn, from which its gold must be
+/// explained.  Like [`Economy`], this is ignored when comparing shops.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Audit {
+    gold: usize,
+    economy: Economy,
+}
+
+impl PartialEq for Audit {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Audit {}
+
+impl std::hash::Hash for Audit {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+/// What the previous step did, for [pruning rules](PruneConfig) which
+/// depend on it.  This is part of a shop's [canonical form](Shop::canonical),
+/// since it decides which actions are pruned next: a shop reached along two
+/// paths may not be pruned the same way along both.  It's only recorded
+/// while a rule which reads it is enabled, so it doesn't split shops
+/// otherwise.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize,
+)]
+pub struct Recent {
+    /// The previous action, if it triggered no abilities
+    quiet: Option<Move>,
+}
+
+/// An action which triggers no abilities, so that it commutes with any other
+/// such action which doesn't touch the same friend.  Independent moves are
+/// taken in the order of their [keys](Move::key); see
+/// [`PruneConfig::commute`].
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+enum Move {
+    /// Bought a friend, given as it was offered and as it joined the team
+    Buy { offered: Friend, bought: Friend },
+    /// Fed food to a friend, given before and after eating it
+    Feed {
+        food: Food,
+        before: Friend,
+        after: Friend,
+    },
+}
+
+impl Move {
+    /// Returns the order in which independent moves are made: purchases
+    /// first, by friend, then feeding, by food and friend.  Food only
+    /// changes the friend which eats it, so a quiet purchase after any
+    /// feeding could have been made before it instead.
+    fn key(&self) -> (Option<Food>, Friend) {
+        match self {
+            Move::Buy { offered, .. } => (None, *offered),
+            Move::Feed { food, before, .. } => (Some(*food), *before),
+        }
+    }
+
+    /// Checks whether this move (which was just made) and a move onto the
+    /// friend at the given team position could be made in either order.
+    /// Identical friends can't be told apart, so a friend which looks like
+    /// the one that this move touched is assumed to be that one.
+    fn independent(&self, team: &Team, pos: usize) -> bool {
+        let touched = match self {
+            Move::Buy { bought, .. } => bought,
+            Move::Feed { after, .. } => after,
+        };
+        team[pos] != Some(*touched)
+    }
+}
+
+/// Checks whether the friend has an ability which triggers on the given cause
+fn reacts(f: &Friend, cause: Cause) -> bool {
+    f.species.scripted(cause, f.level()).next().is_some()
+}
+
+impl std::ops::AddAssign for Economy {
+    fn add_assign(&mut self, other: Self) {
+        self.rerolls += other.rerolls;
+        self.friends += other.friends;
+        self.food += other.food;
+        self.sold += other.sold;
+        self.abilities += other.abilities;
+    }
+}
+
+impl PartialEq for Economy {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Economy {}
+
+impl std::hash::Hash for Economy {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+/// A team along with the shop it's buying from.
+///
+/// Shops compare and hash by their [canonical form](Shop::canonical), so
+/// that shops which can make the same choices are a single state when
+/// deduplicating the search frontier.  Offers and pending effects are also
+/// stored in sorted order, which [`Shop::check`] enforces.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 pub struct Shop {
     pub team: Team,
     pub gold: usize,
+    /// Current turn, starting from 1, which sets the shop size and tier
+    pub turn: usize,
+
+    /// Slots beyond those available on this turn are empty, except for bonus
+    /// friends added when a team member levels up
+    shop_friends: [Option<Friend>; MAX_SHOP_ANIMALS],
+    /// Number of bonus friends added since the last reroll
+    bonus_friends: usize,
+    shop_foods: [Option<Food>; MAX_SHOP_FOODS],
+
+    /// Effects waiting for the start of the next turn.  This is kept sorted,
+    /// so that shops which scheduled the same effects compare equal.
+    pending: [Option<Pending>; MAX_PENDING],
+
+    /// Gold spent and earned so far, which is ignored when comparing shops
+    pub economy: Economy,
 
-    // XXX More slots get added to the shop over time
-    shop_friends: [Option<Friend>; SHOP_ANIMAL_COUNT],
-    shop_foods: [Option<Food>; SHOP_FOOD_COUNT],
+    /// If set, gold is checked after every action
+    #[serde(default)]
+    audit: Option<Audit>,
+
+    /// Rule changes for custom game modes
+    #[serde(default)]
+    pub challenge: Challenge,
+
+    /// What the previous step did, for pruning rules
+    #[serde(default)]
+    recent: Recent,
+}
+
+/// The parts of a [`Shop`] which decide what it can do next
+type Canonical = (
+    Team,
+    usize,
+    usize,
+    [Option<Friend>; MAX_SHOP_ANIMALS],
+    [Option<Food>; MAX_SHOP_FOODS],
+    [Option<Pending>; MAX_PENDING],
+    Challenge,
+    Recent,
+);
+
+impl PartialEq for Shop {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+impl Eq for Shop {}
+
+impl std::hash::Hash for Shop {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state)
+    }
 }
 
 impl Shop {
-    pub fn new<R: Dice>(rng: &mut R) -> Self {
+    /// Returns the shop's canonical form, which leaves out bookkeeping (the
+    /// economy, audit, and count of bonus friends) and sorts the team, since
+    /// no shop action depends on the team's order.  Every action places or
+    /// targets friends by position, and every position is tried, so shops
+    /// whose teams hold the same friends in another order reach the same
+    /// teams.
+    fn canonical(&self) -> Canonical {
+        let mut team = self.team;
+        team.sort();
+        (
+            team,
+            self.gold,
+            self.turn,
+            self.shop_friends,
+            self.shop_foods,
+            self.pending,
+            self.challenge,
+            self.recent,
+        )
+    }
+
+    /// Builds an empty team and a shop for the given turn (starting from 1)
+    pub fn new<R: Dice>(turn: usize, rng: &mut R) -> Self {
+        Self::with_challenge(turn, Challenge::default(), rng)
+    }
+
+    /// Builds an empty team and a shop for the given turn, under the given
+    /// rule changes
+    pub fn with_challenge<R: Dice>(
+        turn: usize,
+        challenge: Challenge,
+        rng: &mut R,
+    ) -> Self {
+        assert!(turn >= 1, "Turns start from 1");
         let mut out = Shop {
             team: Team::new(),
-            gold: DEFAULT_GOLD,
-            shop_friends: [None; SHOP_ANIMAL_COUNT],
-            shop_foods: [None; SHOP_FOOD_COUNT],
+            gold: 0,
+            turn: turn - 1,
+            shop_friends: [None; MAX_SHOP_ANIMALS],
+            bonus_friends: 0,
+            shop_foods: [None; MAX_SHOP_FOODS],
+            pending: [None; MAX_PENDING],
+            economy: Economy::default(),
+            audit: None,
+            challenge,
+            recent: Recent::default(),
         };
-        out.reroll(rng);
+        out.start_turn(rng, &mut ());
         out
     }
 
+    /// Enables audit mode, in which every step checks that the shop's gold is
+    /// explained by what it has spent and earned this turn (panicking if
+    /// not).  This is slow-ish, so it's meant for tests and debugging.
+    pub fn audited(mut self) -> Self {
+        self.audit = Some(Audit {
+            gold: self.gold,
+            economy: self.economy,
+        });
+        self
+    }
+
+    /// Checks that the shop's gold matches the gold at the start of the turn,
+    /// plus gold earned and minus gold spent since then.  Always succeeds
+    /// unless the shop is [audited](Shop::audited).
+    pub fn check_gold(&self) -> Result<(), String> {
+        let Some(a) = self.audit else {
+            return Ok(());
+        };
+        let spent = self.economy.spent() - a.economy.spent();
+        let earned = self.economy.earned() - a.economy.earned();
+        if self.gold + spent != a.gold + earned {
+            return Err(format!(
+                "{} gold doesn't match {} at the start of the turn, \
+                 {} earned, and {} spent",
+                self.gold, a.gold, earned, spent
+            ));
+        }
+        Ok(())
+    }
+
+    /// Spends gold, panicking if the shop can't afford it
+    fn spend<L: Log>(&mut self, cause: GoldCause, amount: usize, log: &mut L) {
+        assert!(
+            self.gold >= amount,
+            "Can't spend {} gold on {:?} with only {}",
+            amount,
+            cause,
+            self.gold
+        );
+        self.gold -= amount;
+        if let Some(t) = self.economy.total(cause) {
+            *t += amount;
+        }
+        log.gold(cause, -(amount as isize));
+    }
+
+    /// Earns gold
+    fn earn<L: Log>(&mut self, cause: GoldCause, amount: usize, log: &mut L) {
+        self.gold += amount;
+        if let Some(t) = self.economy.total(cause) {
+            *t += amount;
+        }
+        log.gold(cause, amount as isize);
+    }
+
+    /// Starts the next turn: resets gold, rerolls the shop, then applies
+    /// effects scheduled during the previous turn and runs start-of-turn
+    /// abilities.  Per-turn trigger counts are cleared first.
+    pub fn start_turn<R: Dice, L: Log>(&mut self, rng: &mut R, log: &mut L) {
+        self.turn += 1;
+        let span = trace_span!(
+            "shop_turn",
+            turn = self.turn,
+            team = self.team.fingerprint(),
+            dice = rng.index(),
+        );
+        let _guard = span.enter();
+        let gold = self.challenge.gold();
+        let delta = gold as isize - self.gold as isize;
+        self.gold = gold;
+        log.gold(GoldCause::Turn, delta);
+        if let Some(a) = self.audit.as_mut() {
+            a.gold = self.gold;
+            a.economy = self.economy;
+        }
+        self.team.clear_state();
+        self.reroll(rng);
+
+        for p in std::mem::replace(&mut self.pending, [None; MAX_PENDING])
+            .into_iter()
+            .flatten()
+        {
+            match p {
+                Pending::Gold(n) => {
+                    trace!(gold = n, "Scheduled effect gives gold");
+                    self.earn(GoldCause::Ability, n, log);
+                }
+                Pending::Summon(f) => {
+                    let room = self.has_room();
+                    match (0..TEAM_SIZE).find(|i| self.team[*i].is_none()) {
+                        Some(i) if room => {
+                            trace!(
+                                species = ?f.species,
+                                pos = i,
+                                "Scheduled summon"
+                            );
+                            self.team.summon(f, i, true, log);
+                        }
+                        _ => {
+                            trace!(species = ?f.species, "No room to summon")
+                        }
+                    }
+                }
+            }
+        }
+        for i in 0..TEAM_SIZE {
+            if self.team[i].is_some() {
+                self.on_start_turn(i, rng, log);
+            }
+        }
+    }
+
+    /// Schedules an effect for the start of the next turn.  Panics if too
+    /// many effects are already pending.
+    pub fn schedule(&mut self, p: Pending) {
+        let slot = self
+            .pending
+            .iter_mut()
+            .find(|p| p.is_none())
+            .expect("Too many pending effects");
+        *slot = Some(p);
+        self.pending.sort();
+    }
+
+    /// Returns effects waiting for the start of the next turn
+    pub fn pending(&self) -> impl Iterator<Item = &Pending> {
+        self.pending.iter().flatten()
+    }
+
+    /// Returns the friends on offer in the shop
+    pub fn offers(&self) -> impl Iterator<Item = &Friend> {
+        self.shop_friends.iter().flatten()
+    }
+
+    /// Returns the food on offer in the shop
+    pub fn foods(&self) -> impl Iterator<Item = &Food> {
+        self.shop_foods.iter().flatten()
+    }
+
+    /// Returns `(animal slots, food slots, max tier)` for the current turn
+    pub fn limits(&self) -> (usize, usize, usize) {
+        let i = (self.turn.max(1) - 1) / 2;
+        let (animals, foods, tier) = SHOP_TABLE[i.min(SHOP_TABLE.len() - 1)];
+        let foods = if self.challenge.no_food { 0 } else { foods };
+        (animals, foods, tier)
+    }
+
+    /// Checks whether the team has room for another friend under the
+    /// challenge's team size
+    pub fn has_room(&self) -> bool {
+        self.team.count() < self.challenge.team_size()
+    }
+
+    /// Replaces the friends and food on offer, e.g. to match a shop from the
+    /// real game.  Any bonus friends are discarded.
+    pub fn stock(
+        &mut self,
+        friends: &[Species],
+        foods: &[Food],
+    ) -> Result<(), Error> {
+        let (animals, max_foods, _) = self.limits();
+        if friends.len() > animals || foods.len() > max_foods {
+            return Err(Error::Action(format!(
+                "turn {} shops have {} friends and {} food",
+                self.turn, animals, max_foods
+            )));
+        }
+        self.bonus_friends = 0;
+        self.shop_friends = [None; MAX_SHOP_ANIMALS];
+        for (slot, s) in self.shop_friends.iter_mut().zip(friends) {
+            *slot = Some(Friend::new(*s));
+        }
+        self.shop_foods = [None; MAX_SHOP_FOODS];
+        for (slot, f) in self.shop_foods.iter_mut().zip(foods) {
+            *slot = Some(*f);
+        }
+        self.shop_friends.sort();
+        self.shop_foods.sort();
+        self.check().map_err(Error::Action)
+    }
+
     /// Rerolls the shop
     fn reroll<R: Dice>(&mut self, rng: &mut R) {
-        for a in self.shop_friends.iter_mut() {
-            *a = Some(Friend::new(Species::sample(rng)));
+        let (animals, foods, tier) = self.limits();
+        self.bonus_friends = 0;
+        // Offers are sorted below, so their order doesn't matter
+        let animals = animals.min(self.shop_friends.len());
+        let species = Species::sample_n(tier, animals, rng);
+        for (i, a) in self.shop_friends.iter_mut().enumerate() {
+            *a = species.get(i).map(|s| Friend::new(*s));
         }
 
-        for f in self.shop_foods.iter_mut() {
-            *f = Some(Food::sample(rng));
+        let foods = foods.min(self.shop_foods.len());
+        let food = Food::sample_n(tier, foods, rng);
+        for (i, f) in self.shop_foods.iter_mut().enumerate() {
+            *f = food.get(i).copied();
         }
         self.shop_friends.sort();
         self.shop_foods.sort();
@@ -80,199 +610,642 @@ impl Shop {
     }
 
     /// Buys the friend at `shop_pos` and adds it to `team_pos`
-    fn buy_friend<R: Dice>(
+    fn buy_friend<R: Dice, L: Log>(
         &mut self,
         shop_pos: usize,
         team_pos: usize,
         rng: &mut R,
+        log: &mut L,
     ) {
-        assert!(self.gold >= 3);
         assert!(self.team[team_pos].is_none());
 
-        self.gold -= 3;
-        let friend = self.shop_friends[shop_pos].take().unwrap();
+        self.spend(GoldCause::Friend, 3, log);
+        let mut friend = self.shop_friends[shop_pos].take().unwrap();
         self.shop_friends.sort();
+        if self.challenge.honey {
+            friend.modifier = Some(Modifier::Honey);
+        }
 
-        trace!("Buying {} at position {}", friend.species, team_pos);
-        self.on_buy(friend, rng);
-        self.team.summon(friend, team_pos);
+        trace!(species = ?friend.species, pos = team_pos, "Buying friend");
+        self.on_buy(friend, team_pos, rng, log);
+        self.team.summon(friend, team_pos, true, log);
         // XXX: There are also "friend is bought" triggers, but nothing in Tier
         // 1 uses them
     }
 
-    fn combine_friends(&mut self, team_pos: usize, g: Friend) {
+    /// Buys the friend at `shop_pos` and merges it onto the friend at
+    /// `team_pos`, which must be able to merge with it
+    fn buy_combine_friend<R: Dice, L: Log>(
+        &mut self,
+        shop_pos: usize,
+        team_pos: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let friend = self.shop_friends[shop_pos].take().unwrap();
+        self.shop_friends.sort();
+
+        self.spend(GoldCause::Friend, 3, log);
+        self.combine_friends(team_pos, friend, rng, log);
+        trace!(
+            species = ?friend.species,
+            pos = team_pos,
+            "Buying and combining"
+        );
+
+        // The on-buy trigger happens after the friends are combined, which
+        // matters in cases where the species levels up.  For convenience, we
+        // remove the species from the team briefly, then reinstall it.
+        let friend = self.team[team_pos].take().unwrap();
+        self.on_buy(friend, team_pos, rng, log);
+        self.team[team_pos] = Some(friend);
+
+        // XXX: There are also "friend is bought" triggers, but nothing in
+        // Tier 1 uses them
+    }
+
+    fn combine_friends<R: Dice, L: Log>(
+        &mut self,
+        team_pos: usize,
+        g: Friend,
+        rng: &mut R,
+        log: &mut L,
+    ) {
         let f = self.team[team_pos].as_mut().unwrap();
-        assert!(f.species == g.species);
-        trace!("Combining {} at position {}", f.species, team_pos);
-        f.health = std::cmp::max(f.health, g.health) + 1;
-        f.attack = std::cmp::max(f.attack, g.attack) + 1;
-        f.exp += 1;
-        // TODO: handle level-up here
+        trace!(species = ?f.species, pos = team_pos, "Combining friend");
+        let level = f.level();
+        f.merge(g);
+        if f.level() > level {
+            trace!(species = ?f.species, level = f.level(), "Level up");
+            self.on_level_up(team_pos, rng, log);
+            self.add_bonus_friend(rng);
+        }
+    }
+
+    /// Asks the friend at the given position to perform its on-level-up
+    /// action, after it has reached its new level
+    fn on_level_up<R: Dice, L: Log>(
+        &mut self,
+        pos: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let f = self.team[pos].unwrap();
+        self.run_scripted(&f, pos, true, Cause::LevelUp, rng, log);
     }
 
-    fn sell_friend<R: Dice>(&mut self, team_pos: usize, rng: &mut R) {
+    /// Adds a friend from the tier above the current shop to a bonus slot,
+    /// as a reward for leveling up.  The bonus friend is lost if the shop is
+    /// rerolled, and nothing is added if every slot is taken.
+    fn add_bonus_friend<R: Dice>(&mut self, rng: &mut R) {
+        let (_, _, tier) = self.limits();
+        let slot = match self.shop_friends.iter().position(Option::is_none) {
+            Some(i) => i,
+            None => {
+                trace!("No room in the shop for a bonus friend");
+                return;
+            }
+        };
+        let s = Species::sample_tier(tier + 1, rng);
+        trace!(species = ?s, "Adding bonus friend to the shop");
+        self.shop_friends[slot] = Some(Friend::new(s));
+        self.shop_friends.sort();
+        self.bonus_friends += 1;
+    }
+
+    fn sell_friend<R: Dice, L: Log>(
+        &mut self,
+        team_pos: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
         assert!(self.team[team_pos].is_some());
 
         let a = self.team[team_pos].take().unwrap();
-        trace!("Selling {} at position {}", a.species, team_pos);
+        trace!(species = ?a.species, pos = team_pos, "Selling friend");
 
-        self.gold += a.level();
-        self.on_sell(a, rng);
+        self.earn(GoldCause::Sold, a.level(), log);
+        self.on_sell(a, team_pos, rng, log);
         for i in 0..TEAM_SIZE {
             if i != team_pos && self.team[i].is_some() {
-                self.on_sold(i);
+                self.on_sold(i, rng, log);
             }
         }
     }
 
     /// Purchases food at the given shop position, applying it to the chosen
     /// member of the team.
-    fn buy_food(&mut self, shop_pos: usize, team_pos: usize) {
+    fn buy_food<L: Log>(
+        &mut self,
+        shop_pos: usize,
+        team_pos: usize,
+        log: &mut L,
+    ) {
         assert!(self.shop_foods[shop_pos].is_some());
         assert!(self.team[team_pos].is_some());
 
+        self.spend(GoldCause::Food, 3, log);
         let friend = self.team[team_pos].as_mut().unwrap();
         let food = self.shop_foods[shop_pos].take().unwrap();
         self.shop_foods.sort();
 
-        self.gold -= 3;
         trace!(
-            "Buying {} for {} at position {}",
-            food,
-            friend.species,
-            team_pos
+            ?food,
+            species = ?friend.species,
+            pos = team_pos,
+            "Buying food"
         );
         match food {
             Food::Apple => {
-                trace!("    Buffing by ❤️  +1, ⚔️  +1");
+                trace!(attack = 1, health = 1, "Buffing");
                 friend.attack += 1;
                 friend.health += 1;
             }
             Food::Honey => {
-                trace!("    Applying honey modifier");
+                trace!("Applying honey modifier");
                 friend.modifier = Some(Modifier::Honey);
             }
         }
     }
 
     /// Asks an species to perform its on-buy action.  It has not yet been added
-    /// to the team at this point.
-    fn on_buy<R: Dice>(&mut self, f: Friend, rng: &mut R) {
-        match f.species {
-            Species::Otter => {
-                // Give a random friend (+1, +1)
-                for i in self.team.random_friends(1, rng) {
-                    let g = self.team[i].as_mut().unwrap();
-                    trace!(
-                        "    {} on buy bufs {} at {} by ❤️  +1, ⚔️  +1",
-                        f.species,
-                        g.species,
-                        i
-                    );
-                    g.health += 1;
-                    g.attack += 1;
+    /// to the team at this point; `pos` is where it will be placed.
+    fn on_buy<R: Dice, L: Log>(
+        &mut self,
+        f: Friend,
+        pos: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        self.run_scripted(&f, pos, false, Cause::Buy, rng, log);
+    }
+
+    /// Asks an species to perform its on-sell action.  It has been removed
+    /// from the team at this point; `pos` is where it was.
+    fn on_sell<R: Dice, L: Log>(
+        &mut self,
+        a: Friend,
+        pos: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        self.run_scripted(&a, pos, false, Cause::Sell, rng, log);
+    }
+
+    /// Runs a friend's [scripted abilities](crate::ability) for the given
+    /// cause, as a single trigger in the log.  `present` is whether the
+    /// friend is on the team at `pos`; friends which aren't (e.g. while
+    /// being bought or once sold) don't exclude themselves from selection.
+    ///
+    /// Level-up abilities use the magnitudes of the level before, since
+    /// they're described by the old level.
+    fn run_scripted<R: Dice, L: Log>(
+        &mut self,
+        f: &Friend,
+        pos: usize,
+        present: bool,
+        cause: Cause,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        let level = match cause {
+            Cause::LevelUp => f.level() - 1,
+            _ => f.level(),
+        };
+        let mut scripted = f.species.scripted(cause, level);
+        let Some(first) = scripted.next() else {
+            return;
+        };
+        let user = present.then_some(pos);
+        log.begin_trigger(Trigger {
+            team: true,
+            pos,
+            species: f.species,
+            cause,
+        });
+        for (a, p) in std::iter::once(first).chain(scripted) {
+            if a.effect == Effect::Gold {
+                trace!(species = ?f.species, gold = p.gold, "Ability gold");
+                self.earn(GoldCause::Ability, p.gold, log);
+                continue;
+            }
+            let apply = |g: &mut Friend| {
+                trace!(
+                    species = ?f.species,
+                    target_species = ?g.species,
+                    attack = p.attack,
+                    health = p.health,
+                    effect = ?a.effect,
+                    "Shop ability"
+                );
+                match a.effect {
+                    Effect::Buff => {
+                        g.attack += p.attack;
+                        g.health += p.health;
+                    }
+                    Effect::TempBuff => {
+                        g.temp_attack += p.attack;
+                        g.temp_health += p.health;
+                    }
+                    Effect::Equip(m) => g.modifier = Some(m),
+                    // Only in battle, or handled above
+                    Effect::Damage | Effect::Gold => (),
+                }
+            };
+            match (a.target, a.target.target(p.targets)) {
+                (Selector::Shop, _) => {
+                    self.shop_friends.iter_mut().flatten().for_each(apply);
+                    // Offers may no longer be in order (e.g. if they were
+                    // equipped), so restore the canonical order
+                    self.shop_friends.sort();
                 }
+                (Selector::User, _) => {
+                    if let Some(g) = user.and_then(|i| self.team[i].as_mut()) {
+                        apply(g);
+                    }
+                }
+                (_, Some(t)) => {
+                    let targets = t.select(&self.team, user, None, rng);
+                    log.targets(true, &targets);
+                    for i in targets {
+                        apply(self.team[i].as_mut().unwrap());
+                    }
+                }
+                // Summoned friends are handled by Team::on_summon
+                (_, None) => (),
             }
-            _ => (),
         }
+        log.end_trigger();
     }
 
-    /// Asks an species to perform its on-sell action.  It has been removed
-    /// from the team at this point.
-    fn on_sell<R: Dice>(&mut self, a: Friend, rng: &mut R) {
-        match a.species {
-            Species::Beaver => {
-                // Give two random friends +1 Health
-                let delta = a.level();
-                for i in self.team.random_friends(2, rng) {
-                    let f = self.team[i].as_mut().unwrap();
-                    trace!(
-                        "    {} on sell bufs {} at {} b❤️  +{} ",
-                        a.species,
-                        f.species,
-                        i,
-                        delta
-                    );
-                    f.health += delta;
+    fn on_sold<R: Dice, L: Log>(&mut self, i: usize, rng: &mut R, log: &mut L) {
+        if self.team[i].as_mut().unwrap().try_trigger(Cause::Sold) {
+            let f = self.team[i].unwrap();
+            self.run_scripted(&f, i, true, Cause::Sold, rng, log);
+        }
+    }
+
+    /// Ends the turn, running end-of-turn abilities for every friend on the
+    /// team (front to back).  This is also where end-of-turn food effects
+    /// would expire, though Tier 1 has none.
+    pub fn end_turn<R: Dice, L: Log>(&mut self, rng: &mut R, log: &mut L) {
+        trace!(turn = self.turn, "Ending turn");
+        for i in 0..TEAM_SIZE {
+            if self.team[i].is_some() {
+                self.on_end_turn(i, rng, log);
+            }
+        }
+    }
+
+    fn on_end_turn<R: Dice, L: Log>(
+        &mut self,
+        i: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        if self.team[i].as_mut().unwrap().try_trigger(Cause::EndTurn) {
+            let f = self.team[i].unwrap();
+            self.run_scripted(&f, i, true, Cause::EndTurn, rng, log);
+        }
+    }
+
+    fn on_start_turn<R: Dice, L: Log>(
+        &mut self,
+        i: usize,
+        rng: &mut R,
+        log: &mut L,
+    ) {
+        if self.team[i].as_mut().unwrap().try_trigger(Cause::StartTurn) {
+            let f = self.team[i].unwrap();
+            self.run_scripted(&f, i, true, Cause::StartTurn, rng, log);
+        }
+    }
+
+    /// Checks that the team is valid and fits the challenge's team size, that
+    /// offers and pending effects are in canonical (sorted) order, and that
+    /// every friend in the shop has stock experience and nonzero health, and
+    /// is allowed on this turn.  Gold
+    /// can't go negative, since it's unsigned (and underflow panics in debug
+    /// builds).
+    pub fn check(&self) -> Result<(), String> {
+        self.team.check()?;
+        if self.team.count() > self.challenge.team_size() {
+            return Err(format!(
+                "Too many friends for a team of {}",
+                self.challenge.team_size()
+            ));
+        }
+        if !self.shop_friends.is_sorted()
+            || !self.shop_foods.is_sorted()
+            || !self.pending.is_sorted()
+        {
+            return Err("Shop slots aren't in canonical order".to_owned());
+        }
+        for f in self.shop_friends.iter().flatten() {
+            if f.health == 0 || f.exp != 0 {
+                return Err(format!("Invalid shop friend {:?}", f));
+            }
+        }
+        let (animals, foods, mut tier) = self.limits();
+        if self.shop_friends.iter().flatten().count()
+            > animals + self.bonus_friends
+            || self.shop_foods.iter().flatten().count() > foods
+        {
+            return Err(format!("Too many shop slots on turn {}", self.turn));
+        }
+        if self.bonus_friends > 0 {
+            tier += 1;
+        }
+        for f in self.shop_friends.iter().flatten() {
+            if pack::active().tier(f.species).is_none_or(|t| t > tier) {
+                return Err(format!(
+                    "{} can't be sold on turn {}",
+                    f.species, self.turn
+                ));
+            }
+        }
+        for p in self.pending() {
+            if let Pending::Summon(f) = p {
+                if f.health == 0 {
+                    return Err(format!("Invalid pending summon {:?}", f));
                 }
             }
-            Species::Duck => {
-                // Give shop pets bonus health depending on level
-                let delta = a.level();
-                for f in self.shop_friends.iter_mut().flatten() {
-                    trace!(
-                        "    {} on sell bufs {} in shop by ❤️  +{}",
-                        a.species,
-                        f.species,
-                        delta
-                    );
-                    f.health += delta;
+        }
+        Ok(())
+    }
+
+    /// Executes a single randomly-chosen action, returning whether it was
+    /// taken, ended the turn, or wasn't possible (see [`Step`])
+    pub fn step<R: Dice + std::fmt::Debug>(&mut self, rng: &mut R) -> Step {
+        self.step_logged(rng, &mut ())
+    }
+
+    /// Executes a single randomly-chosen action, reporting triggered
+    /// abilities to the given log
+    pub fn step_logged<R: Dice, L: Log>(
+        &mut self,
+        rng: &mut R,
+        log: &mut L,
+    ) -> Step {
+        self.step_pruned(rng, &PruneConfig::default(), log)
+    }
+
+    /// Executes a single randomly-chosen action, unless the given rules skip
+    /// it (returning [`Step::Pruned`])
+    pub fn step_pruned<R: Dice, L: Log>(
+        &mut self,
+        rng: &mut R,
+        prune: &PruneConfig,
+        log: &mut L,
+    ) -> Step {
+        let span = trace_span!(
+            "shop_step",
+            turn = self.turn,
+            team = self.team.fingerprint(),
+            gold = self.gold,
+            dice = rng.index(),
+            action = tracing::field::Empty,
+        );
+        let _guard = span.enter();
+        let recent = std::mem::take(&mut self.recent);
+        let step = self.step_inner(rng, prune, recent, log);
+        if matches!(step, Step::Impossible | Step::Pruned) {
+            self.recent = recent;
+        } else if !prune.commute && !prune.sell_bought {
+            self.recent = Recent::default();
+        }
+        self.check_action();
+        step
+    }
+
+    /// Forgets what the previous step did, so that shops reached with and
+    /// without pruning rules can be compared
+    #[cfg(all(test, feature = "proptest"))]
+    pub(crate) fn forget_recent(&mut self) {
+        self.recent = Recent::default();
+    }
+
+    /// Checks whether buying the given friend triggers no abilities, and
+    /// whether no friend it's bought alongside could (so that buying it
+    /// commutes with other such purchases)
+    fn buys_quietly(&self, f: &Friend) -> bool {
+        !reacts(f, Cause::Buy)
+            && !reacts(f, Cause::Summon)
+            && !self.team.iter().flatten().any(|g| reacts(g, Cause::Summon))
+    }
+
+    /// Checks whether selling the friend at the given position triggers no
+    /// abilities
+    fn sells_quietly(&self, pos: usize) -> bool {
+        let others = (0..TEAM_SIZE).filter(|i| *i != pos);
+        !reacts(&self.team[pos].unwrap(), Cause::Sell)
+            && !others
+                .filter_map(|i| self.team[i])
+                .any(|g| reacts(&g, Cause::Sold))
+    }
+
+    /// Performs the given action, returning an error (and leaving the shop
+    /// unchanged) if it isn't possible.  Ending the turn doesn't start the
+    /// next one; call [`Shop::start_turn`] for that.
+    pub fn apply<R: Dice, L: Log>(
+        &mut self,
+        action: Action,
+        rng: &mut R,
+        log: &mut L,
+    ) -> Result<(), Error> {
+        let span = trace_span!(
+            "shop_action",
+            turn = self.turn,
+            team = self.team.fingerprint(),
+            gold = self.gold,
+            ?action,
+        );
+        let _guard = span.enter();
+        let err = |s: &str| Err(Error::Action(s.to_owned()));
+        let friend = |i: usize| self.team.iter().nth(i).cloned().flatten();
+        // Converts a position among the offers into a slot index
+        fn slot<T>(slots: &[Option<T>], i: usize) -> Option<usize> {
+            slots
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.is_some())
+                .nth(i)
+                .map(|(j, _)| j)
+        }
+        match action {
+            Action::BuyFriend { shop, team } => {
+                let Some(shop) = slot(&self.shop_friends, shop) else {
+                    return err("no friend at that shop position");
+                };
+                let a = self.shop_friends[shop].unwrap();
+                if team >= TEAM_SIZE {
+                    return err("invalid team position");
+                } else if self.gold < 3 {
+                    return err("not enough gold");
+                }
+                match friend(team) {
+                    Some(b) if a.can_merge(&b) => {
+                        self.buy_combine_friend(shop, team, rng, log)
+                    }
+                    _ if self.has_room() && self.team.make_space_at(team) => {
+                        self.buy_friend(shop, team, rng, log)
+                    }
+                    _ => return err("team is full"),
                 }
             }
-            Species::Pig => {
-                let delta = a.level();
-                trace!("    {} on sell gives 🪙 +{}", a.species, delta);
-                self.gold += delta;
+            Action::BuyFood { shop, team } => {
+                let Some(shop) = slot(&self.shop_foods, shop) else {
+                    return err("no food at that shop position");
+                };
+                if friend(team).is_none() {
+                    return err("no friend at that team position");
+                } else if self.gold < 3 {
+                    return err("not enough gold");
+                }
+                self.buy_food(shop, team, log);
             }
-            _ => (),
+            Action::Sell { team } => {
+                if friend(team).is_none() {
+                    return err("no friend at that team position");
+                }
+                self.sell_friend(team, rng, log);
+            }
+            Action::Combine { from, to } => {
+                match (friend(from), friend(to)) {
+                    (Some(a), Some(b)) if from != to && a.can_merge(&b) => (),
+                    _ => return err("those friends can't be combined"),
+                }
+                let f = self.team[from].take().unwrap();
+                self.combine_friends(to, f, rng, log);
+            }
+            Action::Reroll => {
+                if self.gold == 0 {
+                    return err("not enough gold");
+                }
+                self.reroll(rng);
+                self.spend(GoldCause::Reroll, 1, log);
+            }
+            Action::EndTurn => self.end_turn(rng, log),
         }
+        self.recent = Recent::default();
+        self.check_action();
+        Ok(())
     }
 
-    fn on_sold(&mut self, _i: usize) {
-        // No Tier 1 friends have an on-sold trigger
+    /// Checks invariants after an action (the gold only in audit mode)
+    fn check_action(&self) {
+        debug_assert_eq!(self.check(), Ok(()));
+        if let Err(e) = self.check_gold() {
+            panic!("Gold audit failed: {}", e);
+        }
     }
 
-    pub fn step<R: Dice + std::fmt::Debug>(&mut self, rng: &mut R) -> bool {
-        match ShopAction::sample(rng) {
+    fn step_inner<R: Dice, L: Log>(
+        &mut self,
+        rng: &mut R,
+        prune: &PruneConfig,
+        recent: Recent,
+        log: &mut L,
+    ) -> Step {
+        let action = ShopAction::sample(rng);
+        tracing::Span::current()
+            .record("action", tracing::field::debug(action));
+        match action {
             // Buy an species
             ShopAction::BuyFriend => {
                 if self.gold < 3 {
-                    trace!("Not enough gold to buy a friend; exiting");
-                    return true;
+                    trace!("Not enough gold to buy a friend");
+                    return Step::Impossible;
                 }
                 if let Some(i) = self.random_friend(rng) {
-                    let a = self.shop_friends[i].unwrap().species;
+                    let a = self.shop_friends[i].unwrap();
                     let j = rng.roll(0..TEAM_SIZE);
-                    if self.team.make_space_at(j) {
-                        self.buy_friend(i, j, rng);
+                    let quiet = self.buys_quietly(&a);
+                    // A purchase never touches a friend on the team, so it's
+                    // independent of the previous move if both are quiet
+                    if prune.commute
+                        && quiet
+                        && recent.quiet.is_some_and(|m| (None, a) < m.key())
+                    {
+                        trace!(species = ?a.species, "Buying out of order");
+                        return Step::Pruned;
+                    }
+                    if self.has_room() && self.team.make_space_at(j) {
+                        self.buy_friend(i, j, rng, log);
+                        self.recent.quiet = quiet.then(|| Move::Buy {
+                            offered: a,
+                            bought: self.team[j].unwrap(),
+                        });
                     } else {
-                        trace!("Can't make space for {}; exiting", a);
-                        return true;
+                        trace!(
+                            species = ?a.species,
+                            "Can't make space for friend"
+                        );
+                        return Step::Impossible;
                     }
                 } else {
-                    trace!("No friends in the shop; exiting");
-                    return true;
+                    trace!("No friends in the shop");
+                    return Step::Impossible;
                 }
             }
             // Buy food
             ShopAction::BuyFood => {
                 if self.gold < 3 {
-                    trace!("Not enough gold to buy food; exiting");
-                    return true;
+                    trace!("Not enough gold to buy food");
+                    return Step::Impossible;
                 }
                 let i = match self.random_food(rng) {
                     Some(i) => i,
                     None => {
-                        trace!("No food in the shop; exiting");
-                        return true;
+                        trace!("No food in the shop");
+                        return Step::Impossible;
                     }
                 };
                 let j = match self.team.random_friend(rng) {
                     Some(j) => j,
                     None => {
-                        trace!("No friends to feed; exiting");
-                        return true;
+                        trace!("No friends to feed");
+                        return Step::Impossible;
                     }
                 };
-                self.buy_food(i, j);
+                // Food never triggers abilities, so feeding is always quiet
+                let food = self.shop_foods[i].unwrap();
+                let before = self.team[j].unwrap();
+                if prune.commute
+                    && recent.quiet.is_some_and(|m| {
+                        (Some(food), before) < m.key()
+                            && m.independent(&self.team, j)
+                    })
+                {
+                    trace!(?food, "Feeding out of order");
+                    return Step::Pruned;
+                }
+                self.buy_food(i, j, log);
+                self.recent.quiet = Some(Move::Feed {
+                    food,
+                    before,
+                    after: self.team[j].unwrap(),
+                });
             }
             // Sell friend
             ShopAction::SellFriend => {
                 if let Some(j) = self.team.random_friend(rng) {
-                    self.sell_friend(j, rng);
+                    // Selling a friend right after buying it only wastes
+                    // gold, unless either triggers an ability
+                    let bought = match recent.quiet {
+                        Some(Move::Buy { bought, .. }) => {
+                            self.team[j] == Some(bought)
+                        }
+                        _ => false,
+                    };
+                    if prune.sell_bought && bought && self.sells_quietly(j) {
+                        trace!("Selling the friend which was just bought");
+                        return Step::Pruned;
+                    }
+                    self.sell_friend(j, rng, log);
                 } else {
-                    trace!("No friends to sell; exiting");
-                    return true;
+                    trace!("No friends to sell");
+                    return Step::Impossible;
                 }
             }
             // Reroll
@@ -282,20 +1255,34 @@ impl Shop {
                 // animals with default power, then we
                 // could have _different_ animals in a different timeline,
                 // so rerolling doesn't accomplish anything.
+                let (animals, foods, _) = self.limits();
                 if self.gold == 0 {
-                    trace!("No gold to reroll; exiting");
-                    return true;
-                } else if self.shop_foods.iter().any(Option::is_none)
-                    || self.shop_friends.iter().any(Option::is_none)
+                    trace!("No gold to reroll");
+                    return Step::Impossible;
+                } else if prune.reroll_duplicates
+                    && self.gold >= 3
+                    && self.offers().any(|a| {
+                        self.team.iter().flatten().any(|b| a.can_merge(b))
+                    })
+                {
+                    trace!("Rerolling away a duplicate of a friend");
+                    return Step::Pruned;
+                } else if self.shop_foods.iter().flatten().count() < foods
+                    || self.shop_friends.iter().flatten().count() < animals
                 {
                     trace!("Re-rolling shop");
                     self.reroll(rng);
-                    self.gold -= 1;
+                    self.spend(GoldCause::Reroll, 1, log);
                 } else {
                     trace!("Rerolling shop doesn't accomplish anything");
-                    return true;
+                    return Step::Impossible;
                 }
             }
+            // End the turn, running end-of-turn abilities
+            ShopAction::EndTurn => {
+                self.end_turn(rng, log);
+                return Step::Ended;
+            }
             // Attempt to combine
             ShopAction::CombineFriends => {
                 let mut has_targets = [false; TEAM_SIZE];
@@ -304,10 +1291,7 @@ impl Shop {
                     for j in (i + 1)..TEAM_SIZE {
                         let a = self.team[i];
                         let b = self.team[j];
-                        if a.is_some()
-                            && b.is_some()
-                            && a.unwrap().species == b.unwrap().species
-                        {
+                        if matches!(a.zip(b), Some((a, b)) if a.can_merge(&b)) {
                             targets[i][j] = true;
                             targets[j][i] = true;
                             has_targets[i] = true;
@@ -316,6 +1300,11 @@ impl Shop {
                     }
                 }
                 let num = has_targets.iter().filter(|i| **i).count();
+                if num == 0 {
+                    // Bail out early, since rolling an empty range is invalid
+                    trace!("No friends to combine");
+                    return Step::Impossible;
+                }
                 let i = has_targets
                     .iter()
                     .enumerate()
@@ -334,36 +1323,41 @@ impl Shop {
 
                     assert!(b);
                     let friend = self.team[i].take().unwrap();
-                    trace!("Merging {} at {} into {}", friend.species, i, j);
-                    self.combine_friends(j, friend);
+                    trace!(
+                        species = ?friend.species,
+                        from = i,
+                        to = j,
+                        "Merging"
+                    );
+                    self.combine_friends(j, friend, rng, log);
                 } else {
-                    trace!("No friends to combine; exiting");
-                    return true;
+                    trace!("No friends to combine");
+                    return Step::Impossible;
                 }
             }
             ShopAction::BuyCombineFriend => {
                 if self.gold < 3 {
-                    trace!(
-                        "Not enough gold to buy and combine friend; exiting"
-                    );
-                    return true;
+                    trace!("Not enough gold to buy and combine friend");
+                    return Step::Impossible;
                 }
-                let mut has_targets = [false; SHOP_ANIMAL_COUNT];
-                let mut targets = [[false; TEAM_SIZE]; SHOP_ANIMAL_COUNT];
-                for i in 0..SHOP_ANIMAL_COUNT {
-                    for j in 0..TEAM_SIZE {
-                        let a = self.shop_friends[i];
-                        let b = self.team[j];
-                        if a.is_some()
-                            && b.is_some()
-                            && a.unwrap().species == b.unwrap().species
+                let mut has_targets = [false; MAX_SHOP_ANIMALS];
+                let mut targets = [[false; TEAM_SIZE]; MAX_SHOP_ANIMALS];
+                for i in 0..MAX_SHOP_ANIMALS {
+                    let a = self.shop_friends[i];
+                    for (t, b) in targets[i].iter_mut().zip(self.team.iter()) {
+                        if matches!(a.zip(*b), Some((a, b)) if a.can_merge(&b))
                         {
-                            targets[i][j] = true;
+                            *t = true;
                             has_targets[i] = true;
                         }
                     }
                 }
                 let num = has_targets.iter().filter(|i| **i).count();
+                if num == 0 {
+                    // Bail out early, since rolling an empty range is invalid
+                    trace!("No friends to combine");
+                    return Step::Impossible;
+                }
                 let i = has_targets
                     .iter()
                     .enumerate()
@@ -372,8 +1366,8 @@ impl Shop {
 
                 let i = match i {
                     None => {
-                        trace!("No friends to combine; exiting");
-                        return true;
+                        trace!("No friends to combine");
+                        return Step::Impossible;
                     }
                     Some((i, b)) => {
                         assert!(b);
@@ -389,25 +1383,408 @@ impl Shop {
                     .unwrap();
 
                 assert!(b);
-                let friend = self.shop_friends[i].take().unwrap();
-                self.shop_friends.sort();
-
-                self.gold -= 3;
-                self.combine_friends(j, friend);
-                trace!("Buying {} and combining at {}", friend.species, j);
-
-                // The on-buy trigger happens after the friends are
-                // combined, which matters in cases where the species
-                // levels up.  For convenience, we remove the species from
-                // the team briefly, then reinstall it.
-                let friend = self.team[j].take().unwrap();
-                self.on_buy(friend, rng);
-                self.team[j] = Some(friend);
-
-                // XXX: There are also "friend is bought" triggers, but
-                // nothing in Tier 1 uses them
+                self.buy_combine_friend(i, j, rng, log);
+            }
+        }
+        Step::Acted
+    }
+}
+
+/// The given slots of a team or the shop, for printing with
+/// [`fmt_friends`]
+struct Slots<'a> {
+    friends: &'a [Option<Friend>],
+    range: Vec<usize>,
+    prefix: char,
+}
+
+impl std::fmt::Display for Slots<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let range = self.range.iter().copied();
+        fmt_friends(self.friends, f, range, Some(self.prefix))
+    }
+}
+
+/// Prints the turn and gold, the team (front on the right) beside the
+/// friends on offer, the food on offer, and any effects scheduled for the
+/// next turn; or just the first four on a single line with the alternate
+/// flag (`{:#}`).  Slots are labelled by their [script](crate::script)
+/// positions, which count only the occupied slots in the shop, e.g. `a0`
+/// for the first friend on offer.
+impl std::fmt::Display for Shop {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let members: [Option<Friend>; TEAM_SIZE] =
+            std::array::from_fn(|i| self.team[i]);
+        let team = Slots {
+            friends: &members,
+            range: (0..TEAM_SIZE).rev().collect(),
+            prefix: 't',
+        };
+        let offered: Vec<Option<Friend>> =
+            self.offers().copied().map(Some).collect();
+        let offers = Slots {
+            friends: &offered,
+            range: (0..offered.len()).collect(),
+            prefix: 'a',
+        };
+        if f.alternate() {
+            write!(
+                f,
+                "Turn {}, {} {}: {:#} | {:#} |",
+                self.turn,
+                Icon::Gold,
+                self.gold,
+                team,
+                offers
+            )?;
+            for food in self.foods() {
+                write!(f, " {}", food)?;
+            }
+            return Ok(());
+        }
+
+        writeln!(f, "Turn {}, {} {}", self.turn, Icon::Gold, self.gold)?;
+        if offers.range.is_empty() {
+            writeln!(f, "{}", team)?;
+            write!(f, "Shop: (empty)")?;
+        } else {
+            let team = format!("{}", team);
+            let offers = format!("{}", offers);
+            for (i, (a, b)) in
+                team.split('\n').zip(offers.split('\n')).enumerate()
+            {
+                if i > 0 {
+                    writeln!(f)?;
+                }
+                write!(f, "{}   {}", a, b)?;
+            }
+        }
+        write!(f, "\nFood:")?;
+        for (i, food) in self.foods().enumerate() {
+            write!(f, " f{} {}", i, food)?;
+        }
+        for p in self.pending() {
+            write!(f, "\nNext turn: {:?}", p)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dice::DeterministicDice, script, timeline::Timeline};
+    use std::{
+        collections::{HashMap, HashSet},
+        hash::BuildHasher,
+    };
+
+    /// Runs a script against a copy of the shop
+    fn run(shop: &Shop, s: &str) -> Shop {
+        let mut shop = *shop;
+        let commands = script::parse(s).unwrap();
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        script::run(&mut shop, &commands, &mut dice, &mut ()).unwrap();
+        shop
+    }
+
+    #[test]
+    fn display_labels_are_script_positions() {
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let shop = Shop::with_challenge(3, Challenge::default(), &mut dice);
+        // Buying leaves gaps among the offers, which scripts skip over
+        let shop = run(
+            &shop,
+            "shop Ant Fish Pig; food Apple Honey; buy a0 t0; buy f0 t0",
+        );
+
+        // Each label in the header is above a friend in the species row
+        let art = shop.to_string();
+        let lines: Vec<&str> = art.lines().collect();
+        let labels = lines[1]
+            .split_whitespace()
+            .filter(|w| w.starts_with(['t', 'a']));
+        let cells = lines[3].split('│').skip(1).step_by(2);
+        let offers: Vec<(&str, &str)> = labels
+            .zip(cells)
+            .filter(|(l, _)| l.starts_with('a'))
+            .collect();
+        assert_eq!(offers.len(), shop.offers().count());
+        for (label, cell) in offers {
+            let after = run(&shop, &format!("buy {} t1", label));
+            let bought = after.team[1].unwrap().species;
+            assert!(cell.contains(&bought.to_string()), "{}: {}", label, cell);
+        }
+
+        let food = lines.iter().find(|l| l.starts_with("Food:")).unwrap();
+        let words: Vec<&str> = food.split_whitespace().skip(1).collect();
+        assert_eq!(words.len(), 2 * shop.foods().count());
+        for w in words.chunks(2) {
+            let after = run(&shop, &format!("buy {} t0", w[0]));
+            assert_eq!(after.foods().count(), shop.foods().count() - 1);
+            assert!(after.foods().all(|f| f.to_string() != w[1]));
+        }
+    }
+
+    #[test]
+    fn display_layout() {
+        // Shops print as a header, box art (with the team and offers side
+        // by side), food and pending effects, or on a single line
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let mut full = Shop::new(1, &mut dice);
+        full.schedule(Pending::Gold(1));
+        let empty = run(&full, "buy a0 t0; buy a0 t1; buy a0 t2");
+        assert_eq!(empty.offers().count(), 0);
+        for (shop, lines) in [(full, 10), (empty, 11)] {
+            let art = shop.to_string();
+            assert_eq!(art.lines().count(), lines, "{}", art);
+            assert!(art.lines().nth(1).unwrap().starts_with("t4 "));
+            assert!(!format!("{:#}", shop).contains('\n'));
+        }
+    }
+
+    #[test]
+    fn canonical_frontier() {
+        // Exploring a few actions from one shop, without sorting teams,
+        // reaches fewer canonical shops than raw states, and the same teams
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let start = Shop::new(1, &mut dice);
+        let mut frontier = HashSet::from([start]);
+        let mut raw = HashSet::new();
+        let mut canonical = HashSet::new();
+        for _ in 0..3 {
+            let mut next = HashSet::new();
+            for shop in frontier {
+                let mut dice = DeterministicDice::new();
+                while dice.next() {
+                    let mut shop = shop;
+                    if shop.step(&mut dice) == Step::Acted {
+                        raw.insert((
+                            shop.team,
+                            shop.gold,
+                            shop.shop_friends,
+                            shop.shop_foods,
+                            shop.pending,
+                        ));
+                        canonical.insert(shop);
+                        next.insert(shop);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        assert!(canonical.len() < raw.len());
+        let sorted = |mut t: Team| {
+            t.sort();
+            t
+        };
+        let teams: HashSet<Team> =
+            canonical.iter().map(|s| sorted(s.team)).collect();
+        let raw_teams: HashSet<Team> =
+            raw.iter().map(|r| sorted(r.0)).collect();
+        assert_eq!(teams, raw_teams);
+    }
+
+    /// Rolls shops until one has no two offers or foods alike, so that
+    /// buying or feeding them can commute
+    fn varied(roll: impl Fn(&mut DeterministicDice) -> Shop) -> Shop {
+        let mut dice = DeterministicDice::new().multisets();
+        loop {
+            assert!(dice.next());
+            let shop = roll(&mut dice);
+            let offers: Vec<&Friend> = shop.offers().collect();
+            let foods: Vec<&Food> = shop.foods().collect();
+            if offers.windows(2).all(|w| w[0] != w[1])
+                && foods.windows(2).all(|w| w[0] != w[1])
+            {
+                return shop;
+            }
+        }
+    }
+
+    /// Takes every action from the given shops until their turn ends,
+    /// returning the ended shops and how many steps were pruned.  Like
+    /// `generate_teams`, this skips shops which were seen with more gold.
+    fn end_turns(
+        start: impl IntoIterator<Item = Shop>,
+        prune: &PruneConfig,
+    ) -> (HashSet<Shop>, usize) {
+        let mut frontier: HashSet<Shop> = start.into_iter().collect();
+        let mut seen = HashMap::new();
+        let mut ended = HashSet::new();
+        let mut pruned = 0;
+        while !frontier.is_empty() {
+            let mut next = HashSet::new();
+            for shop in frontier {
+                let mut without_gold = shop;
+                without_gold.gold = 0;
+                if seen.get(&without_gold).is_some_and(|g| *g >= shop.gold) {
+                    continue;
+                }
+                seen.insert(without_gold, shop.gold);
+                let mut dice = DeterministicDice::new().multisets();
+                while dice.next() {
+                    let mut shop = shop;
+                    match shop.step_pruned(&mut dice, prune, &mut ()) {
+                        Step::Acted => next.insert(shop),
+                        Step::Ended => ended.insert(shop),
+                        Step::Pruned => {
+                            pruned += 1;
+                            continue;
+                        }
+                        Step::Impossible => continue,
+                    };
+                }
+            }
+            frontier = next;
+        }
+        (ended, pruned)
+    }
+
+    #[test]
+    fn pruning_keeps_teams() {
+        // Commuting and not selling what was just bought skip paths, but
+        // end each turn with the same teams
+        let commute = PruneConfig {
+            commute: true,
+            ..PruneConfig::default()
+        };
+        let prune = PruneConfig {
+            sell_bought: true,
+            ..commute
+        };
+        let sorted = |s: &Shop| {
+            let mut t = s.team;
+            t.sort();
+            t
+        };
+        let teams = |shops: &HashSet<Shop>| {
+            let mut out: Vec<Team> = shops.iter().map(sorted).collect();
+            out.sort();
+            out.dedup();
+            out
+        };
+        // A small team and little gold keep the search small: six gold pays
+        // for two moves on the first turn, and five (plus a sale) on the
+        // second, which starts with one of the first turn's teams
+        let challenge = Challenge {
+            team_size: Some(2),
+            ..Challenge::default()
+        };
+        let mut start = varied(|dice| {
+            let mut shop = Shop::with_challenge(1, challenge, dice);
+            shop.gold = 6;
+            shop
+        });
+        for _ in 0..2 {
+            let (all, _) = end_turns([start], &PruneConfig::default());
+            let (commuted, n) = end_turns([start], &commute);
+            assert!(n > 0);
+            assert_eq!(teams(&all), teams(&commuted));
+            let (pruned, n) = end_turns([start], &prune);
+            assert!(n > 0);
+            assert_eq!(teams(&all), teams(&pruned));
+
+            let team = teams(&all).into_iter().find(|t| t.count() == 1);
+            let prev = *all.iter().find(|s| Some(sorted(s)) == team).unwrap();
+            start = varied(|dice| {
+                let mut shop = prev;
+                shop.start_turn(dice, &mut ());
+                shop.gold = 5;
+                shop
+            });
+        }
+    }
+
+    #[test]
+    fn multiset_shops() {
+        // Rolling offers as multisets visits fewer paths, but reaches the
+        // same shops with the same probabilities
+        for turn in 1..3 {
+            let tally = |mut dice: DeterministicDice| {
+                let mut out = std::collections::HashMap::new();
+                let mut paths = 0;
+                while dice.next() {
+                    let shop = Shop::new(turn, &mut dice);
+                    *out.entry(shop).or_insert(0.0) += dice.probability();
+                    paths += 1;
+                }
+                (out, paths)
+            };
+            let (ordered, n) = tally(DeterministicDice::new());
+            let (multisets, m) = tally(DeterministicDice::new().multisets());
+            assert!(m < n);
+            assert_eq!(ordered.len(), multisets.len());
+            for (shop, p) in &ordered {
+                assert!((multisets[shop] - p).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_stock_order() {
+        // Restocking the same offers in any order gives the same shop, down
+        // to its hash, and so does reordering the team
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let shop = run(&Shop::new(3, &mut dice), "buy a0 t0; buy a1 t3");
+        let (mut a, mut b) = (shop, shop);
+        let friends = [Species::Ant, Species::Fish, Species::Mosquito];
+        a.stock(&friends, &[Food::Apple, Food::Honey]).unwrap();
+        b.stock(
+            &[friends[2], friends[0], friends[1]],
+            &[Food::Honey, Food::Apple],
+        )
+        .unwrap();
+        b.team.move_friend(3, 1);
+        assert_ne!(a.team, b.team);
+        assert_eq!(a, b);
+        let state = std::hash::RandomState::new();
+        assert_eq!(state.hash_one(a), state.hash_one(b));
+
+        // Gold and offers still count
+        b.stock(&friends[..2], &[Food::Apple, Food::Honey]).unwrap();
+        assert_ne!(a, b);
+        let mut c = a;
+        c.gold += 1;
+        assert_ne!(a, c);
+        assert!(a.stock(&[Species::Ant; 4], &[]).is_err());
+    }
+
+    #[test]
+    fn fish_level_up() {
+        // Combining two Fish into a level 2 Fish buffs every other friend,
+        // whichever Fish is moved onto the other
+        for s in Species::builtin().filter(|s| *s != Species::Fish) {
+            for first in 0..2 {
+                let mut shop = Shop::new(1, &mut DeterministicDice::new());
+                let fish = |exp| Friend {
+                    exp,
+                    ..Friend::new(Species::Fish)
+                };
+                let f = Friend::new(s);
+                shop.team[0] = Some(fish(2));
+                shop.team[1] = Some(fish(0));
+                shop.team[2] = Some(f);
+
+                // Pick the CombineFriends action, then which Fish to move
+                let key = format!("4{}0", first);
+                let mut dice = DeterministicDice::from_key(&key).unwrap();
+                let mut timeline = Timeline::new();
+                shop.step_logged(&mut dice, &mut timeline);
+                let g = shop.team[2].unwrap();
+                assert_eq!((g.attack, g.health), (f.attack + 1, f.health + 1));
+                let level_ups: Vec<&Trigger> = timeline
+                    .triggers()
+                    .filter(|t| t.cause == Cause::LevelUp)
+                    .collect();
+                assert_eq!(level_ups.len(), 1);
+                assert_eq!(level_ups[0].species, Species::Fish);
             }
         }
-        false
     }
 }
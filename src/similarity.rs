@@ -0,0 +1,215 @@
+//! Team similarity, for finding the teams closest to a given team and
+//! seeing which small differences change how well they do.
+//!
+//! Teams are compared first by their species (as multisets), then by their
+//! perks, then by their stats, and only then by the order of their friends;
+//! see [`Distance`].  The [`Index`] groups teams
+//! by species, so a search only compares stats within the nearest groups.
+
+use std::cmp::Ordering;
+
+use hashbrown::HashMap;
+use serde::Serialize;
+
+use crate::{friend::Friend, species::Species, team::Team};
+
+/// How different two teams are, compared field by field (so any difference
+/// in species outweighs any difference in stats)
+#[derive(
+    Copy, Clone, Debug, Default, Eq, Ord, PartialEq, PartialOrd, Serialize,
+)]
+pub struct Distance {
+    /// Friends whose species isn't matched on the other team, counting both
+    /// teams
+    pub species: usize,
+    /// Matched friends whose perks differ
+    pub perks: usize,
+    /// Total difference in attack and health between matched friends, plus
+    /// the total stats of unmatched friends
+    pub stats: usize,
+    /// Slots (in battle order) holding friends of different species
+    pub order: usize,
+}
+
+/// Returns a team's friends in battle-ready form, sorted by species and then
+/// by stats
+fn friends(team: &Team) -> Vec<Friend> {
+    let mut out: Vec<Friend> =
+        team.for_battle().iter().flatten().copied().collect();
+    out.sort_by_key(|f| (f.species, f.attack, f.health));
+    out
+}
+
+/// Returns the sorted species of a team's friends
+fn species(friends: &[Friend]) -> Vec<Species> {
+    friends.iter().map(|f| f.species).collect()
+}
+
+/// Returns the number of items which aren't shared by two sorted lists,
+/// counting duplicates separately
+fn unmatched<T: Ord>(a: &[T], b: &[T]) -> usize {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    a.len() + b.len() - 2 * shared
+}
+
+/// Returns the distance between two sorted lists of friends.  Friends of the
+/// same species are matched in order of their stats.
+fn distance_sorted(a: &[Friend], b: &[Friend]) -> Distance {
+    let mut out = Distance::default();
+    let stats = |f: &Friend| f.attack + f.health;
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let order = match (a.get(i), b.get(j)) {
+            (Some(f), Some(g)) => f.species.cmp(&g.species),
+            (Some(_), None) => Ordering::Less,
+            (None, _) => Ordering::Greater,
+        };
+        match order {
+            Ordering::Less => {
+                out.species += 1;
+                out.stats += stats(&a[i]);
+                i += 1;
+            }
+            Ordering::Greater => {
+                out.species += 1;
+                out.stats += stats(&b[j]);
+                j += 1;
+            }
+            Ordering::Equal => {
+                let (f, g) = (&a[i], &b[j]);
+                out.perks += (f.modifier != g.modifier) as usize;
+                out.stats +=
+                    f.attack.abs_diff(g.attack) + f.health.abs_diff(g.health);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Returns the number of slots whose friends differ in species, for two
+/// battle-ready teams
+fn misplaced(a: &Team, b: &Team) -> usize {
+    let species = |f: &Option<Friend>| f.map(|f| f.species);
+    a.iter()
+        .zip(b.iter())
+        .filter(|(f, g)| species(f) != species(g))
+        .count()
+}
+
+/// Returns the distance between two teams
+pub fn distance(a: &Team, b: &Team) -> Distance {
+    let (a, b) = (a.for_battle(), b.for_battle());
+    Distance {
+        order: misplaced(&a, &b),
+        ..distance_sorted(&friends(&a), &friends(&b))
+    }
+}
+
+/// A team in an [`Index`], with its index, battle-ready form and sorted
+/// friends
+type Entry = (usize, Team, Vec<Friend>);
+
+/// Teams grouped by their species, for finding the nearest teams
+pub struct Index {
+    /// Each group's species, with its teams
+    groups: Vec<(Vec<Species>, Vec<Entry>)>,
+}
+
+impl Index {
+    pub fn new(teams: &[Team]) -> Self {
+        let mut groups: HashMap<Vec<Species>, Vec<Entry>> = HashMap::new();
+        for (i, t) in teams.iter().enumerate() {
+            let t = t.for_battle();
+            let fs = friends(&t);
+            groups.entry(species(&fs)).or_default().push((i, t, fs));
+        }
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        Self { groups }
+    }
+
+    /// Returns up to `n` teams which are nearest to the given team, nearest
+    /// first (breaking ties by index), along with their distances
+    pub fn nearest(&self, team: &Team, n: usize) -> Vec<(usize, Distance)> {
+        let team = team.for_battle();
+        let fs = friends(&team);
+        let key = species(&fs);
+        let mut groups: Vec<(usize, usize)> = self
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(g, (s, _))| (unmatched(&key, s), g))
+            .collect();
+        groups.sort();
+
+        let mut out: Vec<(usize, Distance)> = vec![];
+        let mut prev = 0;
+        for (species, g) in groups {
+            // Teams in later groups differ in more species than the `n`
+            // teams found so far, so can't be any nearer
+            if out.len() >= n && species > prev {
+                break;
+            }
+            prev = species;
+            for (i, t, other) in &self.groups[g].1 {
+                let d = Distance {
+                    order: misplaced(&team, t),
+                    ..distance_sorted(&fs, other)
+                };
+                out.push((*i, d));
+            }
+        }
+        out.sort_by_key(|(i, d)| (*d, *i));
+        out.truncate(n);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_teams() {
+        let team = |s: &str| s.parse::<Team>().unwrap();
+        let q = team("Ant:2/1,Fish:2/3");
+        let teams = [
+            q,
+            team("Fish:2/4,Ant:2/1+Honey"),
+            team("Ant:2/1,Pig:1/3"),
+            team("Ant:2/1"),
+        ];
+        assert_eq!(distance(&q, &q), Distance::default());
+        let d = |species, perks, stats, order| Distance {
+            species,
+            perks,
+            stats,
+            order,
+        };
+        // Species outweigh perks, which outweigh stats and then order
+        assert_eq!(distance(&q, &teams[1]), d(0, 1, 1, 2));
+        assert_eq!(distance(&q, &teams[2]), d(2, 0, 9, 1));
+        assert_eq!(distance(&teams[2], &q), d(2, 0, 9, 1));
+        assert_eq!(distance(&q, &teams[3]), d(1, 0, 5, 1));
+
+        let index = Index::new(&teams);
+        let nearest: Vec<usize> =
+            index.nearest(&q, 8).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(nearest, [0, 1, 3, 2]);
+        assert_eq!(index.nearest(&q, 2)[1], (1, d(0, 1, 1, 2)));
+        assert!(index.nearest(&q, 0).is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//! Meta snapshots, which record the distribution of teams fielded on one
+//! turn, so that later turns and the [opponent model](crate::opponent) can
+//! be built from them.
+//!
+//! The [ladder](crate::ladder) writes one snapshot per turn, and snapshots
+//! written by other tools (e.g. from real match data) can be injected in
+//! their place.  Each snapshot is a JSON file named `turn-<N>.json` (see
+//! [`file_name`]), e.g.
+//!
+//! ```json
+//! {
+//!     "turn": 3,
+//!     "teams": [
+//!         { "team": "Ant:3/4:1,Fish:3/3", "weight": 12 },
+//!         { "team": "Mosquito:2/2+Honey", "weight": 4.5 }
+//!     ]
+//! }
+//! ```
+//!
+//! Teams are written as [team codes](crate::team::Team::code), and weights
+//! are relative (they needn't sum to anything in particular), but can't be
+//! negative.  Teams which appear more than once have their weights added.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    opponent::Opponents,
+    team::Team,
+};
+
+/// A team in a [`Snapshot`], along with its relative weight
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Weighted {
+    pub team: String,
+    pub weight: f64,
+}
+
+/// The distribution of teams fielded on one turn
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    /// Turn on which the teams were fielded, starting from 1
+    pub turn: usize,
+    pub teams: Vec<Weighted>,
+}
+
+/// Returns the file name of the snapshot for the given turn
+pub fn file_name(turn: usize) -> String {
+    format!("turn-{}.json", turn)
+}
+
+impl Snapshot {
+    /// Builds a snapshot from teams and their weights, merging duplicate
+    /// teams (in their battle-ready form) and listing the heaviest first
+    pub fn new<I: IntoIterator<Item = (Team, f64)>>(
+        turn: usize,
+        teams: I,
+    ) -> Self {
+        let mut merged: BTreeMap<Team, f64> = BTreeMap::new();
+        for (t, w) in teams {
+            *merged.entry(t.for_battle()).or_default() += w;
+        }
+        let mut teams: Vec<(Team, f64)> = merged.into_iter().collect();
+        teams.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Self {
+            turn,
+            teams: teams
+                .into_iter()
+                .map(|(t, weight)| Weighted {
+                    team: t.code(),
+                    weight,
+                })
+                .collect(),
+        }
+    }
+
+    /// Loads and checks a snapshot from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let s: Self = serde_json::from_str(&data)?;
+        s.check()?;
+        Ok(s)
+    }
+
+    /// Writes the snapshot to a JSON file
+    pub fn save(&self, path: &str) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Parses every team, returning them with their weights
+    pub fn teams(&self) -> Result<Vec<(Team, f64)>> {
+        self.teams
+            .iter()
+            .map(|w| {
+                let t: Team = w.team.parse()?;
+                t.check().map_err(Error::Team)?;
+                Ok((t, w.weight))
+            })
+            .collect()
+    }
+
+    /// Checks that the turn is valid, that every team parses, and that
+    /// weights are finite, not negative, and not all zero
+    pub fn check(&self) -> Result<()> {
+        let err = |s: String| {
+            Err(Error::Snapshot(format!("turn {}: {}", self.turn, s)))
+        };
+        if self.turn == 0 {
+            return err("turns start at 1".to_owned());
+        }
+        for w in &self.teams {
+            if !w.weight.is_finite() || w.weight < 0.0 {
+                return err(format!("{} has weight {}", w.team, w.weight));
+            }
+        }
+        self.teams()?;
+        if self.teams.iter().all(|w| w.weight == 0.0) {
+            return err("no team has any weight".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Converts the snapshot into a model of the opponent on its turn
+    pub fn opponents(&self) -> Result<Opponents> {
+        self.check()?;
+        Ok(Opponents::new(self.turn, self.teams()?).unwrap())
+    }
+}
+
+/// Loads every snapshot in a directory, by turn, skipping turns which have
+/// no snapshot file
+pub fn load_dir(
+    dir: &str,
+    max_turn: usize,
+) -> Result<BTreeMap<usize, Snapshot>> {
+    let mut out = BTreeMap::new();
+    for turn in 1..=max_turn {
+        let path = std::path::Path::new(dir).join(file_name(turn));
+        if !path.exists() {
+            continue;
+        }
+        let s = Snapshot::load(&path.to_string_lossy())?;
+        if s.turn != turn {
+            return Err(Error::Snapshot(format!(
+                "{} is for turn {}",
+                path.display(),
+                s.turn
+            )));
+        }
+        out.insert(turn, s);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        // Snapshots survive a trip through their file format, with
+        // duplicate teams merged, and become the same opponent model
+        let team = |s: &str| s.parse::<Team>().unwrap();
+        let mut buffed = team("Ant:1/2");
+        buffed[0].as_mut().unwrap().temp_attack = 2;
+        let s = Snapshot::new(
+            2,
+            [
+                (team("Ant:3/2"), 1.0),
+                (team("Fish:2/3"), 2.5),
+                (buffed, 1.5),
+            ],
+        );
+        assert!(s.check().is_ok());
+        let weights: Vec<f64> = s.teams.iter().map(|w| w.weight).collect();
+        assert_eq!(weights, [2.5, 2.5]);
+
+        let dir = std::env::temp_dir()
+            .join(format!("sas-snapshots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path =
+            |turn| dir.join(file_name(turn)).to_string_lossy().into_owned();
+        s.save(&path(2)).unwrap();
+        let loaded = load_dir(&dir.to_string_lossy(), 4).unwrap();
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), [&2]);
+        assert_eq!(loaded[&2], s);
+
+        let model = loaded[&2].opponents().unwrap();
+        assert_eq!(model.teams().len(), 2);
+        for (_, p) in model.teams() {
+            assert!((p - 0.5).abs() < 1e-9);
+        }
+
+        // A snapshot filed under the wrong turn is rejected
+        s.save(&path(3)).unwrap();
+        assert!(load_dir(&dir.to_string_lossy(), 4).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_weights() {
+        let s = Snapshot::new(1, [("Ant:1/2".parse().unwrap(), 1.0)]);
+        assert!(s.check().is_ok());
+        for weight in [-1.0, 0.0, f64::NAN] {
+            let mut bad = s.clone();
+            bad.teams[0].weight = weight;
+            assert!(bad.check().is_err());
+        }
+        let mut bad = s.clone();
+        bad.turn = 0;
+        assert!(bad.check().is_err());
+        let mut bad = s;
+        bad.teams[0].team = "Ant".to_owned();
+        assert!(bad.check().is_err());
+    }
+}
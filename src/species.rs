@@ -1,18 +1,19 @@
-use crate::{dice::Dice, modifier::Modifier};
+use crate::{
+    ability::{self, Ability},
+    custom,
+    dice::Dice,
+    error::Error,
+    event::Cause,
+    modifier::Modifier,
+    pack::{self, Pack},
+    rules,
+    token::{Summoner, Token, TOKENS},
+};
 use serde::{Deserialize, Serialize};
 
 /// Tier 1 speciess in the free-to-play pack
 #[derive(
-    Copy,
-    Clone,
-    Debug,
-    Deserialize,
-    Eq,
-    Hash,
-    Ord,
-    PartialEq,
-    PartialOrd,
-    Serialize,
+    Copy, Clone, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
 )]
 pub enum Species {
     Ant,
@@ -25,11 +26,40 @@ pub enum Species {
     Otter,
     Pig,
 
+    /// Higher-tier species, which aren't sold in the Tier 1 shop
+    Whale,
+    Kangaroo,
+    Ox,
+
     GhostCricket,
     Bee,
+
+    /// A species registered at run time, by index into the
+    /// [registry](crate::custom)
+    Custom(u8),
 }
 
 impl Species {
+    /// Returns a two-letter abbreviation, for [ASCII mode](crate::render)
+    pub fn abbrev(&self) -> &'static str {
+        match self {
+            Self::Ant => "An",
+            Self::Beaver => "Bv",
+            Self::Cricket => "Cr",
+            Self::Duck => "Du",
+            Self::Fish => "Fi",
+            Self::Horse => "Ho",
+            Self::Mosquito => "Mq",
+            Self::Otter => "Ot",
+            Self::Pig => "Pi",
+            Self::Whale => "Wh",
+            Self::Kangaroo => "Ka",
+            Self::Ox => "Ox",
+            Self::GhostCricket => "Gc",
+            Self::Bee => "Be",
+            Self::Custom(i) => &custom::get(*i).abbrev,
+        }
+    }
     pub fn emoji(&self) -> char {
         match self {
             Self::Ant => '🐜',
@@ -41,14 +71,26 @@ impl Species {
             Self::Mosquito => '🦟',
             Self::Otter => '🦦',
             Self::Pig => '🐷',
+            Self::Whale => '🐋',
+            Self::Kangaroo => '🦘',
+            Self::Ox => '🐂',
             Self::GhostCricket => '🦗',
             Self::Bee => '🐝',
+            Self::Custom(i) => custom::get(*i).species.emoji,
         }
     }
 
-    /// Returns the default health and attack for an species; panics if the
-    /// selected species can't be purchased from the shop.
+    /// Returns the default health and attack for an species, under the
+    /// [active rule set](crate::rules).  For tokens, which can't be purchased
+    /// from the shop, these are their stats when summoned at level 1.
     pub fn default_power(&self) -> (usize, usize) {
+        rules::active()
+            .power(*self)
+            .unwrap_or_else(|| self.current_power())
+    }
+
+    /// Returns the default health and attack under the current rules
+    pub fn current_power(&self) -> (usize, usize) {
         match self {
             Self::Ant => (2, 1),
             Self::Beaver => (2, 2),
@@ -59,37 +101,443 @@ impl Species {
             Self::Mosquito => (2, 2),
             Self::Otter => (1, 2),
             Self::Pig => (3, 1),
+            Self::Whale => (8, 3),
+            Self::Kangaroo => (2, 1),
+            Self::Ox => (3, 1),
 
             Self::GhostCricket | Self::Bee => {
-                panic!("Cannot purchase {:?}", self)
+                let t = Token::get(*self).expect("Missing token");
+                (t.power)(1)
+            }
+            Self::Custom(i) => {
+                let [attack, health] = custom::get(*i).species.stats;
+                (health, attack)
             }
         }
     }
 
+    /// Returns how often the species' ability may trigger, or `None` if it's
+    /// unlimited (which is the case for all Tier 1 units).  Custom species
+    /// may set their own limit, or inherit one from their templates.
+    pub fn trigger_limit(&self) -> Option<TriggerLimit> {
+        match self {
+            Self::Ox => Some(TriggerLimit::PerBattle(1)),
+            Self::Custom(i) => custom::get(*i)
+                .species
+                .limit
+                .or_else(|| self.templates().find_map(|t| t.trigger_limit())),
+            _ => None,
+        }
+    }
+
     /// Returns the default modifier for the species, which is `None` for all
     /// Tier 1 units.
     pub fn default_modifier(&self) -> Option<Modifier> {
         None
     }
 
-    pub fn sample<R: Dice>(rng: &mut R) -> Self {
-        match rng.roll(0..9) {
-            0 => Species::Ant,
-            1 => Species::Beaver,
-            2 => Species::Cricket,
-            3 => Species::Duck,
-            4 => Species::Fish,
-            5 => Species::Horse,
-            6 => Species::Mosquito,
-            7 => Species::Otter,
-            8 => Species::Pig,
-            _ => panic!("Invalid random number"),
+    /// Returns the shop tier at which the species is sold in the Turtle pack,
+    /// or `None` for tokens which are only ever summoned
+    pub fn tier(&self) -> Option<usize> {
+        match self {
+            Self::Ant
+            | Self::Beaver
+            | Self::Cricket
+            | Self::Duck
+            | Self::Fish
+            | Self::Horse
+            | Self::Mosquito
+            | Self::Otter
+            | Self::Pig => Some(1),
+            Self::Kangaroo => Some(2),
+            Self::Ox => Some(3),
+            Self::Whale => Some(4),
+            Self::GhostCricket | Self::Bee => None,
+            Self::Custom(i) => Some(custom::get(*i).species.tier),
+        }
+    }
+
+    /// Returns every built-in pack which sells the species
+    pub fn packs(&self) -> impl Iterator<Item = &'static Pack> {
+        let s = *self;
+        Pack::builtin()
+            .into_iter()
+            .filter(move |p| p.tier(s).is_some())
+    }
+
+    /// Returns the magnitudes of the species' ability at the given level
+    /// (clamped to 1-3), under the [active rule set](crate::rules)
+    pub fn ability_params(&self, level: usize) -> AbilityParams {
+        rules::active()
+            .ability_params(*self, level)
+            .unwrap_or_else(|| self.current_ability_params(level))
+    }
+
+    /// Returns the magnitudes of the species' ability under the current rules
+    pub fn current_ability_params(&self, level: usize) -> AbilityParams {
+        let p = |targets, attack, health| AbilityParams {
+            targets,
+            attack,
+            health,
+            ..AbilityParams::default()
+        };
+        let table = match self {
+            Self::Ant => [p(1, 2, 1), p(1, 4, 2), p(1, 6, 3)],
+            Self::Beaver => [p(2, 0, 1), p(2, 0, 2), p(2, 0, 3)],
+            Self::Duck => [p(0, 0, 1), p(0, 0, 2), p(0, 0, 3)],
+            Self::Fish => [p(0, 1, 1), p(0, 2, 2), p(0, 3, 3)],
+            Self::Horse => [p(1, 1, 1), p(1, 2, 2), p(1, 3, 3)],
+            Self::Otter => [p(1, 1, 1), p(2, 1, 1), p(3, 1, 1)],
+            Self::Kangaroo => [p(0, 2, 2), p(0, 4, 4), p(0, 6, 6)],
+            Self::Ox => [p(0, 1, 0), p(0, 2, 0), p(0, 3, 0)],
+            Self::Mosquito => [1, 2, 3].map(|n| AbilityParams {
+                targets: n,
+                damage: 1,
+                ..AbilityParams::default()
+            }),
+            Self::Pig => [1, 2, 3].map(|gold| AbilityParams {
+                gold,
+                ..AbilityParams::default()
+            }),
+            // Summons are described by the friend they release or the
+            // token registry, so they have no magnitudes here; custom
+            // species have magnitudes per template
+            Self::Cricket
+            | Self::Whale
+            | Self::GhostCricket
+            | Self::Bee
+            | Self::Custom(..) => [AbilityParams::default(); 3],
+        };
+        table[level.clamp(1, 3) - 1]
+    }
+
+    /// Returns the species whose abilities this species has, which is just
+    /// itself unless it's a [custom species](crate::custom).  Ability
+    /// dispatch matches on templates, rather than on the species itself.
+    pub fn templates(&self) -> impl Iterator<Item = Species> + '_ {
+        let templates = match self {
+            Self::Custom(i) => custom::get(*i).species.templates.as_slice(),
+            s => std::slice::from_ref(s),
+        };
+        templates.iter().copied()
+    }
+
+    /// Checks whether this species has the given template's ability
+    pub fn has_template(&self, template: Species) -> bool {
+        self.templates().any(|t| t == template)
+    }
+
+    /// Returns the magnitudes of one of the species' templates at the given
+    /// level, which are the template species' own unless a custom species
+    /// overrides them
+    pub fn template_params(
+        &self,
+        template: Species,
+        level: usize,
+    ) -> AbilityParams {
+        match self {
+            Self::Custom(i) => custom::get(*i)
+                .species
+                .params
+                .get(&template)
+                .map(|p| p[level.clamp(1, 3) - 1])
+                .unwrap_or_else(|| template.ability_params(level)),
+            s => s.ability_params(level),
+        }
+    }
+
+    /// Returns the species' own [scripted abilities](crate::ability), which
+    /// are empty for species whose abilities are bespoke
+    pub fn abilities(&self) -> &'static [Ability] {
+        match self {
+            Self::Custom(i) => &custom::get(*i).species.abilities,
+            s => ability::builtin(*s),
+        }
+    }
+
+    /// Returns the scripted abilities which trigger on the given cause, with
+    /// their magnitudes at the given level: those of each template, then
+    /// (for custom species) the species' own
+    pub fn scripted(
+        &self,
+        cause: Cause,
+        level: usize,
+    ) -> impl Iterator<Item = (Ability, AbilityParams)> + '_ {
+        let own = match self {
+            Self::Custom(..) => self.abilities(),
+            _ => &[],
+        };
+        let templates = self
+            .templates()
+            .flat_map(|t| t.abilities().iter().map(move |a| (t, *a)))
+            .filter(move |(_, a)| a.trigger == cause)
+            .map(move |(t, a)| (a, self.template_params(t, level)));
+        let own = own
+            .iter()
+            .filter(move |a| a.trigger == cause)
+            .map(move |a| (*a, a.params(level).unwrap_or_default()));
+        templates.chain(own)
+    }
+
+    /// Checks whether the species' ability can act in a battle where no
+    /// other friend has an ability or perk, i.e. whether anything besides
+    /// the shop or a summon (which only another ability could cause)
+    /// triggers it
+    pub fn acts_in_battle(&self) -> bool {
+        let own = match self {
+            Self::Custom(..) => self.abilities(),
+            _ => &[],
+        };
+        let scripted = self
+            .templates()
+            .flat_map(|t| t.abilities())
+            .chain(own)
+            .any(|a| ability::in_battle(a.trigger));
+        let summons = TOKENS.iter().any(|t| match t.summoner {
+            Summoner::Species(s) => self.has_template(s),
+            Summoner::Modifier(..) => false,
+        });
+        // The Whale's swallow is bespoke, rather than scripted
+        scripted || summons || self.has_template(Self::Whale)
+    }
+
+    /// Checks whether this is a [custom species](crate::custom)
+    pub fn is_custom(&self) -> bool {
+        matches!(self, Self::Custom(..))
+    }
+
+    /// Describes the species' ability at the given level, as simulated
+    pub fn ability_text(&self, level: usize) -> String {
+        self.describe_ability(level, self.ability_params(level))
+    }
+
+    /// Describes the species' ability at the given level, with the given
+    /// magnitudes.  Custom species describe each of their templates (with
+    /// their own magnitudes), then each of their scripted abilities.
+    pub fn describe_ability(&self, level: usize, p: AbilityParams) -> String {
+        let l = level;
+        match self {
+            Self::Ant => {
+                format!(
+                    "Faint: give a random friend +{}/+{}",
+                    p.attack, p.health
+                )
+            }
+            Self::Beaver => format!(
+                "Sell: give {} random friends +{} health",
+                p.targets, p.health
+            ),
+            Self::Cricket => {
+                format!("Faint: summon a {}/{} Zombie Cricket", l, l)
+            }
+            Self::Duck => format!("Sell: give shop pets +{} health", p.health),
+            Self::Fish => {
+                format!(
+                    "Level-up: give all friends +{}/+{}",
+                    p.attack, p.health
+                )
+            }
+            Self::Horse => format!(
+                "Friend summoned: give it +{}/+{} until end of battle",
+                p.attack, p.health
+            ),
+            Self::Mosquito => format!(
+                "Start of battle: deal {} damage to {} random {}",
+                p.damage,
+                p.targets,
+                if p.targets == 1 { "enemy" } else { "enemies" }
+            ),
+            Self::Otter => format!(
+                "Buy: give {} random {} +{}/+{}",
+                p.targets,
+                if p.targets == 1 { "friend" } else { "friends" },
+                p.attack,
+                p.health
+            ),
+            Self::Pig => format!("Sell: gain {} extra gold", p.gold),
+            Self::Whale => format!(
+                "Start of battle: swallow the friend ahead, and release it \
+                 as a level {} friend on faint",
+                l
+            ),
+            Self::Kangaroo => {
+                format!(
+                    "Friend ahead attacks: gain +{}/+{}",
+                    p.attack, p.health
+                )
+            }
+            Self::Ox => format!(
+                "Friend ahead faints: gain Melon armor and +{} attack; \
+                 works 1 time per battle",
+                p.attack
+            ),
+            Self::GhostCricket | Self::Bee => "No ability".to_owned(),
+            Self::Custom(..) => {
+                let templates = self.templates().map(|t| {
+                    t.describe_ability(level, self.template_params(t, level))
+                });
+                let own = self
+                    .abilities()
+                    .iter()
+                    .map(|a| a.describe(a.params(level).unwrap_or_default()));
+                let out: Vec<String> = templates.chain(own).collect();
+                if out.is_empty() {
+                    "No ability".to_owned()
+                } else {
+                    out.join("; ")
+                }
+            }
+        }
+    }
+
+    /// Iterates over every species, including tokens and registered custom
+    /// species
+    pub fn all() -> impl Iterator<Item = Species> {
+        Self::builtin().chain(custom::registered())
+    }
+
+    /// Iterates over every built-in species, including tokens
+    pub fn builtin() -> impl Iterator<Item = Species> {
+        Self::ALL.into_iter()
+    }
+
+    /// Returns the species' name, as used in team codes
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ant => "Ant",
+            Self::Beaver => "Beaver",
+            Self::Cricket => "Cricket",
+            Self::Duck => "Duck",
+            Self::Fish => "Fish",
+            Self::Horse => "Horse",
+            Self::Mosquito => "Mosquito",
+            Self::Otter => "Otter",
+            Self::Pig => "Pig",
+            Self::Whale => "Whale",
+            Self::Kangaroo => "Kangaroo",
+            Self::Ox => "Ox",
+            Self::GhostCricket => "GhostCricket",
+            Self::Bee => "Bee",
+            Self::Custom(i) => &custom::get(*i).species.name,
+        }
+    }
+
+    const ALL: [Species; 14] = [
+        Self::Ant,
+        Self::Beaver,
+        Self::Cricket,
+        Self::Duck,
+        Self::Fish,
+        Self::Horse,
+        Self::Mosquito,
+        Self::Otter,
+        Self::Pig,
+        Self::Whale,
+        Self::Kangaroo,
+        Self::Ox,
+        Self::GhostCricket,
+        Self::Bee,
+    ];
+
+    /// Picks a random species from those sold at or below the given tier in
+    /// the [active pack](crate::pack::active), according to its
+    /// [chances](Pack::chances)
+    pub fn sample<R: Dice>(max_tier: usize, rng: &mut R) -> Self {
+        let pack = pack::active();
+        let options: Vec<Species> = pack.species(max_tier).collect();
+        Self::pick(&options, &pack.chances(max_tier), rng)
+    }
+
+    /// Picks `n` random species as if by [`Species::sample`], without
+    /// regard to order (see [`Dice::roll_multiset`])
+    pub fn sample_n<R: Dice>(
+        max_tier: usize,
+        n: usize,
+        rng: &mut R,
+    ) -> Vec<Self> {
+        let pack = pack::active();
+        let options: Vec<Species> = pack.species(max_tier).collect();
+        rng.roll_multiset(&pack.chances(max_tier), n)
+            .into_iter()
+            .map(|i| options[i])
+            .collect()
+    }
+
+    /// Picks a random species sold at exactly the given tier in the active
+    /// pack, or at the highest tier below it which sells any
+    pub fn sample_tier<R: Dice>(tier: usize, rng: &mut R) -> Self {
+        let pack = pack::active();
+        let options = pack.species_at(tier);
+        let weights: Vec<usize> =
+            options.iter().map(|s| pack.weight(*s)).collect();
+        Self::pick(options, &weights, rng)
+    }
+
+    /// Picks from the given species, using a uniform roll unless they have
+    /// different weights (which keeps dice keys unchanged)
+    fn pick<R: Dice>(
+        options: &[Species],
+        weights: &[usize],
+        rng: &mut R,
+    ) -> Self {
+        if weights.iter().all(|w| *w == weights[0]) {
+            options[rng.roll(0..options.len())]
+        } else {
+            options[rng.roll_weighted(weights)]
         }
     }
 }
 
+/// How strong a species' ability is at a given level, from
+/// [`Species::ability_params`].  Fields which the ability doesn't use are 0.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct AbilityParams {
+    /// Number of friends or enemies targeted
+    pub targets: usize,
+    pub attack: usize,
+    pub health: usize,
+    pub damage: usize,
+    pub gold: usize,
+}
+
+/// A limit on how many times an ability may trigger.  Counts are kept in
+/// each friend's [`Triggers`](crate::friend::Triggers), which are left out of
+/// deduplication.  Per-battle counts are reset at the start of every battle,
+/// and per-turn counts at the start of every shop turn (carrying over into
+/// that turn's battle).
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TriggerLimit {
+    PerBattle(usize),
+    PerTurn(usize),
+}
+
+impl std::str::FromStr for Species {
+    type Err = Error;
+
+    /// Parses a species from its name, e.g. `Ant`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .find(|t| t.name() == s)
+            .ok_or_else(|| Error::Species(s.to_owned()))
+    }
+}
+
+/// Species are debug-printed by name, so that custom species show up as
+/// themselves (e.g. in team codes)
+impl std::fmt::Debug for Species {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 impl std::fmt::Display for Species {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if crate::render::ascii() {
+            return write!(f, "{}", self.abbrev());
+        }
         write!(f, "{}", self.emoji())?;
         if *self == Self::Beaver {
             write!(f, " ")?; // Work around an iTerm2 bug (#10186)
@@ -97,3 +545,42 @@ impl std::fmt::Display for Species {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        for s in Species::builtin() {
+            assert!(Species::all().any(|t| t == s));
+            for level in 1..=3 {
+                assert!(!s.ability_text(level).is_empty(), "{:?}", s);
+            }
+            let turtle = s.packs().any(|p| p.name == "turtle");
+            assert_eq!(turtle, s.tier().is_some(), "{:?}", s);
+            assert_eq!(s.tier().is_none(), Token::get(s).is_some());
+        }
+    }
+
+    #[test]
+    fn ability_params_scale() {
+        // Abilities never get weaker with level, and levels are clamped
+        for s in Species::builtin() {
+            for level in 1..=3 {
+                let p = s.ability_params(level);
+                let q = s.ability_params(level + 1);
+                for (a, b) in [
+                    (p.targets, q.targets),
+                    (p.attack, q.attack),
+                    (p.health, q.health),
+                    (p.damage, q.damage),
+                    (p.gold, q.gold),
+                ] {
+                    assert!(a <= b, "{:?}", s);
+                }
+            }
+            assert_eq!(s.ability_params(0), s.ability_params(1));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Seed sweeps, which run a single matchup with real random dice and compare
+//! the spread of outcomes against exhaustive enumeration.
+//!
+//! Scoring averages over every [`DeterministicDice`] path with equal weight,
+//! but paths aren't equally likely in a real game: a path which rolls once
+//! out of two options is far more likely than one which rolls three times out
+//! of five.  A sweep samples the real distribution, so that a matchup where
+//! the unweighted average is misleading stands out.
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    battle::{Battle, Outcome, Winner},
+    config::GameConfig,
+    dice::DeterministicDice,
+};
+
+/// A sampled rate is flagged when it's this many standard errors away from
+/// the exhaustive rate
+pub const SUSPICIOUS_Z: f64 = 3.0;
+
+/// Fraction of battles with each winner, along with the mean and variance
+/// of the number of rounds
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Rates {
+    pub wins: f64,
+    pub loses: f64,
+    pub ties: f64,
+    pub rounds: f64,
+    pub rounds_variance: f64,
+}
+
+impl Rates {
+    /// Builds rates from outcomes and their relative weights
+    fn new<I: IntoIterator<Item = (Outcome, f64)>>(outcomes: I) -> Self {
+        let outcomes: Vec<(Outcome, f64)> = outcomes.into_iter().collect();
+        let total: f64 = outcomes.iter().map(|(_, w)| w).sum();
+        if total == 0.0 {
+            return Self::default();
+        }
+        let mean = |f: &dyn Fn(&Outcome) -> f64| {
+            outcomes.iter().map(|(o, w)| f(o) * w).sum::<f64>() / total
+        };
+        let rate = |winner: Winner| {
+            mean(&|o: &Outcome| (o.winner == winner) as usize as f64)
+        };
+        let rounds = mean(&|o: &Outcome| o.rounds as f64);
+        Self {
+            wins: rate(Winner::TeamA),
+            loses: rate(Winner::TeamB),
+            ties: rate(Winner::Tied),
+            rounds,
+            rounds_variance: mean(&|o: &Outcome| {
+                (o.rounds as f64 - rounds).powi(2)
+            }),
+        }
+    }
+
+    /// Returns the rate of the given winner
+    pub fn rate(&self, winner: Winner) -> f64 {
+        match winner {
+            Winner::TeamA => self.wins,
+            Winner::TeamB => self.loses,
+            Winner::Tied => self.ties,
+        }
+    }
+}
+
+/// How a single winner's sampled rate compares to enumeration
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Spread {
+    pub winner: Winner,
+    /// Variance of the per-seed indicator, i.e. `p (1 - p)`
+    pub variance: f64,
+    /// Standard error of the sampled rate
+    pub std_error: f64,
+    /// Distance from the sampled rate to the unweighted rate, in standard
+    /// errors (or infinite, if the rates differ with no sampled variance)
+    pub z: f64,
+}
+
+/// The result of a seed sweep
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sweep {
+    /// Seeds run, starting from `first_seed`
+    pub seeds: usize,
+    pub first_seed: u64,
+    /// Number of exhaustive dice paths
+    pub paths: usize,
+    /// Rates from the real dice, one battle per seed
+    pub sampled: Rates,
+    /// Rates averaged over every dice path with equal weight, as in scoring
+    pub unweighted: Rates,
+    /// Rates averaged over every dice path by
+    /// [path weight](DeterministicDice::weight)
+    pub weighted: Rates,
+    pub spread: Vec<Spread>,
+}
+
+impl Sweep {
+    /// Runs the matchup along every dice path, then once per seed
+    pub fn run(
+        cfg: &GameConfig,
+        battle: Battle,
+        seeds: usize,
+        first_seed: u64,
+    ) -> Self {
+        let mut paths = vec![];
+        let mut dice = DeterministicDice::new();
+        while dice.next() {
+            let outcome = battle.run(cfg, &mut dice);
+            paths.push((outcome, dice.weight()));
+        }
+        let sampled = Rates::new((0..seeds).map(|i| {
+            let mut rng =
+                StdRng::seed_from_u64(first_seed.wrapping_add(i as u64));
+            (battle.run(cfg, &mut rng), 1.0)
+        }));
+        let unweighted = Rates::new(paths.iter().map(|(o, _)| (*o, 1.0)));
+        let weighted = Rates::new(paths.iter().cloned());
+
+        let spread = [Winner::TeamA, Winner::TeamB, Winner::Tied]
+            .into_iter()
+            .map(|winner| {
+                let p = sampled.rate(winner);
+                let variance = p * (1.0 - p);
+                let std_error = (variance / seeds.max(1) as f64).sqrt();
+                let delta = (p - unweighted.rate(winner)).abs();
+                let z = if delta < 1e-9 { 0.0 } else { delta / std_error };
+                Spread {
+                    winner,
+                    variance,
+                    std_error,
+                    z,
+                }
+            })
+            .collect();
+        Self {
+            seeds,
+            first_seed,
+            paths: paths.len(),
+            sampled,
+            unweighted,
+            weighted,
+            spread,
+        }
+    }
+
+    /// Returns the largest distance (in standard errors) between a sampled
+    /// rate and its unweighted rate
+    pub fn max_z(&self) -> f64 {
+        self.spread.iter().map(|s| s.z).fold(0.0, f64::max)
+    }
+
+    /// Checks whether the unweighted average looks misleading for this
+    /// matchup, i.e. whether any sampled rate is at least
+    /// [`SUSPICIOUS_Z`] standard errors away from it
+    pub fn is_suspicious(&self) -> bool {
+        self.max_z() >= SUSPICIOUS_Z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::team::Team;
+
+    fn battle(a: &str, b: &str) -> Battle {
+        Battle(a.parse::<Team>().unwrap(), b.parse::<Team>().unwrap())
+    }
+
+    #[test]
+    fn fixed_outcome() {
+        // Without any rolls, every seed agrees with the single dice path
+        let cfg = GameConfig::default();
+        let s = Sweep::run(&cfg, battle("Fish:3/3", "Fish:2/2"), 16, 7);
+        assert_eq!(s.paths, 1);
+        assert_eq!((s.sampled.wins, s.sampled.rounds_variance), (1.0, 0.0));
+        assert_eq!(s.sampled, s.unweighted);
+        assert_eq!(s.weighted, s.unweighted);
+        assert_eq!(s.max_z(), 0.0);
+        assert!(!s.is_suspicious());
+    }
+
+    #[test]
+    fn sampled_rates() {
+        // The Mosquito's snipe picks the winner, so seeds land on both
+        // sides of an even split, and the same seeds give the same sweep
+        let cfg = GameConfig::default();
+        let b = battle("Mosquito:2/2", "Fish:1/1,Fish:1/2");
+        let s = Sweep::run(&cfg, b, 200, 0);
+        assert_eq!((s.unweighted.wins, s.unweighted.ties), (0.5, 0.5));
+        assert_eq!(s.weighted, s.unweighted);
+        assert!(s.sampled.wins > 0.0 && s.sampled.ties > 0.0);
+        assert_eq!(s.sampled.loses, 0.0);
+        assert_eq!(s.spread.len(), 3);
+        assert!(!s.is_suspicious());
+        assert_eq!(s, Sweep::run(&cfg, b, 200, 0));
+        assert_eq!(Rates::new([]), Rates::default());
+    }
+}
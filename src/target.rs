@@ -0,0 +1,126 @@
+use crate::{dice::Dice, friend::Friend, params::TEAM_SIZE, team::Team};
+
+/// Selects the targets of an ability, relative to the friend using it.
+///
+/// Selection is driven by [`Dice`] wherever the game picks randomly, including
+/// tie-breaking, so exhaustive enumeration sees every possible choice.
+/// Friends with no health (which have fainted but not yet been removed) are
+/// never selected.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// The nearest friend ahead of (i.e. closer to the front than) the user
+    NearestAhead,
+    /// The nearest friend behind the user
+    NearestBehind,
+    /// The enemy with the lowest health
+    LowestHealth,
+    /// The enemy with the highest attack
+    HighestAttack,
+    /// Up to `n` distinct random enemies
+    RandomEnemy(usize),
+    /// Up to `n` distinct random friends, other than the user
+    RandomFriend(usize),
+    /// Every friend other than the user
+    AllFriends,
+    /// Every enemy
+    AllEnemies,
+}
+
+impl Target {
+    /// Checks whether this selector picks from the enemy team
+    pub fn is_enemy(&self) -> bool {
+        matches!(
+            self,
+            Self::LowestHealth
+                | Self::HighestAttack
+                | Self::RandomEnemy(..)
+                | Self::AllEnemies
+        )
+    }
+
+    /// Returns the positions of selected targets, on the enemy team if
+    /// [`Target::is_enemy`] and on `own` otherwise.
+    ///
+    /// `pos` is the user's position on `own`, or `None` if it's not on the
+    /// team (e.g. it was just sold or has fainted).  `enemy` is `None` outside
+    /// of battle, in which case enemy selectors find nothing.
+    pub fn select<R: Dice>(
+        self,
+        own: &Team,
+        pos: Option<usize>,
+        enemy: Option<&Team>,
+        rng: &mut R,
+    ) -> Vec<usize> {
+        // Living friends other than the user
+        let mut friends = [None; TEAM_SIZE];
+        for (i, f) in own.iter().enumerate() {
+            if Some(i) != pos {
+                friends[i] = f.filter(|f| f.health > 0);
+            }
+        }
+        let mut enemies = [None; TEAM_SIZE];
+        if let Some(e) = enemy {
+            for (i, f) in e.iter().enumerate() {
+                enemies[i] = f.filter(|f| f.health > 0);
+            }
+        }
+
+        match self {
+            Self::NearestAhead => match pos {
+                Some(p) => (0..p).rev().find(|i| friends[*i].is_some()),
+                None => None,
+            }
+            .into_iter()
+            .collect(),
+            Self::NearestBehind => match pos {
+                Some(p) => ((p + 1)..TEAM_SIZE).find(|i| friends[*i].is_some()),
+                None => None,
+            }
+            .into_iter()
+            .collect(),
+            Self::LowestHealth => {
+                best_by(&enemies, |f| std::cmp::Reverse(f.health), rng)
+            }
+            Self::HighestAttack => best_by(&enemies, |f| f.attack, rng),
+            Self::RandomEnemy(n) => {
+                crate::dice::pick_some(rng, n, &enemies).collect()
+            }
+            Self::RandomFriend(n) => {
+                crate::dice::pick_some(rng, n, &friends).collect()
+            }
+            Self::AllFriends => all(&friends),
+            Self::AllEnemies => all(&enemies),
+        }
+    }
+}
+
+fn all(fs: &[Option<Friend>]) -> Vec<usize> {
+    fs.iter()
+        .enumerate()
+        .filter(|(_, f)| f.is_some())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Picks the friend which maximizes `key`, breaking ties randomly
+fn best_by<K: Ord, F: Fn(&Friend) -> K, R: Dice>(
+    fs: &[Option<Friend>],
+    key: F,
+    rng: &mut R,
+) -> Vec<usize> {
+    let best = match fs.iter().flatten().map(&key).max() {
+        Some(b) => b,
+        None => return vec![],
+    };
+    let tied: Vec<Option<usize>> = fs
+        .iter()
+        .enumerate()
+        .map(|(i, f)| f.filter(|f| key(f) == best).map(|_| i))
+        .collect();
+    // Only roll the dice if there's actually a tie
+    if tied.iter().flatten().count() == 1 {
+        tied.into_iter().flatten().collect()
+    } else {
+        crate::dice::pick_one(rng, &tied).into_iter().collect()
+    }
+}
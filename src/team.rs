@@ -1,9 +1,19 @@
+use std::ops::RangeInclusive;
+
 use itertools::Itertools;
-use log::trace;
 use serde::{Deserialize, Serialize};
+use tracing::trace;
 
 use crate::{
-    dice::Dice, friend::Friend, modifier::Modifier, params::TEAM_SIZE,
+    ability::{Effect, Selector},
+    config::DumbConfig,
+    dice::Dice,
+    error::Error,
+    event::{Cause, Log, Trigger},
+    friend::{AbilityState, Friend, Triggers},
+    modifier::Modifier,
+    params::{MAX_EXP, MAX_STAT, TEAM_SIZE},
+    render::{self, Paint, Style},
     species::Species,
 };
 
@@ -14,6 +24,7 @@ use crate::{
     Clone,
     Hash,
     Debug,
+    Default,
     Deserialize,
     Eq,
     Ord,
@@ -28,6 +39,51 @@ impl Team {
         Team([None; TEAM_SIZE])
     }
 
+    /// Returns a hash of the team, for correlating log messages
+    pub fn fingerprint(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        self.hash(&mut h);
+        h.finish()
+    }
+
+    /// Returns a builder, which checks that the team is legal in the game
+    pub fn builder() -> TeamBuilder {
+        TeamBuilder::default()
+    }
+
+    /// Builds a random team which is [legal](Friend::check_legal) in the
+    /// game, within the given constraints.  Friends are packed against the
+    /// front of the team, and have random experience.
+    pub fn random<R: Dice>(
+        rng: &mut R,
+        constraints: &Constraints,
+    ) -> Result<Self, Error> {
+        constraints.check()?;
+        let roll = |rng: &mut R, r: &RangeInclusive<usize>| {
+            rng.roll(*r.start()..*r.end() + 1)
+        };
+        let mut team = Team::new();
+        for i in 0..roll(rng, &constraints.friends) {
+            let species = if constraints.species.is_empty() {
+                Species::sample(usize::MAX, rng)
+            } else {
+                let s = &constraints.species;
+                s[rng.roll(0..s.len())]
+            };
+            let mut f = Friend::new(species);
+            f.attack = roll(rng, &constraints.attack);
+            f.health = roll(rng, &constraints.health);
+            f.exp = rng.roll(0..MAX_EXP + 1);
+            if rng.roll(0..100) < constraints.perk_chance {
+                let p = &constraints.perks;
+                f.modifier = Some(p[rng.roll(0..p.len())]);
+            }
+            team[i] = Some(f);
+        }
+        Ok(team)
+    }
+
     pub fn sort(&mut self) {
         self.0.sort()
     }
@@ -48,41 +104,83 @@ impl Team {
             })
     }
 
-    /// Checks to see whether the given team is dumb.  A dumb team has fewer
-    /// than three members and all of its members have their stock HP, i.e.
-    /// there's no reason not to have three members
+    /// Checks to see whether the given team is dumb, with default thresholds.
+    /// A dumb team has fewer than three members and all of its members have
+    /// their stock HP, i.e. there's no reason not to have three members
     pub fn is_dumb(&self) -> bool {
-        self.count() < 3
-            && self
-                .0
-                .iter()
-                .flatten()
-                .all(|f| f.modifier.is_none() && f.has_default_power())
+        self.is_dumb_with(&DumbConfig::default())
+    }
+
+    /// Checks to see whether the given team is dumb, with the given thresholds
+    pub fn is_dumb_with(&self, cfg: &DumbConfig) -> bool {
+        self.count() < cfg.min_friends
+            && (!cfg.require_stock
+                || self
+                    .0
+                    .iter()
+                    .flatten()
+                    .all(|f| f.modifier.is_none() && f.has_default_power()))
     }
 
     /// Asks the species at position `i` to perform on-summon actions, with
-    /// a new species summoned at position `pos`
-    pub fn on_summon(&mut self, i: usize, pos: usize) {
+    /// a new species summoned at position `pos`.  `side` identifies the team
+    /// for logging, and is always `true` in the shop.
+    pub fn on_summon<L: Log>(
+        &mut self,
+        i: usize,
+        pos: usize,
+        side: bool,
+        log: &mut L,
+    ) {
         assert!(i != pos);
         assert!(self[i].is_some());
         assert!(self[pos].is_some());
 
-        match self[i].unwrap().species {
-            Species::Horse => {
-                // This is technically a temporary buf, but we're only
-                // simulating a single turn here, so it doesn't matter.
-                let f = self[pos].as_mut().unwrap();
-                trace!(
-                    "    🐴 at {} bufs {}  at {} by ❤️  +1, ⚔️  +1",
-                    i,
-                    f.species,
-                    pos
-                );
-                f.attack += 1;
-                f.health += 1;
+        let f = self[i].unwrap();
+        let mut scripted = f.species.scripted(Cause::Summon, f.level());
+        let Some(first) = scripted.next() else {
+            return;
+        };
+        log.begin_trigger(Trigger {
+            team: side,
+            pos: i,
+            species: f.species,
+            cause: Cause::Summon,
+        });
+        for (a, p) in std::iter::once(first).chain(scripted) {
+            // Abilities can only target the user or the summoned friend
+            // (see Ability::check)
+            let j = if a.target == Selector::Summoned {
+                log.targets(side, &[pos]);
+                pos
+            } else {
+                i
+            };
+            let g = self[j].as_mut().unwrap();
+            trace!(
+                species = ?f.species,
+                pos = i,
+                target_species = ?g.species,
+                target_pos = j,
+                attack = p.attack,
+                health = p.health,
+                effect = ?a.effect,
+                "On-summon ability"
+            );
+            match a.effect {
+                Effect::Buff => {
+                    g.attack += p.attack;
+                    g.health += p.health;
+                }
+                Effect::TempBuff => {
+                    g.temp_attack += p.attack;
+                    g.temp_health += p.health;
+                }
+                Effect::Equip(m) => g.modifier = Some(m),
+                Effect::Damage | Effect::Gold => (),
             }
-            _ => (),
         }
+        log.end_trigger();
     }
 
     pub fn count(&self) -> usize {
@@ -93,13 +191,38 @@ impl Team {
         self.count() == 0
     }
 
-    /// Picks some number of random friends from the team, returning an iterator
-    pub fn random_friends<'a, 'b, R: Dice>(
-        &'a self,
-        n: usize,
-        rng: &'b mut R,
-    ) -> impl Iterator<Item = usize> + 'b {
-        crate::dice::pick_some(rng, n, &self.0)
+    /// Checks that every friend is alive and has a valid amount of experience,
+    /// returning a description of the first violation.
+    pub fn check(&self) -> Result<(), String> {
+        for (i, f) in self.0.iter().enumerate() {
+            if let Some(f) = f {
+                if f.health == 0 {
+                    return Err(format!(
+                        "{} at {} has no health",
+                        f.species, i
+                    ));
+                } else if f.exp > MAX_EXP {
+                    return Err(format!(
+                        "{} at {} has invalid exp {}",
+                        f.species, i, f.exp
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether friends are tightly packed against 0
+    pub fn is_compact(&self) -> bool {
+        self.0
+            .iter()
+            .skip_while(|f| f.is_some())
+            .all(Option::is_none)
+    }
+
+    /// Iterates over team slots, starting from the front of the team
+    pub fn iter(&self) -> impl Iterator<Item = &Option<Friend>> {
+        self.0.iter()
     }
 
     /// Returns a random friend's index, or `None` if the team is empty
@@ -117,6 +240,42 @@ impl Team {
         out
     }
 
+    /// Clears every team member's ability state and trigger counts, e.g. at
+    /// the start of a turn
+    pub fn clear_state(&mut self) {
+        for f in self.0.iter_mut().flatten() {
+            f.state = AbilityState::None;
+            f.triggers = Triggers::default();
+        }
+    }
+
+    /// Clears every team member's ability state and per-battle trigger
+    /// counts, at the start of a battle.  Per-turn counts carry over from the
+    /// shop.
+    pub fn clear_battle_state(&mut self) {
+        for f in self.0.iter_mut().flatten() {
+            f.state = AbilityState::None;
+            f.triggers.battle = 0;
+        }
+    }
+
+    /// Makes every team member's temporary buffs permanent
+    pub fn apply_temp(&mut self) {
+        for f in self.0.iter_mut().flatten() {
+            f.apply_temp();
+        }
+    }
+
+    /// Returns the team as it would enter battle, without experience or ability
+    /// state and with temporary buffs made permanent.  This is useful for
+    /// deduplicating teams, since none of these distinctions matter in battle.
+    pub fn for_battle(&self) -> Self {
+        let mut out = self.without_exp();
+        out.apply_temp();
+        out.clear_state();
+        out
+    }
+
     /// Shuffles team members so they're tightly packed against 0
     pub fn compact(&mut self) {
         let mut i = 0;
@@ -135,174 +294,199 @@ impl Team {
         }
     }
 
-    fn fmt_with_range<I: Iterator<Item = usize> + Clone>(
-        &self,
-        f: &mut std::fmt::Formatter,
-        range: I,
-    ) -> std::fmt::Result {
-        for i in range.clone() {
-            write!(f, "{} ───┐ ", i)?;
-        }
-        writeln!(f)?;
-        for i in range.clone() {
-            if let Some(m) = self[i].and_then(|a| a.modifier) {
-                write!(f, "│ {} │ ", m)?;
-            } else {
-                write!(f, "│    │ ")?;
-            }
-        }
-        writeln!(f)?;
-        for i in range.clone() {
-            if let Some(a) = self[i] {
-                write!(f, "│ {} │ ", a.species)?;
-            } else {
-                write!(f, "│    │ ")?;
+    pub fn summon<L: Log>(
+        &mut self,
+        friend: Friend,
+        team_pos: usize,
+        side: bool,
+        log: &mut L,
+    ) {
+        self[team_pos] = Some(friend);
+
+        for i in 0..TEAM_SIZE {
+            if i != team_pos && self[i].is_some() {
+                self.on_summon(i, team_pos, side, log);
             }
         }
-        writeln!(f)?;
-        for i in range.clone() {
-            if let Some(a) = self[i] {
-                write!(f, "│❤️  {}│ ", a.health)?;
-            } else {
-                write!(f, "│    │ ")?;
+    }
+
+    /// Returns the position of the nearest friend ahead of position `i`,
+    /// skipping empty slots.  Unlike [`Target::NearestAhead`], this includes
+    /// friends which have fainted but not yet been removed, so adjacency
+    /// is stable while hits are being resolved.
+    ///
+    /// [`Target::NearestAhead`]: crate::target::Target::NearestAhead
+    pub fn ahead(&self, i: usize) -> Option<usize> {
+        (0..i).rev().find(|j| self[*j].is_some())
+    }
+
+    /// Returns the position of the nearest friend behind position `i`; see
+    /// [`Team::ahead`]
+    pub fn behind(&self, i: usize) -> Option<usize> {
+        ((i + 1)..TEAM_SIZE).find(|j| self[*j].is_some())
+    }
+
+    /// Attempts to make space at the given position, shoving friends around.
+    /// Returns false if the team is full.
+    pub fn make_space_at(&mut self, i: usize) -> bool {
+        // Prefer an empty slot behind the target slot, which shifts friends
+        // backwards (away from 0); otherwise, take one in front of it.
+        let j =
+            ((i..TEAM_SIZE).chain((0..i).rev())).find(|j| self[*j].is_none());
+        match j {
+            Some(j) => {
+                self.move_friend(j, i);
+                assert!(self[i].is_none());
+                true
             }
+            None => false,
         }
-        writeln!(f)?;
-        for i in range.clone() {
-            if let Some(a) = self[i] {
-                write!(f, "│⚔️  {}│ ", a.attack)?;
-            } else {
-                write!(f, "│    │ ")?;
+    }
+
+    /// Attempts to make space at exactly the given position, only pushing
+    /// friends behind it backwards (away from 0).  Returns false if every
+    /// slot from `i` backwards is full, even if there's room in front.
+    pub fn push_at(&mut self, i: usize) -> bool {
+        match (i..TEAM_SIZE).find(|j| self[*j].is_none()) {
+            Some(j) => {
+                self.move_friend(j, i);
+                assert!(self[i].is_none());
+                true
             }
+            None => false,
         }
-        writeln!(f)?;
-        for _ in range.clone() {
-            write!(f, "└────┘ ")?;
-        }
-        Ok(())
     }
-    /// Removes dead speciess from the team, performing their on-death actions
-    /// then compacting the team afterwards.
-    pub fn remove_dead<R: Dice>(&mut self, rng: &mut R) {
-        let mut changed = false;
-        for i in 0..TEAM_SIZE {
-            if self[i].is_some() && self[i].unwrap().health == 0 {
-                let f = self[i].take().unwrap();
-                trace!("{} at {} is dead, removing", f.species, i);
-                self.on_death(f, i, rng);
-                changed = true;
-            }
+
+    /// Moves the slot at `from` to `to`, sliding every slot in between one
+    /// space towards `from` to fill the gap.  Empty slots move like friends.
+    pub fn move_friend(&mut self, from: usize, to: usize) {
+        if from < to {
+            self.0[from..=to].rotate_left(1);
+        } else {
+            self.0[to..=from].rotate_right(1);
         }
-        if changed {
-            trace!("Compacting team");
-            self.compact();
+    }
+
+    /// Returns the new position of the slot at `i` after a call to
+    /// [`Team::move_friend(from, to)`](Team::move_friend)
+    pub fn reindex(from: usize, to: usize, i: usize) -> usize {
+        if i == from {
+            to
+        } else if from < to && (from..=to).contains(&i) {
+            i - 1
+        } else if to < from && (to..=from).contains(&i) {
+            i + 1
+        } else {
+            i
         }
     }
 
-    pub fn summon(&mut self, friend: Friend, team_pos: usize) {
-        self[team_pos] = Some(friend);
+    /// Pushes the friend at `pos` forward (towards 0) by up to `n` spaces,
+    /// returning its new position.
+    pub fn push_forward(&mut self, pos: usize, n: usize) -> usize {
+        let to = pos.saturating_sub(n);
+        self.move_friend(pos, to);
+        to
+    }
 
-        for i in 0..TEAM_SIZE {
-            if i != team_pos && self[i].is_some() {
-                self.on_summon(i, team_pos);
-            }
-        }
+    /// Pushes the friend at `pos` back (away from 0) by up to `n` spaces,
+    /// returning its new position.  The friend won't be pushed behind the
+    /// last occupied slot, so a compact team stays compact.
+    pub fn push_back(&mut self, pos: usize, n: usize) -> usize {
+        let last = (0..TEAM_SIZE).rev().find(|i| self[*i].is_some());
+        let to = (pos + n).min(last.unwrap_or(pos).max(pos));
+        self.move_friend(pos, to);
+        to
     }
+}
 
-    pub fn on_death<R: Dice>(&mut self, f: Friend, i: usize, rng: &mut R) {
-        assert!(self[i].is_none());
-        match f.species {
-            Species::Cricket => {
-                trace!("Summoning ghost cricket at {}", i);
-                self[i] = Some(Friend {
-                    species: Species::GhostCricket,
-                    attack: f.level(),
-                    health: f.level(),
-                    modifier: None,
-                    exp: 0,
-                });
-                for j in 0..TEAM_SIZE {
-                    if i != j && self[j].is_some() {
-                        self.on_summon(j, i);
-                    }
-                }
-            }
-            Species::Ant => {
-                if let Some(j) = self.random_friend(rng) {
-                    let g = self[j].as_mut().unwrap();
-                    let attack = f.level() * 2;
-                    let health = f.level();
-                    trace!(
-                        "{} on death is buffing {} at {} by ❤️  +{}, ⚔️  + {}",
-                        f.species,
-                        g.species,
-                        j,
-                        health,
-                        attack
-                    );
-                    g.attack += attack;
-                    g.health += health;
-                }
-            }
-            _ => (),
-        }
-        match f.modifier {
-            Some(Modifier::Honey) => {
-                let bee = Friend {
-                    species: Species::Bee,
-                    attack: 1,
-                    health: 1,
-                    modifier: None,
-                    exp: 0,
-                };
-                if self.make_space_at(i) {
-                    trace!("Summoning {} at {}", bee.species, i);
-                    self[i] = Some(bee);
-                    for j in 0..TEAM_SIZE {
-                        if i != j && self[j].is_some() {
-                            self.on_summon(j, i);
-                        }
-                    }
-                } else {
-                    trace!("No room to summon {}", bee.species);
-                }
-            }
-            None => (),
+/// Limits on the teams built by [`Team::random`]
+#[derive(Clone, Debug)]
+pub struct Constraints {
+    /// Species to pick from uniformly, or (if empty) every species sold by
+    /// the [active pack](crate::pack::active), according to its weights
+    pub species: Vec<Species>,
+    /// Number of friends on the team
+    pub friends: RangeInclusive<usize>,
+    pub attack: RangeInclusive<usize>,
+    pub health: RangeInclusive<usize>,
+    /// Chance of each friend holding a perk, in percent (otherwise, it keeps
+    /// its species' default modifier)
+    pub perk_chance: usize,
+    /// Perks to pick from uniformly
+    pub perks: Vec<Modifier>,
+}
+
+impl Default for Constraints {
+    /// Teams of any size, with stats typical of the early turns
+    fn default() -> Self {
+        Self {
+            species: vec![],
+            friends: 1..=TEAM_SIZE,
+            attack: 1..=10,
+            health: 1..=10,
+            perk_chance: 25,
+            perks: Modifier::ALL.to_vec(),
         }
     }
-    /// Attempts to make space at the given position.  Returns the empty
-    /// position, after shoving speciess around, or None if the team is full.
-    pub fn make_space_at(&mut self, i: usize) -> bool {
-        if self[i].is_none() {
-            return true;
-        }
-        // Look for an empty slot behind the target slot, and shift
-        // friends backwards (away from 0) to free up slot i
-        for j in (i + 1)..TEAM_SIZE {
-            if self[j].is_none() {
-                for k in (i..j).rev() {
-                    assert!(self[k + 1].is_none());
-                    self[k + 1] = self[k].take();
-                }
-                assert!(self[i].is_none());
-                return true;
+}
+
+impl Constraints {
+    /// Checks that every team within the constraints would be legal
+    pub fn check(&self) -> Result<(), Error> {
+        let err = |s: String| Err(Error::Team(format!("constraints: {}", s)));
+        for (name, r, min, max) in [
+            ("friends", &self.friends, 0, TEAM_SIZE),
+            ("attack", &self.attack, 1, MAX_STAT),
+            ("health", &self.health, 1, MAX_STAT),
+        ] {
+            if r.is_empty() || *r.start() < min || *r.end() > max {
+                return err(format!(
+                    "{} must be a range within {}-{}, not {:?}",
+                    name, min, max, r
+                ));
             }
         }
-        // Otherwise, look for an empty slot in front of the target
-        // slot, and shift friends forwards (towards 0)
-        for j in 0..i {
-            if self[j].is_none() {
-                for k in j..i {
-                    assert!(self[k].is_none());
-                    self[k] = self[k + 1].take();
-                }
-                assert!(self[i].is_none());
-                return true;
-            }
+        if let Some(s) = self.species.iter().find(|s| s.tier().is_none()) {
+            return err(format!("{:?} is a token", s));
+        } else if self.perk_chance > 100 {
+            return err(format!("{}% perk chance", self.perk_chance));
+        } else if self.perk_chance > 0 && self.perks.is_empty() {
+            return err("no perks to pick from".to_owned());
         }
+        Ok(())
+    }
+}
 
-        assert!(self[i].is_some());
-        false
+/// Builds a [`Team`] from front to back
+#[derive(Clone, Debug, Default)]
+pub struct TeamBuilder {
+    friends: Vec<Friend>,
+}
+
+impl TeamBuilder {
+    /// Adds a friend behind those already on the team
+    pub fn friend(mut self, f: Friend) -> Self {
+        self.friends.push(f);
+        self
+    }
+    /// Builds the team, checking its size and that every friend is
+    /// [legal](Friend::check_legal)
+    pub fn build(self) -> Result<Team, Error> {
+        if self.friends.len() > TEAM_SIZE {
+            return Err(Error::Team(format!(
+                "{} friends (expected at most {})",
+                self.friends.len(),
+                TEAM_SIZE
+            )));
+        }
+        let mut team = Team::new();
+        for (i, f) in self.friends.into_iter().enumerate() {
+            f.check_legal()?;
+            team[i] = Some(f);
+        }
+        team.check().map_err(Error::Team)?;
+        Ok(team)
     }
 }
 
@@ -321,18 +505,310 @@ impl std::ops::IndexMut<usize> for Team {
 
 pub struct TeamPrinter<'a>(pub &'a Team, pub bool);
 
+impl Team {
+    /// Returns a compact, single-line code for the team, which can be parsed
+    /// back with [`str::parse`].  Friends are listed front first, separated
+    /// by commas, as `Species:attack/health`, followed by `:exp` if nonzero
+    /// and `+Modifier` if present, e.g. `Ant:2/1+Honey,Fish:3/3:1`.
+    /// Temporary stats and ability state aren't included.
+    pub fn code(&self) -> String {
+        self.0
+            .iter()
+            .flatten()
+            .map(|f| {
+                let mut s =
+                    format!("{:?}:{}/{}", f.species, f.attack, f.health);
+                if f.exp != 0 {
+                    s += &format!(":{}", f.exp);
+                }
+                if let Some(m) = f.modifier {
+                    s += &format!("+{:?}", m);
+                }
+                s
+            })
+            .join(",")
+    }
+}
+
+impl std::str::FromStr for Team {
+    type Err = Error;
+
+    /// Parses a team from its [code](Team::code)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut team = Team::new();
+        let parts: Vec<&str> = s.split(',').filter(|p| !p.is_empty()).collect();
+        if parts.len() > TEAM_SIZE {
+            return Err(Error::Team(format!("too many friends in {:?}", s)));
+        }
+        for (i, p) in parts.into_iter().enumerate() {
+            let err = || Error::Team(format!("invalid friend {:?}", p));
+            let (p, modifier) = match p.split_once('+') {
+                Some((p, m)) => (p, Some(m.parse()?)),
+                None => (p, None),
+            };
+            let mut fields = p.split(':');
+            let species: Species = fields.next().ok_or_else(err)?.parse()?;
+            let (attack, health) = fields
+                .next()
+                .and_then(|s| s.split_once('/'))
+                .ok_or_else(err)?;
+            let exp = match fields.next() {
+                Some(e) => e.parse().map_err(|_| err())?,
+                None => 0,
+            };
+            if fields.next().is_some() {
+                return Err(err());
+            }
+            team[i] = Some(Friend {
+                species,
+                attack: attack.parse().map_err(|_| err())?,
+                health: health.parse().map_err(|_| err())?,
+                modifier,
+                exp,
+                temp_attack: 0,
+                temp_health: 0,
+                state: AbilityState::None,
+                triggers: Triggers::default(),
+            });
+        }
+        team.check().map_err(Error::Team)?;
+        Ok(team)
+    }
+}
+
+/// Prints the given slots (of a team or the shop) as box art, in the order
+/// given by `range`, or on a single line with the alternate flag.  Slots are
+/// labelled by index, after the prefix if there is one (e.g. `t0` for the
+/// front of the team, as in [scripts](crate::script)).
+pub(crate) fn fmt_friends<I: Iterator<Item = usize> + Clone>(
+    friends: &[Option<Friend>],
+    f: &mut std::fmt::Formatter,
+    range: I,
+    prefix: Option<char>,
+) -> std::fmt::Result {
+    if f.alternate() {
+        return fmt_compact(friends, f, range);
+    }
+    for i in range.clone() {
+        match prefix {
+            Some(p) => write!(f, "{}{} ──┐ ", p, i)?,
+            None => write!(f, "{} ───┐ ", i)?,
+        }
+    }
+    writeln!(f)?;
+    // Friends with no health left are dimmed; otherwise, stats are
+    // colored relative to the species' base stats
+    let style = |a: &Friend, s: Style| {
+        if a.total_health() == 0 {
+            Style::Dim
+        } else {
+            s
+        }
+    };
+    for i in range.clone() {
+        if let Some(a) = friends[i].filter(|a| a.modifier.is_some()) {
+            let m = Paint(style(&a, Style::Perk), a.modifier.unwrap());
+            write!(f, "│ {} │ ", m)?;
+        } else {
+            write!(f, "│    │ ")?;
+        }
+    }
+    writeln!(f)?;
+    for i in range.clone() {
+        if let Some(a) = friends[i] {
+            write!(f, "│ {} │ ", Paint(style(&a, Style::Plain), a.species))?;
+        } else {
+            write!(f, "│    │ ")?;
+        }
+    }
+    writeln!(f)?;
+    for i in range.clone() {
+        if let Some(a) = friends[i] {
+            let (base, _) = a.species.default_power();
+            let s = style(&a, Style::for_stat(a.total_health(), base));
+            if render::ascii() {
+                let v = format!("{:>2}", a.total_health());
+                write!(f, "│hp{}│ ", Paint(s, v))?;
+            } else {
+                write!(f, "│❤️  {}│ ", Paint(s, a.total_health()))?;
+            }
+        } else {
+            write!(f, "│    │ ")?;
+        }
+    }
+    writeln!(f)?;
+    for i in range.clone() {
+        if let Some(a) = friends[i] {
+            let (_, base) = a.species.default_power();
+            let s = style(&a, Style::for_stat(a.total_attack(), base));
+            if render::ascii() {
+                let v = format!("{:>2}", a.total_attack());
+                write!(f, "│at{}│ ", Paint(s, v))?;
+            } else {
+                write!(f, "│⚔️  {}│ ", Paint(s, a.total_attack()))?;
+            }
+        } else {
+            write!(f, "│    │ ")?;
+        }
+    }
+    writeln!(f)?;
+    for i in range.clone() {
+        if let Some(a) = friends[i] {
+            write!(f, "│L{} {}│ ", a.level(), a.exp)?;
+        } else {
+            write!(f, "│    │ ")?;
+        }
+    }
+    writeln!(f)?;
+    for _ in range.clone() {
+        write!(f, "└────┘ ")?;
+    }
+    Ok(())
+}
+/// Prints slots on a single line, e.g. `🦦2/1 🐟3/2🍯`, with total stats and
+/// skipping empty ones
+fn fmt_compact<I: Iterator<Item = usize>>(
+    friends: &[Option<Friend>],
+    f: &mut std::fmt::Formatter,
+    range: I,
+) -> std::fmt::Result {
+    let mut first = true;
+    for a in range.filter_map(|i| friends[i]) {
+        if !first {
+            write!(f, " ")?;
+        }
+        first = false;
+        write!(f, "{}{}/{}", a.species, a.total_attack(), a.total_health())?;
+        if let Some(m) = a.modifier {
+            write!(f, "{}", m)?;
+        }
+    }
+    if first {
+        write!(f, "-")?;
+    }
+    Ok(())
+}
+
+/// Prints the team as box art, front on the right, or on a single line with
+/// the alternate flag (`{:#}`)
 impl std::fmt::Display for Team {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", TeamPrinter(self, true))
+        std::fmt::Display::fmt(&TeamPrinter(self, true), f)
     }
 }
 
 impl std::fmt::Display for TeamPrinter<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         if self.1 {
-            self.0.fmt_with_range(f, (0..TEAM_SIZE).rev())
+            fmt_friends(&self.0 .0, f, (0..TEAM_SIZE).rev(), None)
         } else {
-            self.0.fmt_with_range(f, 0..TEAM_SIZE)
+            fmt_friends(&self.0 .0, f, 0..TEAM_SIZE, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::DeterministicDice;
+
+    #[test]
+    fn codes() {
+        // Codes round-trip, leaving out temporary stats
+        let code = "Ant:2/1+Honey,Fish:3/3:1";
+        let mut team: Team = code.parse().unwrap();
+        assert_eq!(team.count(), 2);
+        assert_eq!(team[0].unwrap().modifier, Some(Modifier::Honey));
+        assert_eq!(team[1].unwrap().exp, 1);
+        team[1].as_mut().unwrap().temp_attack = 2;
+        assert_eq!(team.code(), code);
+        assert_eq!("".parse::<Team>().unwrap(), Team::new());
+        for bad in [
+            "Ant",
+            "Ant:2",
+            "Ant:2/x",
+            "Ant:2/1:1:1",
+            "Dog:2/1",
+            "Ant:2/1+Cheese",
+            "Ant:2/1,Ant:2/1,Ant:2/1,Ant:2/1,Ant:2/1,Ant:2/1",
+        ] {
+            assert!(bad.parse::<Team>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let ant = Friend::new(Species::Ant);
+        let team = Team::builder().friend(ant).friend(ant).build().unwrap();
+        assert_eq!(team.count(), 2);
+        assert!(team.is_compact());
+        let full = (0..TEAM_SIZE).fold(Team::builder(), |b, _| b.friend(ant));
+        assert!(full.clone().build().is_ok());
+        assert!(full.friend(ant).build().is_err());
+        let illegal = [
+            Friend::new(Species::Bee),
+            Friend { attack: 0, ..ant },
+            Friend {
+                exp: MAX_EXP + 1,
+                ..ant
+            },
+        ];
+        for f in illegal {
+            assert!(Team::builder().friend(f).build().is_err());
+        }
+    }
+
+    #[test]
+    fn random_teams() {
+        // Random teams are always legal and within their constraints
+        let c = Constraints {
+            species: vec![Species::Fish, Species::Pig],
+            friends: 2..=3,
+            attack: 3..=4,
+            health: 5..=5,
+            perk_chance: 50,
+            perks: vec![Modifier::Garlic],
+        };
+        let mut dice = DeterministicDice::new();
+        for _ in 0..64 {
+            assert!(dice.next());
+            let team = Team::random(&mut dice, &c).unwrap();
+            assert!(team.is_compact());
+            assert!(c.friends.contains(&team.count()));
+            for f in team.iter().flatten() {
+                assert!(f.check_legal().is_ok());
+                assert!(c.species.contains(&f.species));
+                assert!(c.attack.contains(&f.attack));
+                assert_eq!(f.health, 5);
+                assert!(matches!(f.modifier, None | Some(Modifier::Garlic)));
+            }
+        }
+
+        let bad = [
+            Constraints {
+                attack: 0..=1,
+                ..c.clone()
+            },
+            Constraints {
+                friends: 0..=TEAM_SIZE + 1,
+                ..c.clone()
+            },
+            Constraints {
+                species: vec![Species::Bee],
+                ..c.clone()
+            },
+            Constraints {
+                perk_chance: 101,
+                ..c.clone()
+            },
+            Constraints {
+                perks: vec![],
+                ..c.clone()
+            },
+        ];
+        for b in bad {
+            assert!(Team::random(&mut dice, &b).is_err());
         }
+        assert!(Constraints::default().check().is_ok());
     }
 }
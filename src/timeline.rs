@@ -0,0 +1,248 @@
+use std::{cell::RefCell, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dice::Dice,
+    event::{Event, Log, Trigger},
+    render::Icon,
+    shop::GoldCause,
+};
+
+/// A single dice roll, as seen by a [`Watched`] dice
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Roll {
+    pub range: std::ops::Range<usize>,
+    pub value: usize,
+}
+
+/// One line in a [`Timeline`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Entry {
+    /// An ability triggers; entries until the matching [`Entry::End`] are
+    /// caused by it
+    Trigger(Trigger),
+    /// The current trigger picks targets on the given team
+    Targets { team: bool, pos: Vec<usize> },
+    /// Dice were rolled
+    Roll(Roll),
+    /// Something observable happened (only in battle)
+    Event(Event),
+    /// The shop's gold changed (only in the shop)
+    Gold { cause: GoldCause, delta: isize },
+    /// The current trigger is done
+    End,
+}
+
+/// A structured record of every trigger fired during a shop turn or battle,
+/// along with the targets it picked, the dice it consumed, and the events it
+/// caused.
+///
+/// Dice rolls are captured by wrapping the dice with [`Timeline::watch`]; each
+/// roll is attributed to whatever trigger is active when the next entry is
+/// recorded.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    entries: Vec<(usize, Entry)>,
+    depth: usize,
+    rolls: Rc<RefCell<Vec<Roll>>>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps the given dice, so that its rolls are recorded in this timeline
+    pub fn watch<'a, D: Dice>(&self, dice: &'a mut D) -> Watched<'a, D> {
+        Watched {
+            dice,
+            rolls: self.rolls.clone(),
+        }
+    }
+
+    /// Returns every entry, paired with its trigger nesting depth
+    pub fn entries(&mut self) -> &[(usize, Entry)] {
+        self.flush();
+        &self.entries
+    }
+
+    /// Returns the triggers that fired, in order
+    pub fn triggers(&mut self) -> impl Iterator<Item = &Trigger> {
+        self.entries().iter().filter_map(|(_, e)| match e {
+            Entry::Trigger(t) => Some(t),
+            _ => None,
+        })
+    }
+
+    /// Moves pending dice rolls into the timeline, at the current depth
+    fn flush(&mut self) {
+        let depth = self.depth;
+        self.entries.extend(
+            self.rolls
+                .borrow_mut()
+                .drain(..)
+                .map(|r| (depth, Entry::Roll(r))),
+        );
+    }
+
+    fn push(&mut self, e: Entry) {
+        self.flush();
+        self.entries.push((self.depth, e));
+    }
+}
+
+impl Log for Timeline {
+    fn log(&mut self, e: Event) {
+        self.push(Entry::Event(e))
+    }
+    fn begin_trigger(&mut self, t: Trigger) {
+        self.push(Entry::Trigger(t));
+        self.depth += 1;
+    }
+    fn targets(&mut self, team: bool, pos: &[usize]) {
+        self.push(Entry::Targets {
+            team,
+            pos: pos.to_vec(),
+        })
+    }
+    fn gold(&mut self, cause: GoldCause, delta: isize) {
+        self.push(Entry::Gold { cause, delta })
+    }
+    fn end_trigger(&mut self) {
+        self.flush();
+        self.depth = self.depth.checked_sub(1).expect("Unbalanced trigger");
+        self.entries.push((self.depth, Entry::End));
+    }
+}
+
+impl std::fmt::Display for Timeline {
+    /// Prints the timeline, indenting entries by trigger depth.  Rolls which
+    /// haven't been attributed yet are printed at the end.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let pending = self.rolls.borrow();
+        let pending =
+            pending.iter().map(|r| (self.depth, Entry::Roll(r.clone())));
+        for (depth, e) in self.entries.iter().cloned().chain(pending) {
+            let indent = "  ".repeat(depth);
+            match e {
+                Entry::Trigger(t) => writeln!(f, "{}{}", indent, t)?,
+                Entry::Targets { team, pos } => writeln!(
+                    f,
+                    "{}targets {:?} on team {}",
+                    indent,
+                    pos,
+                    if team { 'A' } else { 'B' }
+                )?,
+                Entry::Roll(r) => writeln!(
+                    f,
+                    "{}{} {} in {:?}",
+                    indent,
+                    Icon::Dice,
+                    r.value,
+                    r.range
+                )?,
+                Entry::Event(e) => writeln!(f, "{}{}", indent, e)?,
+                Entry::Gold { cause, delta } => writeln!(
+                    f,
+                    "{}{} {:+} ({:?})",
+                    indent,
+                    Icon::Gold,
+                    delta,
+                    cause
+                )?,
+                Entry::End => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Dice which reports every roll to a [`Timeline`]
+pub struct Watched<'a, D> {
+    dice: &'a mut D,
+    rolls: Rc<RefCell<Vec<Roll>>>,
+}
+
+impl<D: Dice> Dice for Watched<'_, D> {
+    fn roll(&mut self, range: std::ops::Range<usize>) -> usize {
+        let value = self.dice.roll(range.clone());
+        self.rolls.borrow_mut().push(Roll { range, value });
+        value
+    }
+    /// Weighted rolls are recorded as a pick from every index, so that the
+    /// wrapped dice sees the same call as it would if it weren't watched
+    fn roll_weighted(&mut self, weights: &[usize]) -> usize {
+        let value = self.dice.roll_weighted(weights);
+        let range = 0..weights.len();
+        self.rolls.borrow_mut().push(Roll { range, value });
+        value
+    }
+    fn index(&self) -> Option<usize> {
+        self.dice.index()
+    }
+}
+
+impl<D: std::fmt::Debug> std::fmt::Debug for Watched<'_, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.dice.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        battle::Battle, config::GameConfig, dice::DeterministicDice,
+        event::Cause, species::Species, team::Team,
+    };
+
+    #[test]
+    fn mosquito_snipe() {
+        // The Mosquito's roll and targets are nested under its trigger, and
+        // every trigger is closed
+        let a: Team = "Mosquito:2/2".parse().unwrap();
+        let b: Team = "Fish:1/1,Fish:1/2".parse().unwrap();
+        let mut dice = DeterministicDice::new();
+        dice.next();
+        let mut timeline = Timeline::new();
+        let mut watched = timeline.watch(&mut dice);
+        let cfg = GameConfig::default();
+        Battle(a, b).run_logged(&cfg, &mut watched, &mut timeline);
+        let text = timeline.to_string();
+
+        let entries = timeline.entries();
+        let (depth, Entry::Trigger(t)) = &entries[0] else {
+            panic!("Expected a trigger, got {:?}", entries[0]);
+        };
+        assert_eq!(*depth, 0);
+        assert_eq!(
+            (t.species, t.cause),
+            (Species::Mosquito, Cause::BattleStart)
+        );
+        let roll = Entry::Roll(Roll {
+            range: 0..2,
+            value: 0,
+        });
+        assert_eq!(entries[1], (1, roll));
+        let targets = Entry::Targets {
+            team: false,
+            pos: vec![0],
+        };
+        assert!(entries.contains(&(1, targets)));
+        assert_eq!(entries.last().map(|e| e.0), Some(0));
+        let opened = entries
+            .iter()
+            .filter(|e| matches!(e.1, Entry::Trigger(..)))
+            .count();
+        let closed = entries.iter().filter(|e| e.1 == Entry::End).count();
+        assert_eq!(opened, closed);
+
+        // Entries inside the trigger are indented
+        assert_eq!(text.lines().next(), Some(t.to_string().as_str()));
+        assert!(text.contains("\n  targets [0] on team B\n"));
+        assert_eq!(timeline.triggers().count(), opened);
+    }
+}
@@ -0,0 +1,112 @@
+//! Tokens, which are never sold in the shop and are only summoned when
+//! another friend faints (or loses a perk).
+//!
+//! Each token is described by a row in [`TOKENS`], so that adding one only
+//! needs a new [`Species`] variant and a row here, rather than another arm in
+//! [`Battle::on_death`](crate::battle::Battle).
+
+use crate::{friend::Friend, modifier::Modifier, species::Species};
+
+/// What causes a token to be summoned when a friend faints
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Summoner {
+    /// A friend of the given species faints
+    Species(Species),
+    /// A friend holding the given perk faints
+    Modifier(Modifier),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Token {
+    pub species: Species,
+    pub summoner: Summoner,
+    /// Returns the token's `(health, attack)` given the summoner's level
+    pub power: fn(usize) -> (usize, usize),
+}
+
+fn per_level(level: usize) -> (usize, usize) {
+    (level, level)
+}
+
+fn fixed(_level: usize) -> (usize, usize) {
+    (1, 1)
+}
+
+/// Every token, in the order that they're summoned when a friend faints
+pub const TOKENS: [Token; 2] = [
+    Token {
+        species: Species::GhostCricket,
+        summoner: Summoner::Species(Species::Cricket),
+        power: per_level,
+    },
+    Token {
+        species: Species::Bee,
+        summoner: Summoner::Modifier(Modifier::Honey),
+        power: fixed,
+    },
+];
+
+impl Token {
+    /// Looks up the token for the given species, if it is one
+    pub fn get(species: Species) -> Option<&'static Token> {
+        TOKENS.iter().find(|t| t.species == species)
+    }
+
+    /// Returns every token summoned when the given friend faints, in order
+    pub fn summoned_by(f: &Friend) -> impl Iterator<Item = &'static Token> {
+        let (species, modifier) = (f.species, f.modifier);
+        TOKENS.iter().filter(move |t| match t.summoner {
+            Summoner::Species(s) => species.has_template(s),
+            Summoner::Modifier(m) => Some(m) == modifier,
+        })
+    }
+
+    /// Builds the token, as summoned by a friend of the given level
+    pub fn summon(&self, level: usize) -> Friend {
+        let (health, attack) = (self.power)(level);
+        Friend {
+            health,
+            attack,
+            ..Friend::new(self.species)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        // Tokens are never sold, and a friend summons exactly the tokens
+        // whose summoner matches its species or perk
+        let modifiers = Modifier::ALL.map(Some);
+        for t in &TOKENS {
+            assert!(t.species.tier().is_none());
+            assert_eq!(t.species.default_power(), (t.power)(1));
+            assert_eq!(Token::get(t.species).unwrap().species, t.species);
+        }
+        for s in Species::builtin() {
+            for modifier in modifiers.into_iter().chain([None]) {
+                for level in 1..=3 {
+                    let f = Friend {
+                        modifier,
+                        ..Friend::with_level(s, level)
+                    };
+                    for t in &TOKENS {
+                        let summoned = Token::summoned_by(&f)
+                            .any(|u| u.species == t.species);
+                        let expected = match t.summoner {
+                            Summoner::Species(s) => f.species == s,
+                            Summoner::Modifier(m) => f.modifier == Some(m),
+                        };
+                        assert_eq!(summoned, expected);
+                        let g = t.summon(f.level());
+                        assert_eq!((g.health, g.attack), (t.power)(level));
+                    }
+                }
+            }
+        }
+        assert!(Token::get(Species::Ant).is_none());
+    }
+}
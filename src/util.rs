@@ -1,28 +1,94 @@
 use std::io::{Read, Write};
 
 use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use log::warn;
 use serde::{de::DeserializeOwned, Serialize};
 
-pub fn write_compressed<D: Serialize>(d: &D, f: &str) {
+use crate::error::Result;
+
+pub fn write_compressed<D: Serialize>(d: &D, f: &str) -> Result<()> {
     let mut compressor = ZlibEncoder::new(Vec::new(), Compression::default());
-    let data = &bincode::serialize(d).expect("Failed to serialize");
-    compressor
-        .write_all(data)
-        .expect("Failed to write data to compressor");
-    let compressed = compressor.finish().expect("Failed to compress");
-    std::fs::write(f, compressed).expect("Failed to save");
+    let data = &bincode::serialize(d)?;
+    compressor.write_all(data)?;
+    let compressed = compressor.finish()?;
+    std::fs::write(f, compressed)?;
+    Ok(())
+}
+
+/// Returns a checksum (64-bit FNV-1a) of a value's serialized form, which
+/// doesn't depend on the build or platform
+pub fn checksum<D: Serialize>(d: &D) -> Result<u64> {
+    let data = bincode::serialize(d)?;
+    Ok(data.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    }))
+}
+
+/// Reads a compressed file, returning `Ok(None)` if it doesn't exist
+pub fn read_compressed<D: DeserializeOwned>(f: &str) -> Result<Option<D>> {
+    let d = match std::fs::read(f) {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut decompressor = ZlibDecoder::new(&*d);
+    let mut data = vec![];
+    decompressor.read_to_end(&mut data)?;
+    Ok(Some(bincode::deserialize(&data)?))
+}
+
+/// Opens a compressed file to be read a piece at a time, returning
+/// `Ok(None)` if it doesn't exist
+pub fn open_compressed(f: &str) -> Result<Option<impl Read>> {
+    match std::fs::File::open(f) {
+        Ok(file) => Ok(Some(ZlibDecoder::new(std::io::BufReader::new(file)))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
 
-pub fn read_compressed<D: DeserializeOwned>(f: &str) -> Option<D> {
-    if let Ok(d) = std::fs::read(f) {
-        let mut decompressor = ZlibDecoder::new(&*d);
-        let mut data = vec![];
-        decompressor
-            .read_to_end(&mut data)
-            .expect("Could not decoompress");
-        let out: D = bincode::deserialize(&data).unwrap();
-        Some(out)
-    } else {
-        None
+/// Reads a cache file, returning `None` if it doesn't exist or can't be read
+/// (e.g. because its format has changed), in which case it should be rebuilt
+pub fn read_cache<D: DeserializeOwned>(f: &str) -> Option<D> {
+    match read_compressed(f) {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("Ignoring stale cache {}: {}", f, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        // FNV-1a of nothing is its offset basis, and checksums notice any
+        // change to the data (including its length)
+        assert_eq!(checksum(&()).unwrap(), 0xcbf29ce484222325);
+        let data: Vec<u32> = vec![1, 2, 3];
+        let sum = checksum(&data).unwrap();
+        assert_eq!(checksum(&data.clone()).unwrap(), sum);
+        assert_ne!(checksum(&data[..2].to_vec()).unwrap(), sum);
+        assert_ne!(checksum(&vec![1u32, 3, 2]).unwrap(), sum);
+    }
+
+    #[test]
+    fn compressed_cache() {
+        let path = std::env::temp_dir()
+            .join(format!("sas-cache-{}.binz", std::process::id()));
+        let f = path.to_string_lossy();
+        assert!(read_compressed::<Vec<u32>>(&f).unwrap().is_none());
+        assert!(read_cache::<Vec<u32>>(&f).is_none());
+        write_compressed(&vec![1u32, 2, 3], &f).unwrap();
+        assert_eq!(read_cache::<Vec<u32>>(&f), Some(vec![1, 2, 3]));
+
+        // A cache in another format is ignored, so that it gets rebuilt
+        std::fs::write(&path, b"not compressed").unwrap();
+        assert!(read_compressed::<Vec<u32>>(&f).is_err());
+        assert!(read_cache::<Vec<u32>>(&f).is_none());
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,12 @@
+//! Runs every golden replay fixture in `tests/fixtures` through the engine
+
+#[test]
+fn fixtures() {
+    let status =
+        std::process::Command::new(env!("CARGO_BIN_EXE_super-auto-sim"))
+            .arg("fixtures")
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
+            .status()
+            .expect("Failed to run fixtures");
+    assert!(status.success());
+}